@@ -755,6 +755,15 @@ fn handle_path_events(client: &mut Client) {
                     peer_addr
                 );
             },
+
+            quiche::PathEvent::BlackholeSuspected(local_addr, peer_addr) => {
+                info!(
+                    "{} Path ({}, {}) is suspected to be a black hole",
+                    client.conn.trace_id(),
+                    local_addr,
+                    peer_addr
+                );
+            },
         }
     }
 }
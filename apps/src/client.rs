@@ -467,6 +467,13 @@ pub fn connect(
                 },
 
                 quiche::PathEvent::PeerMigrated(..) => unreachable!(),
+
+                quiche::PathEvent::BlackholeSuspected(local_addr, peer_addr) => {
+                    info!(
+                        "Path ({}, {}) is suspected to be a black hole",
+                        local_addr, peer_addr
+                    );
+                },
             }
         }
 
@@ -0,0 +1,14 @@
+#![no_main]
+
+#[macro_use]
+extern crate libfuzzer_sys;
+
+// Fuzzer for loss recovery. Replays sequences of sent packets, received ACK
+// frames, loss detection timeouts and other events decoded from the input, and
+// checks that the loss recovery state stays consistent after each of them.
+//
+// ACK frames are received from the network, so their ranges and ACK delay are
+// taken from the input as-is, including for packets that were never sent.
+fuzz_target!(|data: &[u8]| {
+    quiche::testing::recovery::replay(data);
+});
@@ -316,6 +316,9 @@ pub enum RecoveryEventType {
 pub enum CongestionStateUpdatedTrigger {
     PersistentCongestion,
     Ecn,
+
+    // quiche defined: congestion control restarted after an idle period.
+    IdleRestart,
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
@@ -727,6 +730,10 @@ pub struct RecoveryParametersSet {
     pub minimum_congestion_window: Option<u32>,
     pub loss_reduction_factor: Option<f32>,
     pub persistent_congestion_threshold: Option<u16>,
+
+    // quiche defined: whether loss recovery follows RFC 9002 as written,
+    // without quiche's own heuristics.
+    pub strict_rfc9002: Option<bool>,
 }
 
 #[serde_with::skip_serializing_none]
@@ -748,6 +755,12 @@ pub struct MetricsUpdated {
     pub packets_in_flight: Option<u64>,
 
     pub pacing_rate: Option<u64>,
+
+    // quiche defined: the number of lost packets, by the congestion control
+    // phase they were sent in.
+    pub slow_start_losses: Option<u64>,
+    pub avoidance_losses: Option<u64>,
+    pub recovery_losses: Option<u64>,
 }
 
 #[serde_with::skip_serializing_none]
@@ -768,6 +781,13 @@ pub struct LossTimerUpdated {
     pub event_type: LossTimerEventType,
 
     pub delta: Option<f32>,
+
+    // quiche defined: the number of probe packets scheduled on expiration.
+    pub probes: Option<u64>,
+
+    // quiche defined: the number of frames of packets in flight queued for
+    // retransmission in the probes on expiration.
+    pub requeued_frames: Option<u64>,
 }
 
 #[serde_with::skip_serializing_none]
@@ -778,6 +798,10 @@ pub struct PacketLost {
     pub frames: Option<Vec<QuicFrame>>,
 
     pub trigger: Option<PacketLostTrigger>,
+
+    // quiche defined: the time between sending the packet and declaring it
+    // lost, in milliseconds.
+    pub loss_latency: Option<f32>,
 }
 
 #[serde_with::skip_serializing_none]
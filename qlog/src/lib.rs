@@ -391,6 +391,22 @@
 //! Serialization to JSON occurs as methods on the [`QlogStreamer`]
 //! are called. No additional steps are required.
 //!
+//! ### Reading
+//!
+//! Streamed traces can be read back with a [`QlogSeqReader`], which reads the
+//! header when it is created and then yields [`Event`]s as an iterator:
+//!
+//! ```no_run
+//! let file = std::fs::File::open("foo.sqlog").unwrap();
+//! let reader = std::io::BufReader::new(file);
+//!
+//! let reader = qlog::reader::QlogSeqReader::new(Box::new(reader)).unwrap();
+//!
+//! for event in reader {
+//!     println!("{:?}", event.data);
+//! }
+//! ```
+//!
 //! [`Trace`]: struct.Trace.html
 //! [`TraceSeq`]: struct.TraceSeq.html
 //! [`VantagePoint`]: struct.VantagePoint.html
@@ -398,6 +414,8 @@
 //! [`qlog::Trace.events`]: struct.Trace.html#structfield.events
 //! [`push_event()`]: struct.Trace.html#method.push_event
 //! [`QlogStreamer`]: struct.QlogStreamer.html
+//! [`QlogSeqReader`]: reader/struct.QlogSeqReader.html
+//! [`Event`]: events/struct.Event.html
 //! [`Write`]: https://doc.rust-lang.org/std/io/trait.Write.html
 //! [`start_log()`]: streamer/struct.QlogStreamer.html#method.start_log
 //! [`add_event()`]: streamer/struct.QlogStreamer.html#method.add_event
@@ -970,4 +988,5 @@ mod tests {
 }
 
 pub mod events;
+pub mod reader;
 pub mod streamer;
@@ -0,0 +1,204 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::events::Event;
+use crate::Error;
+use crate::QlogSeq;
+use crate::Result;
+
+/// The record separator that precedes each JSON text in a JSON-SEQ stream.
+const RECORD_SEPARATOR: u8 = 0x1e;
+
+/// A helper object for reading JSON-SEQ-serialized qlog from a [`BufRead`]
+/// trait, such as the output of a [`QlogStreamer`].
+///
+/// The [`QlogSeq`] header is read when the reader is created, and [Event]s
+/// are then read one at a time by iterating over the reader. Records that are
+/// not valid [Event]s, for example events this crate doesn't know about, are
+/// skipped.
+///
+/// [`BufRead`]: https://doc.rust-lang.org/std/io/trait.BufRead.html
+/// [`QlogStreamer`]: ../streamer/struct.QlogStreamer.html
+pub struct QlogSeqReader<'a> {
+    pub qlog: QlogSeq,
+
+    reader: Box<dyn std::io::BufRead + Send + Sync + 'a>,
+}
+
+impl<'a> QlogSeqReader<'a> {
+    /// Creates a [QlogSeqReader] and reads the [QlogSeq] header from the
+    /// provided [`BufRead`].
+    ///
+    /// [`BufRead`]: https://doc.rust-lang.org/std/io/trait.BufRead.html
+    pub fn new(
+        mut reader: Box<dyn std::io::BufRead + Send + Sync + 'a>,
+    ) -> Result<Self> {
+        let record =
+            Self::read_record(reader.as_mut())?.ok_or(Error::InvalidFormat)?;
+
+        let qlog =
+            serde_json::from_slice(&record).map_err(|_| Error::InvalidFormat)?;
+
+        Ok(QlogSeqReader { qlog, reader })
+    }
+
+    /// Reads the next JSON text, or returns `None` at the end of the stream.
+    fn read_record(
+        reader: &mut (dyn std::io::BufRead + Send + Sync + 'a),
+    ) -> Result<Option<Vec<u8>>> {
+        loop {
+            let mut record = Vec::new();
+
+            if reader.read_until(RECORD_SEPARATOR, &mut record)? == 0 {
+                return Ok(None);
+            }
+
+            if record.last() == Some(&RECORD_SEPARATOR) {
+                record.pop();
+            }
+
+            // Skip the empty record before the first separator.
+            if record.iter().all(|b| b.is_ascii_whitespace()) {
+                continue;
+            }
+
+            return Ok(Some(record));
+        }
+    }
+}
+
+impl<'a> Iterator for QlogSeqReader<'a> {
+    type Item = Event;
+
+    #[inline]
+    fn next(&mut self) -> Option<Event> {
+        loop {
+            let record = Self::read_record(self.reader.as_mut()).ok()??;
+
+            if let Ok(event) = serde_json::from_slice(&record) {
+                return Some(event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::quic::PacketType;
+    use crate::events::EventData;
+    use crate::events::EventImportance;
+    use crate::streamer::QlogStreamer;
+    use crate::testing::*;
+
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    // A writer that can still be read from once it's been given to a
+    // streamer.
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn read_streamed_events() {
+        let buf = SharedBuf::default();
+
+        let mut s = QlogStreamer::new(
+            crate::QLOG_VERSION.to_string(),
+            Some("title".to_string()),
+            Some("description".to_string()),
+            None,
+            std::time::Instant::now(),
+            make_trace_seq(),
+            EventImportance::Base,
+            Box::new(buf.clone()),
+        );
+
+        s.start_log().unwrap();
+
+        for (i, ty) in
+            [PacketType::Initial, PacketType::OneRtt].iter().enumerate()
+        {
+            let ev_data =
+                EventData::PacketSent(crate::events::quic::PacketSent {
+                    header: make_pkt_hdr(ty.clone()),
+                    frames: None,
+                    is_coalesced: None,
+                    retry_token: None,
+                    stateless_reset_token: None,
+                    supported_versions: None,
+                    raw: None,
+                    datagram_id: None,
+                    send_at_time: None,
+                    trigger: None,
+                });
+
+            s.add_event(Event::with_time(i as f32, ev_data)).unwrap();
+        }
+
+        s.finish_log().unwrap();
+
+        let mut data = buf.0.lock().unwrap().clone();
+
+        // Add a record this crate doesn't know about, which is skipped.
+        data.extend_from_slice(b"\x1e{\"time\":2.0,\"name\":\"foo:bar\"}\n");
+
+        let reader = QlogSeqReader::new(Box::new(&data[..])).unwrap();
+
+        assert_eq!(reader.qlog.title, Some("title".to_string()));
+        assert_eq!(reader.qlog.trace, make_trace_seq());
+
+        let events: Vec<Event> = reader.collect();
+        assert_eq!(events.len(), 2);
+
+        for (i, ev) in events.iter().enumerate() {
+            assert_eq!(ev.time, i as f32);
+
+            match &ev.data {
+                EventData::PacketSent(pkt) =>
+                    assert_eq!(pkt.header.packet_number, 0),
+
+                _ => panic!("unexpected event"),
+            }
+        }
+    }
+
+    #[test]
+    fn read_invalid_header() {
+        assert!(QlogSeqReader::new(Box::new(&b""[..])).is_err());
+        assert!(QlogSeqReader::new(Box::new(&b"\x1e{}\n"[..])).is_err());
+    }
+}
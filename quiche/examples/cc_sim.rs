@@ -0,0 +1,77 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Compares CUBIC and Reno over simulated links.
+//!
+//! Each scenario is run once per algorithm with a single flow, then once with
+//! both algorithms competing for the same link.
+
+use quiche::simulation::Report;
+use quiche::simulation::Scenario;
+use quiche::CongestionControlAlgorithm;
+
+const CC_ALGORITHMS: [CongestionControlAlgorithm; 2] = [
+    CongestionControlAlgorithm::CUBIC,
+    CongestionControlAlgorithm::Reno,
+];
+
+fn main() {
+    let scenarios = [
+        ("bufferbloat", Scenario::bufferbloat()),
+        ("lossy_wireless", Scenario::lossy_wireless()),
+        ("bandwidth_drop", Scenario::bandwidth_drop()),
+    ];
+
+    println!(
+        "{:<16} {:<8} {:<6} {:>10} {:>14} {:>6} {:>8}",
+        "scenario", "flows", "cc", "Mbps", "p99 queue ms", "lost", "dropped"
+    );
+
+    for (name, scenario) in scenarios.iter() {
+        for &cc in CC_ALGORITHMS.iter() {
+            print_report(name, "alone", &scenario.run(&[cc])[0]);
+        }
+
+        for report in scenario.run(&CC_ALGORITHMS) {
+            print_report(name, "shared", &report);
+        }
+    }
+}
+
+fn print_report(scenario: &str, flows: &str, report: &Report) {
+    let summary = &report.summary;
+
+    println!(
+        "{:<16} {:<8} {:<6} {:>10.2} {:>14.1} {:>6} {:>8}",
+        scenario,
+        flows,
+        format!("{:?}", report.cc_algorithm),
+        summary.throughput as f64 * 8.0 / 1e6,
+        summary.queuing_delay_p99.as_secs_f64() * 1e3,
+        summary.lost_count,
+        summary.dropped_count,
+    );
+}
@@ -0,0 +1,71 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Replays a qlog trace through loss recovery and reports where the locally
+//! computed metrics differ from the recorded ones.
+
+use std::str::FromStr;
+
+use quiche::CongestionControlAlgorithm;
+
+// The maximum number of divergences to print.
+const MAX_DIVERGENCES: usize = 20;
+
+fn main() {
+    let mut args = std::env::args();
+
+    let cmd = &args.next().unwrap();
+
+    if args.len() != 1 && args.len() != 2 {
+        println!("Usage: {} FILE [CC_ALGORITHM]", cmd);
+        return;
+    }
+
+    let file = std::fs::File::open(args.next().unwrap()).unwrap();
+    let reader = std::io::BufReader::new(file);
+
+    let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+
+    if let Some(cc) = args.next() {
+        config
+            .set_cc_algorithm(CongestionControlAlgorithm::from_str(&cc).unwrap());
+    }
+
+    let report = quiche::replay::replay_seq(Box::new(reader), &config).unwrap();
+
+    println!(
+        "sent={} acks={} timeouts={} checked={} diverged={}",
+        report.packets_sent,
+        report.acks_received,
+        report.loss_detection_timeouts,
+        report.metrics_checked,
+        report.divergences.len()
+    );
+
+    for d in report.divergences.iter().take(MAX_DIVERGENCES) {
+        println!("{}", d);
+    }
+}
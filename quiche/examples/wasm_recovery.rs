@@ -0,0 +1,75 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Drives loss recovery with timestamps supplied by the caller, as on
+//! WebAssembly targets without a usable `std::time::Instant`.
+//!
+//! On wasm32-unknown-unknown, `run()` is exported through wasm-bindgen, and
+//! JavaScript code gives it the value of `performance.now()`, which quiche also
+//! reads as its own clock:
+//!
+//! ```js
+//! import { run } from "./wasm_recovery.js";
+//!
+//! console.log(run(performance.now()));
+//! ```
+//!
+//! On other targets, `main()` runs it with the current time.
+
+use quiche::testing::recovery::AckScript;
+use quiche::testing::recovery::PacketTrain;
+use quiche::testing::recovery::Scenario;
+use quiche::time::Duration;
+use quiche::time::Instant;
+use quiche::CongestionControlAlgorithm;
+
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
+
+/// Sends 10 packets at `now_ms`, in milliseconds since the origin of the
+/// clock, acknowledges all but the first two of them 20ms later, and returns
+/// the number of packets declared lost.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub fn run(now_ms: f64) -> u32 {
+    let now = Instant::from_origin(Duration::from_secs_f64(now_ms / 1000.0));
+
+    let script = AckScript::new().after(Duration::from_millis(20)).ack(2..10);
+
+    let mut scenario = Scenario::starting_at(
+        CongestionControlAlgorithm::CUBIC,
+        Some(PacketTrain::new(10, 1200)),
+        script,
+        now,
+    );
+
+    scenario.run().lost_packets() as u32
+}
+
+fn main() {
+    let now = Instant::now().since_origin();
+
+    println!("lost {} packets", run(now.as_secs_f64() * 1000.0));
+}
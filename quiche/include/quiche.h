@@ -115,6 +115,10 @@ enum quiche_error {
 
     // Error in congestion control.
     QUICHE_ERR_CONGESTION_CONTROL = -14,
+
+    // The peer acknowledged a packet number that was never sent, as in an
+    // optimistic ACK attack.
+    QUICHE_ERR_OPTIMISTIC_ACK = -19,
 };
 
 // Returns a human readable string with the quiche version number.
@@ -212,9 +216,116 @@ void quiche_config_set_cc_algorithm(quiche_config *config, enum quiche_cc_algori
 // Configures whether to use HyStart++.
 void quiche_config_enable_hystart(quiche_config *config, bool v);
 
+// Configures whether to use the latency spin bit (disabled by default).
+void quiche_config_enable_spin_bit(quiche_config *config, bool v);
+
 // Configures whether to enable pacing (enabled by default).
 void quiche_config_enable_pacing(quiche_config *config, bool v);
 
+// Sets the maximum pacing rate in bytes/s (no limit by default). Returns
+// QUICHE_ERR_CONGESTION_CONTROL if `v` is zero.
+int quiche_config_set_max_pacing_rate(quiche_config *config, uint64_t v);
+
+// Sets the initial congestion window in packets (10 by default). Returns
+// QUICHE_ERR_CONGESTION_CONTROL if `packets` is lower than 2.
+int quiche_config_set_initial_congestion_window_packets(quiche_config *config, size_t packets);
+
+// Sets the initial packet reordering threshold used by loss detection (3 by
+// default). Returns QUICHE_ERR_CONGESTION_CONTROL if `v` is lower than 3 or
+// higher than 20.
+int quiche_config_set_packet_reorder_threshold(quiche_config *config, uint64_t v);
+
+// Sets the maximum number of probe packets pending at once across all packet
+// number spaces (2 by default). Returns QUICHE_ERR_CONGESTION_CONTROL if `v`
+// is zero.
+int quiche_config_set_max_loss_probes(quiche_config *config, size_t v);
+
+// Sets how many non-ack-eliciting packets can be sent in a row before a PING
+// is added to solicit an ACK (24 by default).
+void quiche_config_set_max_outstanding_non_ack_eliciting(quiche_config *config, size_t v);
+
+// Configures whether the probe timeout backs off when only packets with
+// nothing to retransmit are in flight (enabled by default).
+void quiche_config_enable_non_retransmittable_pto_backoff(quiche_config *config, bool v);
+
+// Sets the factor applied to the probe timeout of the Initial and Handshake
+// packet number spaces (1.0 by default). Returns QUICHE_ERR_CONGESTION_CONTROL
+// if `v` is not in (0.0, 1.0].
+int quiche_config_set_handshake_pto_multiplier(quiche_config *config, double v);
+
+// Configures whether a probe is sent right away when sending is resumed on a
+// paused path (disabled by default).
+void quiche_config_enable_path_resume_probe(quiche_config *config, bool v);
+
+// Configures whether packets coalesced in the same UDP datagram are declared
+// lost together (disabled by default).
+void quiche_config_enable_coalesced_loss(quiche_config *config, bool v);
+
+// Configures whether packets below a long run of acknowledged packets are
+// declared lost without waiting for the time reordering threshold (disabled
+// by default).
+void quiche_config_enable_ack_implied_loss(quiche_config *config, bool v);
+
+// Configures whether the congestion response to a loss is held back for a
+// fraction of the RTT, in case the lost packets are acknowledged in the
+// meantime (disabled by default).
+void quiche_config_enable_loss_hold(quiche_config *config, bool v);
+
+// Configures whether the probe timeout is brought forward when it would fire
+// after the idle timeout (disabled by default).
+void quiche_config_enable_idle_bounded_pto(quiche_config *config, bool v);
+
+// Configures whether ACKs that only acknowledge non-ack-eliciting packets are
+// fed into the delivery rate sampler (enabled by default).
+void quiche_config_enable_ack_only_rate_samples(quiche_config *config, bool v);
+
+// Sets how far the loss detection timer can move, in milliseconds, before
+// quiche_conn_timeout_as_nanos() reports the new deadline (0 by default).
+void quiche_config_set_loss_timer_coalescing(quiche_config *config, uint64_t v);
+
+// Sets how much work processing the peer's ACK frames can take every second
+// before the connection is considered overloaded (0, i.e. unlimited, by
+// default).
+void quiche_config_set_ack_cost_budget(quiche_config *config, uint64_t v);
+
+// Sets the number of consecutive PTOs without any packet acknowledged after
+// which a network black hole is suspected on a path (3 by default, 0
+// disables it).
+void quiche_config_set_blackhole_pto_threshold(quiche_config *config, uint32_t v);
+
+// Configures whether the datagram size is lowered to the minimum when a
+// network black hole is suspected (disabled by default).
+void quiche_config_enable_blackhole_mtu_clamp(quiche_config *config, bool v);
+
+// Sets the number of consecutive handshake PTOs after which the peer is deemed
+// unreachable (0, i.e. unlimited, by default).
+void quiche_config_set_max_handshake_ptos(quiche_config *config, uint32_t v);
+
+// Sets the number of PTOs without sending anything after which congestion
+// control restarts (3 by default, 0 disables it).
+void quiche_config_set_idle_restart_threshold(quiche_config *config, uint32_t v);
+
+// Sets how many frames the acknowledged and lost frames backlogs of each
+// packet number space can hold (0, i.e. unlimited, by default).
+void quiche_config_set_max_frame_backlog(quiche_config *config, size_t v);
+
+// Sets the minimum amount of data of a new stream to send in a packet limited
+// by the congestion window or the pacing burst (0, i.e. none, by default).
+void quiche_config_set_min_new_stream_chunk(quiche_config *config, size_t v);
+
+// Configures whether loss recovery follows RFC 9002 as written, overriding the
+// settings of quiche's own heuristics (disabled by default).
+void quiche_config_set_strict_rfc9002(quiche_config *config, bool v);
+
+// Sets the initial time reordering threshold used by loss detection, as a
+// multiple of the RTT (1.125 by default). Returns
+// QUICHE_ERR_CONGESTION_CONTROL if `v` is lower than 1.0 or not finite.
+int quiche_config_set_time_reorder_threshold(quiche_config *config, double v);
+
+// Sets the RTT used before any RTT sample is available, in milliseconds (333
+// by default). Returns QUICHE_ERR_CONGESTION_CONTROL if `v` is zero.
+int quiche_config_set_initial_rtt(quiche_config *config, uint64_t v);
+
 // Configures whether to enable receiving DATAGRAM frames.
 void quiche_config_enable_dgram(quiche_config *config, bool enabled,
                                 size_t recv_queue_len,
@@ -296,6 +407,34 @@ bool quiche_conn_set_qlog_path(quiche_conn *conn, const char *path,
 void quiche_conn_set_qlog_fd(quiche_conn *conn, int fd, const char *log_title,
                              const char *log_desc);
 
+enum quiche_qlog_level {
+    QUICHE_QLOG_LEVEL_CORE = 0,
+    QUICHE_QLOG_LEVEL_BASE = 1,
+    QUICHE_QLOG_LEVEL_EXTRA = 2,
+};
+
+// Enables qlog to the specified callback, without ever blocking on it.
+//
+// The callback is called synchronously, on the thread processing the
+// connection, with each serialized JSON-SEQ record, including the trailing
+// newline. It must either consume the whole record and return 0, or return a
+// negative value if it can't accept it right now, in which case the record is
+// buffered and passed again later. When the buffer is full, new records are
+// dropped instead (see `quiche_conn_qlog_dropped_events()`).
+//
+// The `argp` argument is passed as-is to the callback and must stay valid for
+// the lifetime of the connection.
+void quiche_conn_set_qlog_sink(quiche_conn *conn,
+                               int (*cb)(const uint8_t *buf, size_t buf_len,
+                                         void *argp),
+                               void *argp, const char *log_title,
+                               const char *log_desc,
+                               enum quiche_qlog_level qlog_level);
+
+// Returns the number of qlog records dropped because the callback set with
+// `quiche_conn_set_qlog_sink()` couldn't keep up.
+uint64_t quiche_conn_qlog_dropped_events(quiche_conn *conn);
+
 // Configures the given session for resumption.
 int quiche_conn_set_session(quiche_conn *conn, const uint8_t *buf, size_t buf_len);
 
@@ -324,15 +463,70 @@ typedef struct {
 
     // The time to send the packet out.
     struct timespec at;
+
+    // New fields are only appended after this point, and are only written by
+    // `quiche_conn_send_sized()`.
+
+    // The time to send the packet out, on the CLOCK_MONOTONIC clock. This can
+    // be passed as-is to SO_TXTIME, when configured with CLOCK_MONOTONIC.
+    struct timespec release_time;
+
+    // The pacing rate of the path the packet is sent on, in bytes/s.
+    uint64_t pacing_rate;
 } quiche_send_info;
 
 // Writes a single QUIC packet to be sent to the peer.
+//
+// Only the fields of `out_info` up to `at` are written, for compatibility with
+// applications built against older versions of this header. Use
+// `quiche_conn_send_sized()` to also collect the others.
 ssize_t quiche_conn_send(quiche_conn *conn, uint8_t *out, size_t out_len,
                          quiche_send_info *out_info);
 
+// Writes a single QUIC packet to be sent to the peer.
+//
+// The `info_len` argument must be set to `sizeof(quiche_send_info)`. Fields
+// that don't fit in `info_len` bytes are left untouched, so that applications
+// built against older versions of this header keep working with newer
+// versions of the library.
+ssize_t quiche_conn_send_sized(quiche_conn *conn, uint8_t *out, size_t out_len,
+                               quiche_send_info *out_info, size_t info_len);
+
+// Reports that the packet last written by `quiche_conn_send()` couldn't be
+// sent, so that it stops being tracked and its data is retransmitted. Returns
+// QUICHE_ERR_DONE if none of its contents were still tracked.
+int quiche_conn_on_send_failed(quiche_conn *conn);
+
 // Returns the size of the send quantum, in bytes.
 size_t quiche_conn_send_quantum(quiche_conn *conn);
 
+typedef struct {
+    // The size of each segment, except the last one which may be smaller.
+    size_t segment_size;
+
+    // The maximum number of segments in the batch.
+    size_t max_segments;
+
+    // The time to send the batch out, on the CLOCK_MONOTONIC clock.
+    struct timespec release_time;
+} quiche_batch_hint;
+
+// Returns how many packets can be sent on the active path in a single batch,
+// and when.
+//
+// Applications sending with UDP GSO can call `quiche_conn_send()` up to
+// `max_segments` times with a buffer of `segment_size` bytes, and send the
+// resulting packets with a single `sendmsg()` with UDP_SEGMENT set to
+// `segment_size`. The batch must end early when a packet smaller than
+// `segment_size` is written, as only the last segment may be smaller.
+void quiche_conn_send_batch_hint(quiche_conn *conn, quiche_batch_hint *out);
+
+// Returns the pacing rate of the active path, in bytes/s.
+//
+// Applications that leave pacing to the kernel can use it to set
+// SO_MAX_PACING_RATE on the socket.
+uint64_t quiche_conn_pacing_rate(quiche_conn *conn);
+
 // Reads contiguous data from a stream.
 ssize_t quiche_conn_stream_recv(quiche_conn *conn, uint64_t stream_id,
                                 uint8_t *out, size_t buf_len, bool *fin);
@@ -414,6 +608,14 @@ bool quiche_conn_is_in_early_data(const quiche_conn *conn);
 // Returns whether there is stream or DATAGRAM data available to read.
 bool quiche_conn_is_readable(const quiche_conn *conn);
 
+// Returns whether an ACK made room in a full congestion window since the last
+// call to quiche_conn_send().
+bool quiche_conn_is_writable_again(const quiche_conn *conn);
+
+// Returns whether processing the peer's ACK frames exceeded the configured
+// budget in the current one second window.
+bool quiche_conn_is_ack_overloaded(const quiche_conn *conn);
+
 // Returns true if the connection is draining.
 bool quiche_conn_is_draining(const quiche_conn *conn);
 
@@ -431,6 +633,10 @@ bool quiche_conn_is_closed(const quiche_conn *conn);
 // Returns true if the connection was closed due to the idle timeout.
 bool quiche_conn_is_timed_out(const quiche_conn *conn);
 
+// Returns true if the handshake was given up on after the maximum number of
+// handshake PTOs.
+bool quiche_conn_is_handshake_unreachable(const quiche_conn *conn);
+
 // Returns true if a connection error was received, and updates the provided
 // parameters accordingly.
 bool quiche_conn_peer_error(quiche_conn *conn,
@@ -447,6 +653,15 @@ bool quiche_conn_local_error(quiche_conn *conn,
                             const uint8_t **reason,
                             size_t *reason_len);
 
+// Returns true if the idle timeout fired before the handshake completed, and
+// updates the provided parameters accordingly. The latest RTT sample is in
+// nanoseconds, or 0 if no packet was ever acknowledged.
+bool quiche_conn_handshake_timeout(const quiche_conn *conn,
+                                   size_t *initial_ptos,
+                                   size_t *handshake_ptos,
+                                   uint64_t *lost_bytes,
+                                   uint64_t *last_rtt_sample);
+
 // Initializes the stream's application data.
 //
 // Stream data can only be initialized once. Additional calls to this method
@@ -533,11 +748,49 @@ typedef struct {
 
     // DATAGRAM frame extension parameter, if any.
     ssize_t peer_max_datagram_frame_size;
+
+    // New fields are only appended after this point, and are only written by
+    // `quiche_conn_stats_sized()`.
+
+    // The number of QUIC packets that were declared lost, but were
+    // acknowledged afterwards.
+    size_t spurious_lost;
+
+    // The number of times the CONNECTION_CLOSE frame was re-sent because the
+    // peer kept sending packets after the connection was closed.
+    size_t close_resent;
+
+    // The number of PING frames sent to solicit an ACK after too many
+    // non-ack-eliciting packets were sent in a row.
+    size_t solicited_pings;
+
+    // The number of frames queued for retransmission on PTO that weren't sent
+    // because the packet originally carrying them was acknowledged first.
+    size_t suppressed_retrans;
+
+    // The number of one second windows in which processing the peer's ACK
+    // frames exceeded the configured budget.
+    size_t ack_overloads;
+
+    // The number of packet numbers skipped to detect optimistic ACKs.
+    size_t skipped_pkt_nums;
 } quiche_stats;
 
 // Collects and returns statistics about the connection.
+//
+// Only the fields up to `peer_max_datagram_frame_size` are written, for
+// compatibility with applications built against older versions of this
+// header. Use `quiche_conn_stats_sized()` to also collect the others.
 void quiche_conn_stats(quiche_conn *conn, quiche_stats *out);
 
+// Collects and returns statistics about the connection.
+//
+// The `out_len` argument must be set to `sizeof(quiche_stats)`. Fields that
+// don't fit in `out_len` bytes are left untouched, so that applications built
+// against older versions of this header keep working with newer versions of
+// the library.
+int quiche_conn_stats_sized(quiche_conn *conn, quiche_stats *out, size_t out_len);
+
 typedef struct {
     // The local address used by this path.
     struct sockaddr_storage local_addr;
@@ -588,15 +841,189 @@ typedef struct {
 
     // The most recent data delivery rate estimate in bytes/s.
     uint64_t delivery_rate;
+
+    // New fields are only appended after this point, and are only written by
+    // `quiche_conn_path_stats_sized()`.
+
+    // The minimum round-trip time observed on the path (in nanoseconds).
+    uint64_t min_rtt;
+
+    // The slow start threshold in bytes, or SIZE_MAX if not set yet.
+    size_t ssthresh;
+
+    // The pacing rate in bytes/s.
+    uint64_t pacing_rate;
+
+    // The number of consecutive PTOs since the last acknowledgement.
+    uint32_t pto_count;
+
+    // The number of QUIC packets that were declared lost on this path, but
+    // were acknowledged afterwards.
+    size_t spurious_lost;
+
+    // The number of PTOs that fired in the Initial and Handshake packet number
+    // spaces.
+    size_t handshake_pto_count;
+
+    // Whether sending on the path is paused.
+    bool paused;
+
+    // The number of ACKs that newly acknowledged packets, none of which were
+    // ack-eliciting.
+    size_t acks_without_ack_eliciting;
+
+    // The number of ACKs that newly acknowledged packets without producing
+    // an RTT sample.
+    size_t rtt_samples_skipped;
+
+    // The number of loss detection timer deadline changes that were not
+    // reported.
+    size_t loss_timer_suppressed_updates;
+
+    // The number of times more bytes were removed from the flight than were
+    // in it.
+    size_t bytes_in_flight_underflows;
+
+    // Whether a network black hole is suspected on the path.
+    bool blackhole_suspected;
+
+    // The number of acknowledged and lost frames waiting to be processed.
+    size_t frame_backlog;
+
+    // The number of times frames were left out of a full backlog of
+    // acknowledged or lost frames.
+    size_t frame_backlog_overflows;
+
+    // The packet reordering threshold currently used by loss detection.
+    uint64_t packet_reorder_threshold;
+
+    // The time reordering threshold currently used by loss detection.
+    double time_reorder_threshold;
 } quiche_path_stats;
 
 
+// Returns the number of known paths for the connection.
+//
+// Paths are indexed from 0 to this value minus one, in the order in which they
+// were created, so that statistics about each can be collected with
+// `quiche_conn_path_stats()`.
+size_t quiche_conn_paths_count(quiche_conn *conn);
+
 // Collects and returns statistics about the specified path for the connection.
 //
-// The `idx` argument represent the path's index (also see the `paths_count`
-// field of `quiche_stats`).
+// The `idx` argument represent the path's index (also see
+// `quiche_conn_paths_count()`).
+//
+// Only the fields up to `delivery_rate` are written, for compatibility with
+// applications built against older versions of this header. Use
+// `quiche_conn_path_stats_sized()` to also collect the others.
 int quiche_conn_path_stats(quiche_conn *conn, size_t idx, quiche_path_stats *out);
 
+// Collects and returns statistics about the specified path for the connection.
+//
+// The `out_len` argument must be set to `sizeof(quiche_path_stats)`. Fields
+// that don't fit in `out_len` bytes are left untouched, so that applications
+// built against older versions of this header keep working with newer
+// versions of the library.
+int quiche_conn_path_stats_sized(quiche_conn *conn, size_t idx, quiche_path_stats *out, size_t out_len);
+
+typedef struct {
+    // The smoothed round-trip time in microseconds (tcpi_rtt).
+    uint64_t rtt;
+
+    // The round-trip time variation in microseconds (tcpi_rttvar).
+    uint64_t rttvar;
+
+    // The size of the congestion window in bytes, rather than in segments
+    // like tcpi_snd_cwnd.
+    size_t snd_cwnd;
+
+    // The number of sent packets with retransmitted data (tcpi_total_retrans).
+    size_t retrans;
+
+    // The number of packets declared lost (tcpi_lost).
+    size_t lost;
+
+    // The most recent delivery rate estimate in bytes/s (tcpi_delivery_rate).
+    uint64_t delivery_rate;
+
+    // The pacing rate in bytes/s (tcpi_pacing_rate).
+    uint64_t pacing_rate;
+
+    // The time spent with data in flight, in microseconds (tcpi_busy_time).
+    uint64_t busy_time;
+
+    // The part of `busy_time` spent with a full congestion window, in
+    // microseconds.
+    uint64_t cwnd_limited;
+
+    // The time spent blocked by the peer's connection-level flow control, in
+    // microseconds (tcpi_rwnd_limited).
+    uint64_t rwnd_limited;
+} quiche_transport_info;
+
+// Collects the transport state of the connection in the shape of TCP_INFO.
+//
+// Path-specific fields refer to the active path. There is no equivalent of
+// tcpi_sndbuf_limited, as time blocked by flow control is reported in
+// `rwnd_limited`. The `out_len` argument must be set to
+// `sizeof(quiche_transport_info)`.
+int quiche_conn_transport_info(quiche_conn *conn, quiche_transport_info *out, size_t out_len);
+
+typedef struct {
+    // The packet number.
+    uint64_t pkt_num;
+
+    // The size of the packet in bytes.
+    size_t size;
+} quiche_lost_packet;
+
+typedef struct {
+    // The previous size of the congestion window in bytes.
+    size_t prev_cwnd;
+
+    // The new size of the congestion window in bytes.
+    size_t cwnd;
+
+    // The slow start threshold in bytes, or SIZE_MAX if not set yet.
+    size_t ssthresh;
+
+    // The number of bytes in flight.
+    size_t bytes_in_flight;
+} quiche_cwnd_change;
+
+typedef struct {
+    // Called when a packet is declared lost.
+    void (*on_packet_lost)(const quiche_lost_packet *lost, void *argp);
+
+    // Called when a packet that was declared lost is acknowledged.
+    void (*on_spurious_loss)(const quiche_lost_packet *lost, void *argp);
+
+    // Called when the congestion window of a path changes.
+    void (*on_cwnd_change)(const quiche_cwnd_change *change, void *argp);
+} quiche_recovery_observer;
+
+// Registers callbacks notified of loss recovery events on all paths.
+//
+// The callbacks are called synchronously, on the calling thread, before
+// `quiche_conn_recv()` and `quiche_conn_on_timeout()` return, in the order in
+// which the events happened. They must not call back into the connection.
+//
+// The callbacks are copied, so `observer` doesn't need to outlive this call,
+// and any of them may be NULL. The pointers passed to them are only valid for
+// the duration of the call. The `argp` argument is passed as-is to each
+// callback and must stay valid while the callbacks are registered.
+//
+// Passing a NULL `observer` unregisters any previously registered callbacks.
+void quiche_conn_set_recovery_observer(quiche_conn *conn,
+                                       const quiche_recovery_observer *observer,
+                                       void *argp);
+
+// Cancels the retransmission of the lost application data packet `pkt_num`,
+// until the next call to `quiche_conn_send()`. Returns the number of frames
+// that won't be retransmitted.
+size_t quiche_conn_cancel_retransmission(quiche_conn *conn, uint64_t pkt_num);
+
 // Returns the maximum DATAGRAM payload that can be sent.
 ssize_t quiche_conn_dgram_max_writable_len(quiche_conn *conn);
 
@@ -755,6 +1182,9 @@ enum quiche_h3_error {
 
     // See QUICHE_ERR_CONGESTION_CONTROL.
     QUICHE_H3_TRANSPORT_ERR_CONGESTION_CONTROL = QUICHE_ERR_CONGESTION_CONTROL - 1000,
+
+    // See QUICHE_ERR_OPTIMISTIC_ACK.
+    QUICHE_H3_TRANSPORT_ERR_OPTIMISTIC_ACK = QUICHE_ERR_OPTIMISTIC_ACK - 1000,
 };
 
 // Stores configuration shared between multiple connections.
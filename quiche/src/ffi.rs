@@ -100,6 +100,17 @@ use winapi::shared::ws2ipdef::SOCKADDR_IN6_LH_u;
 
 use crate::*;
 
+// Returns the offset of the end of `$field` in the structure pointed to by
+// `$out`. Fields are only ever appended to the stats structures, so comparing
+// this with the size of the structure known to the application tells whether
+// the application knows about the field.
+macro_rules! field_end {
+    ($out:expr, $field:ident) => {
+        &$out.$field as *const _ as usize - $out as *const _ as usize +
+            std::mem::size_of_val(&$out.$field)
+    };
+}
+
 #[no_mangle]
 pub extern fn quiche_version() -> *const u8 {
     static VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), "\0");
@@ -329,11 +340,202 @@ pub extern fn quiche_config_enable_hystart(config: &mut Config, v: bool) {
     config.enable_hystart(v);
 }
 
+#[no_mangle]
+pub extern fn quiche_config_enable_spin_bit(config: &mut Config, v: bool) {
+    config.enable_spin_bit(v);
+}
+
 #[no_mangle]
 pub extern fn quiche_config_enable_pacing(config: &mut Config, v: bool) {
     config.enable_pacing(v);
 }
 
+#[no_mangle]
+pub extern fn quiche_config_set_max_pacing_rate(
+    config: &mut Config, v: u64,
+) -> c_int {
+    match config.set_max_pacing_rate(v) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_initial_congestion_window_packets(
+    config: &mut Config, packets: size_t,
+) -> c_int {
+    match config.set_initial_congestion_window_packets(packets) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_packet_reorder_threshold(
+    config: &mut Config, v: u64,
+) -> c_int {
+    match config.set_packet_reorder_threshold(v) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_max_loss_probes(
+    config: &mut Config, v: size_t,
+) -> c_int {
+    match config.set_max_loss_probes(v) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_max_outstanding_non_ack_eliciting(
+    config: &mut Config, v: size_t,
+) {
+    config.set_max_outstanding_non_ack_eliciting(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_enable_non_retransmittable_pto_backoff(
+    config: &mut Config, v: bool,
+) {
+    config.enable_non_retransmittable_pto_backoff(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_handshake_pto_multiplier(
+    config: &mut Config, v: f64,
+) -> c_int {
+    match config.set_handshake_pto_multiplier(v) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_config_enable_path_resume_probe(
+    config: &mut Config, v: bool,
+) {
+    config.enable_path_resume_probe(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_enable_coalesced_loss(config: &mut Config, v: bool) {
+    config.enable_coalesced_loss(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_enable_ack_implied_loss(
+    config: &mut Config, v: bool,
+) {
+    config.enable_ack_implied_loss(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_enable_loss_hold(config: &mut Config, v: bool) {
+    config.enable_loss_hold(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_enable_idle_bounded_pto(
+    config: &mut Config, v: bool,
+) {
+    config.enable_idle_bounded_pto(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_enable_ack_only_rate_samples(
+    config: &mut Config, v: bool,
+) {
+    config.enable_ack_only_rate_samples(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_loss_timer_coalescing(
+    config: &mut Config, v: u64,
+) {
+    config.set_loss_timer_coalescing(std::time::Duration::from_millis(v));
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_ack_cost_budget(config: &mut Config, v: u64) {
+    config.set_ack_cost_budget(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_blackhole_pto_threshold(
+    config: &mut Config, v: u32,
+) {
+    config.set_blackhole_pto_threshold(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_enable_blackhole_mtu_clamp(
+    config: &mut Config, v: bool,
+) {
+    config.enable_blackhole_mtu_clamp(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_max_handshake_ptos(config: &mut Config, v: u32) {
+    config.set_max_handshake_ptos(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_idle_restart_threshold(
+    config: &mut Config, v: u32,
+) {
+    config.set_idle_restart_threshold(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_max_frame_backlog(
+    config: &mut Config, v: size_t,
+) {
+    config.set_max_frame_backlog(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_min_new_stream_chunk(
+    config: &mut Config, v: size_t,
+) {
+    config.set_min_new_stream_chunk(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_strict_rfc9002(config: &mut Config, v: bool) {
+    config.set_strict_rfc9002(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_time_reorder_threshold(
+    config: &mut Config, v: f64,
+) -> c_int {
+    match config.set_time_reorder_threshold(v) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_initial_rtt(
+    config: &mut Config, v: u64,
+) -> c_int {
+    match config.set_initial_rtt(std::time::Duration::from_millis(v)) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_config_enable_dgram(
     config: &mut Config, enabled: bool, recv_queue_len: size_t,
@@ -671,6 +873,58 @@ pub extern fn quiche_conn_set_qlog_fd(
     );
 }
 
+// Passes each qlog record to the callback registered by the application.
+#[cfg(feature = "qlog")]
+struct QlogCallback {
+    cb: extern fn(buf: *const u8, buf_len: size_t, argp: *mut c_void) -> c_int,
+    argp: *mut c_void,
+}
+
+#[cfg(feature = "qlog")]
+unsafe impl Send for QlogCallback {}
+#[cfg(feature = "qlog")]
+unsafe impl Sync for QlogCallback {}
+
+#[cfg(feature = "qlog")]
+impl std::io::Write for QlogCallback {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if (self.cb)(buf.as_ptr(), buf.len(), self.argp) < 0 {
+            return Err(std::io::ErrorKind::WouldBlock.into());
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[no_mangle]
+#[cfg(feature = "qlog")]
+pub extern fn quiche_conn_set_qlog_sink(
+    conn: &mut Connection,
+    cb: extern fn(buf: *const u8, buf_len: size_t, argp: *mut c_void) -> c_int,
+    argp: *mut c_void, log_title: *const c_char, log_desc: *const c_char,
+    qlog_level: QlogLevel,
+) {
+    let title = unsafe { ffi::CStr::from_ptr(log_title).to_str().unwrap() };
+    let description = unsafe { ffi::CStr::from_ptr(log_desc).to_str().unwrap() };
+
+    conn.set_qlog_sink(
+        Box::new(QlogCallback { cb, argp }),
+        title.to_string(),
+        format!("{} id={}", description, conn.trace_id),
+        qlog_level,
+    );
+}
+
+#[no_mangle]
+#[cfg(feature = "qlog")]
+pub extern fn quiche_conn_qlog_dropped_events(conn: &Connection) -> u64 {
+    conn.qlog_dropped_events()
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_set_session(
     conn: &mut Connection, buf: *const u8, buf_len: size_t,
@@ -726,11 +980,45 @@ pub struct SendInfo {
     to_len: socklen_t,
 
     at: timespec,
+    release_time: timespec,
+    pacing_rate: u64,
 }
 
 #[no_mangle]
 pub extern fn quiche_conn_send(
     conn: &mut Connection, out: *mut u8, out_len: size_t, out_info: &mut SendInfo,
+) -> ssize_t {
+    // Applications built against older versions of the header don't know
+    // about fields added after `at`.
+    let info_len = field_end!(out_info, at);
+
+    conn_send_to_c(conn, out, out_len, out_info, info_len)
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_send_sized(
+    conn: &mut Connection, out: *mut u8, out_len: size_t,
+    out_info: &mut SendInfo, info_len: size_t,
+) -> ssize_t {
+    if info_len < field_end!(out_info, at) {
+        return Error::BufferTooShort.to_c();
+    }
+
+    conn_send_to_c(conn, out, out_len, out_info, info_len)
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_on_send_failed(conn: &mut Connection) -> c_int {
+    match conn.on_send_failed() {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+fn conn_send_to_c(
+    conn: &mut Connection, out: *mut u8, out_len: size_t,
+    out_info: &mut SendInfo, info_len: usize,
 ) -> ssize_t {
     if out_len > <ssize_t>::max_value() as usize {
         panic!("The provided buffer is too large");
@@ -745,6 +1033,17 @@ pub extern fn quiche_conn_send(
 
             std_time_to_c(&info.at, &mut out_info.at);
 
+            if field_end!(out_info, pacing_rate) <= info_len {
+                std_time_to_monotonic(&info.at, &mut out_info.release_time);
+
+                out_info.pacing_rate = conn
+                    .paths
+                    .path_id_from_addrs(&(info.from, info.to))
+                    .and_then(|pid| conn.paths.get(pid).ok())
+                    .map(|p| p.recovery.pacer.rate())
+                    .unwrap_or(0);
+            }
+
             v as ssize_t
         },
 
@@ -859,6 +1158,16 @@ pub extern fn quiche_conn_is_readable(conn: &Connection) -> bool {
     conn.is_readable()
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_is_writable_again(conn: &Connection) -> bool {
+    conn.is_writable_again()
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_is_ack_overloaded(conn: &Connection) -> bool {
+    conn.is_ack_overloaded()
+}
+
 struct AppData(*mut c_void);
 unsafe impl Send for AppData {}
 unsafe impl Sync for AppData {}
@@ -1016,6 +1325,11 @@ pub extern fn quiche_conn_is_timed_out(conn: &Connection) -> bool {
     conn.is_timed_out()
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_is_handshake_unreachable(conn: &Connection) -> bool {
+    conn.is_handshake_unreachable()
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_peer_error(
     conn: &mut Connection, is_app: *mut bool, error_code: *mut u64,
@@ -1054,6 +1368,27 @@ pub extern fn quiche_conn_local_error(
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_handshake_timeout(
+    conn: &Connection, initial_ptos: *mut size_t, handshake_ptos: *mut size_t,
+    lost_bytes: *mut u64, last_rtt_sample: *mut u64,
+) -> bool {
+    match conn.handshake_timeout() {
+        Some(details) => unsafe {
+            *initial_ptos = details.initial_ptos;
+            *handshake_ptos = details.handshake_ptos;
+            *lost_bytes = details.lost_bytes;
+            *last_rtt_sample = details
+                .last_rtt_sample
+                .map_or(0, |rtt| rtt.as_nanos() as u64);
+
+            true
+        },
+
+        None => false,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_stream_iter_next(
     iter: &mut StreamIter, stream_id: *mut u64,
@@ -1095,11 +1430,38 @@ pub struct Stats {
     peer_disable_active_migration: bool,
     peer_active_conn_id_limit: u64,
     peer_max_datagram_frame_size: ssize_t,
+    spurious_lost: usize,
+    close_resent: usize,
+    solicited_pings: usize,
+    suppressed_retrans: usize,
+    ack_overloads: usize,
+    skipped_pkt_nums: usize,
     paths: [PathStats; 8],
 }
 
 #[no_mangle]
 pub extern fn quiche_conn_stats(conn: &Connection, out: &mut Stats) {
+    // Applications built against older versions of the header don't know
+    // about fields added after `peer_max_datagram_frame_size`.
+    let out_len = field_end!(out, peer_max_datagram_frame_size);
+
+    conn_stats_to_c(conn, out, out_len);
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_stats_sized(
+    conn: &Connection, out: &mut Stats, out_len: size_t,
+) -> c_int {
+    if out_len < field_end!(out, peer_max_datagram_frame_size) {
+        return Error::BufferTooShort.to_c() as c_int;
+    }
+
+    conn_stats_to_c(conn, out, out_len);
+
+    0
+}
+
+fn conn_stats_to_c(conn: &Connection, out: &mut Stats, out_len: usize) {
     let stats = conn.stats();
 
     out.recv = stats.recv;
@@ -1130,6 +1492,30 @@ pub extern fn quiche_conn_stats(conn: &Connection, out: &mut Stats) {
 
         Some(v) => v as ssize_t,
     };
+
+    if field_end!(out, spurious_lost) <= out_len {
+        out.spurious_lost = stats.spurious_lost;
+    }
+
+    if field_end!(out, close_resent) <= out_len {
+        out.close_resent = stats.close_resent;
+    }
+
+    if field_end!(out, solicited_pings) <= out_len {
+        out.solicited_pings = stats.solicited_pings;
+    }
+
+    if field_end!(out, suppressed_retrans) <= out_len {
+        out.suppressed_retrans = stats.suppressed_retrans;
+    }
+
+    if field_end!(out, ack_overloads) <= out_len {
+        out.ack_overloads = stats.ack_overloads;
+    }
+
+    if field_end!(out, skipped_pkt_nums) <= out_len {
+        out.skipped_pkt_nums = stats.skipped_pkt_nums;
+    }
 }
 
 #[repr(C)]
@@ -1152,11 +1538,53 @@ pub struct PathStats {
     stream_retrans_bytes: u64,
     pmtu: usize,
     delivery_rate: u64,
+    min_rtt: u64,
+    ssthresh: usize,
+    pacing_rate: u64,
+    pto_count: u32,
+    spurious_lost: usize,
+    handshake_pto_count: usize,
+    paused: bool,
+    acks_without_ack_eliciting: usize,
+    rtt_samples_skipped: usize,
+    loss_timer_suppressed_updates: usize,
+    bytes_in_flight_underflows: usize,
+    blackhole_suspected: bool,
+    frame_backlog: usize,
+    frame_backlog_overflows: usize,
+    packet_reorder_threshold: u64,
+    time_reorder_threshold: f64,
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_paths_count(conn: &Connection) -> size_t {
+    conn.paths.len()
 }
 
 #[no_mangle]
 pub extern fn quiche_conn_path_stats(
     conn: &Connection, idx: usize, out: &mut PathStats,
+) -> c_int {
+    // Applications built against older versions of the header don't know
+    // about fields added after `delivery_rate`.
+    let out_len = field_end!(out, delivery_rate);
+
+    path_stats_to_c(conn, idx, out, out_len)
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_path_stats_sized(
+    conn: &Connection, idx: usize, out: &mut PathStats, out_len: size_t,
+) -> c_int {
+    if out_len < field_end!(out, delivery_rate) {
+        return Error::BufferTooShort.to_c() as c_int;
+    }
+
+    path_stats_to_c(conn, idx, out, out_len)
+}
+
+fn path_stats_to_c(
+    conn: &Connection, idx: usize, out: &mut PathStats, out_len: usize,
 ) -> c_int {
     let stats = match conn.path_stats().nth(idx) {
         Some(p) => p,
@@ -1180,9 +1608,154 @@ pub extern fn quiche_conn_path_stats(
     out.pmtu = stats.pmtu;
     out.delivery_rate = stats.delivery_rate;
 
+    if field_end!(out, spurious_lost) <= out_len {
+        out.min_rtt = stats.min_rtt.as_nanos() as u64;
+        out.ssthresh = stats.ssthresh;
+        out.pacing_rate = stats.pacing_rate;
+        out.pto_count = stats.pto_count;
+        out.spurious_lost = stats.spurious_lost;
+    }
+
+    if field_end!(out, handshake_pto_count) <= out_len {
+        out.handshake_pto_count = stats.handshake_pto_count;
+    }
+
+    if field_end!(out, paused) <= out_len {
+        out.paused = stats.paused;
+    }
+
+    if field_end!(out, acks_without_ack_eliciting) <= out_len {
+        out.acks_without_ack_eliciting = stats.acks_without_ack_eliciting;
+    }
+
+    if field_end!(out, rtt_samples_skipped) <= out_len {
+        out.rtt_samples_skipped = stats.rtt_samples_skipped;
+    }
+
+    if field_end!(out, loss_timer_suppressed_updates) <= out_len {
+        out.loss_timer_suppressed_updates = stats.loss_timer_suppressed_updates;
+    }
+
+    if field_end!(out, bytes_in_flight_underflows) <= out_len {
+        out.bytes_in_flight_underflows = stats.bytes_in_flight_underflows;
+    }
+
+    if field_end!(out, blackhole_suspected) <= out_len {
+        out.blackhole_suspected = stats.blackhole_suspected;
+    }
+
+    if field_end!(out, frame_backlog) <= out_len {
+        out.frame_backlog = stats.frame_backlog;
+    }
+
+    if field_end!(out, frame_backlog_overflows) <= out_len {
+        out.frame_backlog_overflows = stats.frame_backlog_overflows;
+    }
+
+    if field_end!(out, packet_reorder_threshold) <= out_len {
+        out.packet_reorder_threshold = stats.packet_reorder_threshold;
+    }
+
+    if field_end!(out, time_reorder_threshold) <= out_len {
+        out.time_reorder_threshold = stats.time_reorder_threshold;
+    }
+
     0
 }
 
+#[repr(C)]
+pub struct LostPacket {
+    pkt_num: u64,
+    size: usize,
+}
+
+#[repr(C)]
+pub struct CwndChange {
+    prev_cwnd: usize,
+    cwnd: usize,
+    ssthresh: usize,
+    bytes_in_flight: usize,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RecoveryCallbacks {
+    on_packet_lost: Option<extern fn(lost: *const LostPacket, argp: *mut c_void)>,
+    on_spurious_loss:
+        Option<extern fn(lost: *const LostPacket, argp: *mut c_void)>,
+    on_cwnd_change:
+        Option<extern fn(change: *const CwndChange, argp: *mut c_void)>,
+}
+
+// Forwards loss recovery events to the callbacks registered by the
+// application. The callbacks are only called from the thread processing the
+// connection, so the pointer is never shared.
+struct CallbacksObserver {
+    cb: RecoveryCallbacks,
+    argp: *mut c_void,
+}
+
+unsafe impl Send for CallbacksObserver {}
+unsafe impl Sync for CallbacksObserver {}
+
+impl RecoveryObserver for CallbacksObserver {
+    fn on_packet_lost(&mut self, lost: &recovery::LostPacket) {
+        if let Some(cb) = self.cb.on_packet_lost {
+            let lost = LostPacket {
+                pkt_num: lost.pkt_num,
+                size: lost.size,
+            };
+
+            cb(&lost, self.argp);
+        }
+    }
+
+    fn on_spurious_loss(&mut self, lost: &recovery::LostPacket) {
+        if let Some(cb) = self.cb.on_spurious_loss {
+            let lost = LostPacket {
+                pkt_num: lost.pkt_num,
+                size: lost.size,
+            };
+
+            cb(&lost, self.argp);
+        }
+    }
+
+    fn on_cwnd_change(&mut self, change: &recovery::CwndChange) {
+        if let Some(cb) = self.cb.on_cwnd_change {
+            let change = CwndChange {
+                prev_cwnd: change.prev_cwnd,
+                cwnd: change.cwnd,
+                ssthresh: change.ssthresh,
+                bytes_in_flight: change.bytes_in_flight,
+            };
+
+            cb(&change, self.argp);
+        }
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_set_recovery_observer(
+    conn: &mut Connection, observer: *const RecoveryCallbacks, argp: *mut c_void,
+) {
+    if observer.is_null() {
+        conn.recovery_observer = None;
+        return;
+    }
+
+    let cb = unsafe { *observer };
+
+    conn.set_recovery_observer(Box::new(CallbacksObserver { cb, argp }));
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_cancel_retransmission(
+    conn: &mut Connection, pkt_num: u64,
+) -> size_t {
+    conn.cancel_retransmission(pkt_num)
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_dgram_max_writable_len(conn: &Connection) -> ssize_t {
     match conn.dgram_max_writable_len() {
@@ -1293,6 +1866,68 @@ pub extern fn quiche_conn_send_quantum(conn: &mut Connection) -> size_t {
     conn.send_quantum() as size_t
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_pacing_rate(conn: &Connection) -> u64 {
+    conn.pacing_rate()
+}
+
+#[repr(C)]
+pub struct BatchHint {
+    segment_size: size_t,
+    max_segments: size_t,
+    release_time: timespec,
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_send_batch_hint(
+    conn: &Connection, out: &mut BatchHint,
+) {
+    let hint = conn.send_batch_hint();
+
+    out.segment_size = hint.segment_size;
+    out.max_segments = hint.max_segments;
+
+    std_time_to_monotonic(&hint.release_time, &mut out.release_time);
+}
+
+#[repr(C)]
+pub struct TransportInfo {
+    rtt: u64,
+    rttvar: u64,
+    snd_cwnd: size_t,
+    retrans: size_t,
+    lost: size_t,
+    delivery_rate: u64,
+    pacing_rate: u64,
+    busy_time: u64,
+    cwnd_limited: u64,
+    rwnd_limited: u64,
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_transport_info(
+    conn: &Connection, out: &mut TransportInfo, out_len: size_t,
+) -> c_int {
+    if out_len < field_end!(out, rwnd_limited) {
+        return Error::BufferTooShort.to_c() as c_int;
+    }
+
+    let info = conn.transport_info();
+
+    out.rtt = info.rtt.as_micros() as u64;
+    out.rttvar = info.rttvar.as_micros() as u64;
+    out.snd_cwnd = info.snd_cwnd;
+    out.retrans = info.retrans;
+    out.lost = info.lost;
+    out.delivery_rate = info.delivery_rate;
+    out.pacing_rate = info.pacing_rate;
+    out.busy_time = info.busy_time.as_micros() as u64;
+    out.cwnd_limited = info.cwnd_limited.as_micros() as u64;
+    out.rwnd_limited = info.rwnd_limited.as_micros() as u64;
+
+    0
+}
+
 fn std_addr_from_c(addr: &sockaddr, addr_len: socklen_t) -> SocketAddr {
     match addr.sa_family as i32 {
         AF_INET => {
@@ -1460,6 +2095,45 @@ fn std_time_to_c(_time: &std::time::Instant, out: &mut timespec) {
     out.tv_nsec = 0;
 }
 
+// Converts `time` to the `CLOCK_MONOTONIC` clock, which is the one expected
+// by `SO_TXTIME` by default.
+//
+// `Instant` already uses that clock on Linux, so it is copied as is, which
+// keeps converted times in the same order as the original ones.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn std_time_to_monotonic(time: &std::time::Instant, out: &mut timespec) {
+    std_time_to_c(time, out)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", windows)))]
+fn std_time_to_monotonic(time: &std::time::Instant, out: &mut timespec) {
+    let mut now: timespec = unsafe { std::mem::zeroed() };
+
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now);
+    }
+
+    let std_now = std::time::Instant::now();
+
+    let now = now.tv_sec as i128 * 1_000_000_000 + now.tv_nsec as i128;
+
+    let time = if *time >= std_now {
+        now + time.duration_since(std_now).as_nanos() as i128
+    } else {
+        now - std_now.duration_since(*time).as_nanos() as i128
+    };
+
+    out.tv_sec = (time / 1_000_000_000) as _;
+    out.tv_nsec = (time % 1_000_000_000) as _;
+}
+
+#[cfg(windows)]
+fn std_time_to_monotonic(_time: &std::time::Instant, out: &mut timespec) {
+    // TODO: implement Instant conversion for systems without CLOCK_MONOTONIC.
+    out.tv_sec = 0;
+    out.tv_nsec = 0;
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1557,6 +2231,490 @@ mod tests {
         );
     }
 
+    #[test]
+    fn config_cc_knobs() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+
+        let err = Error::CongestionControl.to_c() as c_int;
+
+        assert_eq!(quiche_config_set_max_pacing_rate(&mut config, 0), err);
+        assert_eq!(
+            quiche_config_set_initial_congestion_window_packets(&mut config, 1),
+            err
+        );
+        assert_eq!(
+            quiche_config_set_packet_reorder_threshold(&mut config, 21),
+            err
+        );
+        assert_eq!(
+            quiche_config_set_time_reorder_threshold(&mut config, f64::INFINITY),
+            err
+        );
+        assert_eq!(quiche_config_set_initial_rtt(&mut config, 0), err);
+
+        assert_eq!(quiche_config_set_max_pacing_rate(&mut config, 1_000_000), 0);
+        assert_eq!(
+            quiche_config_set_initial_congestion_window_packets(&mut config, 32),
+            0
+        );
+        assert_eq!(
+            quiche_config_set_packet_reorder_threshold(&mut config, 4),
+            0
+        );
+        assert_eq!(
+            quiche_config_set_time_reorder_threshold(&mut config, 1.25),
+            0
+        );
+        assert_eq!(quiche_config_set_initial_rtt(&mut config, 100), 0);
+
+        let snapshot = config.snapshot();
+        assert_eq!(snapshot.max_pacing_rate, Some(1_000_000));
+        assert_eq!(snapshot.initial_congestion_window_packets, 32);
+        assert_eq!(snapshot.packet_reorder_threshold, 4);
+        assert_eq!(snapshot.time_reorder_threshold, 1.25);
+        assert_eq!(snapshot.initial_rtt, std::time::Duration::from_millis(100));
+
+        // The initial congestion window is visible through the path stats.
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+
+        let pipe = testing::Pipe::with_config(&mut config).unwrap();
+
+        let mut out: PathStats = unsafe { std::mem::zeroed() };
+
+        assert_eq!(
+            quiche_conn_path_stats_sized(
+                &pipe.client,
+                0,
+                &mut out,
+                std::mem::size_of::<PathStats>()
+            ),
+            0
+        );
+        assert_eq!(out.cwnd, out.pmtu * 32);
+        assert_eq!(out.rtt, 100_000_000);
+    }
+
+    #[test]
+    fn stats_sized() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::default().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
+
+        // The packet is declared lost, but still reaches the server and is
+        // acknowledged afterwards.
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+
+        let pn = pipe.client.pkt_num_spaces[packet::EPOCH_APPLICATION]
+            .next_pkt_num -
+            1;
+
+        assert_eq!(pipe.client.inject_loss(pn), Ok(()));
+
+        testing::process_flight(&mut pipe.server, flight).unwrap();
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.stream_recv(0, &mut buf), Ok((5, true)));
+
+        // Fields added to the structures are only written when the
+        // application knows about them.
+        let mut out: Stats = unsafe { std::mem::zeroed() };
+        out.spurious_lost = 42;
+
+        quiche_conn_stats(&pipe.client, &mut out);
+        assert_eq!(out.lost, 1);
+        assert_eq!(out.spurious_lost, 42);
+
+        assert_eq!(
+            quiche_conn_stats_sized(&pipe.client, &mut out, 8),
+            Error::BufferTooShort.to_c() as c_int
+        );
+
+        assert_eq!(
+            quiche_conn_stats_sized(
+                &pipe.client,
+                &mut out,
+                std::mem::size_of::<Stats>()
+            ),
+            0
+        );
+        assert_eq!(out.lost, 1);
+        assert_eq!(out.spurious_lost, 1);
+
+        let stats = pipe.client.path_stats().next().unwrap();
+
+        let mut out: PathStats = unsafe { std::mem::zeroed() };
+        out.pto_count = 42;
+
+        assert_eq!(quiche_conn_path_stats(&pipe.client, 0, &mut out), 0);
+        assert_eq!(out.cwnd, stats.cwnd);
+        assert_eq!(out.pto_count, 42);
+
+        assert_eq!(
+            quiche_conn_path_stats_sized(
+                &pipe.client,
+                0,
+                &mut out,
+                std::mem::size_of::<PathStats>()
+            ),
+            0
+        );
+        assert_eq!(out.min_rtt, stats.min_rtt.as_nanos() as u64);
+        assert_eq!(out.ssthresh, stats.ssthresh);
+        assert_eq!(out.pacing_rate, stats.pacing_rate);
+        assert_eq!(out.pto_count, 0);
+        assert_eq!(out.spurious_lost, 1);
+
+        assert_eq!(
+            quiche_conn_path_stats_sized(
+                &pipe.client,
+                1,
+                &mut out,
+                std::mem::size_of::<PathStats>()
+            ),
+            Error::Done.to_c() as c_int
+        );
+    }
+
+    #[test]
+    fn send_release_time() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(1_000_000);
+        config.set_initial_max_stream_data_bidi_local(1_000_000);
+        config.set_initial_max_stream_data_bidi_remote(1_000_000);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
+        config.enable_pacing(true);
+        assert_eq!(config.set_max_pacing_rate(10_000), Ok(()));
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Only part of the data fits in the congestion window, which is enough
+        // to send more than one packet.
+        assert!(pipe.client.stream_send(0, &[0; 100_000], true).is_ok());
+
+        let mut info: SendInfo = unsafe { std::mem::zeroed() };
+
+        // Fields added to the structure are only written when the
+        // application knows about them.
+        info.pacing_rate = 42;
+
+        let len = quiche_conn_send(
+            &mut pipe.client,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut info,
+        );
+        assert!(len > 0);
+        assert_eq!(info.pacing_rate, 42);
+
+        testing::process_flight(&mut pipe.server, vec![(
+            buf[..len as usize].to_vec(),
+            crate::SendInfo {
+                from: testing::Pipe::client_addr(),
+                to: testing::Pipe::server_addr(),
+                at: std::time::Instant::now(),
+            },
+        )])
+        .unwrap();
+
+        assert_eq!(
+            quiche_conn_send_sized(
+                &mut pipe.client,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut info,
+                8
+            ),
+            Error::BufferTooShort.to_c()
+        );
+
+        let mut last = None;
+        let mut sent = 0;
+
+        for _ in 0..10 {
+            let mut flight = Vec::new();
+
+            loop {
+                let len = quiche_conn_send_sized(
+                    &mut pipe.client,
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut info,
+                    std::mem::size_of::<SendInfo>(),
+                );
+
+                if len == Error::Done.to_c() {
+                    break;
+                }
+
+                assert!(len > 0);
+
+                // Release times never go backwards.
+                let release_time = info.release_time.tv_sec as i128 *
+                    1_000_000_000 +
+                    info.release_time.tv_nsec as i128;
+
+                if let Some(last) = last {
+                    assert!(release_time >= last);
+                }
+
+                last = Some(release_time);
+
+                assert!(info.pacing_rate <= 10_000);
+                assert_eq!(
+                    info.pacing_rate,
+                    quiche_conn_pacing_rate(&pipe.client)
+                );
+
+                flight.push((buf[..len as usize].to_vec(), crate::SendInfo {
+                    from: testing::Pipe::client_addr(),
+                    to: testing::Pipe::server_addr(),
+                    at: std::time::Instant::now(),
+                }));
+
+                sent += 1;
+            }
+
+            testing::process_flight(&mut pipe.server, flight).unwrap();
+
+            if let Ok(flight) = testing::emit_flight(&mut pipe.server) {
+                testing::process_flight(&mut pipe.client, flight).unwrap();
+            }
+        }
+
+        assert!(sent > 1);
+    }
+
+    #[test]
+    fn path_stats_migration() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.set_active_connection_id_limit(2);
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(quiche_conn_paths_count(&pipe.client), 1);
+
+        // Provide spare connection IDs on both sides for the new path.
+        let (scid, reset_token) = testing::create_cid_and_reset_token(16);
+        assert_eq!(pipe.client.new_source_cid(&scid, reset_token, true), Ok(1));
+
+        let (scid, reset_token) = testing::create_cid_and_reset_token(16);
+        assert_eq!(pipe.server.new_source_cid(&scid, reset_token, true), Ok(1));
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let server_addr = testing::Pipe::server_addr();
+        let client_addr_2 = "127.0.0.1:5678".parse().unwrap();
+
+        assert_eq!(pipe.client.probe_path(client_addr_2, server_addr), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.migrate(client_addr_2, server_addr), Ok(1));
+
+        assert_eq!(pipe.client.stream_send(0, b"data", true), Ok(4));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.stream_recv(0, &mut buf), Ok((4, true)));
+
+        assert_eq!(quiche_conn_paths_count(&pipe.client), 2);
+
+        let mut stats: Vec<PathStats> = Vec::new();
+
+        for idx in 0..2 {
+            let mut out: PathStats = unsafe { std::mem::zeroed() };
+
+            assert_eq!(
+                quiche_conn_path_stats_sized(
+                    &pipe.client,
+                    idx,
+                    &mut out,
+                    std::mem::size_of::<PathStats>()
+                ),
+                0
+            );
+
+            stats.push(out);
+        }
+
+        let addrs: Vec<SocketAddr> = stats
+            .iter()
+            .map(|s| {
+                let sa = &s.local_addr as *const _ as *const sockaddr;
+                std_addr_from_c(unsafe { &*sa }, s.local_addr_len)
+            })
+            .collect();
+
+        assert_eq!(addrs, [testing::Pipe::client_addr(), client_addr_2]);
+
+        assert!(!stats[0].active);
+        assert!(stats[1].active);
+        assert_eq!(stats[1].validation_state, path::PathState::Validated.to_c());
+
+        // Each path has its own counters.
+        assert!(stats[0].sent > 0);
+        assert!(stats[1].sent > 0);
+        assert_ne!(
+            (stats[0].sent, stats[0].sent_bytes, stats[0].recv_bytes),
+            (stats[1].sent, stats[1].sent_bytes, stats[1].recv_bytes)
+        );
+
+        let mut out: PathStats = unsafe { std::mem::zeroed() };
+
+        assert_eq!(
+            quiche_conn_path_stats(&pipe.client, 2, &mut out),
+            Error::Done.to_c() as c_int
+        );
+    }
+
+    #[derive(Default)]
+    struct RecoveryCounts {
+        lost: Vec<u64>,
+        spurious: Vec<u64>,
+        cwnd_changes: usize,
+        cwnd: usize,
+    }
+
+    extern fn count_lost(lost: *const LostPacket, argp: *mut c_void) {
+        let counts = unsafe { &mut *(argp as *mut RecoveryCounts) };
+        counts.lost.push(unsafe { (*lost).pkt_num });
+    }
+
+    extern fn count_spurious(lost: *const LostPacket, argp: *mut c_void) {
+        let counts = unsafe { &mut *(argp as *mut RecoveryCounts) };
+        counts.spurious.push(unsafe { (*lost).pkt_num });
+    }
+
+    extern fn count_cwnd_change(change: *const CwndChange, argp: *mut c_void) {
+        let counts = unsafe { &mut *(argp as *mut RecoveryCounts) };
+        counts.cwnd_changes += 1;
+        counts.cwnd = unsafe { (*change).cwnd };
+    }
+
+    #[test]
+    fn recovery_observer() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::default().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let mut counts = RecoveryCounts::default();
+
+        let cb = RecoveryCallbacks {
+            on_packet_lost: Some(count_lost),
+            on_spurious_loss: Some(count_spurious),
+            on_cwnd_change: Some(count_cwnd_change),
+        };
+
+        quiche_conn_set_recovery_observer(
+            &mut pipe.client,
+            &cb,
+            &mut counts as *mut RecoveryCounts as *mut c_void,
+        );
+
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
+
+        // The packet is declared lost, but still reaches the server and is
+        // acknowledged afterwards.
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+
+        let pn = pipe.client.pkt_num_spaces[packet::EPOCH_APPLICATION]
+            .next_pkt_num -
+            1;
+
+        assert_eq!(pipe.client.inject_loss(pn), Ok(()));
+
+        // Callbacks are only called from `recv()` and `on_timeout()`.
+        assert!(counts.lost.is_empty());
+
+        testing::process_flight(&mut pipe.server, flight).unwrap();
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.stream_recv(0, &mut buf), Ok((5, true)));
+
+        assert_eq!(counts.lost, [pn]);
+        assert_eq!(counts.spurious, [pn]);
+        assert!(counts.cwnd_changes > 0);
+        assert_eq!(counts.cwnd, pipe.client.path_stats().next().unwrap().cwnd);
+
+        // Callbacks may be NULL.
+        let cb = RecoveryCallbacks {
+            on_packet_lost: None,
+            on_spurious_loss: None,
+            on_cwnd_change: Some(count_cwnd_change),
+        };
+
+        quiche_conn_set_recovery_observer(
+            &mut pipe.client,
+            &cb,
+            &mut counts as *mut RecoveryCounts as *mut c_void,
+        );
+
+        assert_eq!(pipe.client.stream_send(4, b"world", true), Ok(5));
+
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+
+        let pn = pipe.client.pkt_num_spaces[packet::EPOCH_APPLICATION]
+            .next_pkt_num -
+            1;
+
+        assert_eq!(pipe.client.inject_loss(pn), Ok(()));
+
+        testing::process_flight(&mut pipe.server, flight).unwrap();
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(counts.lost.len(), 1);
+        assert_eq!(counts.spurious.len(), 1);
+
+        // Unregistering the observer stops notifications.
+        quiche_conn_set_recovery_observer(
+            &mut pipe.client,
+            ptr::null(),
+            ptr::null_mut(),
+        );
+        assert!(pipe.client.recovery_observer.is_none());
+    }
+
     #[cfg(not(windows))]
     extern {
         fn inet_ntop(
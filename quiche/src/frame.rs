@@ -68,6 +68,10 @@ pub enum Frame {
         ecn_counts: Option<EcnCounts>,
     },
 
+    ACKHeader {
+        largest_acked: u64,
+    },
+
     ResetStream {
         stream_id: u64,
         error_code: u64,
@@ -454,6 +458,8 @@ impl Frame {
 
             Frame::StreamHeader { .. } => (),
 
+            Frame::ACKHeader { .. } => (),
+
             Frame::MaxData { max } => {
                 b.put_varint(0x10)?;
 
@@ -784,6 +790,131 @@ impl Frame {
                 2 + // length, always encode as 2-byte varint
                 *length // data
             },
+
+            // Only used to track sent ACK frames, never encoded.
+            Frame::ACKHeader { .. } => 0,
+        }
+    }
+
+    /// Returns an ACK frame for the given ranges that is at most `max_len`
+    /// bytes long, leaving out the oldest ranges if needed.
+    ///
+    /// The range with the largest packet number is always included, even if
+    /// it doesn't fit on its own.
+    pub fn ack_within(
+        ack_delay: u64, ranges: &ranges::RangeSet, max_len: usize,
+    ) -> Frame {
+        let mut included = ranges.clone();
+
+        let mut it = ranges.iter().rev();
+
+        if let Some(first) = it.next() {
+            let len = 1 + // frame type
+                octets::varint_len(first.end - 1) + // largest_ack
+                octets::varint_len(ack_delay) + // ack_delay
+                octets::varint_len((first.end - 1) - first.start); // first_block
+
+            let mut blocks_len = 0;
+
+            let mut smallest_ack = first.start;
+
+            for (i, block) in it.enumerate() {
+                let gap = smallest_ack - block.end - 1;
+                let ack_block = (block.end - 1) - block.start;
+
+                blocks_len += octets::varint_len(gap) + // gap
+                    octets::varint_len(ack_block); // ack_block
+
+                let block_count = i as u64 + 1;
+
+                if len + octets::varint_len(block_count) + blocks_len > max_len {
+                    included.remove_until(smallest_ack - 1);
+                    break;
+                }
+
+                smallest_ack = block.start;
+            }
+        }
+
+        Frame::ACK {
+            ack_delay,
+            ranges: included,
+            ecn_counts: None,
+        }
+    }
+
+    /// Returns an estimate of the memory allocated by the frame, in addition
+    /// to the size of the `Frame` itself.
+    pub fn heap_size(&self) -> usize {
+        match self {
+            Frame::ACK { ranges, .. } =>
+                ranges.len() * std::mem::size_of::<(u64, u64)>(),
+
+            Frame::Crypto { data } => data.len(),
+
+            Frame::Stream { data, .. } => data.len(),
+
+            Frame::NewToken { token } => token.capacity(),
+
+            Frame::NewConnectionId { conn_id, .. } => conn_id.capacity(),
+
+            Frame::ConnectionClose { reason, .. } => reason.capacity(),
+
+            Frame::ApplicationClose { reason, .. } => reason.capacity(),
+
+            Frame::Datagram { data } => data.capacity(),
+
+            _ => 0,
+        }
+    }
+
+    /// Returns the name of the frame's type, as used in the specification.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Frame::Padding { .. } => "PADDING",
+
+            Frame::Ping => "PING",
+
+            Frame::ACK { .. } | Frame::ACKHeader { .. } => "ACK",
+
+            Frame::ResetStream { .. } => "RESET_STREAM",
+
+            Frame::StopSending { .. } => "STOP_SENDING",
+
+            Frame::Crypto { .. } | Frame::CryptoHeader { .. } => "CRYPTO",
+
+            Frame::NewToken { .. } => "NEW_TOKEN",
+
+            Frame::Stream { .. } | Frame::StreamHeader { .. } => "STREAM",
+
+            Frame::MaxData { .. } => "MAX_DATA",
+
+            Frame::MaxStreamData { .. } => "MAX_STREAM_DATA",
+
+            Frame::MaxStreamsBidi { .. } | Frame::MaxStreamsUni { .. } =>
+                "MAX_STREAMS",
+
+            Frame::DataBlocked { .. } => "DATA_BLOCKED",
+
+            Frame::StreamDataBlocked { .. } => "STREAM_DATA_BLOCKED",
+
+            Frame::StreamsBlockedBidi { .. } |
+            Frame::StreamsBlockedUni { .. } => "STREAMS_BLOCKED",
+
+            Frame::NewConnectionId { .. } => "NEW_CONNECTION_ID",
+
+            Frame::RetireConnectionId { .. } => "RETIRE_CONNECTION_ID",
+
+            Frame::PathChallenge { .. } => "PATH_CHALLENGE",
+
+            Frame::PathResponse { .. } => "PATH_RESPONSE",
+
+            Frame::ConnectionClose { .. } | Frame::ApplicationClose { .. } =>
+                "CONNECTION_CLOSE",
+
+            Frame::HandshakeDone => "HANDSHAKE_DONE",
+
+            Frame::Datagram { .. } | Frame::DatagramHeader { .. } => "DATAGRAM",
         }
     }
 
@@ -793,6 +924,7 @@ impl Frame {
             self,
             Frame::Padding { .. } |
                 Frame::ACK { .. } |
+                Frame::ACKHeader { .. } |
                 Frame::ApplicationClose { .. } |
                 Frame::ConnectionClose { .. }
         )
@@ -843,6 +975,16 @@ impl Frame {
                 }
             },
 
+            Frame::ACKHeader { largest_acked } => QuicFrame::Ack {
+                ack_delay: None,
+                acked_ranges: Some(AckedRanges::Single(vec![vec![
+                    *largest_acked,
+                ]])),
+                ect1: None,
+                ect0: None,
+                ce: None,
+            },
+
             Frame::ResetStream {
                 stream_id,
                 error_code,
@@ -1020,6 +1162,10 @@ impl std::fmt::Debug for Frame {
                 )?;
             },
 
+            Frame::ACKHeader { largest_acked } => {
+                write!(f, "ACK largest={}", largest_acked)?;
+            },
+
             Frame::ResetStream {
                 stream_id,
                 error_code,
@@ -1425,6 +1571,63 @@ mod tests {
         assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_ok());
     }
 
+    #[test]
+    fn ack_within() {
+        let mut ranges = ranges::RangeSet::default();
+
+        for pn in (0..200).step_by(2) {
+            ranges.insert(pn..pn + 1);
+        }
+
+        let full = Frame::ack_within(0, &ranges, usize::MAX);
+        assert_eq!(full, Frame::ACK {
+            ack_delay: 0,
+            ranges: ranges.clone(),
+            ecn_counts: None,
+        });
+
+        // The most recent ranges are kept.
+        let frame = Frame::ack_within(0, &ranges, 50);
+        assert!(frame.wire_len() <= 50);
+
+        let included = match &frame {
+            Frame::ACK { ranges, .. } => ranges.clone(),
+
+            _ => unreachable!(),
+        };
+
+        assert_eq!(included.len(), (50 - 5) / 2 + 1);
+        assert_eq!(included.last(), Some(198));
+        assert_eq!(
+            included.first(),
+            Some(198 - 2 * (included.len() as u64 - 1))
+        );
+
+        let mut d = [42; 128];
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(wire_len, frame.wire_len());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(Frame::from_bytes(&mut b, packet::Type::Short), Ok(frame));
+
+        // The largest range is kept even if it doesn't fit.
+        let frame = Frame::ack_within(0, &ranges, 1);
+
+        match &frame {
+            Frame::ACK { ranges, .. } => {
+                assert_eq!(ranges.len(), 1);
+                assert_eq!(ranges.last(), Some(198));
+            },
+
+            _ => unreachable!(),
+        }
+    }
+
     #[test]
     fn ack_ecn() {
         let mut d = [42; 128];
@@ -53,7 +53,8 @@
 // holds for 2nd & 3rd best.
 
 use std::time::Duration;
-use std::time::Instant;
+
+use crate::time::Instant;
 
 #[derive(Copy, Clone)]
 struct MinmaxSample<T> {
@@ -66,12 +67,9 @@ pub struct Minmax<T> {
 }
 
 impl<T: PartialOrd + Copy> Minmax<T> {
-    pub fn new(val: T) -> Self {
+    pub fn new(val: T, time: Instant) -> Self {
         Minmax {
-            estimate: [MinmaxSample {
-                time: Instant::now(),
-                value: val,
-            }; 3],
+            estimate: [MinmaxSample { time, value: val }; 3],
         }
     }
 
@@ -173,7 +171,7 @@ mod tests {
 
     #[test]
     fn reset_filter_rtt() {
-        let mut f = Minmax::new(Duration::ZERO);
+        let mut f = Minmax::new(Duration::ZERO, Instant::now());
         let now = Instant::now();
         let rtt = Duration::from_millis(50);
 
@@ -192,7 +190,7 @@ mod tests {
 
     #[test]
     fn reset_filter_bandwidth() {
-        let mut f = Minmax::new(0);
+        let mut f = Minmax::new(0, Instant::now());
         let now = Instant::now();
         let bw = 2000;
 
@@ -211,7 +209,7 @@ mod tests {
 
     #[test]
     fn get_windowed_min_rtt() {
-        let mut f = Minmax::new(Duration::ZERO);
+        let mut f = Minmax::new(Duration::ZERO, Instant::now());
         let rtt_25 = Duration::from_millis(25);
         let rtt_24 = Duration::from_millis(24);
         let win = Duration::from_millis(500);
@@ -235,7 +233,7 @@ mod tests {
 
     #[test]
     fn get_windowed_min_bandwidth() {
-        let mut f = Minmax::new(0);
+        let mut f = Minmax::new(0, Instant::now());
         let bw_200 = 200;
         let bw_500 = 500;
         let win = Duration::from_millis(500);
@@ -259,7 +257,7 @@ mod tests {
 
     #[test]
     fn get_windowed_max_rtt() {
-        let mut f = Minmax::new(Duration::ZERO);
+        let mut f = Minmax::new(Duration::ZERO, Instant::now());
         let rtt_25 = Duration::from_millis(25);
         let rtt_24 = Duration::from_millis(24);
         let win = Duration::from_millis(500);
@@ -283,7 +281,7 @@ mod tests {
 
     #[test]
     fn get_windowed_max_bandwidth() {
-        let mut f = Minmax::new(0);
+        let mut f = Minmax::new(0, Instant::now());
         let bw_200 = 200;
         let bw_500 = 500;
         let win = Duration::from_millis(500);
@@ -307,7 +305,7 @@ mod tests {
 
     #[test]
     fn get_windowed_min_estimates_rtt() {
-        let mut f = Minmax::new(Duration::ZERO);
+        let mut f = Minmax::new(Duration::ZERO, Instant::now());
         let rtt_25 = Duration::from_millis(25);
         let rtt_24 = Duration::from_millis(24);
         let rtt_23 = Duration::from_millis(23);
@@ -339,7 +337,7 @@ mod tests {
 
     #[test]
     fn get_windowed_min_estimates_bandwidth() {
-        let mut f = Minmax::new(0);
+        let mut f = Minmax::new(0, Instant::now());
         let bw_500 = 500;
         let bw_400 = 400;
         let bw_300 = 300;
@@ -371,7 +369,7 @@ mod tests {
 
     #[test]
     fn get_windowed_max_estimates_rtt() {
-        let mut f = Minmax::new(Duration::ZERO);
+        let mut f = Minmax::new(Duration::ZERO, Instant::now());
         let rtt_25 = Duration::from_millis(25);
         let rtt_24 = Duration::from_millis(24);
         let rtt_23 = Duration::from_millis(23);
@@ -403,7 +401,7 @@ mod tests {
 
     #[test]
     fn get_windowed_max_estimates_bandwidth() {
-        let mut f = Minmax::new(0);
+        let mut f = Minmax::new(0, Instant::now());
         let bw_500 = 500;
         let bw_400 = 400;
         let bw_300 = 300;
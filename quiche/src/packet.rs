@@ -24,7 +24,7 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
-use std::time;
+use std::collections::VecDeque;
 
 use ring::aead;
 
@@ -35,10 +35,12 @@ use crate::crypto;
 use crate::rand;
 use crate::ranges;
 use crate::stream;
+use crate::time;
 
 const FORM_BIT: u8 = 0x80;
 const FIXED_BIT: u8 = 0x40;
 const KEY_PHASE_BIT: u8 = 0x04;
+const SPIN_BIT: u8 = 0x20;
 
 const TYPE_MASK: u8 = 0x30;
 const PKT_NUM_MASK: u8 = 0x03;
@@ -61,6 +63,17 @@ pub const EPOCH_COUNT: usize = 3;
 /// number space in `Connection` and `Recovery`.
 pub type Epoch = usize;
 
+/// All packet number space epochs, in increasing order.
+pub const EPOCHS: [Epoch; EPOCH_COUNT] =
+    [EPOCH_INITIAL, EPOCH_HANDSHAKE, EPOCH_APPLICATION];
+
+/// Returns an iterator over all packet number space epochs, in increasing
+/// order.
+#[inline]
+pub fn epochs() -> std::iter::Copied<std::slice::Iter<'static, Epoch>> {
+    EPOCHS.iter().copied()
+}
+
 /// QUIC packet type.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Type {
@@ -271,6 +284,9 @@ pub struct Header<'a> {
     /// The key phase bit of the packet. It's only meaningful after the header
     /// protection is removed.
     pub(crate) key_phase: bool,
+
+    /// The latency spin bit of the packet. Only present in `Short` packets.
+    pub(crate) spin: bool,
 }
 
 impl<'a> Header<'a> {
@@ -318,6 +334,7 @@ impl<'a> Header<'a> {
                 token: None,
                 versions: None,
                 key_phase: false,
+                spin: (first & SPIN_BIT) != 0,
             });
         }
 
@@ -392,6 +409,7 @@ impl<'a> Header<'a> {
             token,
             versions,
             key_phase: false,
+            spin: false,
         })
     }
 
@@ -416,6 +434,11 @@ impl<'a> Header<'a> {
                 first &= !KEY_PHASE_BIT;
             }
 
+            // Set spin bit.
+            if self.spin {
+                first |= SPIN_BIT;
+            }
+
             out.put_u8(first)?;
             out.put_bytes(&self.dcid)?;
 
@@ -505,6 +528,7 @@ impl<'a> std::fmt::Debug for Header<'a> {
 
         if self.ty == Type::Short {
             write!(f, " key_phase={}", self.key_phase)?;
+            write!(f, " spin={}", self.spin)?;
         }
 
         Ok(())
@@ -727,6 +751,7 @@ pub fn retry(
         token: Some(token.to_vec()),
         versions: None,
         key_phase: false,
+        spin: false,
     };
 
     hdr.to_bytes(&mut b)?;
@@ -897,6 +922,78 @@ impl PktNumSpace {
     }
 }
 
+// The minimum number of packet numbers used between two skipped ones.
+const PKT_NUM_SKIP_INTERVAL: u64 = 256;
+
+// The number of skipped packet numbers that are remembered.
+const PKT_NUM_SKIP_HISTORY: usize = 8;
+
+/// Picks packet numbers to skip when sending, so that a peer acknowledging
+/// packets it never received (i.e. an optimistic ACK attack) can be detected,
+/// as described in RFC 9000 Section 21.4.
+///
+/// Packet numbers are skipped at random intervals of between one and two
+/// times `PKT_NUM_SKIP_INTERVAL`.
+#[derive(Default)]
+pub struct PktNumSkipper {
+    next_skip: Option<u64>,
+
+    skipped: VecDeque<u64>,
+
+    skipped_count: usize,
+}
+
+impl PktNumSkipper {
+    /// Returns the packet number to send the next packet with, given the next
+    /// one in sequence, skipping it if needed.
+    pub fn on_send(&mut self, next_pkt_num: u64, rng: &mut rand::Rng) -> u64 {
+        let next_skip = *self.next_skip.get_or_insert_with(|| {
+            next_pkt_num +
+                PKT_NUM_SKIP_INTERVAL +
+                rng.u64_uniform(PKT_NUM_SKIP_INTERVAL)
+        });
+
+        if next_pkt_num != next_skip {
+            return next_pkt_num;
+        }
+
+        if self.skipped.len() == PKT_NUM_SKIP_HISTORY {
+            self.skipped.pop_front();
+        }
+
+        self.skipped.push_back(next_skip);
+
+        self.skipped_count += 1;
+
+        self.next_skip = Some(
+            next_skip +
+                1 +
+                PKT_NUM_SKIP_INTERVAL +
+                rng.u64_uniform(PKT_NUM_SKIP_INTERVAL),
+        );
+
+        next_pkt_num + 1
+    }
+
+    /// Returns whether the given ranges acknowledge a skipped packet number.
+    pub fn is_acked(&self, ranges: &ranges::RangeSet) -> bool {
+        self.skipped
+            .iter()
+            .any(|&pn| ranges.iter().any(|r| r.contains(&pn)))
+    }
+
+    /// Returns the number of packet numbers skipped so far.
+    pub fn skipped_count(&self) -> usize {
+        self.skipped_count
+    }
+
+    #[cfg(test)]
+    /// Returns the most recently skipped packet numbers, in order.
+    pub fn skipped(&self) -> impl Iterator<Item = u64> + '_ {
+        self.skipped.iter().copied()
+    }
+}
+
 #[derive(Clone, Copy, Default)]
 pub struct PktNumWindow {
     lower: u64,
@@ -963,6 +1060,7 @@ mod tests {
             token: Some(vec![0xba; 24]),
             versions: None,
             key_phase: false,
+            spin: false,
         };
 
         let mut d = [0; 63];
@@ -990,6 +1088,7 @@ mod tests {
             token: Some(vec![0x05, 0x06, 0x07, 0x08]),
             versions: None,
             key_phase: false,
+            spin: false,
         };
 
         let mut d = [0; 50];
@@ -1017,6 +1116,7 @@ mod tests {
             token: Some(vec![0x05, 0x06, 0x07, 0x08]),
             versions: None,
             key_phase: false,
+            spin: false,
         };
 
         let mut d = [0; 50];
@@ -1045,6 +1145,7 @@ mod tests {
             token: Some(vec![0x05, 0x06, 0x07, 0x08]),
             versions: None,
             key_phase: false,
+            spin: false,
         };
 
         let mut d = [0; 50];
@@ -1073,6 +1174,7 @@ mod tests {
             token: Some(vec![0x05, 0x06, 0x07, 0x08]),
             versions: None,
             key_phase: false,
+            spin: false,
         };
 
         let mut d = [0; 50];
@@ -1097,6 +1199,7 @@ mod tests {
             token: None,
             versions: None,
             key_phase: false,
+            spin: false,
         };
 
         let mut d = [0; 50];
@@ -1121,6 +1224,7 @@ mod tests {
             token: None,
             versions: None,
             key_phase: false,
+            spin: false,
         };
 
         let mut d = [0; 50];
@@ -1132,6 +1236,32 @@ mod tests {
         assert_eq!(Header::from_bytes(&mut b, 9).unwrap(), hdr);
     }
 
+    #[test]
+    fn application_spin() {
+        let hdr = Header {
+            ty: Type::Short,
+            version: 0,
+            dcid: vec![0xba, 0xba, 0xba, 0xba].into(),
+            scid: ConnectionId::default(),
+            pkt_num: 0,
+            pkt_num_len: 0,
+            token: None,
+            versions: None,
+            key_phase: false,
+            spin: true,
+        };
+
+        let mut d = [0; 50];
+
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert!(hdr.to_bytes(&mut b).is_ok());
+
+        assert_eq!(d[0] & SPIN_BIT, SPIN_BIT);
+
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert_eq!(Header::from_bytes(&mut b, 4).unwrap(), hdr);
+    }
+
     #[test]
     fn pkt_num_decode() {
         let pn = decode_pkt_num(0xa82f30ea, 0x9b32, 2);
@@ -2799,6 +2929,7 @@ mod tests {
             token: None,
             versions: None,
             key_phase: false,
+            spin: false,
         };
 
         hdr.to_bytes(&mut b).unwrap();
@@ -2831,6 +2962,7 @@ mod tests {
             token: None,
             versions: None,
             key_phase: false,
+            spin: false,
         };
 
         hdr.to_bytes(&mut b).unwrap();
@@ -2848,4 +2980,61 @@ mod tests {
             Err(Error::CryptoFail)
         );
     }
+
+    #[test]
+    fn pkt_num_skip_schedule() {
+        // Returns the packet numbers used by the first 10000 packets.
+        fn send(seed: u64) -> (Vec<u64>, PktNumSkipper) {
+            let mut rng = rand::Rng::with_seed(seed);
+            let mut skipper = PktNumSkipper::default();
+
+            let mut next_pkt_num = 0;
+
+            let sent = (0..10000)
+                .map(|_| {
+                    let pn = skipper.on_send(next_pkt_num, &mut rng);
+                    next_pkt_num = pn + 1;
+                    pn
+                })
+                .collect();
+
+            (sent, skipper)
+        }
+
+        let seed = rand::test_seed();
+
+        // The same seed produces the same skip schedule.
+        let (sent, skipper) = send(seed);
+        assert_eq!(send(seed).0, sent);
+
+        // Each packet number is either sent or skipped.
+        let skipped: Vec<u64> = (0..*sent.last().unwrap())
+            .filter(|pn| sent.binary_search(pn).is_err())
+            .collect();
+
+        assert!(skipped.len() >= 10000 / (2 * PKT_NUM_SKIP_INTERVAL as usize));
+        assert!(skipped.len() <= 10000 / PKT_NUM_SKIP_INTERVAL as usize);
+
+        for w in skipped.windows(2) {
+            assert!(w[1] - w[0] > PKT_NUM_SKIP_INTERVAL);
+            assert!(w[1] - w[0] <= 2 * PKT_NUM_SKIP_INTERVAL);
+        }
+
+        // Only the most recently skipped packet numbers are remembered.
+        assert!(skipper.skipped().eq(skipped
+            .iter()
+            .copied()
+            .skip(skipped.len() - PKT_NUM_SKIP_HISTORY)));
+
+        let last = *skipped.last().unwrap();
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..last);
+        assert!(skipper.is_acked(&acked));
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..skipped[0]);
+        acked.insert(last + 1..last + 10);
+        assert!(!skipper.is_acked(&acked));
+    }
 }
@@ -24,8 +24,6 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
-use std::time;
-
 use std::collections::BTreeMap;
 use std::collections::VecDeque;
 use std::net::SocketAddr;
@@ -37,6 +35,7 @@ use crate::Result;
 
 use crate::recovery;
 use crate::recovery::HandshakeStatus;
+use crate::time;
 
 /// The different states of the path validation.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -107,6 +106,15 @@ pub enum PathEvent {
     ///
     /// Note that this event is only raised if the path has been validated.
     PeerMigrated(SocketAddr, SocketAddr),
+
+    /// Consecutive PTOs fired on the related network path without any packet
+    /// being acknowledged, suggesting that packets are silently dropped. The
+    /// application may want to fail over to another network before the idle
+    /// timeout.
+    ///
+    /// The event is raised again if the path recovers and then stops
+    /// delivering packets again.
+    BlackholeSuspected(SocketAddr, SocketAddr),
 }
 
 /// A network path on which QUIC packets can be sent.
@@ -184,9 +192,22 @@ pub struct Path {
     /// Whether the failure of this path was notified.
     failure_notified: bool,
 
+    /// Whether the black hole currently suspected on this path was notified.
+    blackhole_notified: bool,
+
     /// Whether the connection tries to migrate to this path, but it still needs
     /// to be validated.
     migrating: bool,
+
+    /// The latency spin value of the path.
+    spin_value: bool,
+
+    /// The largest packet number received on this path, for the purpose of
+    /// updating the spin value.
+    spin_largest_rx_pkt_num: Option<u64>,
+
+    /// The destination CID sequence number the spin value applies to.
+    spin_dcid_seq: Option<u64>,
 }
 
 impl Path {
@@ -226,7 +247,11 @@ impl Path {
             peer_verified_local_address: false,
             challenge_requested: false,
             failure_notified: false,
+            blackhole_notified: false,
             migrating: false,
+            spin_value: false,
+            spin_largest_rx_pkt_num: None,
+            spin_dcid_seq: active_dcid_seq,
         }
     }
 
@@ -374,13 +399,11 @@ impl Path {
 
     pub fn on_loss_detection_timeout(
         &mut self, handshake_status: HandshakeStatus, now: time::Instant,
-        is_server: bool, trace_id: &str,
-    ) -> (usize, usize) {
-        let (lost_packets, lost_bytes) = self.recovery.on_loss_detection_timeout(
-            handshake_status,
-            now,
-            trace_id,
-        );
+        is_server: bool,
+    ) -> recovery::TimeoutOutcome {
+        let outcome = self
+            .recovery
+            .on_loss_detection_timeout_outcome(handshake_status, now);
 
         let mut lost_probe_time = None;
         self.in_flight_challenges.retain(|(_, _, sent_time)| {
@@ -400,7 +423,9 @@ impl Path {
             self.last_probe_lost_time = match self.last_probe_lost_time {
                 Some(last) => {
                     // Count a loss if at least 1-RTT happened.
-                    if lost_probe_time - last >= self.recovery.rtt() {
+                    if lost_probe_time.saturating_duration_since(last) >=
+                        self.recovery.rtt()
+                    {
                         self.probing_lost += 1;
                         Some(lost_probe_time)
                     } else {
@@ -424,10 +449,53 @@ impl Path {
             }
         }
 
-        (lost_packets, lost_bytes)
+        outcome
+    }
+
+    /// Updates the latency spin value from a 1-RTT packet received on the
+    /// path.
+    ///
+    /// Only packets that increase the largest packet number received on the
+    /// path are taken into account. The server reflects the spin bit it
+    /// receives, while the client inverts it, so that the value toggles once
+    /// per round trip.
+    pub fn on_spin_bit_received(
+        &mut self, pkt_num: u64, spin: bool, is_server: bool,
+    ) {
+        if self.spin_largest_rx_pkt_num >= Some(pkt_num) {
+            return;
+        }
+
+        self.reset_spin_value_on_dcid_change();
+
+        self.spin_largest_rx_pkt_num = Some(pkt_num);
+        self.spin_value = if is_server { spin } else { !spin };
+    }
+
+    /// Returns the spin bit of the next 1-RTT packet sent on the path.
+    pub fn spin_bit_to_send(&mut self) -> bool {
+        self.reset_spin_value_on_dcid_change();
+
+        self.spin_value
+    }
+
+    /// Returns the latency spin value of the path.
+    pub fn spin_value(&self) -> bool {
+        self.spin_value
+    }
+
+    // The spin value is reset when the destination CID used on the path
+    // changes.
+    fn reset_spin_value_on_dcid_change(&mut self) {
+        if self.spin_dcid_seq != self.active_dcid_seq {
+            self.spin_dcid_seq = self.active_dcid_seq;
+            self.spin_value = false;
+        }
     }
 
     pub fn stats(&self) -> PathStats {
+        let recovery_stats = self.recovery.stats();
+
         PathStats {
             local_addr: self.local_addr,
             peer_addr: self.peer_addr,
@@ -445,6 +513,26 @@ impl Path {
             stream_retrans_bytes: self.stream_retrans_bytes,
             pmtu: self.recovery.max_datagram_size(),
             delivery_rate: self.recovery.delivery_rate(),
+            recovery_buffer_capacity: self.recovery.buffer_capacity(),
+            recovery_memory: self.recovery.memory_footprint().total(),
+            min_rtt: recovery_stats.min_rtt,
+            ssthresh: recovery_stats.ssthresh,
+            pacing_rate: recovery_stats.pacing_rate,
+            pto_count: recovery_stats.pto_count,
+            spurious_lost: recovery_stats.spurious_lost_count,
+            handshake_pto_count: self.recovery.handshake_pto_count,
+            paused: self.recovery.is_paused(),
+            acks_without_ack_eliciting: self.recovery.acks_without_ack_eliciting,
+            rtt_samples_skipped: self.recovery.rtt_samples_skipped,
+            loss_timer_suppressed_updates: self
+                .recovery
+                .loss_timer_suppressed_updates(),
+            bytes_in_flight_underflows: self.recovery.bytes_in_flight_underflows,
+            blackhole_suspected: self.recovery.is_blackhole_suspected(),
+            frame_backlog: self.recovery.frame_backlog(),
+            frame_backlog_overflows: self.recovery.frame_backlog_overflows,
+            packet_reorder_threshold: self.recovery.reorder_thresholds().0,
+            time_reorder_threshold: self.recovery.reorder_thresholds().1,
         }
     }
 }
@@ -685,6 +773,22 @@ impl PathMap {
         }
     }
 
+    /// Notifies paths on which a black hole started being suspected.
+    pub fn notify_blackholes(&mut self) {
+        for (_, p) in self.paths.iter_mut() {
+            let suspected = p.recovery.is_blackhole_suspected();
+
+            if suspected && !p.blackhole_notified {
+                self.events.push_back(PathEvent::BlackholeSuspected(
+                    p.local_addr,
+                    p.peer_addr,
+                ));
+            }
+
+            p.blackhole_notified = suspected;
+        }
+    }
+
     /// Finds a path candidate to be active and returns its identifier.
     pub fn find_candidate_path(&self) -> Option<usize> {
         // TODO: also consider unvalidated paths if there are no more validated.
@@ -861,6 +965,69 @@ pub struct PathStats {
     /// [`SendInfo.at`]: struct.SendInfo.html#structfield.at
     /// [Pacing]: index.html#pacing
     pub delivery_rate: u64,
+
+    /// The total capacity, in number of elements, of the loss recovery
+    /// buffers.
+    pub recovery_buffer_capacity: usize,
+
+    /// The estimated memory used by the loss recovery state, in bytes.
+    pub recovery_memory: usize,
+
+    /// The minimum round-trip time observed on the path.
+    pub min_rtt: time::Duration,
+
+    /// The slow start threshold in bytes.
+    pub ssthresh: usize,
+
+    /// The pacing rate in bytes/s.
+    pub pacing_rate: u64,
+
+    /// The number of consecutive PTOs since the last acknowledgement.
+    pub pto_count: u32,
+
+    /// The number of QUIC packets that were declared lost, but were
+    /// acknowledged afterwards.
+    pub spurious_lost: usize,
+
+    /// The number of PTOs that fired in the Initial and Handshake packet
+    /// number spaces.
+    pub handshake_pto_count: usize,
+
+    /// Whether sending on the path is paused.
+    pub paused: bool,
+
+    /// The number of ACKs that newly acknowledged packets, none of which
+    /// were ack-eliciting.
+    pub acks_without_ack_eliciting: usize,
+
+    /// The number of ACKs that newly acknowledged packets without producing
+    /// an RTT sample.
+    pub rtt_samples_skipped: usize,
+
+    /// The number of loss detection timer deadline changes that were not
+    /// reported.
+    pub loss_timer_suppressed_updates: usize,
+
+    /// The number of times more bytes were removed from the flight than were
+    /// in it. This is always zero unless the bytes in flight accounting is
+    /// broken.
+    pub bytes_in_flight_underflows: usize,
+
+    /// Whether a network black hole is suspected on the path.
+    pub blackhole_suspected: bool,
+
+    /// The number of acknowledged and lost frames waiting to be processed.
+    pub frame_backlog: usize,
+
+    /// The number of times frames were left out of a full backlog of
+    /// acknowledged or lost frames.
+    pub frame_backlog_overflows: usize,
+
+    /// The packet reordering threshold currently used by loss detection.
+    pub packet_reorder_threshold: u64,
+
+    /// The time reordering threshold currently used by loss detection.
+    pub time_reorder_threshold: f64,
 }
 
 impl std::fmt::Debug for PathStats {
@@ -892,6 +1059,57 @@ impl std::fmt::Debug for PathStats {
             f,
             " stream_retrans_bytes={} pmtu={} delivery_rate={}",
             self.stream_retrans_bytes, self.pmtu, self.delivery_rate,
+        )?;
+
+        write!(
+            f,
+            " recovery_buffer_capacity={} recovery_memory={}",
+            self.recovery_buffer_capacity, self.recovery_memory,
+        )?;
+
+        write!(
+            f,
+            " min_rtt={:?} ssthresh={} pacing_rate={} pto_count={} spurious_lost={}",
+            self.min_rtt,
+            self.ssthresh,
+            self.pacing_rate,
+            self.pto_count,
+            self.spurious_lost,
+        )?;
+
+        write!(f, " handshake_pto_count={}", self.handshake_pto_count)?;
+        write!(f, " paused={}", self.paused)?;
+
+        write!(
+            f,
+            " acks_without_ack_eliciting={} rtt_samples_skipped={}",
+            self.acks_without_ack_eliciting, self.rtt_samples_skipped,
+        )?;
+
+        write!(
+            f,
+            " loss_timer_suppressed_updates={}",
+            self.loss_timer_suppressed_updates
+        )?;
+
+        write!(
+            f,
+            " bytes_in_flight_underflows={}",
+            self.bytes_in_flight_underflows
+        )?;
+
+        write!(f, " blackhole_suspected={}", self.blackhole_suspected)?;
+
+        write!(
+            f,
+            " frame_backlog={} frame_backlog_overflows={}",
+            self.frame_backlog, self.frame_backlog_overflows,
+        )?;
+
+        write!(
+            f,
+            " packet_reorder_threshold={} time_reorder_threshold={}",
+            self.packet_reorder_threshold, self.time_reorder_threshold,
         )
     }
 }
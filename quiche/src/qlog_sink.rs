@@ -0,0 +1,254 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Non-blocking qlog output.
+//!
+//! The qlog streamer serializes each record in many small writes. The sink
+//! reassembles them, and hands each complete JSON-SEQ record to the inner
+//! writer in a single `write()` call. Records the inner writer can't accept
+//! right away are buffered, up to a limit after which new records are dropped
+//! instead of blocking the connection.
+
+use std::collections::VecDeque;
+
+use std::io;
+use std::io::Write;
+
+use std::sync::atomic;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+
+/// The default number of bytes of records buffered by a sink.
+pub const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// A [`Writer`] that never blocks on the writer it wraps.
+///
+/// Each complete JSON-SEQ record is passed to the inner writer in a single
+/// `write()` call, which is expected to either accept it fully or fail with
+/// [`WouldBlock`] if it can't accept it right now. In the latter case the
+/// record is buffered and retried on the next write or flush. When the buffer
+/// is full, new records are dropped and counted instead.
+///
+/// [`Writer`]: https://doc.rust-lang.org/std/io/trait.Write.html
+/// [`WouldBlock`]: https://doc.rust-lang.org/std/io/enum.ErrorKind.html
+pub struct QlogSink {
+    inner: Box<dyn Write + Send + Sync>,
+
+    // The record being serialized.
+    record: Vec<u8>,
+
+    // Complete records waiting for the inner writer.
+    queue: VecDeque<Vec<u8>>,
+
+    queued_bytes: usize,
+
+    buffer_size: usize,
+
+    dropped: Arc<AtomicU64>,
+}
+
+impl QlogSink {
+    /// Creates a sink writing to `inner`, buffering up to `buffer_size` bytes
+    /// of records.
+    pub fn new(inner: Box<dyn Write + Send + Sync>, buffer_size: usize) -> Self {
+        QlogSink {
+            inner,
+            record: Vec::new(),
+            queue: VecDeque::new(),
+            queued_bytes: 0,
+            buffer_size,
+            dropped: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Returns the number of records dropped so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Returns a shared counter of the records dropped, which keeps being
+    /// updated after the sink is handed to the connection.
+    pub fn dropped_handle(&self) -> Arc<AtomicU64> {
+        self.dropped.clone()
+    }
+
+    // Queues the record being serialized, or drops it if the buffer is full.
+    fn finish_record(&mut self) {
+        let record = std::mem::take(&mut self.record);
+
+        if self.queued_bytes + record.len() > self.buffer_size {
+            self.dropped.fetch_add(1, atomic::Ordering::Relaxed);
+            return;
+        }
+
+        self.queued_bytes += record.len();
+        self.queue.push_back(record);
+    }
+
+    // Passes queued records to the inner writer until it would block.
+    fn drain(&mut self) {
+        while let Some(record) = self.queue.front() {
+            match self.inner.write(record) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+
+                // The record is either written, or can't ever be.
+                Ok(_) => (),
+
+                Err(_) => {
+                    self.dropped.fetch_add(1, atomic::Ordering::Relaxed);
+                },
+            }
+
+            if let Some(record) = self.queue.pop_front() {
+                self.queued_bytes -= record.len();
+            }
+        }
+    }
+}
+
+impl Write for QlogSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut complete = false;
+
+        for chunk in buf.split_inclusive(|&b| b == b'\n') {
+            self.record.extend_from_slice(chunk);
+
+            // Newlines are escaped in JSON texts, so they only ever appear at
+            // the end of a record.
+            if chunk.last() == Some(&b'\n') {
+                self.finish_record();
+                complete = true;
+            }
+        }
+
+        if complete {
+            self.drain();
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.drain();
+
+        self.inner.flush().or_else(|e| match e.kind() {
+            io::ErrorKind::WouldBlock => Ok(()),
+
+            _ => Err(e),
+        })
+    }
+}
+
+impl Drop for QlogSink {
+    fn drop(&mut self) {
+        self.drain();
+
+        // Whatever the inner writer still can't accept is lost.
+        self.dropped
+            .fetch_add(self.queue.len() as u64, atomic::Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    // Records each write, and refuses them while busy.
+    #[derive(Clone, Default)]
+    struct Records {
+        writes: Arc<Mutex<Vec<Vec<u8>>>>,
+        busy: Arc<atomic::AtomicBool>,
+    }
+
+    impl Write for Records {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.busy.load(atomic::Ordering::Relaxed) {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+
+            self.writes.lock().unwrap().push(buf.to_vec());
+
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn one_write_per_record() {
+        let records = Records::default();
+
+        let mut sink = QlogSink::new(Box::new(records.clone()), 1024);
+
+        sink.write_all(b"\x1e{\"a\":").unwrap();
+        sink.write_all(b"1}\n\x1e{\"b\"").unwrap();
+        assert_eq!(records.writes.lock().unwrap().len(), 1);
+
+        sink.write_all(b":2}\n").unwrap();
+
+        assert_eq!(*records.writes.lock().unwrap(), [
+            b"\x1e{\"a\":1}\n".to_vec(),
+            b"\x1e{\"b\":2}\n".to_vec(),
+        ]);
+        assert_eq!(sink.dropped(), 0);
+    }
+
+    #[test]
+    fn buffer_while_busy() {
+        let records = Records::default();
+        records.busy.store(true, atomic::Ordering::Relaxed);
+
+        let mut sink = QlogSink::new(Box::new(records.clone()), 20);
+        let dropped = sink.dropped_handle();
+
+        // Two records fit in the buffer, the third one is dropped.
+        for i in 0..3 {
+            writeln!(sink, "\x1e{{\"n\":{}}}", i).unwrap();
+        }
+
+        assert!(records.writes.lock().unwrap().is_empty());
+        assert_eq!(dropped.load(atomic::Ordering::Relaxed), 1);
+
+        records.busy.store(false, atomic::Ordering::Relaxed);
+        sink.flush().unwrap();
+
+        assert_eq!(*records.writes.lock().unwrap(), [
+            b"\x1e{\"n\":0}\n".to_vec(),
+            b"\x1e{\"n\":1}\n".to_vec(),
+        ]);
+
+        // Records queued when the sink goes away are dropped.
+        records.busy.store(true, atomic::Ordering::Relaxed);
+        writeln!(sink, "\x1e{{\"n\":3}}").unwrap();
+
+        drop(sink);
+        assert_eq!(dropped.load(atomic::Ordering::Relaxed), 2);
+    }
+}
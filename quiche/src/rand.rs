@@ -59,6 +59,141 @@ pub fn rand_u64_uniform(max: u64) -> u64 {
     r / chunk_size
 }
 
+/// A source of randomness for randomized protocol behaviors.
+///
+/// This uses the system RNG by default. Tests can replace it with a PRNG
+/// seeded with a known value, so that randomized behaviors can be reproduced.
+#[derive(Clone, Debug)]
+pub enum Rng {
+    /// The system RNG.
+    System,
+
+    /// A deterministic PRNG.
+    #[cfg(any(test, feature = "test-utils"))]
+    Seeded(SplitMix64),
+}
+
+impl Rng {
+    /// Returns a deterministic PRNG seeded with `seed`.
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn with_seed(seed: u64) -> Rng {
+        Rng::Seeded(SplitMix64 { state: seed })
+    }
+
+    pub fn fill_bytes(&mut self, buf: &mut [u8]) {
+        match self {
+            Rng::System => rand_bytes(buf),
+
+            #[cfg(any(test, feature = "test-utils"))]
+            Rng::Seeded(prng) =>
+                for chunk in buf.chunks_mut(8) {
+                    let v = prng.next_u64().to_ne_bytes();
+
+                    chunk.copy_from_slice(&v[..chunk.len()]);
+                },
+        }
+    }
+
+    pub fn u64(&mut self) -> u64 {
+        match self {
+            Rng::System => rand_u64(),
+
+            #[cfg(any(test, feature = "test-utils"))]
+            Rng::Seeded(prng) => prng.next_u64(),
+        }
+    }
+
+    /// Returns a uniformly distributed value lower than `max`.
+    pub fn u64_uniform(&mut self, max: u64) -> u64 {
+        let chunk_size = u64::MAX / max;
+        let end_of_last_chunk = chunk_size * max;
+
+        let mut r = self.u64();
+
+        while r >= end_of_last_chunk {
+            r = self.u64();
+        }
+
+        r / chunk_size
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Rng {
+        Rng::System
+    }
+}
+
+/// The SplitMix64 PRNG.
+///
+/// This is not cryptographically secure, and is only meant for tests.
+#[cfg(any(test, feature = "test-utils"))]
+#[derive(Clone, Debug)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+
+        z ^ (z >> 31)
+    }
+}
+
+/// Returns a seed for the deterministic PRNG of a test.
+///
+/// The seed is read from the `QUICHE_TEST_SEED` environment variable, or
+/// picked at random if it's not set. Either way it is printed to stderr,
+/// which the test harness only shows for failed tests, so that a failure can
+/// be reproduced by setting the variable to the printed value.
+#[cfg(any(test, feature = "test-utils"))]
+pub fn test_seed() -> u64 {
+    let seed = match std::env::var("QUICHE_TEST_SEED") {
+        Ok(v) => v.parse().expect("QUICHE_TEST_SEED is not a valid u64"),
+
+        Err(_) => rand_u64(),
+    };
+
+    eprintln!("QUICHE_TEST_SEED={}", seed);
+
+    seed
+}
+
 extern {
     fn RAND_bytes(buf: *mut u8, len: libc::size_t) -> libc::c_int;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_rng() {
+        let mut a = Rng::with_seed(42);
+        let mut b = Rng::with_seed(42);
+        let mut c = Rng::with_seed(43);
+
+        let a_values: Vec<u64> = (0..16).map(|_| a.u64()).collect();
+        let b_values: Vec<u64> = (0..16).map(|_| b.u64()).collect();
+        let c_values: Vec<u64> = (0..16).map(|_| c.u64()).collect();
+
+        assert_eq!(a_values, b_values);
+        assert_ne!(a_values, c_values);
+
+        let mut a_buf = [0; 13];
+        let mut b_buf = [0; 13];
+
+        a.fill_bytes(&mut a_buf);
+        b.fill_bytes(&mut b_buf);
+
+        assert_eq!(a_buf, b_buf);
+
+        assert!((0..1000).all(|_| a.u64_uniform(7) < 7));
+    }
+}
@@ -268,6 +268,48 @@ fn range_overlaps(r: &Range<u64>, other: &Range<u64>) -> bool {
 mod tests {
     use super::*;
 
+    use std::collections::BTreeSet;
+
+    use proptest::prelude::*;
+    use proptest::test_runner::Config;
+    use proptest::test_runner::RngAlgorithm;
+    use proptest::test_runner::TestRng;
+    use proptest::test_runner::TestRunner;
+
+    // Returns a property test runner with a fixed seed, so that failures are
+    // reproducible.
+    fn runner() -> TestRunner {
+        let config = Config {
+            cases: 256,
+            failure_persistence: None,
+            ..Config::default()
+        };
+
+        TestRunner::new_with_rng(
+            config,
+            TestRng::deterministic_rng(RngAlgorithm::ChaCha),
+        )
+    }
+
+    // Checks that the set contains exactly the items of the reference set, as
+    // sorted ranges that neither overlap nor touch each other.
+    fn check_reference(
+        r: &RangeSet, reference: &BTreeSet<u64>,
+    ) -> Result<(), TestCaseError> {
+        prop_assert!(r.flatten().eq(reference.iter().copied()));
+        prop_assert!(r.flatten().rev().eq(reference.iter().rev().copied()));
+
+        for (a, b) in r.iter().zip(r.iter().skip(1)) {
+            prop_assert!(a.start < a.end, "empty range {:?}", a);
+            prop_assert!(a.end < b.start, "{:?} and {:?} not merged", a, b);
+        }
+
+        prop_assert_eq!(r.first(), reference.iter().next().copied());
+        prop_assert_eq!(r.last(), reference.iter().next_back().copied());
+
+        Ok(())
+    }
+
     #[test]
     fn insert_non_overlapping() {
         let mut r = RangeSet::default();
@@ -610,4 +652,82 @@ mod tests {
         assert_eq!(r.first(), Some(4));
         assert_eq!(r.last(), Some(19));
     }
+
+    #[test]
+    fn insert_matches_reference() {
+        let ranges = prop::collection::vec((0..256_u64, 1..16_u64), 0..64);
+
+        runner()
+            .run(&ranges, |ranges| {
+                let mut r = RangeSet::default();
+                let mut reference = BTreeSet::new();
+
+                for (start, len) in ranges {
+                    r.insert(start..start + len);
+                    reference.extend(start..start + len);
+
+                    check_reference(&r, &reference)?;
+                }
+
+                Ok(())
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn push_item_and_remove_until_match_reference() {
+        // Each operation either pushes an item, or removes all items up to
+        // it.
+        let ops = prop::collection::vec((any::<bool>(), 0..256_u64), 0..128);
+
+        runner()
+            .run(&ops, |ops| {
+                let mut r = RangeSet::default();
+                let mut reference = BTreeSet::new();
+
+                for (push, item) in ops {
+                    if push {
+                        r.push_item(item);
+                        reference.insert(item);
+                    } else {
+                        r.remove_until(item);
+                        reference = reference.split_off(&(item + 1));
+                    }
+
+                    check_reference(&r, &reference)?;
+                }
+
+                Ok(())
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn capacity_bounds_ranges() {
+        let ranges = prop::collection::vec((0..256_u64, 1..16_u64), 0..64);
+
+        runner()
+            .run(&(1..8_usize, ranges), |(capacity, ranges)| {
+                let mut r = RangeSet::new(capacity);
+                let mut reference = BTreeSet::new();
+
+                for (start, len) in ranges {
+                    r.insert(start..start + len);
+                    reference.extend(start..start + len);
+
+                    prop_assert!(r.len() <= capacity);
+
+                    // Evicting ranges can only drop items.
+                    prop_assert!(r.flatten().all(|i| reference.contains(&i)));
+
+                    // The range that was just inserted is always kept.
+                    prop_assert!(
+                        (start..start + len).all(|i| r.flatten().any(|j| i == j))
+                    );
+                }
+
+                Ok(())
+            })
+            .unwrap();
+    }
 }
@@ -28,7 +28,6 @@ use super::*;
 use crate::recovery::Recovery;
 
 use std::time::Duration;
-use std::time::Instant;
 
 // BBR Functions at Initialization.
 //
@@ -36,10 +35,11 @@ use std::time::Instant;
 // 4.3.1.  Initialization Steps
 pub fn bbr_init(r: &mut Recovery) {
     let rtt = r.rtt();
+    let now = r.clock.now();
     let bbr = &mut r.bbr_state;
 
     bbr.rtprop = rtt;
-    bbr.rtprop_stamp = Instant::now();
+    bbr.rtprop_stamp = now;
     bbr.next_round_delivered = r.delivery_rate.delivered();
 
     r.send_quantum = r.max_datagram_size;
@@ -32,9 +32,9 @@
 use crate::minmax::Minmax;
 use crate::packet;
 use crate::recovery::*;
+use crate::time::Instant;
 
 use std::time::Duration;
-use std::time::Instant;
 
 pub static BBR: CongestionControlOps = CongestionControlOps {
     on_init,
@@ -46,6 +46,7 @@ pub static BBR: CongestionControlOps = CongestionControlOps {
     checkpoint,
     rollback,
     has_custom_pacing,
+    in_slow_start,
     debug_fmt,
 };
 
@@ -188,9 +189,7 @@ pub struct State {
 }
 
 impl State {
-    pub fn new() -> Self {
-        let now = Instant::now();
-
+    pub fn new(now: Instant) -> Self {
         State {
             state: BBRStateMachine::Startup,
 
@@ -198,7 +197,7 @@ impl State {
 
             btlbw: 0,
 
-            btlbwfilter: Minmax::new(0),
+            btlbwfilter: Minmax::new(0, now),
 
             rtprop: Duration::ZERO,
 
@@ -257,7 +256,7 @@ fn bbr_enter_recovery(r: &mut Recovery, now: Instant) {
 
     r.congestion_window = r.bytes_in_flight +
         r.bbr_state.newly_acked_bytes.max(r.max_datagram_size);
-    r.congestion_recovery_start_time = Some(now);
+    r.start_recovery_episode(now);
 
     r.bbr_state.packet_conservation = true;
     r.bbr_state.in_recovery = true;
@@ -283,7 +282,7 @@ fn on_init(r: &mut Recovery) {
 }
 
 fn reset(r: &mut Recovery) {
-    r.bbr_state = State::new();
+    r.bbr_state = State::new(r.clock.now());
 
     init::bbr_init(r);
 }
@@ -295,20 +294,20 @@ fn on_packet_sent(r: &mut Recovery, sent_bytes: usize, _now: Instant) {
 }
 
 fn on_packets_acked(
-    r: &mut Recovery, packets: &[Acked], _epoch: packet::Epoch, now: Instant,
+    r: &mut Recovery, packets: &[Acked], epoch: packet::Epoch, now: Instant,
 ) {
     r.bbr_state.newly_acked_bytes = packets.iter().fold(0, |acked_bytes, p| {
         r.bbr_state.prior_bytes_in_flight = r.bytes_in_flight;
 
         per_ack::bbr_update_model_and_state(r, p, now);
 
-        r.bytes_in_flight = r.bytes_in_flight.saturating_sub(p.size);
+        r.sub_bytes_in_flight(p.size);
 
         acked_bytes + p.size
     });
 
     if let Some(pkt) = packets.last() {
-        if !r.in_congestion_recovery(pkt.time_sent) {
+        if !r.in_congestion_recovery(epoch, pkt.pkt_num) {
             // Upon exiting loss recovery.
             bbr_exit_recovery(r);
         }
@@ -320,13 +319,13 @@ fn on_packets_acked(
 }
 
 fn congestion_event(
-    r: &mut Recovery, lost_bytes: usize, time_sent: Instant,
-    _epoch: packet::Epoch, now: Instant,
+    r: &mut Recovery, lost_bytes: usize, largest_lost_pkt: u64,
+    epoch: packet::Epoch, now: Instant,
 ) {
     r.bbr_state.newly_lost_bytes = lost_bytes;
 
     // Upon entering Fast Recovery.
-    if !r.in_congestion_recovery(time_sent) {
+    if !r.in_congestion_recovery(epoch, largest_lost_pkt) {
         // Upon entering Fast Recovery.
         bbr_enter_recovery(r, now);
     }
@@ -348,6 +347,10 @@ fn has_custom_pacing() -> bool {
     true
 }
 
+fn in_slow_start(r: &Recovery) -> bool {
+    r.bbr_state.state == BBRStateMachine::Startup
+}
+
 fn debug_fmt(r: &Recovery, f: &mut std::fmt::Formatter) -> std::fmt::Result {
     let bbr = &r.bbr_state;
 
@@ -421,7 +424,13 @@ mod tests {
                 delivered_time: now,
                 first_sent_time: now,
                 is_app_limited: false,
-                has_data: false,
+                content: SentContent::default(),
+                is_pmtud_probe: false,
+                padding_bytes: 0,
+                datagram_id: None,
+                burst_index: 0,
+                burst_size: 0,
+                congestion_state: CongestionState::SlowStart,
             };
 
             r.on_packet_sent(
@@ -429,7 +438,6 @@ mod tests {
                 packet::EPOCH_APPLICATION,
                 HandshakeStatus::default(),
                 now,
-                "",
             );
         }
 
@@ -447,7 +455,7 @@ mod tests {
                 packet::EPOCH_APPLICATION,
                 HandshakeStatus::default(),
                 now,
-                "",
+                None,
             ),
             Ok((0, 0)),
         );
@@ -488,7 +496,13 @@ mod tests {
                 delivered_time: now,
                 first_sent_time: now,
                 is_app_limited: false,
-                has_data: false,
+                content: SentContent::default(),
+                is_pmtud_probe: false,
+                padding_bytes: 0,
+                datagram_id: None,
+                burst_index: 0,
+                burst_size: 0,
+                congestion_state: CongestionState::SlowStart,
             };
 
             r.on_packet_sent(
@@ -496,7 +510,6 @@ mod tests {
                 packet::EPOCH_APPLICATION,
                 HandshakeStatus::default(),
                 now,
-                "",
             );
         }
 
@@ -515,7 +528,7 @@ mod tests {
                 packet::EPOCH_APPLICATION,
                 HandshakeStatus::default(),
                 now,
-                "",
+                None,
             ),
             Ok((2, 2400)),
         );
@@ -554,7 +567,13 @@ mod tests {
                 delivered_time: now,
                 first_sent_time: now,
                 is_app_limited: false,
-                has_data: false,
+                content: SentContent::default(),
+                is_pmtud_probe: false,
+                padding_bytes: 0,
+                datagram_id: None,
+                burst_index: 0,
+                burst_size: 0,
+                congestion_state: CongestionState::SlowStart,
             };
 
             r.on_packet_sent(
@@ -562,7 +581,6 @@ mod tests {
                 packet::EPOCH_APPLICATION,
                 HandshakeStatus::default(),
                 now,
-                "",
             );
 
             pn += 1;
@@ -581,7 +599,7 @@ mod tests {
                     packet::EPOCH_APPLICATION,
                     HandshakeStatus::default(),
                     now,
-                    "",
+                    None,
                 ),
                 Ok((0, 0)),
             );
@@ -602,7 +620,13 @@ mod tests {
                 delivered_time: now,
                 first_sent_time: now,
                 is_app_limited: false,
-                has_data: false,
+                content: SentContent::default(),
+                is_pmtud_probe: false,
+                padding_bytes: 0,
+                datagram_id: None,
+                burst_index: 0,
+                burst_size: 0,
+                congestion_state: CongestionState::SlowStart,
             };
 
             r.on_packet_sent(
@@ -610,7 +634,6 @@ mod tests {
                 packet::EPOCH_APPLICATION,
                 HandshakeStatus::default(),
                 now,
-                "",
             );
 
             pn += 1;
@@ -632,7 +655,7 @@ mod tests {
                 packet::EPOCH_APPLICATION,
                 HandshakeStatus::default(),
                 now,
-                "",
+                None,
             ),
             Ok((0, 0)),
         );
@@ -673,7 +696,13 @@ mod tests {
                 delivered_time: now,
                 first_sent_time: now,
                 is_app_limited: false,
-                has_data: false,
+                content: SentContent::default(),
+                is_pmtud_probe: false,
+                padding_bytes: 0,
+                datagram_id: None,
+                burst_index: 0,
+                burst_size: 0,
+                congestion_state: CongestionState::SlowStart,
             };
 
             r.on_packet_sent(
@@ -681,7 +710,6 @@ mod tests {
                 packet::EPOCH_APPLICATION,
                 HandshakeStatus::default(),
                 now,
-                "",
             );
 
             pn += 1;
@@ -699,7 +727,7 @@ mod tests {
                     packet::EPOCH_APPLICATION,
                     HandshakeStatus::default(),
                     now,
-                    "",
+                    None,
                 ),
                 Ok((0, 0)),
             );
@@ -743,7 +771,13 @@ mod tests {
                 delivered_time: now,
                 first_sent_time: now,
                 is_app_limited: false,
-                has_data: false,
+                content: SentContent::default(),
+                is_pmtud_probe: false,
+                padding_bytes: 0,
+                datagram_id: None,
+                burst_index: 0,
+                burst_size: 0,
+                congestion_state: CongestionState::SlowStart,
             };
 
             r.on_packet_sent(
@@ -751,7 +785,6 @@ mod tests {
                 packet::EPOCH_APPLICATION,
                 HandshakeStatus::default(),
                 now,
-                "",
             );
 
             pn += 1;
@@ -769,7 +802,7 @@ mod tests {
                     packet::EPOCH_APPLICATION,
                     HandshakeStatus::default(),
                     now,
-                    "",
+                    None,
                 ),
                 Ok((0, 0)),
             );
@@ -794,7 +827,13 @@ mod tests {
             delivered_time: now,
             first_sent_time: now,
             is_app_limited: false,
-            has_data: false,
+            content: SentContent::default(),
+            is_pmtud_probe: false,
+            padding_bytes: 0,
+            datagram_id: None,
+            burst_index: 0,
+            burst_size: 0,
+            congestion_state: CongestionState::SlowStart,
         };
 
         r.on_packet_sent(
@@ -802,7 +841,6 @@ mod tests {
             packet::EPOCH_APPLICATION,
             HandshakeStatus::default(),
             now,
-            "",
         );
 
         pn += 1;
@@ -822,7 +860,7 @@ mod tests {
                 packet::EPOCH_APPLICATION,
                 HandshakeStatus::default(),
                 now,
-                "",
+                None,
             ),
             Ok((0, 0)),
         );
@@ -25,11 +25,10 @@
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 use super::*;
-use crate::rand;
 use crate::recovery;
+use crate::time::Instant;
 
 use std::cmp;
-use std::time::Instant;
 
 /// 1.2Mbps in bytes/sec
 const PACING_RATE_1_2MBPS: u64 = 1200 * 1000 / 8;
@@ -132,7 +131,7 @@ fn bbr_inflight(r: &mut Recovery, gain: f64) -> usize {
     let bbr = &mut r.bbr_state;
 
     if bbr.rtprop == Duration::MAX {
-        return r.max_datagram_size * INITIAL_WINDOW_PACKETS;
+        return r.max_datagram_size * r.initial_congestion_window_packets;
     }
 
     let quanta = 3 * r.send_quantum;
@@ -199,7 +198,7 @@ fn bbr_set_cwnd(r: &mut Recovery) {
             )
         } else if r.congestion_window < r.bbr_state.target_cwnd ||
             r.delivery_rate.delivered() <
-                r.max_datagram_size * INITIAL_WINDOW_PACKETS
+                r.max_datagram_size * r.initial_congestion_window_packets
         {
             r.congestion_window += acked_bytes;
         }
@@ -279,7 +278,7 @@ fn bbr_enter_probe_bw(r: &mut Recovery, now: Instant) {
     // to avoid index 1 (pacing_gain=3/4). See 4.3.4.2 for details.
     bbr.cycle_index = BBR_GAIN_CYCLE_LEN -
         1 -
-        (rand::rand_u64_uniform(BBR_GAIN_CYCLE_LEN as u64 - 1) as usize);
+        (r.rng.u64_uniform(BBR_GAIN_CYCLE_LEN as u64 - 1) as usize);
 
     bbr_advance_cycle_phase(r, now);
 }
@@ -306,7 +305,8 @@ fn bbr_is_next_cycle_phase(r: &mut Recovery, now: Instant) -> bool {
     let pacing_gain = bbr.pacing_gain;
     let prior_in_flight = bbr.prior_bytes_in_flight;
 
-    let is_full_length = (now - bbr.cycle_stamp) > bbr.rtprop;
+    let is_full_length =
+        now.saturating_duration_since(bbr.cycle_stamp) > bbr.rtprop;
 
     // pacing_gain == 1.0
     if (pacing_gain - 1.0).abs() < f64::EPSILON {
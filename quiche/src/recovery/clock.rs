@@ -0,0 +1,143 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Source of time for loss recovery.
+//!
+//! Most loss recovery methods are given the current time by the caller, but
+//! some state needs to be initialized with the current time, or is formatted
+//! relative to it. Reading the time through a `Clock` allows tests to control
+//! it, making them fully deterministic.
+//!
+//! Loss recovery doesn't assume anything about the origin of `Instant`s, so
+//! that times close to it, as on platforms whose monotonic clock starts with
+//! the process, don't cause arithmetic to overflow. The system clock is read
+//! through [`crate::time`], e.g. from `performance.now()` on
+//! wasm32-unknown-unknown.
+
+#[cfg(any(
+    test,
+    feature = "simulation",
+    feature = "qlog",
+    feature = "test-utils"
+))]
+use std::sync::Arc;
+#[cfg(any(
+    test,
+    feature = "simulation",
+    feature = "qlog",
+    feature = "test-utils"
+))]
+use std::sync::Mutex;
+#[cfg(any(test, feature = "simulation"))]
+use std::time::Duration;
+
+use crate::time::Instant;
+
+/// A source of time.
+#[derive(Clone, Debug)]
+pub enum Clock {
+    /// The system's monotonic clock, as returned by `Instant::now()`.
+    System,
+
+    /// A clock that only moves when told to.
+    #[cfg(any(
+        test,
+        feature = "simulation",
+        feature = "qlog",
+        feature = "test-utils"
+    ))]
+    Manual(ManualClock),
+}
+
+impl Clock {
+    /// Returns the current time.
+    pub fn now(&self) -> Instant {
+        match self {
+            Clock::System => Instant::now(),
+
+            #[cfg(any(
+                test,
+                feature = "simulation",
+                feature = "qlog",
+                feature = "test-utils"
+            ))]
+            Clock::Manual(clock) => clock.now(),
+        }
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock::System
+    }
+}
+
+/// A manually advanced clock.
+///
+/// Clones share the same time, so a test can keep a copy to advance the time
+/// of the clock it gave to `Recovery`.
+#[cfg(any(
+    test,
+    feature = "simulation",
+    feature = "qlog",
+    feature = "test-utils"
+))]
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+#[cfg(any(
+    test,
+    feature = "simulation",
+    feature = "qlog",
+    feature = "test-utils"
+))]
+impl ManualClock {
+    pub fn new() -> Self {
+        ManualClock {
+            now: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    pub fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+
+    /// Moves the time forward by the given duration.
+    #[cfg(any(test, feature = "simulation"))]
+    pub fn advance(&self, d: Duration) {
+        *self.now.lock().unwrap() += d;
+    }
+
+    /// Moves the time forward to the given instant. Times in the past are
+    /// ignored, as the clock is monotonic.
+    pub fn advance_to(&self, t: Instant) {
+        let mut now = self.now.lock().unwrap();
+
+        *now = (*now).max(t);
+    }
+}
@@ -34,11 +34,11 @@
 use std::cmp;
 
 use std::time::Duration;
-use std::time::Instant;
 
 use crate::packet;
 use crate::recovery;
 use crate::recovery::reno;
+use crate::time::Instant;
 
 use crate::recovery::Acked;
 use crate::recovery::CongestionControlOps;
@@ -54,6 +54,7 @@ pub static CUBIC: CongestionControlOps = CongestionControlOps {
     checkpoint,
     rollback,
     has_custom_pacing,
+    in_slow_start,
     debug_fmt,
 };
 
@@ -178,7 +179,7 @@ fn on_packet_sent(r: &mut Recovery, sent_bytes: usize, now: Instant) {
 
     if let Some(last_sent_time) = cubic.last_sent_time {
         if r.bytes_in_flight == 0 {
-            let delta = now - last_sent_time;
+            let delta = now.saturating_duration_since(last_sent_time);
 
             // We were application limited (idle) for a while.
             // Shift epoch start to keep cwnd growth to cubic curve.
@@ -207,9 +208,9 @@ fn on_packets_acked(
 fn on_packet_acked(
     r: &mut Recovery, packet: &Acked, epoch: packet::Epoch, now: Instant,
 ) {
-    let in_congestion_recovery = r.in_congestion_recovery(packet.time_sent);
+    let in_congestion_recovery = r.in_congestion_recovery(epoch, packet.pkt_num);
 
-    r.bytes_in_flight = r.bytes_in_flight.saturating_sub(packet.size);
+    r.sub_bytes_in_flight(packet.size);
 
     if in_congestion_recovery {
         r.prr.on_packet_acked(
@@ -347,15 +348,16 @@ fn on_packet_acked(
 }
 
 fn congestion_event(
-    r: &mut Recovery, _lost_bytes: usize, time_sent: Instant,
+    r: &mut Recovery, _lost_bytes: usize, largest_lost_pkt: u64,
     epoch: packet::Epoch, now: Instant,
 ) {
-    let in_congestion_recovery = r.in_congestion_recovery(time_sent);
+    let in_congestion_recovery =
+        r.in_congestion_recovery(epoch, largest_lost_pkt);
 
     // Start a new congestion event if packet was sent after the
     // start of the previous congestion recovery period.
     if !in_congestion_recovery {
-        r.congestion_recovery_start_time = Some(now);
+        r.start_recovery_episode(now);
 
         // Fast convergence
         if (r.congestion_window as f64) < r.cubic_state.w_max {
@@ -425,6 +427,10 @@ fn has_custom_pacing() -> bool {
     false
 }
 
+fn in_slow_start(r: &Recovery) -> bool {
+    r.congestion_window < r.ssthresh
+}
+
 fn debug_fmt(r: &Recovery, f: &mut std::fmt::Formatter) -> std::fmt::Result {
     write!(
         f,
@@ -482,7 +488,13 @@ mod tests {
             delivered_time: now,
             first_sent_time: now,
             is_app_limited: false,
-            has_data: false,
+            content: recovery::SentContent::default(),
+            is_pmtud_probe: false,
+            padding_bytes: 0,
+            datagram_id: None,
+            burst_index: 0,
+            burst_size: 0,
+            congestion_state: recovery::CongestionState::SlowStart,
         };
 
         // Send initcwnd full MSS packets to become no longer app limited
@@ -530,7 +542,13 @@ mod tests {
             delivered_time: now,
             first_sent_time: now,
             is_app_limited: false,
-            has_data: false,
+            content: recovery::SentContent::default(),
+            is_pmtud_probe: false,
+            padding_bytes: 0,
+            datagram_id: None,
+            burst_index: 0,
+            burst_size: 0,
+            congestion_state: recovery::CongestionState::SlowStart,
         };
 
         // Send initcwnd full MSS packets to become no longer app limited
@@ -590,7 +608,7 @@ mod tests {
 
         r.congestion_event(
             r.max_datagram_size,
-            now,
+            0,
             packet::EPOCH_APPLICATION,
             now,
         );
@@ -617,7 +635,7 @@ mod tests {
         // Trigger congestion event to update ssthresh
         r.congestion_event(
             r.max_datagram_size,
-            now,
+            0,
             packet::EPOCH_APPLICATION,
             now,
         );
@@ -672,7 +690,7 @@ mod tests {
         // Trigger congestion event to update ssthresh
         r.congestion_event(
             r.max_datagram_size,
-            now,
+            0,
             packet::EPOCH_APPLICATION,
             now,
         );
@@ -728,7 +746,13 @@ mod tests {
             delivered_time: now,
             first_sent_time: now,
             is_app_limited: false,
-            has_data: false,
+            content: recovery::SentContent::default(),
+            is_pmtud_probe: false,
+            padding_bytes: 0,
+            datagram_id: None,
+            burst_index: 0,
+            burst_size: 0,
+            congestion_state: recovery::CongestionState::SlowStart,
         };
 
         // 1st round.
@@ -876,7 +900,13 @@ mod tests {
             delivered_time: now,
             first_sent_time: now,
             is_app_limited: false,
-            has_data: false,
+            content: recovery::SentContent::default(),
+            is_pmtud_probe: false,
+            padding_bytes: 0,
+            datagram_id: None,
+            burst_index: 0,
+            burst_size: 0,
+            congestion_state: recovery::CongestionState::SlowStart,
         };
 
         // 1st round.
@@ -1011,7 +1041,7 @@ mod tests {
         // Trigger congestion event to update ssthresh
         r.congestion_event(
             r.max_datagram_size,
-            now,
+            0,
             packet::EPOCH_APPLICATION,
             now,
         );
@@ -1053,7 +1083,7 @@ mod tests {
         let prev_cwnd = r.cwnd();
         r.congestion_event(
             r.max_datagram_size,
-            now,
+            0,
             packet::EPOCH_APPLICATION,
             now,
         );
@@ -1107,7 +1137,7 @@ mod tests {
         // Trigger congestion event to update ssthresh
         r.congestion_event(
             r.max_datagram_size,
-            now,
+            0,
             packet::EPOCH_APPLICATION,
             now,
         );
@@ -1153,7 +1183,7 @@ mod tests {
         // further reduced.
         r.congestion_event(
             r.max_datagram_size,
-            now,
+            0,
             packet::EPOCH_APPLICATION,
             now,
         );
@@ -30,10 +30,10 @@
 //! <https://tools.ietf.org/html/draft-cheng-iccrg-delivery-rate-estimation-01>
 
 use std::time::Duration;
-use std::time::Instant;
 
 use crate::recovery::Acked;
 use crate::recovery::Sent;
+use crate::time::Instant;
 
 #[derive(Debug)]
 pub struct Rate {
@@ -56,10 +56,8 @@ pub struct Rate {
     rate_sample: RateSample,
 }
 
-impl Default for Rate {
-    fn default() -> Self {
-        let now = Instant::now();
-
+impl Rate {
+    pub fn new(now: Instant) -> Self {
         Rate {
             delivered: 0,
 
@@ -76,9 +74,7 @@ impl Default for Rate {
             rate_sample: RateSample::default(),
         }
     }
-}
 
-impl Rate {
     pub fn on_packet_sent(&mut self, pkt: &mut Sent, bytes_in_flight: usize) {
         // No packets in flight.
         if bytes_in_flight == 0 {
@@ -120,7 +116,9 @@ impl Rate {
         self.largest_acked = self.largest_acked.max(pkt.pkt_num);
     }
 
-    pub fn generate_rate_sample(&mut self, min_rtt: Duration) {
+    /// Fills in the rate sample, and returns whether a new delivery rate was
+    /// sampled.
+    pub fn generate_rate_sample(&mut self, min_rtt: Duration) -> bool {
         // End app-limited phase if bubble is ACKed and gone.
         if self.app_limited() && self.largest_acked > self.end_of_app_limited {
             self.update_app_limited(false);
@@ -140,7 +138,7 @@ impl Rate {
                 self.rate_sample.interval = Duration::ZERO;
 
                 // No reliable sample.
-                return;
+                return false;
             }
 
             if !interval.is_zero() {
@@ -148,8 +146,12 @@ impl Rate {
                 self.rate_sample.delivery_rate =
                     (self.rate_sample.delivered as f64 / interval.as_secs_f64())
                         as u64;
+
+                return true;
             }
         }
+
+        false
     }
 
     pub fn update_app_limited(&mut self, v: bool) {
@@ -227,7 +229,13 @@ mod tests {
                 delivered_time: now,
                 first_sent_time: now,
                 is_app_limited: false,
-                has_data: false,
+                content: SentContent::default(),
+                is_pmtud_probe: false,
+                padding_bytes: 0,
+                datagram_id: None,
+                burst_index: 0,
+                burst_size: 0,
+                congestion_state: CongestionState::SlowStart,
             };
 
             r.on_packet_sent(
@@ -235,7 +243,6 @@ mod tests {
                 packet::EPOCH_APPLICATION,
                 HandshakeStatus::default(),
                 now,
-                "",
             );
         }
 
@@ -291,7 +298,13 @@ mod tests {
                 delivered_time: now,
                 first_sent_time: now,
                 is_app_limited: false,
-                has_data: false,
+                content: SentContent::default(),
+                is_pmtud_probe: false,
+                padding_bytes: 0,
+                datagram_id: None,
+                burst_index: 0,
+                burst_size: 0,
+                congestion_state: CongestionState::SlowStart,
             };
 
             r.on_packet_sent(
@@ -299,7 +312,6 @@ mod tests {
                 packet::EPOCH_APPLICATION,
                 HandshakeStatus::default(),
                 now,
-                "",
             );
         }
 
@@ -330,7 +342,13 @@ mod tests {
                 delivered_time: now,
                 first_sent_time: now,
                 is_app_limited: false,
-                has_data: false,
+                content: SentContent::default(),
+                is_pmtud_probe: false,
+                padding_bytes: 0,
+                datagram_id: None,
+                burst_index: 0,
+                burst_size: 0,
+                congestion_state: CongestionState::SlowStart,
             };
 
             r.on_packet_sent(
@@ -338,7 +356,6 @@ mod tests {
                 packet::EPOCH_APPLICATION,
                 HandshakeStatus::default(),
                 now,
-                "",
             );
         }
 
@@ -355,7 +372,7 @@ mod tests {
                 packet::EPOCH_APPLICATION,
                 HandshakeStatus::default(),
                 now,
-                "",
+                None,
             ),
             Ok((0, 0)),
         );
@@ -0,0 +1,363 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Golden scenarios pinning the behavior of congestion control.
+//!
+//! Each scenario runs a fixed script of sent packets, ACKs, losses and PTOs
+//! through loss recovery with a manual clock, and records the congestion
+//! window, bytes in flight and slow start threshold after each step. The
+//! recorded trace is compared against the expectation checked in under
+//! `src/recovery/golden/`, one file per congestion control algorithm and
+//! scenario.
+//!
+//! When a change in behavior is intended, the expectations can be regenerated
+//! by running the tests with the `QUICHE_UPDATE_GOLDEN` environment variable
+//! set, and the resulting diff reviewed like any other change:
+//!
+//! ```sh
+//! QUICHE_UPDATE_GOLDEN=1 cargo test recovery::golden
+//! ```
+
+use std::fmt::Write;
+
+use std::ops::Range;
+
+use std::path::PathBuf;
+
+use std::time::Duration;
+
+use crate::rand;
+
+use crate::recovery::clock::Clock;
+use crate::recovery::clock::ManualClock;
+use crate::recovery::testing::AckScript;
+use crate::recovery::testing::PacketTrain;
+use crate::recovery::testing::PACKET_SIZE;
+use crate::recovery::CongestionControlAlgorithm;
+use crate::recovery::Recovery;
+use crate::recovery::RecoveryConfig;
+
+/// The environment variable requesting expectations to be regenerated.
+const UPDATE_ENV: &str = "QUICHE_UPDATE_GOLDEN";
+
+/// The seed of the RNG used by loss recovery, so that randomized behaviors
+/// are the same across runs.
+const RNG_SEED: u64 = 0;
+
+/// Runs `script` with the given congestion control algorithm, and returns the
+/// formatted trace of the loss recovery state after each step.
+fn trace(cc: CongestionControlAlgorithm, script: &AckScript) -> String {
+    let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+    config.set_cc_algorithm(cc);
+
+    let clock = ManualClock::new();
+    let start = clock.now();
+
+    let mut recovery_config = RecoveryConfig::from_config(&config);
+    recovery_config.clock = Clock::Manual(clock.clone());
+    recovery_config.rng = rand::Rng::with_seed(RNG_SEED);
+
+    let mut r = Recovery::new_with_config(&recovery_config);
+
+    let mut out = format!(
+        "# {:<7} {:>10} {:>8} {:>9} {:>8}\n",
+        "step", "time_us", "cwnd", "in_flight", "ssthresh"
+    );
+
+    script.run_with(&mut r, start, |step, r, now| {
+        // Waiting doesn't change the state, so it isn't recorded.
+        if step == "wait" {
+            return;
+        }
+
+        clock.advance_to(now);
+
+        let ssthresh = if r.ssthresh == usize::MAX {
+            "-".to_string()
+        } else {
+            r.ssthresh.to_string()
+        };
+
+        writeln!(
+            out,
+            "{:<9} {:>10} {:>8} {:>9} {:>8}",
+            step,
+            now.duration_since(start).as_micros(),
+            r.cwnd(),
+            r.bytes_in_flight,
+            ssthresh
+        )
+        .unwrap();
+    });
+
+    out
+}
+
+/// Compares the trace of `script` with the expectation for the given
+/// congestion control algorithm and scenario, or regenerates it.
+fn check(cc: CongestionControlAlgorithm, scenario: &str, script: AckScript) {
+    let name = format!("{}_{}", format!("{:?}", cc).to_lowercase(), scenario);
+
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("src/recovery/golden")
+        .join(format!("{}.txt", name));
+
+    let actual = trace(cc, &script);
+
+    if std::env::var_os(UPDATE_ENV).is_some() {
+        std::fs::write(&path, &actual).unwrap();
+        return;
+    }
+
+    let expected = std::fs::read_to_string(&path).unwrap_or_default();
+
+    if let Some(diff) = diff(&expected, &actual) {
+        panic!(
+            "golden scenario {} does not match {}:\n{}\n\
+             If the change is intended, rerun the test with {}=1 to \
+             regenerate the expectation.",
+            name,
+            path.display(),
+            diff,
+            UPDATE_ENV
+        );
+    }
+}
+
+/// Returns the lines that differ between `expected` and `actual`, along with
+/// their line numbers, or `None` if they are the same.
+fn diff(expected: &str, actual: &str) -> Option<String> {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+
+    let mut out = String::new();
+
+    for i in 0..std::cmp::max(expected.len(), actual.len()) {
+        let e = expected.get(i);
+        let a = actual.get(i);
+
+        if e == a {
+            continue;
+        }
+
+        writeln!(out, "line {}:", i + 1).unwrap();
+
+        if let Some(e) = e {
+            writeln!(out, "  - {}", e).unwrap();
+        }
+
+        if let Some(a) = a {
+            writeln!(out, "  + {}", a).unwrap();
+        }
+    }
+
+    if out.is_empty() {
+        None
+    } else {
+        Some(out)
+    }
+}
+
+/// Receives one ACK frame for every `chunk` packets of the given range, each
+/// `interval` apart.
+fn ack_paced(
+    script: AckScript, range: Range<u64>, chunk: u64, interval: Duration,
+) -> AckScript {
+    range
+        .clone()
+        .step_by(chunk as usize)
+        .fold(script, |script, start| {
+            let end = std::cmp::min(start + chunk, range.end);
+
+            script.ack(start..end).after(interval)
+        })
+}
+
+/// Flights of growing size over a 50ms path, acknowledged every other packet.
+///
+/// One packet of the third flight is declared lost by packet threshold, and a
+/// PTO fires on the fifth flight before it is acknowledged.
+pub(super) fn bulk_transfer() -> AckScript {
+    let rtt = Duration::from_millis(50);
+    let spacing = Duration::from_millis(1);
+
+    let flights = [10, 20, 30, 30, 30, 40];
+
+    let mut script = AckScript::new();
+    let mut pkt_num = 0;
+
+    for (i, &count) in flights.iter().enumerate() {
+        let train = PacketTrain::new(count, PACKET_SIZE)
+            .starting_at(pkt_num)
+            .spaced_by(spacing);
+
+        let pkts = train.pkt_nums();
+
+        script = script.send(train).after(rtt);
+
+        script = match i {
+            // The sixth packet is never acknowledged.
+            2 => {
+                let lost = pkts.start + 5;
+
+                let script = ack_paced(script, pkts.start..lost, 2, spacing);
+
+                ack_paced(script, lost + 1..pkts.end, 2, spacing)
+            },
+
+            // All ACKs are lost until the PTO fires.
+            4 => ack_paced(
+                script.loss_detection_timeout(),
+                pkts.clone(),
+                2,
+                spacing,
+            ),
+
+            _ => ack_paced(script, pkts.clone(), 2, spacing),
+        };
+
+        pkt_num = pkts.end;
+    }
+
+    script
+}
+
+/// Small flights over a 100ms path with delayed ACKs, each packet being
+/// acknowledged separately.
+///
+/// One packet of the third flight is declared lost by time threshold, and the
+/// tail of the sixth flight is only acknowledged after two consecutive PTOs.
+pub(super) fn small_flights() -> AckScript {
+    let rtt = Duration::from_millis(100);
+    let spacing = Duration::from_millis(5);
+
+    let mut script = AckScript::new().ack_delay(10_000);
+    let mut pkt_num = 0;
+
+    for i in 0..8 {
+        let train = PacketTrain::new(4, PACKET_SIZE)
+            .starting_at(pkt_num)
+            .spaced_by(spacing);
+
+        let pkts = train.pkt_nums();
+
+        script = script.send(train).after(rtt);
+
+        script = match i {
+            // The second packet is never acknowledged, and too few packets
+            // are sent after it for packet threshold to apply.
+            2 => {
+                let lost = pkts.start + 1;
+
+                let script = ack_paced(script, pkts.start..lost, 1, spacing);
+
+                ack_paced(script, lost + 1..pkts.end, 1, spacing)
+                    .loss_detection_timeout()
+            },
+
+            // The last two packets are acknowledged after two PTOs.
+            5 => {
+                let tail = pkts.start + 2;
+
+                let script = ack_paced(script, pkts.start..tail, 1, spacing)
+                    .loss_detection_timeout()
+                    .loss_detection_timeout();
+
+                ack_paced(script, tail..pkts.end, 1, spacing)
+            },
+
+            _ => ack_paced(script, pkts.clone(), 1, spacing),
+        };
+
+        pkt_num = pkts.end;
+    }
+
+    script
+}
+
+#[test]
+fn reno_bulk_transfer() {
+    check(
+        CongestionControlAlgorithm::Reno,
+        "bulk_transfer",
+        bulk_transfer(),
+    );
+}
+
+#[test]
+fn reno_small_flights() {
+    check(
+        CongestionControlAlgorithm::Reno,
+        "small_flights",
+        small_flights(),
+    );
+}
+
+#[test]
+fn cubic_bulk_transfer() {
+    check(
+        CongestionControlAlgorithm::CUBIC,
+        "bulk_transfer",
+        bulk_transfer(),
+    );
+}
+
+#[test]
+fn cubic_small_flights() {
+    check(
+        CongestionControlAlgorithm::CUBIC,
+        "small_flights",
+        small_flights(),
+    );
+}
+
+#[test]
+fn bbr_bulk_transfer() {
+    check(
+        CongestionControlAlgorithm::BBR,
+        "bulk_transfer",
+        bulk_transfer(),
+    );
+}
+
+#[test]
+fn bbr_small_flights() {
+    check(
+        CongestionControlAlgorithm::BBR,
+        "small_flights",
+        small_flights(),
+    );
+}
+
+#[test]
+fn diff_lines() {
+    assert_eq!(diff("a\nb\n", "a\nb\n"), None);
+
+    assert_eq!(
+        diff("a\nb\nc\n", "a\nx\n"),
+        Some("line 2:\n  - b\n  + x\nline 3:\n  - c\n".to_string())
+    );
+}
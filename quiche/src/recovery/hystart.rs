@@ -32,10 +32,10 @@
 
 use std::cmp;
 use std::time::Duration;
-use std::time::Instant;
 
 use crate::packet;
 use crate::recovery;
+use crate::time::Instant;
 
 /// Constants from I-D.
 const MIN_RTT_THRESH: Duration = Duration::from_millis(4);
@@ -0,0 +1,690 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+
+//! Lockstep differential testing of loss detection.
+//!
+//! [`Reference`] is a transcription of the loss detection pseudocode of
+//! RFC 9002, Appendix A, for the Application Data packet number space of a
+//! connection whose handshake is confirmed. Congestion control, ECN and the
+//! handshake packet number spaces are left out.
+//!
+//! [`run()`] feeds the same `AckScript` to a `Recovery` instance and to the
+//! reference, and compares the packets each of them declares lost and the
+//! deadline of the loss detection timer after every step, so that subtle
+//! deviations from the RFC, e.g. in how the packet and time thresholds
+//! interact, show up as soon as they happen.
+//!
+//! The peer is assumed to use the default `max_ack_delay` of 25ms.
+//!
+//! [`Reference`]: struct.Reference.html
+//! [`run()`]: fn.run.html
+
+use std::cmp;
+use std::fmt;
+
+use std::collections::BTreeMap;
+
+use std::time::Duration;
+
+use crate::packet;
+use crate::ranges;
+use crate::time::Instant;
+
+use crate::recovery::clock::Clock;
+use crate::recovery::clock::ManualClock;
+use crate::recovery::testing::AckResults;
+use crate::recovery::testing::AckScript;
+use crate::recovery::testing::Step;
+use crate::recovery::Recovery;
+use crate::recovery::RecoveryConfig;
+use crate::recovery::RecoveryEvent;
+
+use crate::Config;
+
+/// kPacketThreshold of RFC 9002.
+const PACKET_THRESHOLD: u64 = 3;
+
+/// kTimeThreshold of RFC 9002.
+const TIME_THRESHOLD: f64 = 9.0 / 8.0;
+
+/// kGranularity of RFC 9002.
+const GRANULARITY: Duration = Duration::from_millis(1);
+
+/// The `max_ack_delay` transport parameter of the peer.
+const PEER_MAX_ACK_DELAY: Duration = Duration::from_millis(25);
+
+#[derive(Clone, Copy, Debug)]
+struct SentPacket {
+    time_sent: Instant,
+
+    ack_eliciting: bool,
+
+    in_flight: bool,
+}
+
+/// The loss detection state of RFC 9002, Appendix A.
+///
+/// Methods follow the pseudocode of the RFC, and are named after it. Durations
+/// are scaled the same way as in `Recovery`, so that rounding errors don't show
+/// up as divergences.
+#[derive(Clone, Debug)]
+pub struct Reference {
+    // The packets that were neither acknowledged nor declared lost, by packet
+    // number.
+    sent_packets: BTreeMap<u64, SentPacket>,
+
+    largest_acked_packet: Option<u64>,
+
+    latest_rtt: Duration,
+
+    smoothed_rtt: Duration,
+
+    rttvar: Duration,
+
+    min_rtt: Duration,
+
+    first_rtt_sample: Option<Instant>,
+
+    time_of_last_ack_eliciting_packet: Option<Instant>,
+
+    loss_time: Option<Instant>,
+
+    pto_count: u32,
+
+    max_ack_delay: Duration,
+
+    loss_detection_timer: Option<Instant>,
+}
+
+impl Reference {
+    /// Creates the loss detection state of a new connection (A.4).
+    pub fn new(initial_rtt: Duration, max_ack_delay: Duration) -> Self {
+        Reference {
+            sent_packets: BTreeMap::new(),
+            largest_acked_packet: None,
+            latest_rtt: Duration::ZERO,
+            smoothed_rtt: initial_rtt,
+            rttvar: initial_rtt / 2,
+            min_rtt: Duration::ZERO,
+            first_rtt_sample: None,
+            time_of_last_ack_eliciting_packet: None,
+            loss_time: None,
+            pto_count: 0,
+            max_ack_delay,
+            loss_detection_timer: None,
+        }
+    }
+
+    /// Returns the deadline of the loss detection timer, if armed.
+    pub fn loss_detection_timer(&self) -> Option<Instant> {
+        self.loss_detection_timer
+    }
+
+    /// Records a sent packet (A.5).
+    pub fn on_packet_sent(
+        &mut self, pkt_num: u64, ack_eliciting: bool, in_flight: bool,
+        now: Instant,
+    ) {
+        self.sent_packets.insert(pkt_num, SentPacket {
+            time_sent: now,
+            ack_eliciting,
+            in_flight,
+        });
+
+        if in_flight {
+            if ack_eliciting {
+                self.time_of_last_ack_eliciting_packet = Some(now);
+            }
+
+            self.set_loss_detection_timer();
+        }
+    }
+
+    /// Processes an ACK frame (A.7), and returns the packets declared lost,
+    /// in packet number order.
+    pub fn on_ack_received(
+        &mut self, ranges: &ranges::RangeSet, ack_delay: Duration, now: Instant,
+    ) -> Vec<u64> {
+        let largest_acked = match ranges.last() {
+            Some(v) => v,
+
+            None => return Vec::new(),
+        };
+
+        self.largest_acked_packet = Some(match self.largest_acked_packet {
+            Some(v) => cmp::max(v, largest_acked),
+
+            None => largest_acked,
+        });
+
+        let newly_acked_packets = self.detect_and_remove_acked_packets(ranges);
+
+        let (largest_newly_acked, largest_newly_acked_packet) =
+            match newly_acked_packets.last() {
+                Some(v) => *v,
+
+                None => return Vec::new(),
+            };
+
+        let includes_ack_eliciting =
+            newly_acked_packets.iter().any(|(_, p)| p.ack_eliciting);
+
+        if largest_newly_acked == largest_acked && includes_ack_eliciting {
+            self.latest_rtt = now
+                .saturating_duration_since(largest_newly_acked_packet.time_sent);
+
+            self.update_rtt(ack_delay, now);
+        }
+
+        let lost_packets = self.detect_and_remove_lost_packets(now);
+
+        self.pto_count = 0;
+
+        self.set_loss_detection_timer();
+
+        lost_packets
+    }
+
+    /// Fires the loss detection timer (A.9), and returns the packets declared
+    /// lost, in packet number order.
+    pub fn on_loss_detection_timeout(&mut self, now: Instant) -> Vec<u64> {
+        if self.loss_time.is_some() {
+            let lost_packets = self.detect_and_remove_lost_packets(now);
+
+            self.set_loss_detection_timer();
+
+            return lost_packets;
+        }
+
+        // One or two ack-eliciting packets would be sent here.
+        self.pto_count += 1;
+
+        self.set_loss_detection_timer();
+
+        Vec::new()
+    }
+
+    fn detect_and_remove_acked_packets(
+        &mut self, ranges: &ranges::RangeSet,
+    ) -> Vec<(u64, SentPacket)> {
+        let mut newly_acked_packets = Vec::new();
+
+        for r in ranges.iter() {
+            let acked: Vec<u64> =
+                self.sent_packets.range(r).map(|(pn, _)| *pn).collect();
+
+            for pn in acked {
+                if let Some(p) = self.sent_packets.remove(&pn) {
+                    newly_acked_packets.push((pn, p));
+                }
+            }
+        }
+
+        newly_acked_packets.sort_by_key(|(pn, _)| *pn);
+
+        newly_acked_packets
+    }
+
+    fn update_rtt(&mut self, ack_delay: Duration, now: Instant) {
+        if self.first_rtt_sample.is_none() {
+            self.min_rtt = self.latest_rtt;
+            self.smoothed_rtt = self.latest_rtt;
+            self.rttvar = self.latest_rtt / 2;
+            self.first_rtt_sample = Some(now);
+            return;
+        }
+
+        self.min_rtt = cmp::min(self.min_rtt, self.latest_rtt);
+
+        let ack_delay = cmp::min(ack_delay, self.max_ack_delay);
+
+        let mut adjusted_rtt = self.latest_rtt;
+
+        if self.latest_rtt >= self.min_rtt + ack_delay {
+            adjusted_rtt = self.latest_rtt - ack_delay;
+        }
+
+        let rttvar_sample = if self.smoothed_rtt > adjusted_rtt {
+            self.smoothed_rtt - adjusted_rtt
+        } else {
+            adjusted_rtt - self.smoothed_rtt
+        };
+
+        self.rttvar =
+            self.rttvar.mul_f64(3.0 / 4.0) + rttvar_sample.mul_f64(1.0 / 4.0);
+
+        self.smoothed_rtt = self.smoothed_rtt.mul_f64(7.0 / 8.0) +
+            adjusted_rtt.mul_f64(1.0 / 8.0);
+    }
+
+    fn no_ack_eliciting_in_flight(&self) -> bool {
+        !self
+            .sent_packets
+            .values()
+            .any(|p| p.ack_eliciting && p.in_flight)
+    }
+
+    // GetPtoTimeAndSpace() (A.8).
+    fn pto_time(&self) -> Option<Instant> {
+        let backoff = 2_u32.pow(self.pto_count);
+
+        let duration = (self.smoothed_rtt +
+            cmp::max(self.rttvar * 4, GRANULARITY)) *
+            backoff +
+            self.max_ack_delay * backoff;
+
+        self.time_of_last_ack_eliciting_packet.map(|t| t + duration)
+    }
+
+    // SetLossDetectionTimer() (A.8).
+    fn set_loss_detection_timer(&mut self) {
+        if self.loss_time.is_some() {
+            self.loss_detection_timer = self.loss_time;
+            return;
+        }
+
+        if self.no_ack_eliciting_in_flight() {
+            self.loss_detection_timer = None;
+            return;
+        }
+
+        self.loss_detection_timer = self.pto_time();
+    }
+
+    // DetectAndRemoveLostPackets() (A.10).
+    fn detect_and_remove_lost_packets(&mut self, now: Instant) -> Vec<u64> {
+        let largest_acked_packet = match self.largest_acked_packet {
+            Some(v) => v,
+
+            None => return Vec::new(),
+        };
+
+        self.loss_time = None;
+
+        let loss_delay =
+            cmp::max(self.latest_rtt, self.smoothed_rtt).mul_f64(TIME_THRESHOLD);
+
+        let loss_delay = cmp::max(loss_delay, GRANULARITY);
+
+        let lost_send_time = now.checked_sub(loss_delay);
+
+        let mut lost_packets = Vec::new();
+
+        for (&pn, unacked) in self.sent_packets.range(..=largest_acked_packet) {
+            if lost_send_time.map_or(false, |t| unacked.time_sent <= t) ||
+                largest_acked_packet >= pn + PACKET_THRESHOLD
+            {
+                lost_packets.push(pn);
+            } else {
+                let loss_time = unacked.time_sent + loss_delay;
+
+                self.loss_time = Some(match self.loss_time {
+                    Some(t) => cmp::min(t, loss_time),
+
+                    None => loss_time,
+                });
+            }
+        }
+
+        for pn in &lost_packets {
+            self.sent_packets.remove(pn);
+        }
+
+        lost_packets
+    }
+}
+
+/// An outcome of a step that differs between `Recovery` and the reference.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Mismatch {
+    /// Different packets were declared lost.
+    Lost {
+        /// The packets declared lost by `Recovery`.
+        computed: Vec<u64>,
+
+        /// The packets declared lost by the reference.
+        reference: Vec<u64>,
+    },
+
+    /// The loss detection timer was armed differently. Deadlines are relative
+    /// to the start of the script.
+    LossDetectionTimer {
+        /// The deadline of the timer of `Recovery`.
+        computed: Option<Duration>,
+
+        /// The deadline of the timer of the reference.
+        reference: Option<Duration>,
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mismatch::Lost {
+                computed,
+                reference,
+            } => write!(
+                f,
+                "lost computed={:?} reference={:?}",
+                computed, reference
+            ),
+
+            Mismatch::LossDetectionTimer {
+                computed,
+                reference,
+            } => write!(
+                f,
+                "loss_detection_timer computed={:?} reference={:?}",
+                computed, reference
+            ),
+        }
+    }
+}
+
+/// A step of the script after which `Recovery` and the reference differ.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    /// The index of the step in the script.
+    pub step: usize,
+
+    /// The kind of step, e.g. "ack" or "timeout".
+    pub name: &'static str,
+
+    /// The time at the end of the step, relative to the start of the script.
+    pub time: Duration,
+
+    /// What differs.
+    pub mismatch: Mismatch,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.3}ms step {} ({}) {}",
+            self.time.as_secs_f64() * 1000.0,
+            self.step,
+            self.name,
+            self.mismatch
+        )
+    }
+}
+
+/// The outcome of running a script in lockstep.
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    /// The number of steps that were run.
+    pub steps: usize,
+
+    /// The number of packets declared lost by `Recovery`.
+    pub packets_lost: usize,
+
+    /// The steps after which `Recovery` and the reference differ, in order.
+    pub divergences: Vec<Divergence>,
+}
+
+impl Report {
+    /// Returns the first step after which `Recovery` and the reference
+    /// differ.
+    pub fn first_divergence(&self) -> Option<&Divergence> {
+        self.divergences.first()
+    }
+}
+
+/// Runs `script` against both `Recovery`, configured from `config`, and the
+/// reference, and reports where they differ.
+pub fn run(script: &AckScript, config: &Config) -> Report {
+    let clock = ManualClock::new();
+    let start = clock.now();
+
+    let mut recovery_config = RecoveryConfig::from_config(config);
+    recovery_config.max_ack_delay = PEER_MAX_ACK_DELAY;
+    recovery_config.observe_events = true;
+
+    // Packets are sent when the script says so, not when the pacer would
+    // release them.
+    recovery_config.pacing = false;
+    recovery_config.clock = Clock::Manual(clock.clone());
+
+    let mut r = Recovery::new_with_config(&recovery_config);
+
+    let mut reference = Reference::new(r.initial_rtt, r.max_ack_delay);
+
+    let ack_delay = Duration::from_micros(script.ack_delay);
+
+    let mut report = Report::default();
+
+    let mut res = AckResults {
+        lost: Vec::new(),
+        acked_frames: 0,
+        lost_frames: 0,
+        now: start,
+    };
+
+    let mut last_ack = None;
+
+    for (i, step) in script.steps.iter().enumerate() {
+        let step_start = res.now;
+
+        script.run_step(step, &mut r, &mut res, &mut last_ack);
+
+        clock.advance_to(res.now);
+
+        let reference_lost = match step {
+            Step::Wait(_) => Vec::new(),
+
+            Step::Send(train) => {
+                for p in train.packets(step_start) {
+                    reference.on_packet_sent(
+                        p.pkt_num,
+                        p.ack_eliciting,
+                        p.in_flight,
+                        p.time_sent,
+                    );
+                }
+
+                Vec::new()
+            },
+
+            Step::Ack(_) | Step::Duplicate => match last_ack {
+                Some(ranges) =>
+                    reference.on_ack_received(ranges, ack_delay, res.now),
+
+                None => Vec::new(),
+            },
+
+            Step::LossDetectionTimeout =>
+                match reference.loss_detection_timer() {
+                    Some(t) if t <= res.now =>
+                        reference.on_loss_detection_timeout(res.now),
+
+                    _ => Vec::new(),
+                },
+        };
+
+        let mut computed_lost: Vec<u64> = r
+            .drain_events()
+            .filter_map(|e| match e {
+                RecoveryEvent::PacketLost(lost, packet::EPOCH_APPLICATION) =>
+                    Some(lost.pkt_num),
+
+                _ => None,
+            })
+            .collect();
+        computed_lost.sort_unstable();
+
+        report.steps += 1;
+        report.packets_lost += computed_lost.len();
+
+        let mut diverge = |mismatch| {
+            report.divergences.push(Divergence {
+                step: i,
+                name: step.name(),
+                time: res.now.duration_since(start),
+                mismatch,
+            })
+        };
+
+        if computed_lost != reference_lost {
+            diverge(Mismatch::Lost {
+                computed: computed_lost,
+                reference: reference_lost,
+            });
+        }
+
+        let computed_timer = r.loss_detection_timer.deadline();
+        let reference_timer = reference.loss_detection_timer();
+
+        if computed_timer != reference_timer {
+            diverge(Mismatch::LossDetectionTimer {
+                computed: computed_timer.map(|t| t.duration_since(start)),
+                reference: reference_timer.map(|t| t.duration_since(start)),
+            });
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::recovery::golden;
+    use crate::recovery::testing::PacketTrain;
+    use crate::recovery::testing::PACKET_SIZE;
+
+    fn run_default(script: &AckScript) -> Report {
+        let config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+
+        run(script, &config)
+    }
+
+    fn assert_clean(report: &Report) {
+        if let Some(d) = report.first_divergence() {
+            panic!("loss detection diverges from RFC 9002: {}", d);
+        }
+    }
+
+    #[test]
+    fn packet_threshold() {
+        let script = AckScript::new()
+            .send(PacketTrain::new(10, PACKET_SIZE))
+            .after(Duration::from_millis(50))
+            .ack(3..10);
+
+        let report = run_default(&script);
+
+        assert_clean(&report);
+        assert_eq!(report.packets_lost, 3);
+    }
+
+    #[test]
+    fn time_threshold() {
+        // The first packet is lost once the second one is acknowledged, but
+        // the packet threshold isn't reached.
+        let script = AckScript::new()
+            .send(
+                PacketTrain::new(2, PACKET_SIZE)
+                    .spaced_by(Duration::from_millis(10)),
+            )
+            .after(Duration::from_millis(50))
+            .ack(1..2)
+            .loss_detection_timeout();
+
+        let report = run_default(&script);
+
+        assert_clean(&report);
+        assert_eq!(report.packets_lost, 1);
+    }
+
+    #[test]
+    fn reordering() {
+        // Packets are acknowledged out of order, but within the thresholds.
+        let script = AckScript::new()
+            .ack_delay(5_000)
+            .send(
+                PacketTrain::new(3, PACKET_SIZE)
+                    .spaced_by(Duration::from_millis(1)),
+            )
+            .after(Duration::from_millis(30))
+            .ack(2..3)
+            .after(Duration::from_millis(2))
+            .ack(0..2)
+            .duplicate();
+
+        let report = run_default(&script);
+
+        assert_clean(&report);
+        assert_eq!(report.packets_lost, 0);
+    }
+
+    #[test]
+    fn pto_backoff() {
+        let script = AckScript::new()
+            .send(PacketTrain::new(2, PACKET_SIZE))
+            .loss_detection_timeout()
+            .loss_detection_timeout()
+            .loss_detection_timeout()
+            .ack(0..2);
+
+        let report = run_default(&script);
+
+        assert_clean(&report);
+        assert_eq!(report.steps, 5);
+    }
+
+    #[test]
+    fn golden_scenarios() {
+        assert_clean(&run_default(&golden::bulk_transfer()));
+        assert_clean(&run_default(&golden::small_flights()));
+    }
+
+    #[test]
+    fn packet_threshold_divergence() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config.set_packet_reorder_threshold(5).unwrap();
+
+        let script = AckScript::new()
+            .send(PacketTrain::new(10, PACKET_SIZE))
+            .after(Duration::from_millis(50))
+            .ack(3..5);
+
+        let report = run(&script, &config);
+
+        let d = report.first_divergence().unwrap();
+
+        assert_eq!(d.step, 2);
+        assert_eq!(d.name, "ack");
+        assert_eq!(d.mismatch, Mismatch::Lost {
+            computed: vec![],
+            reference: vec![0, 1],
+        });
+        assert_eq!(
+            d.to_string(),
+            "50.000ms step 2 (ack) lost computed=[] reference=[0, 1]"
+        );
+    }
+}
@@ -26,34 +26,68 @@
 
 use std::cmp;
 
+use std::convert::TryFrom;
+
+use std::mem;
+
+use std::ops::Range;
+
+use std::num::NonZeroU32;
+
 use std::str::FromStr;
 
+use std::sync::Arc;
+
 use std::time::Duration;
-use std::time::Instant;
 
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::collections::VecDeque;
 
+use crate::BatchHint;
 use crate::Config;
+use crate::ConfigSnapshot;
 use crate::Result;
 
 use crate::frame;
 use crate::minmax;
 use crate::packet;
+use crate::rand;
 use crate::ranges;
+use crate::time::Instant;
 
 #[cfg(feature = "qlog")]
 use qlog::events::EventData;
 
+use self::clock::Clock;
+use self::timer::LossDetectionTimer;
+
+pub use self::observer::CwndChange;
+pub use self::observer::LostPacket;
+pub use self::observer::RecoveryEvent;
+pub use self::observer::RecoveryObserver;
+pub use self::stats::AckCost;
+pub use self::stats::BandwidthDelayEstimates;
+pub use self::stats::BurstLosses;
+pub use self::stats::DelayAsymmetry;
+pub use self::stats::LossLatency;
+pub use self::stats::LossRates;
+pub use self::stats::LostFrames;
+pub use self::stats::PhaseLosses;
+pub use self::stats::RecoveryStats;
+pub use self::stats::RecoveryStatsHandle;
+pub use self::stats::Rounds;
+
 // Loss Recovery
-const INITIAL_PACKET_THRESHOLD: u64 = 3;
+pub const INITIAL_PACKET_THRESHOLD: u64 = 3;
 
-const MAX_PACKET_THRESHOLD: u64 = 20;
+pub const MAX_PACKET_THRESHOLD: u64 = 20;
 
-const INITIAL_TIME_THRESHOLD: f64 = 9.0 / 8.0;
+pub const INITIAL_TIME_THRESHOLD: f64 = 9.0 / 8.0;
 
-const GRANULARITY: Duration = Duration::from_millis(1);
+pub const GRANULARITY: Duration = Duration::from_millis(1);
 
-const INITIAL_RTT: Duration = Duration::from_millis(333);
+pub const INITIAL_RTT: Duration = Duration::from_millis(333);
 
 const PERSISTENT_CONGESTION_THRESHOLD: u32 = 3;
 
@@ -61,29 +95,89 @@ const RTT_WINDOW: Duration = Duration::from_secs(300);
 
 const MAX_PTO_PROBES_COUNT: usize = 2;
 
+// The number of datagrams remembered as lost when coalesced packets are
+// declared lost together.
+const MAX_LOST_DATAGRAMS: usize = 16;
+
+// Packets sent less than this apart belong to the same burst.
+const BURST_INTERVAL: Duration = Duration::from_micros(100);
+
+// Path properties measured longer ago than this aren't used to seed new
+// connections.
+const MAX_PATH_PROPS_AGE: Duration = Duration::from_secs(600);
+
+// The minimum space left for ACK frames when retransmissions are pending.
+pub const MIN_ACK_BUDGET: usize = 64;
+
+// The default maximum number of probe packets pending across all epochs.
+pub const DEFAULT_MAX_LOSS_PROBES: usize = MAX_PTO_PROBES_COUNT;
+
+// The order in which epochs are given probe packets when the total number of
+// pending probes is capped.
+const LOSS_PROBES_PRIORITY: [packet::Epoch; packet::EPOCH_COUNT] = [
+    packet::EPOCH_HANDSHAKE,
+    packet::EPOCH_INITIAL,
+    packet::EPOCH_APPLICATION,
+];
+
 // Congestion Control
-const INITIAL_WINDOW_PACKETS: usize = 10;
+pub const INITIAL_WINDOW_PACKETS: usize = 10;
 
-const MINIMUM_WINDOW_PACKETS: usize = 2;
+pub const MINIMUM_WINDOW_PACKETS: usize = 2;
+
+// The largest congestion window, in packets, a connection can start with when
+// seeded from the properties of a path measured by another connection.
+const MAX_SEEDED_WINDOW_PACKETS: usize = 40;
 
 const LOSS_REDUCTION_FACTOR: f64 = 0.5;
 
 const PACING_MULTIPLIER: f64 = 1.25;
 
-// How many non ACK eliciting packets we send before including a PING to solicit
-// an ACK.
-const MAX_OUTSTANDING_NON_ACK_ELICITING: usize = 24;
+// The fraction of the RTT the congestion response to a loss is held back for,
+// when enabled.
+const LOSS_HOLD_RTT_FRACTION: f64 = 0.25;
+
+// The maximum number of segments of a single UDP GSO send on Linux.
+const MAX_GSO_SEGMENTS: usize = 64;
+
+// The default number of non ACK eliciting packets we send before including a
+// PING to solicit an ACK.
+pub const DEFAULT_MAX_OUTSTANDING_NON_ACK_ELICITING: usize = 24;
+
+// The minimum number of sent packets being tracked before compacting the sent
+// packets list.
+const SENT_COMPACT_MIN_LEN: usize = 1000;
+
+// The default minimum capacity, in number of elements, the loss recovery
+// buffers are shrunk to.
+pub const BUFFER_CAPACITY_FLOOR: usize = 16;
+
+// Buffers are considered under-utilized when less than 1 / BUFFER_SHRINK_RATIO
+// of their capacity is in use.
+const BUFFER_SHRINK_RATIO: usize = 4;
+
+// The number of consecutive maintenance ticks with under-utilized buffers
+// after which they are shrunk.
+const BUFFER_SHRINK_TICKS: u32 = 3;
 
 pub struct Recovery {
-    loss_detection_timer: Option<Instant>,
+    loss_detection_timer: LossDetectionTimer,
 
     pto_count: u32,
 
     time_of_last_sent_ack_eliciting_pkt: [Option<Instant>; packet::EPOCH_COUNT],
 
+    // Unlike `time_of_last_sent_ack_eliciting_pkt` this is not reset when a
+    // packet number space is discarded.
+    last_sent_ack_eliciting_time: Option<Instant>,
+
+    // The time the last in-flight packet was sent, used to detect idle
+    // periods.
+    last_sent_in_flight_time: Option<Instant>,
+
     largest_acked_pkt: [u64; packet::EPOCH_COUNT],
 
-    largest_sent_pkt: [u64; packet::EPOCH_COUNT],
+    largest_sent_pkt: [Option<u64>; packet::EPOCH_COUNT],
 
     latest_rtt: Duration,
 
@@ -101,16 +195,184 @@ pub struct Recovery {
 
     sent: [VecDeque<Sent>; packet::EPOCH_COUNT],
 
+    // Estimated memory used by the frames of the sent packets being tracked.
+    sent_frames_mem: [usize; packet::EPOCH_COUNT],
+
+    sent_compact_len: [usize; packet::EPOCH_COUNT],
+
+    // Buffers capacity decay.
+    buffer_capacity_floor: usize,
+
+    buffer_low_utilization_ticks: u32,
+
+    last_maintenance_tick: Option<Instant>,
+
     pub lost: [Vec<frame::Frame>; packet::EPOCH_COUNT],
 
+    // The packets whose frames were copied to the lost frames on PTO, and
+    // haven't been retransmitted yet.
+    pto_queued: [BTreeSet<u64>; packet::EPOCH_COUNT],
+
+    // Where the frames of the packets declared lost are in `lost`, by packet
+    // number, until they are drained for retransmission, so that the
+    // application can cancel their retransmission.
+    lost_queued: [BTreeMap<u64, Range<usize>>; packet::EPOCH_COUNT],
+
+    // The number of queued retransmissions dropped because the original
+    // packet was acked before they were sent.
+    pub suppressed_retrans_count: usize,
+
     pub acked: [Vec<frame::Frame>; packet::EPOCH_COUNT],
 
     pub lost_count: usize,
 
     pub lost_spurious_count: usize,
 
+    // How long after being sent packets were declared lost.
+    loss_latency: LossLatency,
+
+    // The work done processing ACK frames.
+    ack_cost: AckCost,
+
+    lost_frames: LostFrames,
+
+    delay_asymmetry: DelayAsymmetry,
+
+    // The first packet of the current round, once sent. The round ends when it,
+    // or a later packet, is acknowledged.
+    round_start_pkt_num: Option<u64>,
+
+    rounds: Rounds,
+
+    bandwidth_delay: BandwidthDelayEstimates,
+
+    // The burst the last packets of each epoch were sent in.
+    bursts: [Burst; packet::EPOCH_COUNT],
+
+    burst_losses: BurstLosses,
+
+    phase_losses: PhaseLosses,
+
+    // The packets sent and lost over the last seconds.
+    loss_rate_windows: stats::LossRateWindows,
+
+    // The lowest network round-trip time, i.e. without ACK delays, which the
+    // delay asymmetry estimate uses as symmetric baseline.
+    min_network_rtt: Option<Duration>,
+
     pub loss_probes: [usize; packet::EPOCH_COUNT],
 
+    // The maximum number of probe packets pending across all epochs.
+    max_loss_probes: usize,
+
+    // Whether PTO backs off when nothing retransmittable is in flight.
+    non_retransmittable_pto_backoff: bool,
+
+    // The factor applied to the PTO of the Initial and Handshake epochs.
+    handshake_pto_multiplier: f64,
+
+    // The number of PTOs that fired in the Initial and Handshake epochs.
+    pub handshake_pto_count: usize,
+
+    // The number of PTOs that fired in the Initial epoch.
+    pub initial_pto_count: usize,
+
+    // The time sending was paused at, if it is.
+    paused_at: Option<Instant>,
+
+    // Whether probes are sent when sending is resumed.
+    probe_on_resume: bool,
+
+    // Whether packets coalesced in the same datagram are lost together.
+    coalesced_loss: bool,
+
+    // Whether packets below more than `ack_implied_loss_thresh` contiguous
+    // acknowledged packets are lost after the timer granularity.
+    ack_implied_loss: bool,
+
+    ack_implied_loss_thresh: u64,
+
+    // The packets below this one are lost after the timer granularity, for
+    // the ACK frame being processed.
+    ack_implied_loss_below: Option<u64>,
+
+    // Whether the congestion response to a loss outside of a recovery episode
+    // is held back, in case the lost packets are acked shortly after.
+    loss_hold: bool,
+
+    // The congestion response being held back, if any.
+    held_loss: Option<LossHold>,
+
+    // Whether the probe timeout is brought forward to fire before the idle
+    // timeout, when it would fire after it.
+    idle_bounded_pto: bool,
+
+    // When the connection times out unless something is received, as set by
+    // the connection.
+    idle_deadline: Option<Instant>,
+
+    // The idle deadline the probe timeout was last found to exceed.
+    idle_conflict: Option<IdleConflict>,
+
+    // The number of idle deadlines the probe timeout was found to exceed.
+    idle_timeout_conflicts: u64,
+
+    // The datagrams of the packets recently declared lost by the packet
+    // threshold, most recent last.
+    lost_datagrams: VecDeque<NonZeroU32>,
+
+    // How the last ACK changed the congestion window.
+    ack_window: AckWindowUpdate,
+
+    // Whether ACKs of only non-ack-eliciting packets feed the delivery rate
+    // sampler.
+    ack_only_rate_samples: bool,
+
+    // The number of ACKs that newly acked packets, none of which were
+    // ack-eliciting.
+    pub acks_without_ack_eliciting: usize,
+
+    // The number of ACKs that newly acked packets without producing an RTT
+    // sample.
+    pub rtt_samples_skipped: usize,
+
+    // The number of times more bytes were removed from the flight than were
+    // in it.
+    pub bytes_in_flight_underflows: usize,
+
+    // The number of consecutive PTOs after which a black hole is suspected.
+    blackhole_pto_threshold: u32,
+
+    // Whether the datagram size is lowered to the minimum when a black hole
+    // is suspected.
+    blackhole_mtu_clamp: bool,
+
+    // Whether consecutive PTOs suggest that the path is a black hole.
+    blackhole_suspected: bool,
+
+    // The number of consecutive PTOs of the Initial and Handshake epochs
+    // after which the peer is deemed unreachable, if limited.
+    max_handshake_ptos: u32,
+
+    // Whether the peer didn't acknowledge anything before the maximum number
+    // of handshake PTOs, in which case no more probes are sent.
+    handshake_unreachable: bool,
+
+    // The number of PTOs without sending anything after which congestion
+    // control restarts, if enabled.
+    idle_restart_threshold: u32,
+
+    // The number of frames the acked and lost frames backlogs of each epoch
+    // can hold, if limited.
+    max_frame_backlog: usize,
+
+    // Whether PTOs send a fixed number of probes, without retransmitting the
+    // frames of packets in flight.
+    strict_rfc9002: bool,
+
+    // The number of times frames were left out of a full backlog.
+    pub frame_backlog_overflows: usize,
+
     in_flight_count: [usize; packet::EPOCH_COUNT],
 
     app_limited: bool,
@@ -121,6 +383,16 @@ pub struct Recovery {
 
     time_thresh: f64,
 
+    adaptive_reorder_threshold: bool,
+
+    initial_rtt: Duration,
+
+    initial_congestion_window_packets: usize,
+
+    max_pacing_rate: Option<u64>,
+
+    granularity: Duration,
+
     // Congestion control.
     cc_ops: &'static CongestionControlOps,
 
@@ -140,6 +412,9 @@ pub struct Recovery {
 
     congestion_recovery_start_time: Option<Instant>,
 
+    // The current congestion recovery episode, if any.
+    recovery_episode: Option<RecoveryEpisode>,
+
     max_datagram_size: usize,
 
     cubic_state: cubic::State,
@@ -156,6 +431,10 @@ pub struct Recovery {
     #[cfg(feature = "qlog")]
     qlog_metrics: QlogMetrics,
 
+    // The reordering thresholds last logged to qlog.
+    #[cfg(feature = "qlog")]
+    qlog_reorder_thresholds: (u64, f64),
+
     // The maximum size of a data aggregate scheduled and
     // transmitted together.
     send_quantum: usize,
@@ -163,8 +442,44 @@ pub struct Recovery {
     // BBR state.
     bbr_state: bbr::State,
 
-    /// How many non-ack-eliciting packets have been sent.
-    outstanding_non_ack_eliciting: usize,
+    /// How many non-ack-eliciting packets have been sent in a row, in each
+    /// packet number space.
+    outstanding_non_ack_eliciting: [usize; packet::EPOCH_COUNT],
+
+    // Whether the keys of each packet number space were dropped, after which
+    // nothing can be sent in it anymore.
+    discarded: [bool; packet::EPOCH_COUNT],
+
+    // How many non-ack-eliciting packets can be sent in a row before a PING
+    // is included to solicit an ACK.
+    max_outstanding_non_ack_eliciting: usize,
+
+    // DPLPMTUD state.
+    pmtud: pmtud::Pmtud,
+
+    // Statistics shared with other threads.
+    stats_publisher: stats::StatsPublisher,
+
+    // Events recorded for a `RecoveryObserver`, if any is registered.
+    events: Option<Vec<RecoveryEvent>>,
+
+    // The congestion window last reported to the observer.
+    observed_cwnd: usize,
+
+    // Whether packets were sent in flight since all data was last
+    // acknowledged.
+    busy: bool,
+
+    // Whether the connection is closing, and lost data should no longer be
+    // retransmitted.
+    draining: bool,
+
+    // The identity of the connection, which prefixes log messages.
+    trace_id: Arc<str>,
+
+    clock: Clock,
+
+    rng: rand::Rng,
 }
 
 pub struct RecoveryConfig {
@@ -173,49 +488,159 @@ pub struct RecoveryConfig {
     cc_ops: &'static CongestionControlOps,
     hystart: bool,
     pacing: bool,
+    timer_granularity: Duration,
+    adaptive_reorder_threshold: bool,
+    packet_reorder_threshold: u64,
+    time_reorder_threshold: f64,
+    initial_rtt: Duration,
+    initial_congestion_window_packets: usize,
+    max_pacing_rate: Option<u64>,
+    pmtu_discovery: bool,
+    buffer_capacity_floor: usize,
+    max_loss_probes: usize,
+    pub max_outstanding_non_ack_eliciting: usize,
+    non_retransmittable_pto_backoff: bool,
+    handshake_pto_multiplier: f64,
+    probe_on_resume: bool,
+    coalesced_loss: bool,
+    ack_implied_loss: bool,
+    loss_hold: bool,
+    idle_bounded_pto: bool,
+    ack_only_rate_samples: bool,
+    loss_timer_coalescing: Duration,
+    blackhole_pto_threshold: u32,
+    blackhole_mtu_clamp: bool,
+    max_handshake_ptos: u32,
+    idle_restart_threshold: u32,
+    max_frame_backlog: usize,
+    strict_rfc9002: bool,
+    pub observe_events: bool,
+    pub trace_id: Arc<str>,
+    clock: Clock,
+    pub rng: rand::Rng,
 }
 
 impl RecoveryConfig {
     pub fn from_config(config: &Config) -> Self {
-        Self {
-            max_send_udp_payload_size: config.max_send_udp_payload_size,
+        Self::from_snapshot(&config.snapshot())
+    }
+
+    pub fn from_snapshot(snapshot: &ConfigSnapshot) -> Self {
+        let mut config = Self {
+            max_send_udp_payload_size: snapshot.max_send_udp_payload_size,
             max_ack_delay: Duration::ZERO,
-            cc_ops: config.cc_algorithm.into(),
-            hystart: config.hystart,
-            pacing: config.pacing,
+            cc_ops: snapshot.cc_algorithm.into(),
+            hystart: snapshot.hystart,
+            pacing: snapshot.pacing,
+            timer_granularity: snapshot.timer_granularity,
+            adaptive_reorder_threshold: snapshot.adaptive_reorder_threshold,
+            packet_reorder_threshold: snapshot.packet_reorder_threshold,
+            time_reorder_threshold: snapshot.time_reorder_threshold,
+            initial_rtt: snapshot.initial_rtt,
+            initial_congestion_window_packets: snapshot
+                .initial_congestion_window_packets,
+            max_pacing_rate: snapshot.max_pacing_rate,
+            pmtu_discovery: snapshot.pmtu_discovery,
+            buffer_capacity_floor: snapshot.recovery_buffer_capacity_floor,
+            max_loss_probes: snapshot.max_loss_probes,
+            max_outstanding_non_ack_eliciting: snapshot
+                .max_outstanding_non_ack_eliciting,
+            non_retransmittable_pto_backoff: snapshot
+                .non_retransmittable_pto_backoff,
+            handshake_pto_multiplier: snapshot.handshake_pto_multiplier,
+            probe_on_resume: snapshot.path_resume_probe,
+            coalesced_loss: snapshot.coalesced_loss,
+            ack_implied_loss: snapshot.ack_implied_loss,
+            loss_hold: snapshot.loss_hold,
+            idle_bounded_pto: snapshot.idle_bounded_pto,
+            ack_only_rate_samples: snapshot.ack_only_rate_samples,
+            loss_timer_coalescing: snapshot.loss_timer_coalescing,
+            blackhole_pto_threshold: snapshot.blackhole_pto_threshold,
+            blackhole_mtu_clamp: snapshot.blackhole_mtu_clamp,
+            max_handshake_ptos: snapshot.max_handshake_ptos,
+            idle_restart_threshold: snapshot.idle_restart_threshold,
+            max_frame_backlog: snapshot.max_frame_backlog,
+            strict_rfc9002: snapshot.strict_rfc9002,
+            observe_events: false,
+            trace_id: Arc::from(""),
+            clock: Clock::System,
+            rng: rand::Rng::default(),
+        };
+
+        if config.strict_rfc9002 {
+            config.disable_heuristics();
         }
+
+        config
+    }
+
+    // Replaces the settings of quiche's own heuristics with the behavior of
+    // the RFC 9002 pseudocode. The content and number of PTO probes are
+    // handled by `Recovery::on_pto()` itself.
+    fn disable_heuristics(&mut self) {
+        self.cc_ops = CongestionControlAlgorithm::Reno.into();
+        self.hystart = false;
+        self.adaptive_reorder_threshold = false;
+        self.max_loss_probes = MAX_PTO_PROBES_COUNT;
+        self.non_retransmittable_pto_backoff = true;
+        self.handshake_pto_multiplier = 1.0;
+        self.coalesced_loss = false;
+        self.ack_implied_loss = false;
+        self.loss_hold = false;
+        self.idle_bounded_pto = false;
+        self.loss_timer_coalescing = Duration::ZERO;
+        self.blackhole_pto_threshold = 0;
+        self.idle_restart_threshold = 0;
     }
 }
 
 impl Recovery {
     pub fn new_with_config(recovery_config: &RecoveryConfig) -> Self {
+        let pmtud = pmtud::Pmtud::new(
+            recovery_config.pmtu_discovery,
+            recovery_config.max_send_udp_payload_size,
+        );
+
+        // When probing the path MTU, start from the base size and only use
+        // larger datagrams once confirmed.
+        let max_datagram_size = pmtud.pmtu();
+
         let initial_congestion_window =
-            recovery_config.max_send_udp_payload_size * INITIAL_WINDOW_PACKETS;
+            max_datagram_size * recovery_config.initial_congestion_window_packets;
+
+        let clock = recovery_config.clock.clone();
+        let now = clock.now();
 
         Recovery {
-            loss_detection_timer: None,
+            loss_detection_timer: LossDetectionTimer::new(
+                recovery_config.loss_timer_coalescing,
+            ),
 
             pto_count: 0,
 
             time_of_last_sent_ack_eliciting_pkt: [None; packet::EPOCH_COUNT],
 
+            last_sent_ack_eliciting_time: None,
+
+            last_sent_in_flight_time: None,
+
             largest_acked_pkt: [std::u64::MAX; packet::EPOCH_COUNT],
 
-            largest_sent_pkt: [0; packet::EPOCH_COUNT],
+            largest_sent_pkt: [None; packet::EPOCH_COUNT],
 
             latest_rtt: Duration::ZERO,
 
-            // This field should be initialized to `INITIAL_RTT` for the initial
-            // PTO calculation, but it also needs to be an `Option` to track
-            // whether any RTT sample was received, so the initial value is
-            // handled by the `rtt()` method instead.
+            // This field should be initialized to the initial RTT for the
+            // initial PTO calculation, but it also needs to be an `Option` to
+            // track whether any RTT sample was received, so the initial value
+            // is handled by the `rtt()` method instead.
             smoothed_rtt: None,
 
-            minmax_filter: minmax::Minmax::new(Duration::ZERO),
+            minmax_filter: minmax::Minmax::new(Duration::ZERO, now),
 
             min_rtt: Duration::ZERO,
 
-            rttvar: INITIAL_RTT / 2,
+            rttvar: recovery_config.initial_rtt / 2,
 
             max_ack_delay: recovery_config.max_ack_delay,
 
@@ -223,22 +648,139 @@ impl Recovery {
 
             sent: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
 
+            sent_frames_mem: [0; packet::EPOCH_COUNT],
+
+            sent_compact_len: [SENT_COMPACT_MIN_LEN; packet::EPOCH_COUNT],
+
+            buffer_capacity_floor: recovery_config.buffer_capacity_floor,
+
+            buffer_low_utilization_ticks: 0,
+
+            last_maintenance_tick: None,
+
             lost: [Vec::new(), Vec::new(), Vec::new()],
 
+            pto_queued: [BTreeSet::new(), BTreeSet::new(), BTreeSet::new()],
+
+            lost_queued: [BTreeMap::new(), BTreeMap::new(), BTreeMap::new()],
+
+            suppressed_retrans_count: 0,
+
             acked: [Vec::new(), Vec::new(), Vec::new()],
 
             lost_count: 0,
             lost_spurious_count: 0,
 
+            loss_latency: LossLatency::default(),
+
+            ack_cost: AckCost::default(),
+
+            lost_frames: LostFrames::default(),
+
+            delay_asymmetry: DelayAsymmetry::default(),
+
+            round_start_pkt_num: None,
+
+            rounds: Rounds::default(),
+
+            bandwidth_delay: BandwidthDelayEstimates::default(),
+
+            bursts: [Burst::default(); packet::EPOCH_COUNT],
+
+            burst_losses: BurstLosses::default(),
+
+            phase_losses: PhaseLosses::default(),
+
+            loss_rate_windows: stats::LossRateWindows::new(now),
+
+            min_network_rtt: None,
+
             loss_probes: [0; packet::EPOCH_COUNT],
 
+            max_loss_probes: recovery_config.max_loss_probes,
+
+            non_retransmittable_pto_backoff: recovery_config
+                .non_retransmittable_pto_backoff,
+
+            handshake_pto_multiplier: recovery_config.handshake_pto_multiplier,
+
+            handshake_pto_count: 0,
+
+            initial_pto_count: 0,
+
+            paused_at: None,
+
+            probe_on_resume: recovery_config.probe_on_resume,
+
+            coalesced_loss: recovery_config.coalesced_loss,
+
+            ack_implied_loss: recovery_config.ack_implied_loss,
+
+            ack_implied_loss_thresh: INITIAL_PACKET_THRESHOLD,
+
+            ack_implied_loss_below: None,
+
+            loss_hold: recovery_config.loss_hold,
+
+            held_loss: None,
+
+            idle_bounded_pto: recovery_config.idle_bounded_pto,
+
+            idle_deadline: None,
+
+            idle_conflict: None,
+
+            idle_timeout_conflicts: 0,
+
+            lost_datagrams: VecDeque::new(),
+
+            ack_window: AckWindowUpdate::default(),
+
+            ack_only_rate_samples: recovery_config.ack_only_rate_samples,
+
+            acks_without_ack_eliciting: 0,
+
+            rtt_samples_skipped: 0,
+
+            bytes_in_flight_underflows: 0,
+
+            blackhole_pto_threshold: recovery_config.blackhole_pto_threshold,
+
+            blackhole_mtu_clamp: recovery_config.blackhole_mtu_clamp,
+
+            blackhole_suspected: false,
+
+            max_handshake_ptos: recovery_config.max_handshake_ptos,
+
+            handshake_unreachable: false,
+
+            idle_restart_threshold: recovery_config.idle_restart_threshold,
+
+            max_frame_backlog: recovery_config.max_frame_backlog,
+
+            strict_rfc9002: recovery_config.strict_rfc9002,
+
+            frame_backlog_overflows: 0,
+
             in_flight_count: [0; packet::EPOCH_COUNT],
 
             congestion_window: initial_congestion_window,
 
-            pkt_thresh: INITIAL_PACKET_THRESHOLD,
+            pkt_thresh: recovery_config.packet_reorder_threshold,
+
+            time_thresh: recovery_config.time_reorder_threshold,
+
+            adaptive_reorder_threshold: recovery_config
+                .adaptive_reorder_threshold,
 
-            time_thresh: INITIAL_TIME_THRESHOLD,
+            initial_rtt: recovery_config.initial_rtt,
+
+            initial_congestion_window_packets: recovery_config
+                .initial_congestion_window_packets,
+
+            max_pacing_rate: recovery_config.max_pacing_rate,
+
+            granularity: recovery_config.timer_granularity,
 
             bytes_in_flight: 0,
 
@@ -254,11 +796,13 @@ impl Recovery {
 
             congestion_recovery_start_time: None,
 
-            max_datagram_size: recovery_config.max_send_udp_payload_size,
+            recovery_episode: None,
+
+            max_datagram_size,
 
             cc_ops: recovery_config.cc_ops,
 
-            delivery_rate: delivery_rate::Rate::default(),
+            delivery_rate: delivery_rate::Rate::new(now),
 
             cubic_state: cubic::State::default(),
 
@@ -270,7 +814,8 @@ impl Recovery {
                 recovery_config.pacing,
                 initial_congestion_window,
                 0,
-                recovery_config.max_send_udp_payload_size,
+                max_datagram_size,
+                now,
             ),
 
             prr: prr::PRR::default(),
@@ -280,9 +825,42 @@ impl Recovery {
             #[cfg(feature = "qlog")]
             qlog_metrics: QlogMetrics::default(),
 
-            bbr_state: bbr::State::new(),
+            #[cfg(feature = "qlog")]
+            qlog_reorder_thresholds: (
+                recovery_config.packet_reorder_threshold,
+                recovery_config.time_reorder_threshold,
+            ),
+
+            bbr_state: bbr::State::new(now),
+
+            outstanding_non_ack_eliciting: [0; packet::EPOCH_COUNT],
+
+            discarded: [false; packet::EPOCH_COUNT],
+
+            max_outstanding_non_ack_eliciting: recovery_config
+                .max_outstanding_non_ack_eliciting,
+
+            pmtud,
+
+            stats_publisher: stats::StatsPublisher::default(),
+
+            events: if recovery_config.observe_events {
+                Some(Vec::new())
+            } else {
+                None
+            },
+
+            observed_cwnd: initial_congestion_window,
+
+            busy: false,
 
-            outstanding_non_ack_eliciting: 0,
+            draining: false,
+
+            trace_id: recovery_config.trace_id.clone(),
+
+            clock,
+
+            rng: recovery_config.rng.clone(),
         }
     }
 
@@ -290,53 +868,247 @@ impl Recovery {
         Self::new_with_config(&RecoveryConfig::from_config(config))
     }
 
+    /// Replaces the clock used to read the current time.
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+    }
+
+    /// Replaces the source of randomness used by congestion control.
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn set_rng(&mut self, rng: rand::Rng) {
+        self.rng = rng;
+    }
+
     pub fn on_init(&mut self) {
         (self.cc_ops.on_init)(self);
     }
 
     pub fn reset(&mut self) {
-        self.congestion_window = self.max_datagram_size * INITIAL_WINDOW_PACKETS;
+        if self.pmtud.enabled() {
+            self.pmtud.reset();
+            self.max_datagram_size = self.pmtud.pmtu();
+        }
+
+        self.congestion_window =
+            self.max_datagram_size * self.initial_congestion_window_packets;
         self.in_flight_count = [0; packet::EPOCH_COUNT];
         self.congestion_recovery_start_time = None;
+        self.recovery_episode = None;
+        self.held_loss = None;
+        self.idle_conflict = None;
         self.ssthresh = std::usize::MAX;
         (self.cc_ops.reset)(self);
         self.hystart.reset();
         self.prr = prr::PRR::default();
     }
 
+    /// Returns the properties of the path measured so far, to seed later
+    /// connections to the same peer, or `None` without any RTT sample.
+    pub fn export_path_properties(&self, now: Instant) -> Option<PathProps> {
+        let rtt = self.smoothed_rtt?;
+
+        Some(PathProps {
+            rtt,
+            rttvar: self.rttvar,
+            cwnd: self.congestion_window,
+            measured_at: now,
+        })
+    }
+
+    /// Starts from the properties of the same path measured by another
+    /// connection, instead of the initial RTT and congestion window, and
+    /// returns whether they were used.
+    ///
+    /// Properties are ignored when they are stale, or once an RTT sample was
+    /// taken. Only half of the measured congestion window is used, capped to
+    /// `MAX_SEEDED_WINDOW_PACKETS`, and never less than the initial one.
+    pub fn seed_from_path_properties(
+        &mut self, props: &PathProps, now: Instant,
+    ) -> bool {
+        if self.smoothed_rtt.is_some() ||
+            now.saturating_duration_since(props.measured_at) >
+                MAX_PATH_PROPS_AGE
+        {
+            return false;
+        }
+
+        // The RTT is used in place of the initial RTT, so that the first
+        // sample replaces it.
+        self.initial_rtt = props.rtt;
+        self.rttvar = props.rttvar;
+
+        let max_cwnd = self.max_datagram_size * MAX_SEEDED_WINDOW_PACKETS;
+
+        self.congestion_window =
+            cmp::max(self.congestion_window, cmp::min(props.cwnd / 2, max_cwnd));
+
+        self.on_init();
+
+        true
+    }
+
+    /// Decides what the next packet sent in the given packet number space
+    /// needs, all at once.
+    ///
+    /// If probes are pending in the packet number space, one is reserved for
+    /// the packet. The plan must then be passed to `commit_plan()` once the
+    /// packet is sent, or to `abandon_plan()` if it couldn't be built, so that
+    /// the probe is sent with another packet instead.
+    pub fn plan_packet(
+        &mut self, epoch: packet::Epoch, now: Instant,
+        amplification_credit: Option<usize>,
+    ) -> PacketPlan {
+        let bytes = self.send_allowance(now, amplification_credit).bytes;
+
+        let probe = self.loss_probes[epoch] > 0;
+
+        if probe {
+            self.loss_probes[epoch] -= 1;
+        }
+
+        let solicit_ack = self.should_solicit_ack(epoch);
+
+        PacketPlan {
+            epoch,
+
+            ack_eliciting: probe || solicit_ack,
+
+            solicit_ack,
+
+            probe,
+
+            bytes,
+
+            round_budget: self.remaining_round_budget(),
+
+            lost_frames: !self.lost[epoch].is_empty(),
+        }
+    }
+
+    /// Confirms that the packet planned with `plan_packet()` was sent.
+    pub fn commit_plan(&mut self, _plan: PacketPlan) {}
+
+    /// Gives back the probe reserved by `plan_packet()`, if any, when the
+    /// packet wasn't sent or wasn't ack-eliciting.
+    pub fn abandon_plan(&mut self, plan: PacketPlan) {
+        if plan.probe {
+            self.loss_probes[plan.epoch] += 1;
+        }
+    }
+
     /// Returns whether or not we should elicit an ACK even if we wouldn't
     /// otherwise have constructed an ACK eliciting packet.
     pub fn should_elicit_ack(&self, epoch: packet::Epoch) -> bool {
-        self.loss_probes[epoch] > 0 ||
-            self.outstanding_non_ack_eliciting >=
-                MAX_OUTSTANDING_NON_ACK_ELICITING
+        if self.discarded[epoch] {
+            return false;
+        }
+
+        self.loss_probes[epoch] > 0 || self.should_solicit_ack(epoch)
+    }
+
+    /// Returns whether too many non-ack-eliciting packets were sent in a row,
+    /// so that a PING should be included to solicit an ACK.
+    pub fn should_solicit_ack(&self, epoch: packet::Epoch) -> bool {
+        self.outstanding_non_ack_eliciting[epoch] >=
+            self.max_outstanding_non_ack_eliciting
+    }
+
+    /// Sets how many non-ack-eliciting packets can be sent in a row before a
+    /// PING is included to solicit an ACK.
+    pub fn set_max_outstanding_non_ack_eliciting(&mut self, v: usize) {
+        self.max_outstanding_non_ack_eliciting = v;
     }
 
     pub fn on_packet_sent(
-        &mut self, mut pkt: Sent, epoch: packet::Epoch,
-        handshake_status: HandshakeStatus, now: Instant, trace_id: &str,
+        &mut self, pkt: Sent, epoch: packet::Epoch,
+        handshake_status: HandshakeStatus, now: Instant,
+    ) {
+        self.on_packets_sent(std::iter::once(pkt), epoch, handshake_status, now);
+    }
+
+    /// Records a burst of packets sent at the same time, e.g. as segments of
+    /// a single GSO send.
+    ///
+    /// This is equivalent to calling `on_packet_sent()` for each packet, but
+    /// the loss detection timer is only updated once for the whole burst.
+    ///
+    /// Packets sent in a packet number space that was discarded are a bug,
+    /// and are ignored.
+    pub fn on_packets_sent(
+        &mut self, pkts: impl IntoIterator<Item = Sent>, epoch: packet::Epoch,
+        handshake_status: HandshakeStatus, now: Instant,
+    ) {
+        debug_assert!(
+            !self.discarded[epoch],
+            "packet sent in discarded epoch {}",
+            epoch
+        );
+
+        if self.discarded[epoch] {
+            return;
+        }
+
+        let pkts = pkts.into_iter();
+
+        self.sent[epoch].reserve(pkts.size_hint().0);
+
+        let mut in_flight = false;
+
+        for pkt in pkts {
+            in_flight |= pkt.in_flight;
+
+            self.record_sent_packet(pkt, epoch, now);
+        }
+
+        if in_flight {
+            self.set_loss_detection_timer(handshake_status, now);
+        }
+
+        self.publish_stats();
+
+        trace!("{} {:?}", self.trace_id, self.debug_at(now));
+    }
+
+    fn record_sent_packet(
+        &mut self, mut pkt: Sent, epoch: packet::Epoch, now: Instant,
     ) {
         let ack_eliciting = pkt.ack_eliciting;
         let in_flight = pkt.in_flight;
-        let sent_bytes = pkt.size;
+        let pkt_size = pkt.size;
+        let sent_bytes = pkt.cc_size();
         let pkt_num = pkt.pkt_num;
+        let prior_bytes_in_flight = self.bytes_in_flight;
+
+        if pkt.is_pmtud_probe {
+            self.pmtud.on_probe_sent();
+        }
 
+        // Only an ACK of a packet in the same packet number space can tell
+        // that the ACK frames sent in it were received.
         if ack_eliciting {
-            self.outstanding_non_ack_eliciting = 0;
+            self.outstanding_non_ack_eliciting[epoch] = 0;
         } else {
-            self.outstanding_non_ack_eliciting += 1;
+            self.outstanding_non_ack_eliciting[epoch] += 1;
         }
 
         self.largest_sent_pkt[epoch] =
-            cmp::max(self.largest_sent_pkt[epoch], pkt_num);
+            cmp::max(self.largest_sent_pkt[epoch], Some(pkt_num));
 
         if in_flight {
+            self.maybe_restart_after_idle(now);
+
+            self.last_sent_in_flight_time = Some(now);
+
             if ack_eliciting {
                 self.time_of_last_sent_ack_eliciting_pkt[epoch] = Some(now);
+                self.last_sent_ack_eliciting_time = Some(now);
             }
 
             self.in_flight_count[epoch] += 1;
 
+            self.busy = true;
+
             self.update_app_limited(
                 (self.bytes_in_flight + sent_bytes) < self.congestion_window,
             );
@@ -344,16 +1116,27 @@ impl Recovery {
             self.on_packet_sent_cc(sent_bytes, now);
 
             self.prr.on_packet_sent(sent_bytes);
+        }
 
-            self.set_loss_detection_timer(handshake_status, now);
-        }
+        // Losses are attributed to the phase the packet was sent in, as it may
+        // have changed by the time they are detected.
+        pkt.congestion_state = self.congestion_state();
+
+        // Packet numbers of different packet number spaces can't be compared,
+        // so rounds only account for application data.
+        if epoch == packet::EPOCH_APPLICATION {
+            if self.round_start_pkt_num.is_none() {
+                self.round_start_pkt_num = Some(pkt_num);
+
+                // HyStart++: Start of the round in a slow start.
+                if self.hystart.enabled() &&
+                    self.congestion_window < self.ssthresh
+                {
+                    self.hystart.start_round(pkt_num);
+                }
+            }
 
-        // HyStart++: Start of the round in a slow start.
-        if self.hystart.enabled() &&
-            epoch == packet::EPOCH_APPLICATION &&
-            self.congestion_window < self.ssthresh
-        {
-            self.hystart.start_round(pkt_num);
+            self.rounds.record_sent(pkt_size);
         }
 
         // Pacing: Set the pacing rate if CC doesn't do its own.
@@ -365,18 +1148,255 @@ impl Recovery {
             }
         }
 
-        self.schedule_next_packet(epoch, now, sent_bytes);
+        self.schedule_next_packet(epoch, now, pkt_size);
 
-        pkt.time_sent = self.get_packet_send_time();
+        // Without pacing, packets are sent right away, and the pacer's release
+        // time, which isn't kept up to date, would make them look older.
+        pkt.time_sent = if self.pacer.enabled() {
+            self.get_packet_send_time()
+        } else {
+            now
+        };
 
-        // bytes_in_flight is already updated. Use previous value.
+        // bytes_in_flight might have been updated already, so use the
+        // previous value.
         self.delivery_rate
-            .on_packet_sent(&mut pkt, self.bytes_in_flight - sent_bytes);
+            .on_packet_sent(&mut pkt, prior_bytes_in_flight);
+
+        self.bytes_sent += pkt_size;
+
+        // Packets that are neither ack-eliciting nor in flight only carry ACK
+        // frames, which are never retransmitted, and don't count towards
+        // congestion control, so they are not tracked. The peer doesn't
+        // acknowledge them on their own, so on connections that mostly receive
+        // they would otherwise pile up until a later ACK covered them, and be
+        // declared lost in the meantime.
+        //
+        // This means that ACK frames are only known to be received once they
+        // are sent in an ack-eliciting packet, which happens at least every
+        // `max_outstanding_non_ack_eliciting` packets.
+        if !ack_eliciting && !in_flight {
+            return;
+        }
+
+        self.loss_rate_windows.on_packet_sent(now);
+
+        self.record_burst(&mut pkt, epoch);
+
+        self.sent_frames_mem[epoch] += pkt.frames_mem();
 
         self.sent[epoch].push_back(pkt);
+    }
+
+    // Tags the packet with its position in the current burst of the epoch,
+    // starting a new burst if the packet was sent too long after the previous
+    // one. The packets of the previous burst are then tagged with its size.
+    fn record_burst(&mut self, pkt: &mut Sent, epoch: packet::Epoch) {
+        let burst = &mut self.bursts[epoch];
+
+        let continued = burst.last_sent.map_or(false, |t| {
+            pkt.time_sent.saturating_duration_since(t) < BURST_INTERVAL
+        });
+
+        if !continued {
+            if burst.len > 0 {
+                let size = u8::try_from(burst.len).unwrap_or(u8::MAX);
+
+                self.sent[epoch]
+                    .iter_mut()
+                    .rev()
+                    .take_while(|p| p.pkt_num >= burst.start)
+                    .for_each(|p| p.burst_size = size);
+            }
+
+            *burst = Burst {
+                start: pkt.pkt_num,
+                len: 0,
+                last_sent: None,
+            };
+        }
+
+        pkt.burst_index = u8::try_from(burst.len).unwrap_or(u8::MAX);
+
+        burst.len += 1;
+        burst.last_sent = Some(pkt.time_sent);
+    }
+
+    /// Stops tracking a packet that turned out not to be sent, e.g. because
+    /// the socket failed, as if it was never sent.
+    ///
+    /// Returns the frames of the packet, so they can be retransmitted right
+    /// away, or `None` if the packet isn't tracked, or was already acked or
+    /// declared lost.
+    pub fn on_packet_discarded(
+        &mut self, epoch: packet::Epoch, pkt_num: u64,
+        handshake_status: HandshakeStatus, now: Instant,
+    ) -> Option<Vec<frame::Frame>> {
+        let index = self.sent_index(epoch, pkt_num);
+
+        match self.sent[epoch].get(index) {
+            Some(p)
+                if p.pkt_num == pkt_num &&
+                    p.time_acked.is_none() &&
+                    p.time_lost.is_none() =>
+                (),
+
+            _ => return None,
+        }
+
+        let pkt = self.sent[epoch].remove(index)?;
+
+        self.sent_frames_mem[epoch] =
+            self.sent_frames_mem[epoch].saturating_sub(pkt.frames_mem());
+
+        self.bytes_sent = self.bytes_sent.saturating_sub(pkt.size);
+
+        if pkt.in_flight {
+            self.sub_bytes_in_flight(pkt.cc_size());
+
+            self.in_flight_count[epoch] =
+                self.in_flight_count[epoch].saturating_sub(1);
+
+            // The probe timeout is armed from the previous ack-eliciting
+            // packet instead, if any.
+            self.time_of_last_sent_ack_eliciting_pkt[epoch] = self.sent[epoch]
+                .iter()
+                .rev()
+                .find(|p| p.in_flight && p.ack_eliciting)
+                .map(|p| p.time_sent);
+
+            self.set_loss_detection_timer(handshake_status, now);
+        }
+
+        self.publish_stats();
+
+        #[cfg(debug_assertions)]
+        self.assert_consistent();
+
+        Some(pkt.frames)
+    }
+
+    /// Replaces the time a packet was recorded as sent at with the time it
+    /// actually left, e.g. as reported by hardware transmit timestamps.
+    ///
+    /// RTT samples, delivery rate samples and the probe timeout of a packet
+    /// still awaiting acknowledgement are then based on the corrected time.
+    /// If the packet was already acknowledged, its RTT sample can't be
+    /// updated anymore, and the sample it would have given is returned
+    /// instead. Returns `None` if the packet isn't tracked.
+    pub fn correct_sent_time(
+        &mut self, epoch: packet::Epoch, pkt_num: u64, actual_time: Instant,
+        handshake_status: HandshakeStatus, now: Instant,
+    ) -> Option<SentTimeCorrection> {
+        let index = self.sent_index(epoch, pkt_num);
+
+        let pkt = match self.sent[epoch].get_mut(index) {
+            Some(p) if p.pkt_num == pkt_num => p,
+
+            _ => return None,
+        };
+
+        if let Some(time_acked) = pkt.time_acked {
+            return Some(SentTimeCorrection::Late {
+                rtt: time_acked.saturating_duration_since(actual_time),
+            });
+        }
+
+        let recorded_time = mem::replace(&mut pkt.time_sent, actual_time);
+
+        // The delivery rate sampler starts a new interval from packets sent
+        // while nothing was in flight.
+        if pkt.first_sent_time == recorded_time {
+            pkt.first_sent_time = actual_time;
+        }
+
+        if pkt.delivered_time == recorded_time {
+            pkt.delivered_time = actual_time;
+        }
+
+        if pkt.in_flight && self.last_sent_in_flight_time == Some(recorded_time) {
+            self.last_sent_in_flight_time = Some(actual_time);
+        }
+
+        let latest_ack_eliciting = self.sent[epoch]
+            .iter()
+            .rev()
+            .find(|p| p.in_flight && p.ack_eliciting)
+            .map(|p| p.pkt_num);
+
+        // The probe timeout is armed from the latest ack-eliciting packet.
+        if latest_ack_eliciting == Some(pkt_num) &&
+            self.time_of_last_sent_ack_eliciting_pkt[epoch].is_some()
+        {
+            if self.last_sent_ack_eliciting_time ==
+                self.time_of_last_sent_ack_eliciting_pkt[epoch]
+            {
+                self.last_sent_ack_eliciting_time = Some(actual_time);
+            }
+
+            self.time_of_last_sent_ack_eliciting_pkt[epoch] = Some(actual_time);
+
+            self.set_loss_detection_timer(handshake_status, now);
+        }
+
+        Some(SentTimeCorrection::Applied)
+    }
 
-        self.bytes_sent += sent_bytes;
-        trace!("{} {:?}", trace_id, self);
+    // Restarts congestion control when sending after nothing was sent or in
+    // flight for a while, as the congestion window and pacing rate may no
+    // longer reflect the path.
+    //
+    // As in RFC 2861, the congestion window is halved for every PTO of idle
+    // time, down to the initial window, and the slow start threshold is kept
+    // high enough for it to grow back quickly. BBR handles this on its own.
+    fn maybe_restart_after_idle(&mut self, now: Instant) {
+        if self.bytes_in_flight > 0 {
+            return;
+        }
+
+        let last_sent = match self.last_sent_in_flight_time {
+            Some(v) => v,
+
+            None => return,
+        };
+
+        let idle = now.saturating_duration_since(last_sent);
+        let pto = self.pto();
+
+        if self.idle_restart_threshold == 0 ||
+            idle <= pto * self.idle_restart_threshold
+        {
+            return;
+        }
+
+        let prev_cwnd = self.congestion_window;
+
+        let initial_window =
+            self.max_datagram_size * self.initial_congestion_window_packets;
+
+        if !(self.cc_ops.has_custom_pacing)() && prev_cwnd > initial_window {
+            let halvings = cmp::min(idle.as_nanos() / pto.as_nanos(), 31) as u32;
+
+            self.ssthresh = cmp::max(self.ssthresh, prev_cwnd * 3 / 4);
+            self.congestion_window =
+                cmp::max(prev_cwnd >> halvings, initial_window);
+        }
+
+        self.pacer.reset(now);
+
+        // Rate samples spanning the idle period don't reflect the path.
+        self.delivery_rate.update_app_limited(true);
+
+        if let Some(events) = &mut self.events {
+            events.push(RecoveryEvent::IdleRestart(CwndChange {
+                prev_cwnd,
+                cwnd: self.congestion_window,
+                ssthresh: self.ssthresh,
+                bytes_in_flight: self.bytes_in_flight,
+            }));
+        }
+
+        self.record_cwnd_change();
     }
 
     fn on_packet_sent_cc(&mut self, sent_bytes: usize, now: Instant) {
@@ -384,6 +1404,12 @@ impl Recovery {
     }
 
     pub fn set_pacing_rate(&mut self, rate: u64, now: Instant) {
+        let rate = match self.max_pacing_rate {
+            Some(max_pacing_rate) => cmp::min(rate, max_pacing_rate),
+
+            None => rate,
+        };
+
         self.pacer.update(self.send_quantum, rate, now);
     }
 
@@ -401,8 +1427,8 @@ impl Recovery {
 
         let is_app = epoch == packet::EPOCH_APPLICATION;
 
-        let in_initcwnd =
-            self.bytes_sent < self.max_datagram_size * INITIAL_WINDOW_PACKETS;
+        let in_initcwnd = self.bytes_sent <
+            self.max_datagram_size * self.initial_congestion_window_packets;
 
         let sent_bytes = if !self.pacer.enabled() || !is_app || in_initcwnd {
             0
@@ -413,13 +1439,23 @@ impl Recovery {
         self.pacer.send(sent_bytes, now);
     }
 
+    /// Processes an ACK frame received in the given packet number space.
+    ///
+    /// Frames carried by newly acknowledged packets are passed to
+    /// `on_frame_acked` as they are found, without being copied to an
+    /// intermediate buffer. When `None` is given, they are instead buffered
+    /// in `acked` so they can be processed later.
+    #[allow(clippy::too_many_arguments)]
     pub fn on_ack_received(
         &mut self, ranges: &ranges::RangeSet, ack_delay: u64,
         epoch: packet::Epoch, handshake_status: HandshakeStatus, now: Instant,
-        trace_id: &str,
+        mut on_frame_acked: Option<&mut dyn FnMut(frame::Frame)>,
     ) -> Result<(usize, usize)> {
         let largest_acked = ranges.last().unwrap();
 
+        let cwnd_before = self.congestion_window;
+        let available_before = self.cwnd_available();
+
         // While quiche used to consider ACK frames acknowledging packet numbers
         // larger than the largest sent one as invalid, this is not true anymore
         // if we consider a single packet number space and multiple paths. The
@@ -439,56 +1475,112 @@ impl Recovery {
         let mut largest_newly_acked_pkt_num = 0;
         let mut largest_newly_acked_sent_time = now;
 
+        let mut has_newly_acked = false;
+
         let mut newly_acked = Vec::new();
 
         let mut undo_cwnd = false;
 
+        let mut pmtud_probe_acked = None;
+
         let max_rtt = cmp::max(self.latest_rtt, self.rtt());
 
+        let mut ranges_count = 0;
+        let mut scanned = 0;
+        let mut frames_count = 0;
+
+        let max_frame_backlog = self.max_frame_backlog;
+
         // Detect and mark acked packets, without removing them from the sent
         // packets list.
         for r in ranges.iter() {
             let lowest_acked_in_block = r.start;
             let largest_acked_in_block = r.end - 1;
 
+            ranges_count += 1;
+
+            // Skip packets that precede the lowest acked packet in the block.
+            let start = self.sent_index(epoch, lowest_acked_in_block);
+
             let unacked_iter = self.sent[epoch]
-                .iter_mut()
-                // Skip packets that precede the lowest acked packet in the block.
-                .skip_while(|p| p.pkt_num < lowest_acked_in_block)
+                .range_mut(start..)
                 // Skip packets that follow the largest acked packet in the block.
                 .take_while(|p| p.pkt_num <= largest_acked_in_block)
+                .inspect(|_| scanned += 1)
                 // Skip packets that have already been acked or lost.
                 .filter(|p| p.time_acked.is_none());
 
             for unacked in unacked_iter {
                 unacked.time_acked = Some(now);
 
+                // The frames of the packet queued for retransmission on PTO
+                // don't need to be sent anymore.
+                if self.pto_queued[epoch].remove(&unacked.pkt_num) {
+                    self.suppressed_retrans_count += remove_frames(
+                        &mut self.lost[epoch],
+                        &mut self.lost_queued[epoch],
+                        &unacked.frames,
+                    );
+                }
+
                 // Check if acked packet was already declared lost.
                 if unacked.time_lost.is_some() {
-                    // Calculate new packet reordering threshold.
-                    let pkt_thresh =
-                        self.largest_acked_pkt[epoch] - unacked.pkt_num + 1;
-                    let pkt_thresh = cmp::min(MAX_PACKET_THRESHOLD, pkt_thresh);
-
-                    self.pkt_thresh = cmp::max(self.pkt_thresh, pkt_thresh);
-
-                    // Calculate new time reordering threshold.
-                    let loss_delay = max_rtt.mul_f64(self.time_thresh);
-
-                    // unacked.time_sent can be in the future due to
-                    // pacing.
-                    if now.saturating_duration_since(unacked.time_sent) >
-                        loss_delay
-                    {
-                        // TODO: do time threshold update
-                        self.time_thresh = 5_f64 / 4_f64;
+                    if self.adaptive_reorder_threshold {
+                        // Calculate new packet reordering threshold.
+                        let pkt_thresh =
+                            self.largest_acked_pkt[epoch] - unacked.pkt_num + 1;
+                        let pkt_thresh =
+                            cmp::min(MAX_PACKET_THRESHOLD, pkt_thresh);
+
+                        self.pkt_thresh = cmp::max(self.pkt_thresh, pkt_thresh);
+
+                        // Packets are reordered further than a run of
+                        // acknowledged packets implied.
+                        self.ack_implied_loss_thresh =
+                            cmp::max(self.ack_implied_loss_thresh, pkt_thresh);
+
+                        // Calculate new time reordering threshold.
+                        let loss_delay = max_rtt.mul_f64(self.time_thresh);
+
+                        // unacked.time_sent can be in the future due to
+                        // pacing.
+                        if now.saturating_duration_since(unacked.time_sent) >
+                            loss_delay
+                        {
+                            // TODO: do time threshold update
+                            self.time_thresh =
+                                self.time_thresh.max(5_f64 / 4_f64);
+                        }
                     }
 
-                    if unacked.in_flight {
-                        undo_cwnd = true;
+                    match &mut self.held_loss {
+                        // The window wasn't reduced for losses whose
+                        // congestion response is held back.
+                        Some(hold)
+                            if unacked.in_flight &&
+                                !unacked.is_pmtud_probe &&
+                                unacked.time_lost >= Some(hold.start_time) =>
+                        {
+                            hold.lost_bytes =
+                                hold.lost_bytes.saturating_sub(unacked.cc_size());
+                        },
+
+                        _ if unacked.in_flight => undo_cwnd = true,
+
+                        _ => (),
                     }
 
                     self.lost_spurious_count += 1;
+
+                    if let Some(events) = &mut self.events {
+                        events.push(RecoveryEvent::SpuriousLoss(LostPacket {
+                            pkt_num: unacked.pkt_num,
+                            pkt_type: packet::Type::from_epoch(epoch),
+                            size: unacked.size,
+                            latency: unacked.loss_latency(),
+                        }));
+                    }
+
                     continue;
                 }
 
@@ -496,44 +1588,102 @@ impl Recovery {
                     has_ack_eliciting = true;
                 }
 
+                if unacked.is_pmtud_probe {
+                    pmtud_probe_acked = Some(unacked.size);
+                } else {
+                    self.pmtud.on_packet_acked(unacked.size, unacked.time_sent);
+                }
+
                 largest_newly_acked_pkt_num = unacked.pkt_num;
                 largest_newly_acked_sent_time = unacked.time_sent;
 
-                self.acked[epoch].append(&mut unacked.frames);
+                self.sent_frames_mem[epoch] = self.sent_frames_mem[epoch]
+                    .saturating_sub(frames_heap_size(&unacked.frames));
+
+                frames_count += unacked.frames.len() as u64;
+
+                match on_frame_acked {
+                    Some(ref mut on_frame_acked) =>
+                        for frame in unacked.frames.drain(..) {
+                            on_frame_acked(frame);
+                        },
+
+                    None if backlog_has_room(
+                        &self.acked[epoch],
+                        unacked.frames.len(),
+                        max_frame_backlog,
+                    ) =>
+                        self.acked[epoch].append(&mut unacked.frames),
 
+                    // Only the application's view of acked frames is lost.
+                    None => {
+                        unacked.frames.clear();
+
+                        self.frame_backlog_overflows += 1;
+                    },
+                }
+
+                has_newly_acked = true;
+
+                // Only packets counted in bytes_in_flight are passed on to
+                // congestion control.
                 if unacked.in_flight {
                     self.in_flight_count[epoch] =
                         self.in_flight_count[epoch].saturating_sub(1);
-                }
 
-                newly_acked.push(Acked {
-                    pkt_num: unacked.pkt_num,
+                    newly_acked.push(Acked {
+                        pkt_num: unacked.pkt_num,
 
-                    time_sent: unacked.time_sent,
+                        time_sent: unacked.time_sent,
 
-                    size: unacked.size,
+                        size: unacked.cc_size(),
 
-                    rtt: now.saturating_duration_since(unacked.time_sent),
+                        rtt: now.saturating_duration_since(unacked.time_sent),
 
-                    delivered: unacked.delivered,
+                        delivered: unacked.delivered,
 
-                    delivered_time: unacked.delivered_time,
+                        delivered_time: unacked.delivered_time,
 
-                    first_sent_time: unacked.first_sent_time,
+                        first_sent_time: unacked.first_sent_time,
 
-                    is_app_limited: unacked.is_app_limited,
-                });
+                        is_app_limited: unacked.is_app_limited,
+                    });
+                }
 
-                trace!("{} packet newly acked {}", trace_id, unacked.pkt_num);
+                trace!(
+                    "{} packet newly acked {}",
+                    self.trace_id,
+                    unacked.pkt_num
+                );
             }
         }
 
+        self.ack_cost.record(ranges_count, scanned, frames_count);
+
         // Undo congestion window update.
         if undo_cwnd {
             (self.cc_ops.rollback)(self);
         }
 
-        if newly_acked.is_empty() {
+        let had_held_loss = self.held_loss.is_some();
+
+        // All the packets whose loss was held back were received after all.
+        if self.held_loss.map_or(false, |hold| hold.lost_bytes == 0) {
+            self.held_loss = None;
+        }
+
+        self.maybe_release_loss_hold(now);
+
+        if !has_newly_acked {
+            // The timer no longer needs to fire for the held back response.
+            if had_held_loss && self.held_loss.is_none() {
+                self.set_loss_detection_timer(handshake_status, now);
+
+                self.record_cwnd_change();
+            }
+
+            self.update_ack_window(cwnd_before, available_before, 0);
+
             return Ok((0, 0));
         }
 
@@ -552,713 +1702,3592 @@ impl Recovery {
             // Don't update srtt if rtt is zero.
             if !latest_rtt.is_zero() {
                 self.update_rtt(latest_rtt, ack_delay, now);
+
+                self.update_delay_asymmetry(latest_rtt, ack_delay, now);
             }
+        } else {
+            self.rtt_samples_skipped += 1;
+        }
+
+        if !has_ack_eliciting {
+            self.acks_without_ack_eliciting += 1;
+        }
+
+        if self.ack_implied_loss {
+            let thresh = self.ack_implied_loss_thresh;
+
+            self.ack_implied_loss_below = ranges
+                .iter()
+                .find(|r| r.end - r.start > thresh)
+                .map(|r| r.start);
         }
 
         // Detect and mark lost packets without removing them from the sent
         // packets list.
-        let (lost_packets, lost_bytes) =
-            self.detect_lost_packets(epoch, now, trace_id);
+        let (lost_packets, lost_bytes) = self.detect_lost_packets(epoch, now);
+
+        let acked_bytes = newly_acked.iter().map(|p| p.size).sum();
+
+        if !newly_acked.is_empty() {
+            if has_ack_eliciting || self.ack_only_rate_samples {
+                self.on_packets_acked(newly_acked, epoch, now);
+            } else {
+                self.on_packets_acked_cc(newly_acked, epoch, now);
+            }
+        }
 
-        self.on_packets_acked(newly_acked, epoch, now);
+        if let Some(size) = pmtud_probe_acked {
+            if let Some(pmtu) = self.pmtud.on_probe_acked(size, now) {
+                self.on_pmtu_changed(pmtu);
+            }
+        }
 
         self.pto_count = 0;
+        self.blackhole_suspected = false;
+        self.handshake_unreachable = false;
 
         self.set_loss_detection_timer(handshake_status, now);
 
         self.drain_packets(epoch, now);
 
+        self.record_cwnd_change();
+
+        if self.busy && self.all_acked() {
+            self.busy = false;
+
+            if let Some(events) = &mut self.events {
+                events.push(RecoveryEvent::AllAcked(now));
+            }
+        }
+
+        self.update_ack_window(cwnd_before, available_before, acked_bytes);
+
+        self.publish_stats();
+
+        #[cfg(debug_assertions)]
+        self.assert_consistent();
+
         Ok((lost_packets, lost_bytes))
     }
 
+    // Records how the ACK being processed changed the congestion window.
+    fn update_ack_window(
+        &mut self, cwnd_before: usize, available_before: usize,
+        acked_bytes: usize,
+    ) {
+        let cwnd_after = self.congestion_window;
+
+        // The window is full once it can't fit a full-sized packet anymore,
+        // as that's when senders usually stop.
+        let mss = self.max_datagram_size;
+
+        self.ack_window = AckWindowUpdate {
+            cwnd_before,
+            cwnd_after,
+            freed_bytes: acked_bytes + cwnd_after.saturating_sub(cwnd_before),
+            unblocked: available_before < mss && self.cwnd_available() >= mss,
+        };
+    }
+
+    /// Returns how the last ACK changed the congestion window.
+    pub fn ack_window(&self) -> AckWindowUpdate {
+        self.ack_window
+    }
+
+    /// Returns statistics of the work done processing ACK frames.
+    pub fn ack_cost(&self) -> AckCost {
+        self.ack_cost
+    }
+
+    /// Handles the expiration of the loss detection timer, and returns the
+    /// number of packets and bytes that were declared lost.
+    ///
+    /// See `on_loss_detection_timeout_outcome()` for the details of what the
+    /// timeout did.
     pub fn on_loss_detection_timeout(
         &mut self, handshake_status: HandshakeStatus, now: Instant,
-        trace_id: &str,
     ) -> (usize, usize) {
-        let (earliest_loss_time, epoch) = self.loss_time_and_space();
+        let outcome =
+            self.on_loss_detection_timeout_outcome(handshake_status, now);
 
-        if earliest_loss_time.is_some() {
-            // Time threshold loss detection.
-            let (lost_packets, lost_bytes) =
-                self.detect_lost_packets(epoch, now, trace_id);
+        (outcome.lost_packets, outcome.lost_bytes)
+    }
 
-            self.set_loss_detection_timer(handshake_status, now);
+    /// Handles the expiration of the loss detection timer, and returns what
+    /// the timeout did.
+    pub fn on_loss_detection_timeout_outcome(
+        &mut self, handshake_status: HandshakeStatus, now: Instant,
+    ) -> TimeoutOutcome {
+        // The reported deadline can precede the true one when its updates
+        // are coalesced, in which case the timer is only re-armed.
+        if !self.loss_detection_timer.on_timeout(now) {
+            return TimeoutOutcome {
+                next_timer: self.loss_detection_timer.deadline(),
+                ..TimeoutOutcome::rearmed()
+            };
+        }
+
+        // The timer may only have been armed for the congestion response held
+        // back, in which case nothing else is due yet.
+        if self.held_loss.map_or(false, |hold| hold.deadline <= now) {
+            self.maybe_release_loss_hold(now);
+
+            let due = self
+                .loss_detection_deadline(handshake_status, now)
+                .map_or(false, |t| t <= now);
+
+            if !due {
+                self.set_loss_detection_timer(handshake_status, now);
+
+                self.record_cwnd_change();
 
-            trace!("{} {:?}", trace_id, self);
-            return (lost_packets, lost_bytes);
+                self.publish_stats();
+
+                return TimeoutOutcome {
+                    kind: TimeoutKind::LossHold,
+                    next_timer: self.loss_detection_timer.deadline(),
+                    ..TimeoutOutcome::rearmed()
+                };
+            }
         }
 
-        let epoch = if self.bytes_in_flight > 0 {
-            // Send new data if available, else retransmit old data. If neither
-            // is available, send a single PING frame.
-            let (_, e) = self.pto_time_and_space(handshake_status, now);
+        let (earliest_loss_time, epoch) = self.loss_time_and_space();
 
-            e
-        } else {
-            // Client sends an anti-deadlock packet: Initial is padded to earn
-            // more anti-amplification credit, a Handshake packet proves address
-            // ownership.
-            if handshake_status.has_handshake_keys {
-                packet::EPOCH_HANDSHAKE
-            } else {
-                packet::EPOCH_INITIAL
+        let mut outcome = if earliest_loss_time.is_some() {
+            // Time threshold loss detection.
+            let (lost_packets, lost_bytes) = self.detect_lost_packets(epoch, now);
+
+            TimeoutOutcome {
+                kind: TimeoutKind::LossTime,
+                epoch: Some(epoch),
+                lost_packets,
+                lost_bytes,
+                ..TimeoutOutcome::rearmed()
             }
+        } else {
+            self.on_pto(handshake_status, now)
         };
 
-        self.pto_count += 1;
+        self.set_loss_detection_timer(handshake_status, now);
 
-        self.loss_probes[epoch] =
-            cmp::min(self.pto_count as usize, MAX_PTO_PROBES_COUNT);
+        self.record_cwnd_change();
 
-        let unacked_iter = self.sent[epoch]
-            .iter_mut()
-            // Skip packets that have already been acked or lost, and packets
-            // that don't contain either CRYPTO or STREAM frames.
-            .filter(|p| p.has_data && p.time_acked.is_none() && p.time_lost.is_none())
-            // Only return as many packets as the number of probe packets that
-            // will be sent.
-            .take(self.loss_probes[epoch]);
-
-        // Retransmit the frames from the oldest sent packets on PTO. However
-        // the packets are not actually declared lost (so there is no effect to
-        // congestion control), we just reschedule the data they carried.
-        //
-        // This will also trigger sending an ACK and retransmitting frames like
-        // HANDSHAKE_DONE and MAX_DATA / MAX_STREAM_DATA as well, in addition
-        // to CRYPTO and STREAM, if the original packet carried them.
-        for unacked in unacked_iter {
-            self.lost[epoch].extend_from_slice(&unacked.frames);
-        }
+        self.publish_stats();
 
-        self.set_loss_detection_timer(handshake_status, now);
+        #[cfg(debug_assertions)]
+        self.assert_consistent();
 
-        trace!("{} {:?}", trace_id, self);
+        trace!("{} {:?}", self.trace_id, self.debug_at(now));
 
-        (0, 0)
+        outcome.next_timer = self.loss_detection_timer.deadline();
+
+        outcome
     }
 
-    pub fn on_pkt_num_space_discarded(
-        &mut self, epoch: packet::Epoch, handshake_status: HandshakeStatus,
-        now: Instant,
-    ) {
-        let unacked_bytes = self.sent[epoch]
-            .iter()
-            .filter(|p| {
-                p.in_flight && p.time_acked.is_none() && p.time_lost.is_none()
-            })
-            .fold(0, |acc, p| acc + p.size);
+    /// Fires the probe timeout immediately, regardless of the current loss
+    /// detection timer.
+    ///
+    /// This has the same effect as the PTO timer expiring: the PTO count is
+    /// incremented, probe packets are scheduled and the timer is re-armed
+    /// with the new backoff.
+    ///
+    /// This is only meant for tests and is not covered by semver guarantees.
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn force_pto(&mut self, handshake_status: HandshakeStatus, now: Instant) {
+        self.on_pto(handshake_status, now);
 
-        self.bytes_in_flight = self.bytes_in_flight.saturating_sub(unacked_bytes);
+        self.set_loss_detection_timer(handshake_status, now);
 
-        self.sent[epoch].clear();
-        self.lost[epoch].clear();
-        self.acked[epoch].clear();
+        self.record_cwnd_change();
 
-        self.time_of_last_sent_ack_eliciting_pkt[epoch] = None;
-        self.loss_time[epoch] = None;
-        self.loss_probes[epoch] = 0;
-        self.in_flight_count[epoch] = 0;
+        self.publish_stats();
 
-        self.set_loss_detection_timer(handshake_status, now);
-    }
+        #[cfg(debug_assertions)]
+        self.assert_consistent();
 
-    pub fn loss_detection_timer(&self) -> Option<Instant> {
-        self.loss_detection_timer
+        trace!("{} {:?}", self.trace_id, self.debug_at(now));
     }
 
-    pub fn cwnd(&self) -> usize {
-        self.congestion_window
-    }
+    /// Declares the given packet lost, even though neither the packet nor
+    /// the time threshold have been reached for it.
+    ///
+    /// The packet's frames are scheduled for retransmission and congestion
+    /// control reacts as it would to a regular loss. Returns the number of
+    /// packets and bytes that were declared lost, which is zero if no packet
+    /// with the given number is awaiting acknowledgement.
+    ///
+    /// This is only meant for tests and is not covered by semver guarantees.
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn inject_loss(
+        &mut self, epoch: packet::Epoch, pkt_num: u64,
+        handshake_status: HandshakeStatus, now: Instant,
+    ) -> (usize, usize) {
+        let idx = self.sent_index(epoch, pkt_num);
 
-    pub fn cwnd_available(&self) -> usize {
-        // Ignore cwnd when sending probe packets.
-        if self.loss_probes.iter().any(|&x| x > 0) {
-            return std::usize::MAX;
+        match self.sent[epoch].get(idx) {
+            Some(p)
+                if p.pkt_num == pkt_num &&
+                    p.time_acked.is_none() &&
+                    p.time_lost.is_none() =>
+                (),
+
+            _ => return (0, 0),
         }
 
-        // Open more space (snd_cnt) for PRR when allowed.
-        self.congestion_window.saturating_sub(self.bytes_in_flight) +
-            self.prr.snd_cnt
-    }
+        let mut sent = mem::take(&mut self.sent[epoch]);
 
-    pub fn rtt(&self) -> Duration {
-        self.smoothed_rtt.unwrap_or(INITIAL_RTT)
-    }
+        self.mark_lost(epoch, &mut sent[idx], now);
 
-    pub fn pto(&self) -> Duration {
-        self.rtt() + cmp::max(self.rttvar * 4, GRANULARITY)
-    }
+        let lost_pkt = sent[idx].clone();
 
-    pub fn delivery_rate(&self) -> u64 {
-        self.delivery_rate.sample_delivery_rate()
-    }
+        self.sent[epoch] = sent;
 
-    pub fn max_datagram_size(&self) -> usize {
-        self.max_datagram_size
-    }
+        let mut lost_bytes = 0;
 
-    pub fn update_max_datagram_size(&mut self, new_max_datagram_size: usize) {
-        let max_datagram_size =
-            cmp::min(self.max_datagram_size, new_max_datagram_size);
+        if lost_pkt.in_flight {
+            lost_bytes = lost_pkt.cc_size();
 
-        // Update cwnd if it hasn't been updated yet.
-        if self.congestion_window ==
-            self.max_datagram_size * INITIAL_WINDOW_PACKETS
-        {
-            self.congestion_window = max_datagram_size * INITIAL_WINDOW_PACKETS;
+            self.bytes_lost += lost_bytes as u64;
+
+            self.on_packets_lost(lost_bytes, &lost_pkt, epoch, now);
         }
 
-        self.pacer = pacer::Pacer::new(
-            self.pacer.enabled(),
-            self.congestion_window,
-            0,
-            max_datagram_size,
+        trace!(
+            "{} packet {} lost on epoch {}",
+            self.trace_id,
+            pkt_num,
+            epoch
         );
 
-        self.max_datagram_size = max_datagram_size;
-    }
+        self.drain_packets(epoch, now);
 
-    fn update_rtt(
-        &mut self, latest_rtt: Duration, ack_delay: Duration, now: Instant,
-    ) {
-        self.latest_rtt = latest_rtt;
+        self.set_loss_detection_timer(handshake_status, now);
 
-        match self.smoothed_rtt {
-            // First RTT sample.
-            None => {
-                self.min_rtt = self.minmax_filter.reset(now, latest_rtt);
+        self.record_cwnd_change();
 
-                self.smoothed_rtt = Some(latest_rtt);
+        self.publish_stats();
 
-                self.rttvar = latest_rtt / 2;
-            },
+        #[cfg(debug_assertions)]
+        self.assert_consistent();
 
-            Some(srtt) => {
-                self.min_rtt =
-                    self.minmax_filter.running_min(RTT_WINDOW, now, latest_rtt);
+        (1, lost_bytes)
+    }
 
-                let ack_delay = cmp::min(self.max_ack_delay, ack_delay);
+    /// Returns a snapshot of the loss recovery state.
+    ///
+    /// This is only meant for tests and is not covered by semver guarantees.
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn inspect(&self) -> RecoveryInspection {
+        let mut inspection = RecoveryInspection {
+            sent_packets: [0; packet::EPOCH_COUNT],
+            in_flight_packets: self.in_flight_count,
+            loss_probes: self.loss_probes,
+            loss_detection_timer: self.loss_detection_timer.deadline(),
+            loss_detection_timer_kind: None,
+            draining: self.draining,
+            crypto_packets: [0; packet::EPOCH_COUNT],
+            stream_packets: [0; packet::EPOCH_COUNT],
+            control_packets: [0; packet::EPOCH_COUNT],
+            discarded: self.discarded,
+        };
 
-                // Adjust for ack delay if plausible.
-                let adjusted_rtt = if latest_rtt > self.min_rtt + ack_delay {
-                    latest_rtt - ack_delay
-                } else {
-                    latest_rtt
-                };
+        for e in packet::epochs() {
+            inspection.sent_packets[e] = self.sent[e].len();
 
-                self.rttvar = self.rttvar.mul_f64(3.0 / 4.0) +
-                    sub_abs(srtt, adjusted_rtt).mul_f64(1.0 / 4.0);
+            let unacked = self.sent[e]
+                .iter()
+                .filter(|p| p.time_acked.is_none() && p.time_lost.is_none());
 
-                self.smoothed_rtt = Some(
-                    srtt.mul_f64(7.0 / 8.0) + adjusted_rtt.mul_f64(1.0 / 8.0),
-                );
-            },
+            for p in unacked {
+                inspection.crypto_packets[e] += usize::from(p.content.crypto);
+                inspection.stream_packets[e] += usize::from(p.content.stream);
+                inspection.control_packets[e] += usize::from(p.content.control);
+            }
         }
-    }
-
-    fn loss_time_and_space(&self) -> (Option<Instant>, packet::Epoch) {
-        let mut epoch = packet::EPOCH_INITIAL;
-        let mut time = self.loss_time[epoch];
 
-        // Iterate over all packet number spaces starting from Handshake.
-        for e in packet::EPOCH_HANDSHAKE..packet::EPOCH_COUNT {
-            let new_time = self.loss_time[e];
+        if self.loss_detection_timer.deadline().is_some() {
+            inspection.loss_detection_timer_kind =
+                match self.loss_time_and_space() {
+                    (Some(_), _) => Some(LossDetectionTimerKind::LossTime),
 
-            if time.is_none() || new_time < time {
-                time = new_time;
-                epoch = e;
-            }
+                    (None, _) => Some(LossDetectionTimerKind::Pto),
+                };
         }
 
-        (time, epoch)
+        inspection
     }
 
-    fn pto_time_and_space(
-        &self, handshake_status: HandshakeStatus, now: Instant,
-    ) -> (Option<Instant>, packet::Epoch) {
-        let mut duration = self.pto() * 2_u32.pow(self.pto_count);
+    // Sends probe packets on probe timeout, and returns what was scheduled.
+    fn on_pto(
+        &mut self, handshake_status: HandshakeStatus, now: Instant,
+    ) -> TimeoutOutcome {
+        let mut backoff = true;
 
-        // Arm PTO from now when there are no inflight packets.
-        if self.bytes_in_flight == 0 {
-            if handshake_status.has_handshake_keys {
-                return (Some(now + duration), packet::EPOCH_HANDSHAKE);
-            } else {
-                return (Some(now + duration), packet::EPOCH_INITIAL);
-            }
+        // Once probes are sent, the probe timeout isn't brought forward again
+        // until the idle timer restarts.
+        if let Some(conflict) = &mut self.idle_conflict {
+            conflict.probed = true;
         }
 
-        let mut pto_timeout = None;
-        let mut pto_space = packet::EPOCH_INITIAL;
+        let kind = if self.bytes_in_flight > 0 {
+            TimeoutKind::Pto
+        } else {
+            TimeoutKind::AntiDeadlock
+        };
 
-        // Iterate over all packet number spaces.
-        for e in packet::EPOCH_INITIAL..packet::EPOCH_COUNT {
-            if self.in_flight_count[e] == 0 {
-                continue;
-            }
+        let epoch = if self.bytes_in_flight > 0 {
+            // Send new data if available, else retransmit old data. If neither
+            // is available, send a single PING frame.
+            let (_, e) = self.pto_time_and_space(handshake_status, now);
 
-            if e == packet::EPOCH_APPLICATION {
-                // Skip Application Data until handshake completes.
-                if !handshake_status.completed {
-                    return (pto_timeout, pto_space);
-                }
+            // When only e.g. PMTU probes are in flight, there is nothing to
+            // retransmit, and the timeout may not say much about the path.
+            if !self.non_retransmittable_pto_backoff {
+                let is_retransmittable = |p: &Sent| {
+                    p.time_acked.is_none() &&
+                        p.time_lost.is_none() &&
+                        p.content.is_retransmittable()
+                };
 
-                // Include max_ack_delay and backoff for Application Data.
-                duration += self.max_ack_delay * 2_u32.pow(self.pto_count);
+                // Application Data is probed along with the handshake until
+                // it completes.
+                backoff = self.sent[e].iter().any(is_retransmittable) ||
+                    (!handshake_status.completed &&
+                        self.sent[packet::EPOCH_APPLICATION]
+                            .iter()
+                            .any(is_retransmittable));
             }
 
-            let new_time =
-                self.time_of_last_sent_ack_eliciting_pkt[e].map(|t| t + duration);
-
-            if pto_timeout.is_none() || new_time < pto_timeout {
-                pto_timeout = new_time;
-                pto_space = e;
+            e
+        } else {
+            // Client sends an anti-deadlock packet: Initial is padded to earn
+            // more anti-amplification credit, a Handshake packet proves address
+            // ownership.
+            if handshake_status.has_handshake_keys {
+                packet::EPOCH_HANDSHAKE
+            } else {
+                packet::EPOCH_INITIAL
             }
-        }
+        };
 
-        (pto_timeout, pto_space)
-    }
+        let mut outcome = TimeoutOutcome {
+            kind,
+            epoch: Some(epoch),
+            ..TimeoutOutcome::rearmed()
+        };
 
-    fn set_loss_detection_timer(
-        &mut self, handshake_status: HandshakeStatus, now: Instant,
-    ) {
-        let (earliest_loss_time, _) = self.loss_time_and_space();
+        if !backoff {
+            // Only send a PING probe, without changing the backoff.
+            self.loss_probes[epoch] = 1;
 
-        if earliest_loss_time.is_some() {
-            // Time threshold loss detection.
-            self.loss_detection_timer = earliest_loss_time;
-            return;
-        }
+            self.cap_loss_probes();
 
-        if self.bytes_in_flight == 0 && handshake_status.peer_verified_address {
-            self.loss_detection_timer = None;
-            return;
-        }
+            outcome.probes = self.loss_probes[epoch];
 
-        // PTO timer.
-        let (timeout, _) = self.pto_time_and_space(handshake_status, now);
-        self.loss_detection_timer = timeout;
-    }
+            return outcome;
+        }
 
-    fn detect_lost_packets(
-        &mut self, epoch: packet::Epoch, now: Instant, trace_id: &str,
-    ) -> (usize, usize) {
-        let largest_acked = self.largest_acked_pkt[epoch];
+        self.pto_count += 1;
 
-        self.loss_time[epoch] = None;
+        if epoch != packet::EPOCH_APPLICATION {
+            self.handshake_pto_count += 1;
+        }
 
-        let loss_delay =
-            cmp::max(self.latest_rtt, self.rtt()).mul_f64(self.time_thresh);
+        if epoch == packet::EPOCH_INITIAL {
+            self.initial_pto_count += 1;
+        }
 
-        // Minimum time of kGranularity before packets are deemed lost.
-        let loss_delay = cmp::max(loss_delay, GRANULARITY);
+        if self.blackhole_pto_threshold > 0 &&
+            self.pto_count >= self.blackhole_pto_threshold &&
+            !self.blackhole_suspected
+        {
+            self.on_blackhole_suspected();
+        }
 
-        // Packets sent before this time are deemed lost.
-        let lost_send_time = now - loss_delay;
+        // Give up on the handshake, rather than backing off further.
+        if self.max_handshake_ptos > 0 &&
+            self.pto_count >= self.max_handshake_ptos &&
+            epoch != packet::EPOCH_APPLICATION &&
+            !handshake_status.completed
+        {
+            self.handshake_unreachable = true;
+        }
 
-        let mut lost_packets = 0;
-        let mut lost_bytes = 0;
+        // RFC 9002 always sends two probes, with new data if there is any, and
+        // PINGs otherwise.
+        if self.strict_rfc9002 {
+            self.loss_probes[epoch] = MAX_PTO_PROBES_COUNT;
 
-        let mut largest_lost_pkt = None;
+            self.cap_loss_probes();
 
-        let unacked_iter = self.sent[epoch]
-            .iter_mut()
-            // Skip packets that follow the largest acked packet.
-            .take_while(|p| p.pkt_num <= largest_acked)
-            // Skip packets that have already been acked or lost.
-            .filter(|p| p.time_acked.is_none() && p.time_lost.is_none());
+            outcome.probes = self.loss_probes[epoch];
 
-        for unacked in unacked_iter {
-            // Mark packet as lost, or set time when it should be marked.
-            if unacked.time_sent <= lost_send_time ||
-                largest_acked >= unacked.pkt_num + self.pkt_thresh
-            {
-                self.lost[epoch].append(&mut unacked.frames);
+            return outcome;
+        }
 
-                unacked.time_lost = Some(now);
+        self.loss_probes[epoch] =
+            cmp::min(self.pto_count as usize, MAX_PTO_PROBES_COUNT);
 
-                if unacked.in_flight {
-                    lost_bytes += unacked.size;
+        self.cap_loss_probes();
 
-                    // Frames have already been removed from the packet, so
-                    // cloning the whole packet should be relatively cheap.
-                    largest_lost_pkt = Some(unacked.clone());
+        outcome.probes = self.loss_probes[epoch];
 
-                    self.in_flight_count[epoch] =
-                        self.in_flight_count[epoch].saturating_sub(1);
+        // Retransmit the frames from the oldest sent packets on PTO, as many
+        // as probe packets will be sent, picking packets that carry CRYPTO
+        // frames first during the handshake, and STREAM frames first after
+        // it. However the packets are not actually declared lost (so there is
+        // no effect to congestion control), we just reschedule the data they
+        // carried.
+        //
+        // This will also trigger sending an ACK and retransmitting frames like
+        // HANDSHAKE_DONE and MAX_DATA / MAX_STREAM_DATA as well, in addition
+        // to CRYPTO and STREAM, if the original packet carried them.
+        //
+        // Sent packets only hold frame descriptors (e.g. the offset and length
+        // of STREAM and CRYPTO data, rather than the data itself), so copying
+        // them doesn't copy any payload. The data is read again from the
+        // stream's send buffer when the frames are retransmitted. The
+        // `pto_payload` bench checks that the time spent here doesn't depend
+        // on the amount of data.
+        let mut probes = self.loss_probes[epoch];
+
+        for rank in 0..SentContent::PTO_RANKS {
+            let unacked_iter = self.sent[epoch]
+                .iter()
+                // Skip packets that have already been acked or lost, and
+                // packets that don't contain either CRYPTO or STREAM frames.
+                .filter(|p| {
+                    p.time_acked.is_none() &&
+                        p.time_lost.is_none() &&
+                        p.content.pto_rank(epoch) == Some(rank)
+                })
+                .take(probes);
 
-                    trace!(
-                        "{} packet {} lost on epoch {}",
-                        trace_id,
-                        unacked.pkt_num,
-                        epoch
-                    );
+            for unacked in unacked_iter {
+                // The frames stay with the packet, and are retransmitted if
+                // it's lost, so the copy can be skipped once the backlog is
+                // full.
+                if !backlog_has_room(
+                    &self.lost[epoch],
+                    unacked.frames.len(),
+                    self.max_frame_backlog,
+                ) {
+                    self.frame_backlog_overflows += 1;
+                    break;
                 }
 
-                lost_packets += 1;
-                self.lost_count += 1;
-            } else {
-                let loss_time = match self.loss_time[epoch] {
-                    None => unacked.time_sent + loss_delay,
+                self.lost[epoch].extend_from_slice(&unacked.frames);
+                self.pto_queued[epoch].insert(unacked.pkt_num);
 
-                    Some(loss_time) =>
-                        cmp::min(loss_time, unacked.time_sent + loss_delay),
-                };
+                outcome.requeued_frames += unacked.frames.len();
 
-                self.loss_time[epoch] = Some(loss_time);
+                probes -= 1;
             }
         }
 
-        self.bytes_lost += lost_bytes as u64;
+        outcome
+    }
 
-        if let Some(pkt) = largest_lost_pkt {
-            self.on_packets_lost(lost_bytes, &pkt, epoch, now);
+    // Bounds the number of probe packets pending across all epochs.
+    //
+    // Each epoch with pending probes gets one in order of priority, then the
+    // rest of the budget goes to the highest priority epochs.
+    fn cap_loss_probes(&mut self) {
+        let wanted = self.loss_probes;
+        let mut budget = self.max_loss_probes;
+
+        self.loss_probes = [0; packet::EPOCH_COUNT];
+
+        for &e in &LOSS_PROBES_PRIORITY {
+            if wanted[e] > 0 && budget > 0 {
+                self.loss_probes[e] = 1;
+                budget -= 1;
+            }
         }
 
-        self.drain_packets(epoch, now);
+        for &e in &LOSS_PROBES_PRIORITY {
+            let extra =
+                cmp::min(wanted[e].saturating_sub(self.loss_probes[e]), budget);
 
-        (lost_packets, lost_bytes)
+            self.loss_probes[e] += extra;
+            budget -= extra;
+        }
     }
 
-    fn drain_packets(&mut self, epoch: packet::Epoch, now: Instant) {
-        let mut lowest_non_expired_pkt_index = self.sent[epoch].len();
+    /// Returns the number of probe packets pending across all epochs.
+    pub fn total_loss_probes(&self) -> usize {
+        self.loss_probes.iter().sum()
+    }
 
-        // In order to avoid removing elements from the middle of the list
-        // (which would require copying other elements to compact the list),
-        // we only remove a contiguous range of elements from the start of the
-        // list.
-        //
-        // This means that acked or lost elements coming after this will not
-        // be removed at this point, but their removal is delayed for a later
-        // time, once the gaps have been filled.
+    /// Re-arms the loss detection timer once the handshake completes.
+    ///
+    /// Application Data sent before then, e.g. 0-RTT, is only covered by the
+    /// handshake's probe timeout, which doesn't account for the peer's
+    /// `max_ack_delay`.
+    pub fn on_handshake_completed(
+        &mut self, handshake_status: HandshakeStatus, now: Instant,
+    ) {
+        self.set_loss_detection_timer(handshake_status, now);
+    }
 
-        // First, find the first element that is neither acked nor lost.
-        for (i, pkt) in self.sent[epoch].iter().enumerate() {
-            if let Some(time_lost) = pkt.time_lost {
-                if time_lost + self.rtt() > now {
-                    lowest_non_expired_pkt_index = i;
-                    break;
-                }
+    /// Pauses sending, e.g. while the network interface is unavailable.
+    ///
+    /// Nothing can be sent and the loss detection timer is disarmed until
+    /// `resume()` is called. Packets in flight are not declared lost based on
+    /// how long ago they were sent in the meantime.
+    pub fn pause(&mut self, now: Instant) {
+        if self.paused_at.is_some() {
+            return;
+        }
+
+        self.paused_at = Some(now);
+        self.loss_detection_timer.set(None);
+    }
+
+    /// Resumes sending after `pause()`.
+    ///
+    /// Packets in flight are considered sent later by the duration of the
+    /// pause, so that it counts neither toward time threshold loss detection
+    /// nor toward the RTT, and the loss detection timer is re-armed where it
+    /// left off. If configured to, a probe is also sent right away in each
+    /// packet number space with packets in flight.
+    pub fn resume(&mut self, handshake_status: HandshakeStatus, now: Instant) {
+        let paused_at = match self.paused_at.take() {
+            Some(v) => v,
+
+            None => return,
+        };
+
+        let pause = now.saturating_duration_since(paused_at);
+
+        for e in packet::epochs() {
+            for p in self.sent[e].iter_mut().filter(|p| {
+                p.in_flight && p.time_acked.is_none() && p.time_lost.is_none()
+            }) {
+                p.time_sent += pause;
             }
 
-            if pkt.time_acked.is_none() && pkt.time_lost.is_none() {
-                lowest_non_expired_pkt_index = i;
-                break;
+            if let Some(t) = &mut self.time_of_last_sent_ack_eliciting_pkt[e] {
+                *t += pause;
             }
-        }
 
-        // Then remove elements up to the previously found index.
-        self.sent[epoch].drain(..lowest_non_expired_pkt_index);
-    }
+            if let Some(t) = &mut self.loss_time[e] {
+                *t += pause;
+            }
 
-    fn on_packets_acked(
-        &mut self, acked: Vec<Acked>, epoch: packet::Epoch, now: Instant,
-    ) {
-        // Update delivery rate sample per acked packet.
-        for pkt in &acked {
-            self.delivery_rate.update_rate_sample(pkt, now);
+            if self.probe_on_resume && self.in_flight_count[e] > 0 {
+                self.loss_probes[e] = cmp::max(self.loss_probes[e], 1);
+            }
         }
 
-        // Fill in a rate sample.
-        self.delivery_rate.generate_rate_sample(self.min_rtt);
+        self.cap_loss_probes();
 
-        // Call congestion control hooks.
-        (self.cc_ops.on_packets_acked)(self, &acked, epoch, now);
+        self.set_loss_detection_timer(handshake_status, now);
     }
 
-    fn in_congestion_recovery(&self, sent_time: Instant) -> bool {
-        match self.congestion_recovery_start_time {
-            Some(congestion_recovery_start_time) =>
-                sent_time <= congestion_recovery_start_time,
-
-            None => false,
-        }
+    /// Returns whether sending is paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused_at.is_some()
     }
 
-    fn in_persistent_congestion(&mut self, _largest_lost_pkt_num: u64) -> bool {
-        let _congestion_period = self.pto() * PERSISTENT_CONGESTION_THRESHOLD;
+    /// Returns whether a network black hole is suspected, i.e. whether enough
+    /// consecutive PTOs fired without any packet being acknowledged.
+    ///
+    /// This is cleared by the next ACK that acknowledges new packets.
+    pub fn is_blackhole_suspected(&self) -> bool {
+        self.blackhole_suspected
+    }
 
-        // TODO: properly detect persistent congestion
-        false
+    /// Returns whether the peer is deemed unreachable, i.e. whether the
+    /// maximum number of consecutive PTOs fired in the Initial and Handshake
+    /// epochs without any packet being acknowledged.
+    ///
+    /// The loss detection timer isn't armed anymore then. This is cleared by
+    /// the next ACK that acknowledges new packets.
+    pub fn is_handshake_unreachable(&self) -> bool {
+        self.handshake_unreachable
     }
 
-    fn on_packets_lost(
-        &mut self, lost_bytes: usize, largest_lost_pkt: &Sent,
-        epoch: packet::Epoch, now: Instant,
-    ) {
-        self.bytes_in_flight = self.bytes_in_flight.saturating_sub(lost_bytes);
+    fn on_blackhole_suspected(&mut self) {
+        self.blackhole_suspected = true;
 
-        self.congestion_event(lost_bytes, largest_lost_pkt.time_sent, epoch, now);
+        if !self.blackhole_mtu_clamp {
+            return;
+        }
 
-        if self.in_persistent_congestion(largest_lost_pkt.pkt_num) {
-            self.collapse_cwnd();
+        // Fall back to the minimum datagram size, from which DPLPMTUD, if
+        // enabled, searches for a larger size again.
+        if self.pmtud.enabled() {
+            self.pmtud.reset();
         }
+
+        self.on_pmtu_changed(cmp::min(
+            self.max_datagram_size,
+            pmtud::BASE_PLPMTU,
+        ));
     }
 
-    fn congestion_event(
-        &mut self, lost_bytes: usize, time_sent: Instant, epoch: packet::Epoch,
+    pub fn on_pkt_num_space_discarded(
+        &mut self, epoch: packet::Epoch, handshake_status: HandshakeStatus,
         now: Instant,
     ) {
-        if !self.in_congestion_recovery(time_sent) {
-            (self.cc_ops.checkpoint)(self);
-        }
+        self.discarded[epoch] = true;
 
-        (self.cc_ops.congestion_event)(self, lost_bytes, time_sent, epoch, now);
+        self.on_pkt_num_space_reset(epoch, handshake_status, now);
     }
 
-    fn collapse_cwnd(&mut self) {
-        (self.cc_ops.collapse_cwnd)(self);
-    }
+    /// Forgets the packets sent in the given packet number space, which keeps
+    /// being used, e.g. when the client sends its Initial packets again after
+    /// a Retry or Version Negotiation packet.
+    pub fn on_pkt_num_space_reset(
+        &mut self, epoch: packet::Epoch, handshake_status: HandshakeStatus,
+        now: Instant,
+    ) {
+        let unacked_bytes = self.sent[epoch]
+            .iter()
+            .filter(|p| {
+                p.in_flight && p.time_acked.is_none() && p.time_lost.is_none()
+            })
+            .fold(0, |acc, p| acc + p.cc_size());
 
-    pub fn update_app_limited(&mut self, v: bool) {
-        self.app_limited = v;
-    }
+        self.sub_bytes_in_flight(unacked_bytes);
 
-    pub fn app_limited(&self) -> bool {
-        self.app_limited
-    }
+        self.sent[epoch].clear();
+        self.sent_frames_mem[epoch] = 0;
+        self.sent_compact_len[epoch] = SENT_COMPACT_MIN_LEN;
+        self.lost[epoch].clear();
+        self.pto_queued[epoch].clear();
+        self.lost_queued[epoch].clear();
+        self.acked[epoch].clear();
+        self.bursts[epoch] = Burst::default();
 
-    pub fn delivery_rate_update_app_limited(&mut self, v: bool) {
-        self.delivery_rate.update_app_limited(v);
-    }
+        self.time_of_last_sent_ack_eliciting_pkt[epoch] = None;
+        self.loss_time[epoch] = None;
+        self.loss_probes[epoch] = 0;
+        self.in_flight_count[epoch] = 0;
 
-    #[cfg(feature = "qlog")]
-    pub fn maybe_qlog(&mut self) -> Option<EventData> {
-        let qlog_metrics = QlogMetrics {
-            min_rtt: self.min_rtt,
-            smoothed_rtt: self.rtt(),
-            latest_rtt: self.latest_rtt,
-            rttvar: self.rttvar,
-            cwnd: self.cwnd() as u64,
-            bytes_in_flight: self.bytes_in_flight as u64,
-            ssthresh: self.ssthresh as u64,
-        };
+        self.set_loss_detection_timer(handshake_status, now);
 
-        self.qlog_metrics.maybe_update(qlog_metrics)
+        #[cfg(debug_assertions)]
+        self.assert_consistent();
     }
 
-    pub fn send_quantum(&self) -> usize {
-        self.send_quantum
+    /// Checks that the loss recovery state is internally consistent, and
+    /// panics otherwise.
+    ///
+    /// This recomputes the bytes and packets in flight from the sent packets
+    /// and compares them with the tracked counters, checks that no packet
+    /// above the largest acknowledged one was marked as acked, and that the
+    /// loss detection timer is only disarmed when nothing requires it to be
+    /// armed.
+    ///
+    /// In debug builds this is called at the end of ACK processing, loss
+    /// detection and packet number space discarding, which is where packets
+    /// leave the flight. It is not called when packets are sent, as that would
+    /// make sending quadratic in the number of packets in flight.
+    ///
+    /// It only exists in debug builds, or with the `test-utils` feature.
+    #[cfg(feature = "test-utils")]
+    pub fn assert_consistent(&self) {
+        self.check_consistency();
     }
-}
 
-/// Available congestion control algorithms.
-///
-/// This enum provides currently available list of congestion control
-/// algorithms.
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-#[repr(C)]
-pub enum CongestionControlAlgorithm {
-    /// Reno congestion control algorithm. `reno` in a string form.
-    Reno  = 0,
-    /// CUBIC congestion control algorithm (default). `cubic` in a string form.
-    CUBIC = 1,
-    /// BBR congestion control algorithm. `bbr` in a string form.
-    BBR   = 2,
-}
+    #[cfg(all(any(test, debug_assertions), not(feature = "test-utils")))]
+    pub(crate) fn assert_consistent(&self) {
+        self.check_consistency();
+    }
 
-impl FromStr for CongestionControlAlgorithm {
-    type Err = crate::Error;
+    #[cfg(any(test, debug_assertions, feature = "test-utils"))]
+    fn check_consistency(&self) {
+        let mut bytes_in_flight = 0;
 
-    /// Converts a string to `CongestionControlAlgorithm`.
-    ///
-    /// If `name` is not valid, `Error::CongestionControl` is returned.
-    fn from_str(name: &str) -> std::result::Result<Self, Self::Err> {
-        match name {
-            "reno" => Ok(CongestionControlAlgorithm::Reno),
-            "cubic" => Ok(CongestionControlAlgorithm::CUBIC),
-            "bbr" => Ok(CongestionControlAlgorithm::BBR),
+        for epoch in packet::epochs() {
+            let sent = &self.sent[epoch];
 
-            _ => Err(crate::Error::CongestionControl),
-        }
-    }
-}
+            assert!(
+                sent.iter()
+                    .zip(sent.iter().skip(1))
+                    .all(|(a, b)| a.pkt_num < b.pkt_num),
+                "sent packets out of order in epoch {}",
+                epoch
+            );
 
-pub struct CongestionControlOps {
-    pub on_init: fn(r: &mut Recovery),
+            let largest_acked = self.largest_acked_pkt[epoch];
 
-    pub reset: fn(r: &mut Recovery),
+            if let Some(p) = sent.iter().rev().find(|p| p.time_acked.is_some()) {
+                assert!(
+                    largest_acked != std::u64::MAX && p.pkt_num <= largest_acked,
+                    "packet {} acked above largest acked {} in epoch {}",
+                    p.pkt_num,
+                    largest_acked,
+                    epoch
+                );
+            }
 
-    pub on_packet_sent: fn(r: &mut Recovery, sent_bytes: usize, now: Instant),
+            let (count, bytes) = sent
+                .iter()
+                .filter(|p| {
+                    p.in_flight && p.time_acked.is_none() && p.time_lost.is_none()
+                })
+                .fold((0, 0), |(count, bytes), p| {
+                    (count + 1, bytes + p.cc_size())
+                });
 
-    pub on_packets_acked: fn(
-        r: &mut Recovery,
-        packets: &[Acked],
-        epoch: packet::Epoch,
-        now: Instant,
-    ),
+            assert_eq!(
+                self.in_flight_count[epoch], count,
+                "in_flight_count mismatch in epoch {}",
+                epoch
+            );
 
-    pub congestion_event: fn(
-        r: &mut Recovery,
-        lost_bytes: usize,
-        time_sent: Instant,
-        epoch: packet::Epoch,
-        now: Instant,
-    ),
+            bytes_in_flight += bytes;
+        }
 
-    pub collapse_cwnd: fn(r: &mut Recovery),
+        assert_eq!(
+            self.bytes_in_flight, bytes_in_flight,
+            "bytes_in_flight mismatch"
+        );
 
-    pub checkpoint: fn(r: &mut Recovery),
+        if self.loss_detection_timer.deadline().is_none() &&
+            self.paused_at.is_none()
+        {
+            assert!(
+                self.loss_time.iter().all(|t| t.is_none()),
+                "loss detection timer not armed for pending loss time"
+            );
 
-    pub rollback: fn(r: &mut Recovery) -> bool,
+            // Application Data is only probed once the handshake completes,
+            // so only the handshake spaces always need a PTO, unless the
+            // handshake was given up on.
+            for epoch in [packet::EPOCH_INITIAL, packet::EPOCH_HANDSHAKE] {
+                assert!(
+                    self.handshake_unreachable ||
+                        self.in_flight_count[epoch] == 0 ||
+                        self.time_of_last_sent_ack_eliciting_pkt[epoch]
+                            .is_none(),
+                    "loss detection timer not armed for PTO in epoch {}",
+                    epoch
+                );
+            }
+        }
+    }
 
-    pub has_custom_pacing: fn() -> bool,
+    /// Returns the number of sent packets being tracked, in all packet number
+    /// spaces.
+    pub fn sent_packets_len(&self) -> usize {
+        self.sent.iter().map(|s| s.len()).sum()
+    }
 
-    pub debug_fmt:
-        fn(r: &Recovery, formatter: &mut std::fmt::Formatter) -> std::fmt::Result,
-}
+    /// Returns a handle to the loss recovery statistics, that can be read
+    /// from other threads.
+    ///
+    /// Statistics are only published while there is at least one handle, so
+    /// that there is no overhead otherwise.
+    pub fn stats_handle(&self) -> RecoveryStatsHandle {
+        let handle = self.stats_publisher.handle();
 
-impl From<CongestionControlAlgorithm> for &'static CongestionControlOps {
-    fn from(algo: CongestionControlAlgorithm) -> Self {
-        match algo {
-            CongestionControlAlgorithm::Reno => &reno::RENO,
-            CongestionControlAlgorithm::CUBIC => &cubic::CUBIC,
-            CongestionControlAlgorithm::BBR => &bbr::BBR,
+        self.stats_publisher.publish(&self.stats());
+
+        handle
+    }
+
+    /// Returns a snapshot of the loss recovery statistics.
+    pub fn stats(&self) -> RecoveryStats {
+        RecoveryStats {
+            cwnd: self.cwnd(),
+            bytes_in_flight: self.bytes_in_flight,
+            rtt: self.rtt(),
+            min_rtt: self.min_rtt,
+            pacing_rate: self.pacer.rate(),
+            delivery_rate: self.delivery_rate(),
+            lost_count: self.lost_count,
+            lost_bytes: self.bytes_lost,
+            spurious_lost_count: self.lost_spurious_count,
+            ssthresh: self.ssthresh,
+            pto_count: self.pto_count,
+            loss_latency: self.loss_latency,
+            ack_cost: self.ack_cost,
+            lost_frames: self.lost_frames,
+            delay_asymmetry: self.delay_asymmetry,
+            rounds: self.rounds,
+            bandwidth_delay: self.bandwidth_delay,
+            burst_losses: self.burst_losses,
+            loss_rates: self.loss_rate_windows.loss_rates(),
+            phase_losses: self.phase_losses,
+            idle_timeout_conflicts: self.idle_timeout_conflicts,
         }
     }
-}
 
-impl std::fmt::Debug for Recovery {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self.loss_detection_timer {
-            Some(v) => {
-                let now = Instant::now();
+    /// Returns the lost bytes, by type of frame.
+    pub fn lost_frames(&self) -> LostFrames {
+        self.lost_frames
+    }
 
-                if v > now {
-                    let d = v.duration_since(now);
-                    write!(f, "timer={:?} ", d)?;
-                } else {
-                    write!(f, "timer=exp ")?;
-                }
-            },
+    /// Returns the experimental estimate of how the round-trip delay splits
+    /// between the forward and the reverse path.
+    ///
+    /// See `DelayAsymmetry` for the assumptions it is based on.
+    pub fn delay_asymmetry(&self) -> DelayAsymmetry {
+        self.delay_asymmetry
+    }
 
-            None => {
-                write!(f, "timer=none ")?;
-            },
-        };
+    /// Returns the round trips of the path.
+    ///
+    /// See `Rounds` for how they are delimited.
+    pub fn rounds(&self) -> Rounds {
+        self.rounds
+    }
 
-        write!(f, "latest_rtt={:?} ", self.latest_rtt)?;
-        write!(f, "srtt={:?} ", self.smoothed_rtt)?;
-        write!(f, "min_rtt={:?} ", self.min_rtt)?;
-        write!(f, "rttvar={:?} ", self.rttvar)?;
-        write!(f, "loss_time={:?} ", self.loss_time)?;
-        write!(f, "loss_probes={:?} ", self.loss_probes)?;
-        write!(f, "cwnd={} ", self.congestion_window)?;
-        write!(f, "ssthresh={} ", self.ssthresh)?;
-        write!(f, "bytes_in_flight={} ", self.bytes_in_flight)?;
-        write!(f, "app_limited={} ", self.app_limited)?;
-        write!(
-            f,
-            "congestion_recovery_start_time={:?} ",
-            self.congestion_recovery_start_time
-        )?;
-        write!(f, "{:?} ", self.delivery_rate)?;
-        write!(f, "pacer={:?} ", self.pacer)?;
+    /// Returns the lost packets, by their position in the burst they were sent
+    /// in.
+    ///
+    /// See `BurstLosses` for how bursts are delimited.
+    pub fn burst_losses(&self) -> BurstLosses {
+        self.burst_losses
+    }
 
-        if self.hystart.enabled() {
-            write!(f, "hystart={:?} ", self.hystart)?;
+    /// Returns the phase congestion control is in.
+    pub fn congestion_state(&self) -> CongestionState {
+        if self.recovery_episode.is_some() {
+            CongestionState::Recovery
+        } else if (self.cc_ops.in_slow_start)(self) {
+            CongestionState::SlowStart
+        } else {
+            CongestionState::CongestionAvoidance
         }
+    }
 
-        // CC-specific debug info
-        (self.cc_ops.debug_fmt)(self, f)?;
+    /// Returns the lost packets, by the phase congestion control was in when
+    /// they were sent.
+    pub fn phase_losses(&self) -> PhaseLosses {
+        self.phase_losses
+    }
 
-        Ok(())
+    /// Returns the fraction of packets lost over the last `window` of
+    /// activity, up to 10 seconds.
+    ///
+    /// See `LossRates` for how packets are accounted for.
+    pub fn loss_rate(&self, window: Duration) -> f64 {
+        self.loss_rate_windows.loss_rate(window)
     }
-}
 
-#[derive(Clone)]
-pub struct Sent {
-    pub pkt_num: u64,
+    /// Returns smoothed estimates of the available throughput and of the
+    /// queuing delay of the path.
+    pub fn bandwidth_delay_estimates(&self) -> BandwidthDelayEstimates {
+        self.bandwidth_delay
+    }
 
-    pub frames: Vec<frame::Frame>,
+    /// Returns a conservative estimate of how long it would take for `bytes`
+    /// sent now to be acknowledged.
+    ///
+    /// The estimate assumes that nothing else is sent and that no packet is
+    /// lost. Each RTT, a congestion window worth of data is sent, no faster
+    /// than the pacing rate. In slow start, the window doubles every RTT up
+    /// to the slow start threshold. Past it, the window stays the same, and
+    /// sending is also limited by the delivery rate, unless it was sampled
+    /// while the application was limiting sending. One more RTT accounts for
+    /// the last byte to be acknowledged.
+    ///
+    /// This is purely advisory, and doesn't affect sending.
+    pub fn estimate_transfer_time(&self, bytes: usize) -> Duration {
+        if bytes == 0 {
+            return Duration::ZERO;
+        }
 
-    pub time_sent: Instant,
+        let rtt = self.rtt().as_secs_f64();
 
-    pub time_acked: Option<Instant>,
+        // The most bytes that can be sent in an RTT, whatever the window.
+        let max_round_bytes = if self.pacer.enabled() && self.pacer.rate() > 0 {
+            self.pacer.rate() as f64 * rtt
+        } else {
+            f64::INFINITY
+        };
 
-    pub time_lost: Option<Instant>,
+        let ssthresh = self.ssthresh as f64;
 
-    pub size: usize,
+        let mut window = self.congestion_window as f64;
+        let mut left = bytes as f64;
+        let mut rounds = 0.0;
 
-    pub ack_eliciting: bool,
+        while left > window && window < ssthresh && window < max_round_bytes {
+            left -= window;
+            rounds += 1.0;
 
-    pub in_flight: bool,
+            window = (window * 2.0).min(ssthresh);
+        }
 
-    pub delivered: usize,
+        let mut round_bytes = window.min(max_round_bytes);
 
-    pub delivered_time: Instant,
+        if window >= ssthresh &&
+            self.delivery_rate.sample_delivery_rate() > 0 &&
+            !self.delivery_rate.sample_is_app_limited()
+        {
+            round_bytes = round_bytes
+                .min(self.delivery_rate.sample_delivery_rate() as f64 * rtt);
+        }
 
-    pub first_sent_time: Instant,
+        rounds += left / round_bytes;
 
-    pub is_app_limited: bool,
+        let secs = rtt * (rounds + 1.0);
 
-    pub has_data: bool,
-}
+        if secs < u64::MAX as f64 {
+            Duration::from_secs_f64(secs)
+        } else {
+            Duration::MAX
+        }
+    }
 
-impl std::fmt::Debug for Sent {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "pkt_num={:?} ", self.pkt_num)?;
-        write!(f, "pkt_sent_time={:?} ", self.time_sent)?;
-        write!(f, "pkt_size={:?} ", self.size)?;
-        write!(f, "delivered={:?} ", self.delivered)?;
-        write!(f, "delivered_time={:?} ", self.delivered_time)?;
-        write!(f, "first_sent_time={:?} ", self.first_sent_time)?;
-        write!(f, "is_app_limited={} ", self.is_app_limited)?;
-        write!(f, "has_data={} ", self.has_data)?;
+    /// Accounts for lost bytes of STREAM frames that won't be retransmitted,
+    /// e.g. because the stream was closed in the meantime.
+    pub fn on_lost_stream_bytes_dropped(&mut self, bytes: u64) {
+        self.lost_frames.not_retransmitted += bytes;
+    }
+
+    /// Returns the packet and time reordering thresholds currently used by
+    /// loss detection.
+    ///
+    /// Both start from the configured values, and grow when packets turn out
+    /// to be spuriously lost, unless the adaptive reordering threshold is
+    /// disabled.
+    pub fn reorder_thresholds(&self) -> (u64, f64) {
+        (self.pkt_thresh, self.time_thresh)
+    }
+
+    /// Sets the packet and time reordering thresholds used by loss detection,
+    /// e.g. to reproduce conditions observed in the field.
+    ///
+    /// The thresholds are bounded as the configured ones, otherwise
+    /// `Error::CongestionControl` is returned. They keep adapting to
+    /// spurious losses afterwards.
+    pub fn set_reorder_thresholds(&mut self, pkt: u64, time: f64) -> Result<()> {
+        if !(INITIAL_PACKET_THRESHOLD..=MAX_PACKET_THRESHOLD).contains(&pkt) ||
+            !time.is_finite() ||
+            time < 1.0
+        {
+            return Err(crate::Error::CongestionControl);
+        }
+
+        self.pkt_thresh = pkt;
+        self.time_thresh = time;
 
         Ok(())
     }
-}
 
-#[derive(Clone)]
-pub struct Acked {
-    pub pkt_num: u64,
+    /// Returns statistics of how long after being sent packets were declared
+    /// lost.
+    pub fn loss_latency(&self) -> LossLatency {
+        self.loss_latency
+    }
 
-    pub time_sent: Instant,
+    /// Returns whether all data sent was acknowledged, i.e. nothing is in
+    /// flight, and neither retransmissions nor probes are pending.
+    pub fn all_acked(&self) -> bool {
+        self.bytes_in_flight == 0 &&
+            self.lost.iter().all(|lost| lost.is_empty()) &&
+            self.loss_probes.iter().all(|&probes| probes == 0)
+    }
 
-    pub size: usize,
+    /// Starts recording events for a `RecoveryObserver`.
+    pub fn enable_events(&mut self) {
+        if self.events.is_none() {
+            self.events = Some(Vec::new());
+            self.observed_cwnd = self.cwnd();
+        }
+    }
 
-    pub rtt: Duration,
+    /// Removes and returns the events recorded since the last call.
+    pub fn drain_events(&mut self) -> impl Iterator<Item = RecoveryEvent> + '_ {
+        self.events.iter_mut().flat_map(|events| events.drain(..))
+    }
 
-    pub delivered: usize,
+    // Records a change of the congestion window since the last one, if any.
+    fn record_cwnd_change(&mut self) {
+        let cwnd = self.cwnd();
 
-    pub delivered_time: Instant,
+        if let Some(events) = &mut self.events {
+            if cwnd != self.observed_cwnd {
+                events.push(RecoveryEvent::CwndChange(CwndChange {
+                    prev_cwnd: self.observed_cwnd,
+                    cwnd,
+                    ssthresh: self.ssthresh,
+                    bytes_in_flight: self.bytes_in_flight,
+                }));
 
-    pub first_sent_time: Instant,
+                self.observed_cwnd = cwnd;
+            }
+        }
+    }
 
-    pub is_app_limited: bool,
-}
+    fn publish_stats(&self) {
+        if self.stats_publisher.has_readers() {
+            self.stats_publisher.publish(&self.stats());
+        }
+    }
 
-#[derive(Clone, Copy, Debug)]
-pub struct HandshakeStatus {
-    pub has_handshake_keys: bool,
+    /// Returns an estimate of the memory used by the loss recovery state.
+    ///
+    /// This is cheap to call, as it's based on counters maintained as packets
+    /// are sent, acked and lost. Frames queued in `acked` and `lost` are only
+    /// accounted for by the size of their descriptors.
+    pub fn memory_footprint(&self) -> RecoveryMemory {
+        let frame_size = mem::size_of::<frame::Frame>();
+
+        let sent_packets = self
+            .sent
+            .iter()
+            .map(|s| s.capacity() * mem::size_of::<Sent>())
+            .sum::<usize>() +
+            self.sent_frames_mem.iter().sum::<usize>();
+
+        RecoveryMemory {
+            sent_packets,
+
+            acked_frames: self
+                .acked
+                .iter()
+                .map(|a| a.capacity() * frame_size)
+                .sum(),
+
+            lost_frames: self
+                .lost
+                .iter()
+                .map(|l| l.capacity() * frame_size)
+                .sum(),
+
+            fixed: mem::size_of::<Recovery>(),
+        }
+    }
 
-    pub peer_verified_address: bool,
+    /// Returns the number of frames waiting in the acked and lost frames
+    /// backlogs to be processed by the connection.
+    pub fn frame_backlog(&self) -> usize {
+        self.acked.iter().map(|a| a.len()).sum::<usize>() +
+            self.lost.iter().map(|l| l.len()).sum::<usize>()
+    }
 
-    pub completed: bool,
-}
+    /// Returns the total capacity, in number of elements, of the sent packets,
+    /// lost frames and acked frames buffers.
+    pub fn buffer_capacity(&self) -> usize {
+        self.sent.iter().map(|s| s.capacity()).sum::<usize>() +
+            self.lost.iter().map(|l| l.capacity()).sum::<usize>() +
+            self.acked.iter().map(|a| a.capacity()).sum::<usize>()
+    }
 
-#[cfg(test)]
-impl Default for HandshakeStatus {
-    fn default() -> HandshakeStatus {
-        HandshakeStatus {
-            has_handshake_keys: true,
+    /// Performs periodic housekeeping, to be called when the connection's
+    /// timers fire.
+    ///
+    /// Buffers that grew during a burst keep their peak capacity, so once
+    /// they have been under-utilized for a few consecutive ticks (at most one
+    /// per PTO), they are shrunk towards the configured floor.
+    pub fn on_maintenance(&mut self, now: Instant) {
+        if let Some(last) = self.last_maintenance_tick {
+            if now < last + self.pto() {
+                return;
+            }
+        }
 
-            peer_verified_address: true,
+        self.last_maintenance_tick = Some(now);
 
-            completed: true,
+        let floor = self.buffer_capacity_floor;
+
+        let is_under_utilized = |len: usize, capacity: usize| {
+            capacity > floor && len * BUFFER_SHRINK_RATIO < capacity
+        };
+
+        let under_utilized = packet::epochs().any(|e| {
+            is_under_utilized(self.sent[e].len(), self.sent[e].capacity()) ||
+                is_under_utilized(self.lost[e].len(), self.lost[e].capacity()) ||
+                is_under_utilized(
+                    self.acked[e].len(),
+                    self.acked[e].capacity(),
+                )
+        });
+
+        if !under_utilized {
+            self.buffer_low_utilization_ticks = 0;
+            return;
+        }
+
+        self.buffer_low_utilization_ticks += 1;
+
+        if self.buffer_low_utilization_ticks < BUFFER_SHRINK_TICKS {
+            return;
+        }
+
+        self.buffer_low_utilization_ticks = 0;
+
+        // Leave some room for the buffers to grow again without reallocating
+        // right away.
+        for e in packet::epochs() {
+            let sent = &mut self.sent[e];
+            sent.shrink_to(cmp::max(floor, sent.len() * 2));
+
+            let lost = &mut self.lost[e];
+            lost.shrink_to(cmp::max(floor, lost.len() * 2));
+
+            let acked = &mut self.acked[e];
+            acked.shrink_to(cmp::max(floor, acked.len() * 2));
         }
     }
-}
 
-fn sub_abs(lhs: Duration, rhs: Duration) -> Duration {
-    if lhs > rhs {
-        lhs - rhs
-    } else {
-        rhs - lhs
+    /// Stops retransmitting data, as the connection is closing.
+    ///
+    /// Lost frames other than ACKs are discarded, now and from then on, and
+    /// pending probes are cancelled. Packets keep being acknowledged and
+    /// detected as lost, but the probe timeout is no longer armed.
+    pub fn enter_draining(&mut self) {
+        self.draining = true;
+
+        for e in packet::epochs() {
+            retain_frames(
+                &mut self.lost[e],
+                &mut self.lost_queued[e],
+                is_ack_frame,
+            );
+        }
+
+        self.loss_probes = [0; packet::EPOCH_COUNT];
+
+        let (earliest_loss_time, _) = self.loss_time_and_space();
+        self.loss_detection_timer.set(earliest_loss_time);
     }
-}
 
-// We don't need to log all qlog metrics every time there is a recovery event.
+    /// Returns whether `enter_draining()` was called.
+    pub fn is_draining(&self) -> bool {
+        self.draining
+    }
+
+    /// Returns when the loss detection timer expires.
+    ///
+    /// Small changes of the deadline are not reported when they are
+    /// coalesced, see [`LossDetectionTimer`].
+    pub fn loss_detection_timer(&self) -> Option<Instant> {
+        self.loss_detection_timer.reported()
+    }
+
+    /// Returns the number of loss detection timer deadline changes that were
+    /// not reported.
+    pub fn loss_timer_suppressed_updates(&self) -> usize {
+        self.loss_detection_timer.suppressed_updates()
+    }
+
+    /// Returns the time the last ack-eliciting packet was sent, in any packet
+    /// number space.
+    pub fn last_sent_ack_eliciting_time(&self) -> Option<Instant> {
+        self.last_sent_ack_eliciting_time
+    }
+
+    /// Returns the time elapsed since the last ack-eliciting packet was sent
+    /// in the given packet number space, if any.
+    pub fn time_since_last_ack_eliciting(
+        &self, epoch: packet::Epoch, now: Instant,
+    ) -> Option<Duration> {
+        self.time_of_last_sent_ack_eliciting_pkt[epoch]
+            .map(|t| now.saturating_duration_since(t))
+    }
+
+    /// Returns how long the oldest ack-eliciting packet still in flight in
+    /// the given packet number space has been waiting for an ACK, if any.
+    pub fn oldest_unacked_age(
+        &self, epoch: packet::Epoch, now: Instant,
+    ) -> Option<Duration> {
+        self.sent[epoch]
+            .iter()
+            .find(|p| {
+                p.in_flight &&
+                    p.ack_eliciting &&
+                    p.time_acked.is_none() &&
+                    p.time_lost.is_none()
+            })
+            .map(|p| now.saturating_duration_since(p.time_sent))
+    }
+
+    /// Removes and returns the lost frames of the given packet number space,
+    /// in the order they should be retransmitted.
+    ///
+    /// Frames other than STREAM frames come first. STREAM frames follow, in
+    /// order of the priority returned by `priority` for their stream ID,
+    /// lowest first. Frames with the same priority are kept in the order
+    /// they were declared lost.
+    pub fn drain_lost_frames<K: Ord>(
+        &mut self, epoch: packet::Epoch, mut priority: impl FnMut(u64) -> K,
+    ) -> std::vec::Drain<'_, frame::Frame> {
+        self.lost[epoch].sort_by_key(|f| match f {
+            frame::Frame::StreamHeader { stream_id, .. } =>
+                Some(priority(*stream_id)),
+
+            _ => None,
+        });
+
+        self.pto_queued[epoch].clear();
+        self.lost_queued[epoch].clear();
+
+        self.lost[epoch].drain(..)
+    }
+
+    /// Cancels the retransmission of the frames of the lost packet `pkt_num`
+    /// of the given packet number space, e.g. because its content was
+    /// recovered by other means, and returns the number of frames removed.
+    ///
+    /// This has no effect once the lost frames were drained for
+    /// retransmission, or if the packet wasn't declared lost.
+    pub fn cancel_retransmission(
+        &mut self, epoch: packet::Epoch, pkt_num: u64,
+    ) -> usize {
+        let range = match self.lost_queued[epoch].remove(&pkt_num) {
+            Some(v) => v,
+
+            None => return 0,
+        };
+
+        self.lost[epoch].drain(range.clone());
+
+        // The frames of the packets declared lost later move back.
+        for r in self.lost_queued[epoch].values_mut() {
+            if r.start >= range.end {
+                *r = r.start - range.len()..r.end - range.len();
+            }
+        }
+
+        range.len()
+    }
+
+    /// Returns the packets of the given packet number space that are still in
+    /// flight, neither acked nor declared lost, in packet number order.
+    pub fn outstanding_packets(
+        &self, epoch: packet::Epoch,
+    ) -> impl Iterator<Item = OutstandingPacket<'_>> {
+        self.sent[epoch]
+            .iter()
+            .filter(|p| {
+                p.in_flight && p.time_acked.is_none() && p.time_lost.is_none()
+            })
+            .map(|p| OutstandingPacket {
+                pkt_num: p.pkt_num,
+                size: p.size,
+                time_sent: p.time_sent,
+                ack_eliciting: p.ack_eliciting,
+                frames: &p.frames,
+            })
+    }
+
+    /// Returns a formatter listing the outstanding packets of all packet
+    /// number spaces, with send times relative to `now`.
+    ///
+    /// At most `OUTSTANDING_DUMP_MAX` packets are listed per packet number
+    /// space, followed by the number of packets left out.
+    pub fn outstanding_dump(&self, now: Instant) -> OutstandingDump<'_> {
+        OutstandingDump { r: self, now }
+    }
+
+    /// Stops retransmitting the data of a stream sent before `sent_before`.
+    ///
+    /// Frames of the stream are removed from packets still in flight that
+    /// were sent by then, so they are not retransmitted on loss or picked for
+    /// probes, as well as from the lost frames waiting to be retransmitted.
+    /// Returns the number of frames removed.
+    pub fn drop_stream_frames(
+        &mut self, stream_id: u64, sent_before: Instant,
+    ) -> usize {
+        let epoch = packet::EPOCH_APPLICATION;
+
+        let is_stream_frame = |f: &frame::Frame| {
+            matches!(f, frame::Frame::StreamHeader { stream_id: id, .. }
+                if *id == stream_id)
+        };
+
+        let mut dropped = 0;
+
+        let unacked_iter = self.sent[epoch]
+            .iter_mut()
+            .take_while(|p| p.time_sent <= sent_before)
+            .filter(|p| p.time_acked.is_none() && p.time_lost.is_none());
+
+        for unacked in unacked_iter {
+            let frames_len = unacked.frames.len();
+            let frames_mem = frames_heap_size(&unacked.frames);
+
+            unacked.frames.retain(|f| !is_stream_frame(f));
+
+            if unacked.frames.len() == frames_len {
+                continue;
+            }
+
+            dropped += frames_len - unacked.frames.len();
+
+            unacked.content = SentContent::from_frames(&unacked.frames);
+
+            self.sent_frames_mem[epoch] = self.sent_frames_mem[epoch]
+                .saturating_sub(frames_mem) +
+                frames_heap_size(&unacked.frames);
+        }
+
+        let mut lost_bytes = 0;
+
+        dropped += retain_frames(
+            &mut self.lost[epoch],
+            &mut self.lost_queued[epoch],
+            |f| match f {
+                frame::Frame::StreamHeader { length, .. }
+                    if is_stream_frame(f) =>
+                {
+                    lost_bytes += *length as u64;
+                    false
+                },
+
+                _ => true,
+            },
+        );
+
+        self.lost_frames.not_retransmitted += lost_bytes;
+
+        dropped
+    }
+
+    pub fn cwnd(&self) -> usize {
+        self.congestion_window
+    }
+
+    pub fn bytes_in_flight(&self) -> usize {
+        self.bytes_in_flight
+    }
+
+    pub fn cwnd_available(&self) -> usize {
+        // Ignore cwnd when sending probe packets, of which there are at most
+        // `max_loss_probes` across all epochs.
+        if self.total_loss_probes() > 0 {
+            return std::usize::MAX;
+        }
+
+        self.cwnd_room()
+    }
+
+    /// Returns how much can still be sent in the current round, that is, what's
+    /// left of the congestion window and, when pacing, of the current burst.
+    ///
+    /// This is a hint for schedulers to decide what to do with the last bytes
+    /// of the round, e.g. to avoid starting to send a new stream with them.
+    pub fn remaining_round_budget(&self) -> usize {
+        let budget = self.cwnd_available();
+
+        match self.pacer.burst_budget() {
+            Some(burst) => cmp::min(budget, burst),
+
+            None => budget,
+        }
+    }
+
+    // Returns how much of the congestion window is available.
+    fn cwnd_room(&self) -> usize {
+        // Open more space (snd_cnt) for PRR when allowed.
+        self.congestion_window.saturating_sub(self.bytes_in_flight) +
+            self.prr.snd_cnt
+    }
+
+    /// Returns how much can be sent right now, and when.
+    ///
+    /// The budget is what's left of the congestion window or, when probes are
+    /// pending, enough for all of them if that's more. It is further limited
+    /// by `amplification_credit`, if any, which is what the anti-amplification
+    /// limit still allows to be sent before the peer's address is validated.
+    /// Nothing can be sent while sending is paused.
+    pub fn send_allowance(
+        &self, now: Instant, amplification_credit: Option<usize>,
+    ) -> SendAllowance {
+        if self.paused_at.is_some() {
+            return SendAllowance {
+                bytes: 0,
+
+                probe_only: false,
+
+                deferred_until: None,
+
+                epoch: None,
+            };
+        }
+
+        let cwnd_room = self.cwnd_room();
+
+        let probe_budget = self.total_loss_probes() * self.max_datagram_size;
+
+        let mut bytes = cmp::max(cwnd_room, probe_budget);
+
+        if let Some(credit) = amplification_credit {
+            bytes = cmp::min(bytes, credit);
+        }
+
+        let release_time = self.pacer.next_send_time(now);
+
+        SendAllowance {
+            bytes,
+
+            probe_only: probe_budget > cwnd_room,
+
+            deferred_until: if self.pacer.enabled() && release_time > now {
+                Some(release_time)
+            } else {
+                None
+            },
+
+            epoch: LOSS_PROBES_PRIORITY
+                .iter()
+                .copied()
+                .find(|&e| self.loss_probes[e] > 0),
+        }
+    }
+
+    /// Returns the latest RTT sample, if any was taken.
+    pub fn latest_rtt(&self) -> Option<Duration> {
+        self.smoothed_rtt.map(|_| self.latest_rtt)
+    }
+
+    pub fn rtt(&self) -> Duration {
+        self.smoothed_rtt.unwrap_or(self.initial_rtt)
+    }
+
+    pub fn rttvar(&self) -> Duration {
+        self.rttvar
+    }
+
+    pub fn pto(&self) -> Duration {
+        self.rtt() + cmp::max(self.rttvar * 4, self.granularity)
+    }
+
+    pub fn delivery_rate(&self) -> u64 {
+        self.delivery_rate.sample_delivery_rate()
+    }
+
+    pub fn max_datagram_size(&self) -> usize {
+        self.max_datagram_size
+    }
+
+    /// Returns the size of the next PMTU probe, if one should be sent.
+    pub fn pmtud_probe_size(&mut self, now: Instant) -> Option<usize> {
+        self.pmtud.probe_size(now)
+    }
+
+    /// Lowers the datagram size to the given limit, if it is smaller.
+    ///
+    /// The datagram size is only raised again by DPLPMTUD, once a larger
+    /// size is confirmed by an acknowledged probe.
+    pub fn update_max_datagram_size(&mut self, new_max_datagram_size: usize) {
+        self.pmtud.set_max_size(new_max_datagram_size);
+
+        let max_datagram_size =
+            cmp::min(self.max_datagram_size, new_max_datagram_size);
+
+        // Update cwnd if it hasn't been updated yet.
+        if self.congestion_window ==
+            self.max_datagram_size * self.initial_congestion_window_packets
+        {
+            self.congestion_window =
+                max_datagram_size * self.initial_congestion_window_packets;
+        }
+
+        self.set_max_datagram_size(max_datagram_size);
+    }
+
+    // Applies a new datagram size discovered by DPLPMTUD. The congestion
+    // window and send quantum are kept the same in number of packets.
+    fn on_pmtu_changed(&mut self, new_max_datagram_size: usize) {
+        let old_max_datagram_size = self.max_datagram_size;
+
+        if new_max_datagram_size == old_max_datagram_size {
+            return;
+        }
+
+        self.congestion_window = cmp::max(
+            self.congestion_window * new_max_datagram_size /
+                old_max_datagram_size,
+            new_max_datagram_size * MINIMUM_WINDOW_PACKETS,
+        );
+
+        self.set_max_datagram_size(new_max_datagram_size);
+    }
+
+    // Switches to a new datagram size, keeping the send quantum the same in
+    // number of packets, so that bursts are planned with the new size right
+    // away.
+    fn set_max_datagram_size(&mut self, new_max_datagram_size: usize) {
+        let old_max_datagram_size = self.max_datagram_size;
+
+        if new_max_datagram_size == old_max_datagram_size {
+            return;
+        }
+
+        self.send_quantum = cmp::max(
+            self.send_quantum * new_max_datagram_size / old_max_datagram_size,
+            new_max_datagram_size,
+        );
+
+        self.pacer = pacer::Pacer::new(
+            self.pacer.enabled(),
+            self.send_quantum,
+            self.pacer.rate(),
+            new_max_datagram_size,
+            self.clock.now(),
+        );
+
+        self.max_datagram_size = new_max_datagram_size;
+    }
+
+    fn update_delay_asymmetry(
+        &mut self, latest_rtt: Duration, ack_delay: Duration, now: Instant,
+    ) {
+        // `now` is when the ACK frame was received, which can precede its
+        // processing.
+        let local_delay = self.clock.now().saturating_duration_since(now);
+
+        let mut delay = DelayAsymmetry {
+            latest_rtt,
+            peer_ack_delay: ack_delay,
+            local_delay,
+            forward: Duration::ZERO,
+            reverse: Duration::ZERO,
+        };
+
+        let network_rtt = delay.network_rtt();
+
+        let min_network_rtt = match self.min_network_rtt {
+            Some(v) => cmp::min(v, network_rtt),
+
+            None => network_rtt,
+        };
+
+        self.min_network_rtt = Some(min_network_rtt);
+
+        delay.forward = min_network_rtt / 2;
+        delay.reverse = network_rtt.saturating_sub(delay.forward);
+
+        self.delay_asymmetry = delay;
+    }
+
+    fn update_rtt(
+        &mut self, latest_rtt: Duration, ack_delay: Duration, now: Instant,
+    ) {
+        self.latest_rtt = latest_rtt;
+
+        match self.smoothed_rtt {
+            // First RTT sample.
+            None => {
+                self.min_rtt = self.minmax_filter.reset(now, latest_rtt);
+
+                self.smoothed_rtt = Some(latest_rtt);
+
+                self.rttvar = latest_rtt / 2;
+            },
+
+            Some(srtt) => {
+                self.min_rtt =
+                    self.minmax_filter.running_min(RTT_WINDOW, now, latest_rtt);
+
+                let ack_delay = cmp::min(self.max_ack_delay, ack_delay);
+
+                // Adjust for ack delay if plausible.
+                let adjusted_rtt = if latest_rtt > self.min_rtt + ack_delay {
+                    latest_rtt - ack_delay
+                } else {
+                    latest_rtt
+                };
+
+                self.rttvar = self.rttvar.mul_f64(3.0 / 4.0) +
+                    sub_abs(srtt, adjusted_rtt).mul_f64(1.0 / 4.0);
+
+                self.smoothed_rtt = Some(
+                    srtt.mul_f64(7.0 / 8.0) + adjusted_rtt.mul_f64(1.0 / 8.0),
+                );
+            },
+        }
+
+        self.bandwidth_delay.on_rtt_update(self.rtt(), self.min_rtt);
+    }
+
+    fn loss_time_and_space(&self) -> (Option<Instant>, packet::Epoch) {
+        let mut loss_time = self.loss_time;
+
+        for e in packet::epochs() {
+            if self.discarded[e] {
+                loss_time[e] = None;
+            }
+        }
+
+        earliest_time_and_space(loss_time)
+    }
+
+    fn pto_time_and_space(
+        &self, handshake_status: HandshakeStatus, now: Instant,
+    ) -> (Option<Instant>, packet::Epoch) {
+        let mut duration = self.pto() * 2_u32.pow(self.pto_count);
+
+        // CRYPTO data is probed sooner, unless the server can't send more
+        // until the client's address is verified anyway.
+        let handshake_duration = if handshake_status.amplification_limited {
+            duration
+        } else {
+            duration.mul_f64(self.handshake_pto_multiplier)
+        };
+
+        // Arm PTO from now when there are no inflight packets.
+        if self.bytes_in_flight == 0 {
+            let epoch = if handshake_status.has_handshake_keys {
+                packet::EPOCH_HANDSHAKE
+            } else {
+                packet::EPOCH_INITIAL
+            };
+
+            if self.discarded[epoch] {
+                return (None, epoch);
+            }
+
+            return (Some(now + handshake_duration), epoch);
+        }
+
+        let mut pto_timeouts = [None; packet::EPOCH_COUNT];
+
+        // Iterate over all packet number spaces.
+        for e in packet::epochs() {
+            if self.discarded[e] || self.in_flight_count[e] == 0 {
+                continue;
+            }
+
+            if e == packet::EPOCH_APPLICATION {
+                // Until the handshake completes, Application Data (e.g. 0-RTT)
+                // is probed along with the handshake, so that it's covered
+                // even when it's the only data in flight.
+                if !handshake_status.completed {
+                    let space = if handshake_status.has_handshake_keys {
+                        packet::EPOCH_HANDSHAKE
+                    } else {
+                        packet::EPOCH_INITIAL
+                    };
+
+                    if pto_timeouts[space].is_none() {
+                        pto_timeouts[space] = self
+                            .time_of_last_sent_ack_eliciting_pkt[e]
+                            .map(|t| t + handshake_duration);
+                    }
+
+                    break;
+                }
+
+                // Include max_ack_delay and backoff for Application Data.
+                duration += self.max_ack_delay * 2_u32.pow(self.pto_count);
+            } else {
+                duration = handshake_duration;
+            }
+
+            pto_timeouts[e] =
+                self.time_of_last_sent_ack_eliciting_pkt[e].map(|t| t + duration);
+        }
+
+        earliest_time_and_space(pto_timeouts)
+    }
+
+    fn set_loss_detection_timer(
+        &mut self, handshake_status: HandshakeStatus, now: Instant,
+    ) {
+        if self.paused_at.is_some() {
+            self.loss_detection_timer.set(None);
+            return;
+        }
+
+        let timeout = self.loss_detection_deadline(handshake_status, now);
+
+        let timeout = match timeout {
+            Some(t) if self.loss_time_and_space().0.is_none() =>
+                Some(self.bound_pto_by_idle_deadline(t, now)),
+
+            t => t,
+        };
+
+        // The timer also applies the congestion response held back, if any.
+        let timeout = match (timeout, self.held_loss) {
+            (Some(t), Some(hold)) => Some(cmp::min(t, hold.deadline)),
+
+            (t, hold) => t.or(hold.map(|h| h.deadline)),
+        };
+
+        self.loss_detection_timer.set(timeout);
+    }
+
+    // Returns when the probe timeout should fire, given that it would fire
+    // at `pto`.
+    //
+    // A probe that only fires once the connection timed out is of no use, so
+    // unless one was already sent since the idle timer last restarted, it is
+    // sent early enough for its ACK to restart the idle timer in time, or
+    // right away if that's too late already.
+    fn bound_pto_by_idle_deadline(
+        &mut self, pto: Instant, now: Instant,
+    ) -> Instant {
+        let idle_deadline = match self.idle_deadline {
+            Some(v) if pto > v => v,
+
+            _ => return pto,
+        };
+
+        let conflict = match self.idle_conflict {
+            Some(c) if c.idle_deadline == idle_deadline => c,
+
+            _ => {
+                self.idle_timeout_conflicts += 1;
+
+                trace!(
+                    "{} probe timeout {:?} after idle timeout {:?}",
+                    self.trace_id,
+                    pto.saturating_duration_since(now),
+                    idle_deadline.saturating_duration_since(now)
+                );
+
+                IdleConflict {
+                    idle_deadline,
+                    probed: false,
+                }
+            },
+        };
+
+        self.idle_conflict = Some(conflict);
+
+        if !self.idle_bounded_pto || conflict.probed {
+            return pto;
+        }
+
+        idle_deadline
+            .checked_sub(self.rtt())
+            .map_or(now, |t| cmp::max(t, now))
+    }
+
+    /// Sets when the connection times out unless something is received, so
+    /// that the probe timeout can be bounded by it.
+    ///
+    /// See `Config::enable_idle_bounded_pto()`.
+    pub fn set_idle_deadline(
+        &mut self, deadline: Option<Instant>, handshake_status: HandshakeStatus,
+        now: Instant,
+    ) {
+        if self.idle_deadline == deadline {
+            return;
+        }
+
+        self.idle_deadline = deadline;
+
+        self.set_loss_detection_timer(handshake_status, now);
+    }
+
+    // Returns when packets are to be declared lost or probed next, if ever.
+    fn loss_detection_deadline(
+        &self, handshake_status: HandshakeStatus, now: Instant,
+    ) -> Option<Instant> {
+        let (earliest_loss_time, _) = self.loss_time_and_space();
+
+        if earliest_loss_time.is_some() {
+            // Time threshold loss detection.
+            return earliest_loss_time;
+        }
+
+        if self.draining ||
+            self.handshake_unreachable ||
+            (self.bytes_in_flight == 0 &&
+                handshake_status.peer_verified_address)
+        {
+            return None;
+        }
+
+        // PTO timer.
+        let (timeout, _) = self.pto_time_and_space(handshake_status, now);
+        timeout
+    }
+
+    /// Declares the outstanding packets in the given range of packet numbers
+    /// lost right away, e.g. when the network interface went down, and
+    /// returns how many packets and bytes were declared lost.
+    ///
+    /// Their frames are scheduled for retransmission as with any other loss.
+    /// Packets that were already acknowledged or lost are left alone. If
+    /// `congestion_response` is false, the loss is not treated as a sign of
+    /// congestion, and the congestion window is left unchanged.
+    pub fn declare_lost(
+        &mut self, epoch: packet::Epoch, pkt_nums: Range<u64>,
+        congestion_response: bool, handshake_status: HandshakeStatus,
+        now: Instant,
+    ) -> (usize, usize) {
+        let mut lost_packets = 0;
+        let mut lost_bytes = 0;
+        let mut lost_probe_bytes = 0;
+
+        let mut largest_lost_pkt = None;
+
+        let start = self.sent_index(epoch, pkt_nums.start);
+
+        let mut sent = mem::take(&mut self.sent[epoch]);
+
+        let unacked_iter = sent
+            .iter_mut()
+            .skip(start)
+            .take_while(|p| p.pkt_num < pkt_nums.end)
+            // Skip packets that have already been acked or lost.
+            .filter(|p| p.time_acked.is_none() && p.time_lost.is_none());
+
+        for unacked in unacked_iter {
+            self.mark_lost(epoch, unacked, now);
+
+            trace!(
+                "{} packet {} declared lost on epoch {}",
+                self.trace_id,
+                unacked.pkt_num,
+                epoch
+            );
+
+            // The loss of a PMTU probe says nothing about the path MTU here,
+            // so it's neither treated as a failed probe, nor as a congestion
+            // signal.
+            if unacked.is_pmtud_probe {
+                self.pmtud.on_probe_abandoned();
+
+                lost_probe_bytes += unacked.cc_size();
+
+                continue;
+            }
+
+            if unacked.in_flight {
+                lost_bytes += unacked.cc_size();
+
+                largest_lost_pkt = Some(unacked.clone());
+            }
+
+            lost_packets += 1;
+        }
+
+        self.sent[epoch] = sent;
+
+        self.bytes_lost += lost_bytes as u64;
+
+        self.sub_bytes_in_flight(lost_probe_bytes);
+
+        match largest_lost_pkt {
+            Some(pkt) if congestion_response =>
+                self.on_packets_lost(lost_bytes, &pkt, epoch, now),
+
+            _ => self.sub_bytes_in_flight(lost_bytes),
+        }
+
+        self.drain_packets(epoch, now);
+
+        self.set_loss_detection_timer(handshake_status, now);
+
+        self.record_cwnd_change();
+
+        self.publish_stats();
+
+        #[cfg(debug_assertions)]
+        self.assert_consistent();
+
+        (lost_packets, lost_bytes)
+    }
+
+    fn detect_lost_packets(
+        &mut self, epoch: packet::Epoch, now: Instant,
+    ) -> (usize, usize) {
+        let largest_acked = self.largest_acked_pkt[epoch];
+
+        self.loss_time[epoch] = None;
+
+        let loss_delay =
+            cmp::max(self.latest_rtt, self.rtt()).mul_f64(self.time_thresh);
+
+        // Minimum time of kGranularity before packets are deemed lost.
+        let loss_delay = cmp::max(loss_delay, self.granularity);
+
+        // Packets sent before this time are deemed lost. If the loss delay
+        // goes back further than the clock's origin, which can be close to
+        // zero on some platforms, no packet is old enough yet. The same goes
+        // while sending is paused, as the pause would count toward it.
+        let lost_send_time = if self.paused_at.is_some() {
+            None
+        } else {
+            now.checked_sub(loss_delay)
+        };
+
+        // Packets below a long run of acknowledged packets are only given the
+        // timer granularity to be acknowledged.
+        let ack_implied_loss_below = self.ack_implied_loss_below.take();
+
+        let ack_implied_lost_send_time = if self.paused_at.is_some() {
+            None
+        } else {
+            now.checked_sub(self.granularity)
+        };
+
+        let mut lost_packets = 0;
+        let mut lost_bytes = 0;
+        let mut lost_probe_bytes = 0;
+
+        let mut largest_lost_pkt = None;
+
+        let mut black_hole_pmtu = None;
+
+        let mut new_lost_datagrams = false;
+
+        let mut sent = mem::take(&mut self.sent[epoch]);
+
+        let unacked_iter = sent
+            .iter_mut()
+            // Skip packets that follow the largest acked packet.
+            .take_while(|p| p.pkt_num <= largest_acked)
+            // Skip packets that have already been acked or lost.
+            .filter(|p| p.time_acked.is_none() && p.time_lost.is_none());
+
+        for unacked in unacked_iter {
+            let thresh_lost = largest_acked >= unacked.pkt_num + self.pkt_thresh;
+
+            // A packet that shared its datagram with a packet lost by the
+            // packet threshold is only deemed lost once a later packet of
+            // its own epoch was acked, so delayed ACKs aren't mistaken for
+            // losses.
+            let coalesced_lost = self.coalesced_loss &&
+                unacked.pkt_num < largest_acked &&
+                matches!(unacked.datagram_id,
+                    Some(id) if self.lost_datagrams.contains(&id));
+
+            let (loss_delay, lost_send_time) = match ack_implied_loss_below {
+                Some(pn) if unacked.pkt_num < pn =>
+                    (self.granularity, ack_implied_lost_send_time),
+
+                _ => (loss_delay, lost_send_time),
+            };
+
+            // Mark packet as lost, or set time when it should be marked.
+            if lost_send_time.map_or(false, |t| unacked.time_sent <= t) ||
+                thresh_lost ||
+                coalesced_lost
+            {
+                if let (true, Some(id)) = (thresh_lost, unacked.datagram_id) {
+                    if self.coalesced_loss && !self.lost_datagrams.contains(&id) {
+                        if self.lost_datagrams.len() == MAX_LOST_DATAGRAMS {
+                            self.lost_datagrams.pop_front();
+                        }
+
+                        self.lost_datagrams.push_back(id);
+
+                        new_lost_datagrams = true;
+                    }
+                }
+
+                self.mark_lost(epoch, unacked, now);
+
+                // A lost PMTU probe only means that the path doesn't support
+                // the probed size, so it's not treated as a congestion signal.
+                if unacked.is_pmtud_probe {
+                    self.pmtud.on_probe_lost(unacked.size, now);
+
+                    lost_probe_bytes += unacked.cc_size();
+
+                    trace!(
+                        "{} pmtud probe {} lost on epoch {}",
+                        self.trace_id,
+                        unacked.pkt_num,
+                        epoch
+                    );
+
+                    continue;
+                }
+
+                if unacked.in_flight {
+                    if let Some(pmtu) =
+                        self.pmtud.on_packet_lost(unacked.size, unacked.time_sent)
+                    {
+                        black_hole_pmtu = Some(pmtu);
+                    }
+
+                    lost_bytes += unacked.cc_size();
+
+                    // Frames have already been removed from the packet, so
+                    // cloning the whole packet should be relatively cheap.
+                    largest_lost_pkt = Some(unacked.clone());
+
+                    trace!(
+                        "{} packet {} lost on epoch {}",
+                        self.trace_id,
+                        unacked.pkt_num,
+                        epoch
+                    );
+                }
+
+                lost_packets += 1;
+            } else {
+                let loss_time = match self.loss_time[epoch] {
+                    None => unacked.time_sent + loss_delay,
+
+                    Some(loss_time) =>
+                        cmp::min(loss_time, unacked.time_sent + loss_delay),
+                };
+
+                self.loss_time[epoch] = Some(loss_time);
+            }
+        }
+
+        self.sent[epoch] = sent;
+
+        self.bytes_lost += lost_bytes as u64;
+
+        self.sub_bytes_in_flight(lost_probe_bytes);
+
+        if let Some(pkt) = largest_lost_pkt {
+            self.on_packets_lost(lost_bytes, &pkt, epoch, now);
+        }
+
+        if let Some(pmtu) = black_hole_pmtu {
+            self.on_pmtu_changed(pmtu);
+        }
+
+        self.drain_packets(epoch, now);
+
+        // Packets coalesced with the ones just lost may now be deemed lost
+        // in the other epochs.
+        if new_lost_datagrams {
+            for e in packet::epochs() {
+                if e == epoch || self.largest_acked_pkt[e] == u64::MAX {
+                    continue;
+                }
+
+                let (packets, bytes) = self.detect_lost_packets(e, now);
+
+                lost_packets += packets;
+                lost_bytes += bytes;
+            }
+        }
+
+        (lost_packets, lost_bytes)
+    }
+
+    // Marks an outstanding packet as lost, and queues its frames for
+    // retransmission. Lost PMTU probes are left out of the loss counts and
+    // events, as they don't mean that the path lost anything.
+    //
+    // The packet must be taken out of `sent` while this is called, as it's
+    // borrowed from there.
+    fn mark_lost(
+        &mut self, epoch: packet::Epoch, unacked: &mut Sent, now: Instant,
+    ) {
+        self.sent_frames_mem[epoch] = self.sent_frames_mem[epoch]
+            .saturating_sub(frames_heap_size(&unacked.frames));
+
+        self.lost_frames.record(&unacked.frames);
+        self.burst_losses.record(
+            unacked.burst_index.into(),
+            self.bursts[epoch].size_of(unacked),
+        );
+        self.phase_losses.record(unacked.congestion_state);
+
+        if self.draining {
+            unacked.frames.retain(is_ack_frame);
+        }
+
+        let start = self.lost[epoch].len();
+
+        self.lost[epoch].append(&mut unacked.frames);
+
+        if self.lost[epoch].len() > start {
+            self.lost_queued[epoch]
+                .insert(unacked.pkt_num, start..self.lost[epoch].len());
+        }
+
+        unacked.time_lost = Some(now);
+
+        self.loss_latency.record(unacked.loss_latency());
+
+        if unacked.in_flight || unacked.is_pmtud_probe {
+            self.in_flight_count[epoch] =
+                self.in_flight_count[epoch].saturating_sub(1);
+        }
+
+        if unacked.is_pmtud_probe {
+            return;
+        }
+
+        self.lost_count += 1;
+        self.loss_rate_windows.on_packet_lost(now);
+
+        if let Some(events) = &mut self.events {
+            events.push(RecoveryEvent::PacketLost(
+                LostPacket {
+                    pkt_num: unacked.pkt_num,
+                    pkt_type: packet::Type::from_epoch(epoch),
+                    size: unacked.size,
+                    latency: unacked.loss_latency(),
+                },
+                epoch,
+            ));
+        }
+    }
+
+    // Returns the index of the first packet in the sent packets list whose
+    // packet number is not lower than `pkt_num`.
+    //
+    // Packet numbers are mostly contiguous, so the offset from the first
+    // packet in the list is tried first. This only falls back to a binary
+    // search when some packet numbers are missing, e.g. because they were
+    // used on a different path.
+    fn sent_index(&self, epoch: packet::Epoch, pkt_num: u64) -> usize {
+        let sent = &self.sent[epoch];
+
+        let front_pkt_num = match sent.front() {
+            Some(p) => p.pkt_num,
+
+            None => return 0,
+        };
+
+        if pkt_num <= front_pkt_num {
+            return 0;
+        }
+
+        if let Ok(offset) = usize::try_from(pkt_num - front_pkt_num) {
+            if let Some(p) = sent.get(offset) {
+                if p.pkt_num == pkt_num {
+                    return offset;
+                }
+            }
+        }
+
+        sent.partition_point(|p| p.pkt_num < pkt_num)
+    }
+
+    fn drain_packets(&mut self, epoch: packet::Epoch, now: Instant) {
+        let mut lowest_non_expired_pkt_index = self.sent[epoch].len();
+
+        // In order to avoid removing elements from the middle of the list
+        // (which would require copying other elements to compact the list),
+        // we only remove a contiguous range of elements from the start of the
+        // list.
+        //
+        // This means that acked or lost elements coming after this will not
+        // be removed at this point, but their removal is delayed for a later
+        // time, once the gaps have been filled or the list is compacted.
+
+        // First, find the first element that is neither acked nor lost.
+        for (i, pkt) in self.sent[epoch].iter().enumerate() {
+            if let Some(time_lost) = pkt.time_lost {
+                if time_lost + self.rtt() > now {
+                    lowest_non_expired_pkt_index = i;
+                    break;
+                }
+            }
+
+            if pkt.time_acked.is_none() && pkt.time_lost.is_none() {
+                lowest_non_expired_pkt_index = i;
+                break;
+            }
+        }
+
+        // Then remove elements up to the previously found index.
+        for pkt in self.sent[epoch].drain(..lowest_non_expired_pkt_index) {
+            self.sent_frames_mem[epoch] =
+                self.sent_frames_mem[epoch].saturating_sub(pkt.frames_mem());
+        }
+
+        // A single packet that is neither acked nor lost can prevent a large
+        // number of elements from being removed, so compact the whole list
+        // when most of it is made of expired elements. To amortize the cost
+        // of counting them, this is only checked once the list has grown
+        // enough since the last check.
+        if self.sent[epoch].len() > self.sent_compact_len[epoch] {
+            let rtt = self.rtt();
+
+            let is_expired = |p: &Sent| match p.time_lost {
+                Some(time_lost) => time_lost + rtt <= now,
+
+                None => p.time_acked.is_some(),
+            };
+
+            let expired =
+                self.sent[epoch].iter().filter(|p| is_expired(p)).count();
+
+            if expired * 2 > self.sent[epoch].len() {
+                let sent_frames_mem = &mut self.sent_frames_mem[epoch];
+
+                self.sent[epoch].retain(|p| {
+                    if !is_expired(p) {
+                        return true;
+                    }
+
+                    *sent_frames_mem =
+                        sent_frames_mem.saturating_sub(p.frames_mem());
+
+                    false
+                });
+            }
+
+            self.sent_compact_len[epoch] =
+                cmp::max(SENT_COMPACT_MIN_LEN, self.sent[epoch].len() * 2);
+        }
+    }
+
+    fn on_packets_acked(
+        &mut self, acked: Vec<Acked>, epoch: packet::Epoch, now: Instant,
+    ) {
+        if epoch == packet::EPOCH_APPLICATION {
+            if let Some(start) = self.round_start_pkt_num {
+                if acked.iter().any(|p| p.pkt_num >= start) {
+                    self.round_start_pkt_num = None;
+
+                    self.rounds.end_round();
+                }
+            }
+        }
+
+        self.sample_delivery_rate(&acked, now);
+
+        self.on_packets_acked_cc(acked, epoch, now);
+    }
+
+    fn sample_delivery_rate(&mut self, acked: &[Acked], now: Instant) {
+        // Update delivery rate sample per acked packet.
+        for pkt in acked {
+            self.delivery_rate.update_rate_sample(pkt, now);
+        }
+
+        // Fill in a rate sample.
+        if self.delivery_rate.generate_rate_sample(self.min_rtt) {
+            self.bandwidth_delay.on_delivery_rate(
+                self.delivery_rate.sample_delivery_rate(),
+                self.delivery_rate.sample_is_app_limited(),
+            );
+        }
+    }
+
+    fn on_packets_acked_cc(
+        &mut self, acked: Vec<Acked>, epoch: packet::Epoch, now: Instant,
+    ) {
+        // Call congestion control hooks.
+        (self.cc_ops.on_packets_acked)(self, &acked, epoch, now);
+
+        // The episode ends once a packet sent after it started is acked.
+        if let Some(episode) = self.recovery_episode {
+            if acked.iter().any(|p| !episode.contains(epoch, p.pkt_num)) {
+                self.end_recovery_episode();
+            }
+        }
+    }
+
+    fn in_congestion_recovery(&self, epoch: packet::Epoch, pkt_num: u64) -> bool {
+        match self.recovery_episode {
+            Some(episode) => episode.contains(epoch, pkt_num),
+
+            None => false,
+        }
+    }
+
+    // Starts a congestion recovery episode covering all the packets sent so
+    // far. Called by congestion controllers when reducing the window.
+    fn start_recovery_episode(&mut self, now: Instant) {
+        self.congestion_recovery_start_time = Some(now);
+
+        self.recovery_episode = Some(RecoveryEpisode {
+            start_time: now,
+            largest_sent_pkt: self.largest_sent_pkt,
+        });
+
+        if let Some(events) = &mut self.events {
+            events.push(RecoveryEvent::RecoveryStart);
+        }
+    }
+
+    fn end_recovery_episode(&mut self) {
+        self.recovery_episode = None;
+
+        if let Some(events) = &mut self.events {
+            events.push(RecoveryEvent::RecoveryEnd);
+        }
+    }
+
+    // Removes bytes from the flight. A packet must leave the flight at most
+    // once, so removing more bytes than are in flight means the accounting
+    // has drifted. Rather than wrapping around, the count is clamped to zero
+    // and the underflow is recorded.
+    fn sub_bytes_in_flight(&mut self, bytes: usize) {
+        debug_assert!(
+            bytes <= self.bytes_in_flight,
+            "bytes_in_flight underflow: {} > {}",
+            bytes,
+            self.bytes_in_flight
+        );
+
+        match self.bytes_in_flight.checked_sub(bytes) {
+            Some(v) => self.bytes_in_flight = v,
+
+            None => {
+                self.bytes_in_flight = 0;
+                self.bytes_in_flight_underflows += 1;
+            },
+        }
+    }
+
+    fn in_persistent_congestion(&mut self, _largest_lost_pkt_num: u64) -> bool {
+        let _congestion_period = self.pto() * PERSISTENT_CONGESTION_THRESHOLD;
+
+        // TODO: properly detect persistent congestion
+        false
+    }
+
+    fn on_packets_lost(
+        &mut self, lost_bytes: usize, largest_lost_pkt: &Sent,
+        epoch: packet::Epoch, now: Instant,
+    ) {
+        self.sub_bytes_in_flight(lost_bytes);
+
+        if self.hold_loss(lost_bytes, largest_lost_pkt.pkt_num, epoch, now) {
+            return;
+        }
+
+        self.congestion_event(lost_bytes, largest_lost_pkt.pkt_num, epoch, now);
+
+        if self.in_persistent_congestion(largest_lost_pkt.pkt_num) {
+            self.collapse_cwnd();
+        }
+    }
+
+    // Holds back the congestion response to a loss outside of a recovery
+    // episode, and returns whether it did. Losses detected while a response
+    // is held back are added to it, so the window is reduced at most once.
+    fn hold_loss(
+        &mut self, lost_bytes: usize, largest_lost_pkt: u64,
+        epoch: packet::Epoch, now: Instant,
+    ) -> bool {
+        if let Some(hold) = &mut self.held_loss {
+            hold.lost_bytes += lost_bytes;
+
+            if hold.epoch == epoch {
+                hold.largest_lost_pkt =
+                    cmp::max(hold.largest_lost_pkt, largest_lost_pkt);
+            }
+
+            return true;
+        }
+
+        // BBR doesn't reduce the window on loss the same way.
+        if !self.loss_hold ||
+            (self.cc_ops.has_custom_pacing)() ||
+            self.in_congestion_recovery(epoch, largest_lost_pkt)
+        {
+            return false;
+        }
+
+        self.held_loss = Some(LossHold {
+            epoch,
+            largest_lost_pkt,
+            lost_bytes,
+            start_time: now,
+            largest_sent_pkt: self.largest_sent_pkt,
+            deadline: now + self.rtt().mul_f64(LOSS_HOLD_RTT_FRACTION),
+        });
+
+        true
+    }
+
+    // Applies the congestion response held back, if its hold expired.
+    fn maybe_release_loss_hold(&mut self, now: Instant) {
+        let hold = match self.held_loss {
+            Some(v) if v.deadline <= now => v,
+
+            _ => return,
+        };
+
+        self.held_loss = None;
+
+        // The response is applied as if it wasn't held back, so that the
+        // recovery episode doesn't cover the packets sent in the meantime.
+        self.congestion_event(
+            hold.lost_bytes,
+            hold.largest_lost_pkt,
+            hold.epoch,
+            hold.start_time,
+        );
+
+        if let Some(episode) = &mut self.recovery_episode {
+            if episode.start_time == hold.start_time {
+                episode.largest_sent_pkt = hold.largest_sent_pkt;
+            }
+        }
+    }
+
+    fn congestion_event(
+        &mut self, lost_bytes: usize, largest_lost_pkt: u64,
+        epoch: packet::Epoch, now: Instant,
+    ) {
+        if !self.in_congestion_recovery(epoch, largest_lost_pkt) {
+            (self.cc_ops.checkpoint)(self);
+        }
+
+        (self.cc_ops.congestion_event)(
+            self,
+            lost_bytes,
+            largest_lost_pkt,
+            epoch,
+            now,
+        );
+    }
+
+    fn collapse_cwnd(&mut self) {
+        (self.cc_ops.collapse_cwnd)(self);
+    }
+
+    pub fn update_app_limited(&mut self, v: bool) {
+        self.app_limited = v;
+    }
+
+    pub fn app_limited(&self) -> bool {
+        self.app_limited
+    }
+
+    pub fn delivery_rate_update_app_limited(&mut self, v: bool) {
+        self.delivery_rate.update_app_limited(v);
+    }
+
+    #[cfg(feature = "qlog")]
+    pub fn parameters_to_qlog(&self) -> EventData {
+        EventData::RecoveryParametersSet(
+            qlog::events::quic::RecoveryParametersSet {
+                reordering_threshold: Some(self.pkt_thresh as u16),
+                time_threshold: Some(self.time_thresh as f32),
+                timer_granularity: Some(self.granularity.as_millis() as u16),
+                initial_rtt: Some(self.initial_rtt.as_secs_f32() * 1000.0),
+                max_datagram_size: Some(self.max_datagram_size as u32),
+                initial_congestion_window: None,
+                minimum_congestion_window: None,
+                loss_reduction_factor: None,
+                persistent_congestion_threshold: None,
+                strict_rfc9002: Some(self.strict_rfc9002),
+            },
+        )
+    }
+
+    /// Returns a qlog event with the reordering thresholds, if they changed
+    /// since they were last logged.
+    #[cfg(feature = "qlog")]
+    pub fn maybe_qlog_parameters(&mut self) -> Option<EventData> {
+        let thresholds = self.reorder_thresholds();
+
+        if thresholds == self.qlog_reorder_thresholds {
+            return None;
+        }
+
+        self.qlog_reorder_thresholds = thresholds;
+
+        Some(EventData::RecoveryParametersSet(
+            qlog::events::quic::RecoveryParametersSet {
+                reordering_threshold: Some(thresholds.0 as u16),
+                time_threshold: Some(thresholds.1 as f32),
+                timer_granularity: None,
+                initial_rtt: None,
+                max_datagram_size: None,
+                initial_congestion_window: None,
+                minimum_congestion_window: None,
+                loss_reduction_factor: None,
+                persistent_congestion_threshold: None,
+                strict_rfc9002: None,
+            },
+        ))
+    }
+
+    #[cfg(feature = "qlog")]
+    pub fn maybe_qlog(&mut self) -> Option<EventData> {
+        let qlog_metrics = QlogMetrics {
+            min_rtt: self.min_rtt,
+            smoothed_rtt: self.rtt(),
+            latest_rtt: self.latest_rtt,
+            rttvar: self.rttvar,
+            cwnd: self.cwnd() as u64,
+            bytes_in_flight: self.bytes_in_flight as u64,
+            ssthresh: self.ssthresh as u64,
+            phase_losses: self.phase_losses,
+        };
+
+        self.qlog_metrics.maybe_update(qlog_metrics)
+    }
+
+    pub fn send_quantum(&self) -> usize {
+        self.send_quantum
+    }
+
+    /// Returns how many packets can be sent together, e.g. as segments of a
+    /// single GSO send, and when.
+    ///
+    /// All segments are full-sized, except the last one that is truncated to
+    /// what's left of the congestion window, so that the batch never exceeds
+    /// it. The batch is also limited by the send quantum and, when pacing,
+    /// by what's left of the current burst.
+    pub fn batch_hint(&self, now: Instant) -> BatchHint {
+        let segment_size = self.max_datagram_size;
+
+        let segments = |bytes: usize| {
+            bytes / segment_size + usize::from(bytes % segment_size != 0)
+        };
+
+        let mut max_segments = cmp::min(
+            segments(self.cwnd_available()),
+            cmp::max(self.send_quantum / segment_size, 1),
+        );
+
+        if let Some(budget) = self.pacer.burst_budget() {
+            // The packet that exhausts the budget still belongs to the burst.
+            max_segments = cmp::min(max_segments, cmp::max(segments(budget), 1));
+        }
+
+        BatchHint {
+            segment_size,
+            max_segments: cmp::min(max_segments, MAX_GSO_SEGMENTS),
+            release_time: self.pacer.next_send_time(now).into(),
+        }
+    }
+
+    fn debug_at(&self, now: Instant) -> RecoveryDebug<'_> {
+        RecoveryDebug { r: self, now }
+    }
+}
+
+/// Available congestion control algorithms.
+///
+/// This enum provides currently available list of congestion control
+/// algorithms.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+pub enum CongestionControlAlgorithm {
+    /// Reno congestion control algorithm. `reno` in a string form.
+    Reno  = 0,
+    /// CUBIC congestion control algorithm (default). `cubic` in a string form.
+    CUBIC = 1,
+    /// BBR congestion control algorithm. `bbr` in a string form.
+    BBR   = 2,
+}
+
+impl FromStr for CongestionControlAlgorithm {
+    type Err = crate::Error;
+
+    /// Converts a string to `CongestionControlAlgorithm`.
+    ///
+    /// If `name` is not valid, `Error::CongestionControl` is returned.
+    fn from_str(name: &str) -> std::result::Result<Self, Self::Err> {
+        match name {
+            "reno" => Ok(CongestionControlAlgorithm::Reno),
+            "cubic" => Ok(CongestionControlAlgorithm::CUBIC),
+            "bbr" => Ok(CongestionControlAlgorithm::BBR),
+
+            _ => Err(crate::Error::CongestionControl),
+        }
+    }
+}
+
+pub struct CongestionControlOps {
+    pub on_init: fn(r: &mut Recovery),
+
+    pub reset: fn(r: &mut Recovery),
+
+    pub on_packet_sent: fn(r: &mut Recovery, sent_bytes: usize, now: Instant),
+
+    pub on_packets_acked: fn(
+        r: &mut Recovery,
+        packets: &[Acked],
+        epoch: packet::Epoch,
+        now: Instant,
+    ),
+
+    pub congestion_event: fn(
+        r: &mut Recovery,
+        lost_bytes: usize,
+        largest_lost_pkt: u64,
+        epoch: packet::Epoch,
+        now: Instant,
+    ),
+
+    pub collapse_cwnd: fn(r: &mut Recovery),
+
+    pub checkpoint: fn(r: &mut Recovery),
+
+    pub rollback: fn(r: &mut Recovery) -> bool,
+
+    pub has_custom_pacing: fn() -> bool,
+
+    pub in_slow_start: fn(r: &Recovery) -> bool,
+
+    pub debug_fmt:
+        fn(r: &Recovery, formatter: &mut std::fmt::Formatter) -> std::fmt::Result,
+}
+
+impl From<CongestionControlAlgorithm> for &'static CongestionControlOps {
+    fn from(algo: CongestionControlAlgorithm) -> Self {
+        match algo {
+            CongestionControlAlgorithm::Reno => &reno::RENO,
+            CongestionControlAlgorithm::CUBIC => &cubic::CUBIC,
+            CongestionControlAlgorithm::BBR => &bbr::BBR,
+        }
+    }
+}
+
+impl std::fmt::Debug for Recovery {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&self.debug_at(self.clock.now()), f)
+    }
+}
+
+// Formats the recovery state with timers relative to the given time, so that
+// tracing doesn't need to read the clock again.
+struct RecoveryDebug<'a> {
+    r: &'a Recovery,
+
+    now: Instant,
+}
+
+impl std::fmt::Debug for RecoveryDebug<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let r = self.r;
+
+        match r.loss_detection_timer.deadline() {
+            Some(v) =>
+                if v > self.now {
+                    let d = v.duration_since(self.now);
+                    write!(f, "timer={:?} ", d)?;
+                } else {
+                    write!(f, "timer=exp ")?;
+                },
+
+            None => {
+                write!(f, "timer=none ")?;
+            },
+        };
+
+        write!(f, "latest_rtt={:?} ", r.latest_rtt)?;
+        write!(f, "srtt={:?} ", r.smoothed_rtt)?;
+        write!(f, "min_rtt={:?} ", r.min_rtt)?;
+        write!(f, "rttvar={:?} ", r.rttvar)?;
+        write!(f, "loss_time={:?} ", r.loss_time)?;
+        write!(f, "loss_probes={:?} ", r.loss_probes)?;
+        write!(f, "cwnd={} ", r.congestion_window)?;
+        write!(f, "ssthresh={} ", r.ssthresh)?;
+        write!(f, "bytes_in_flight={} ", r.bytes_in_flight)?;
+        write!(f, "app_limited={} ", r.app_limited)?;
+        write!(
+            f,
+            "congestion_recovery_start_time={:?} ",
+            r.congestion_recovery_start_time
+        )?;
+        write!(f, "recovery_episode={:?} ", r.recovery_episode)?;
+        write!(f, "{:?} ", r.delivery_rate)?;
+        write!(f, "pacer={:?} ", r.pacer)?;
+
+        if r.hystart.enabled() {
+            write!(f, "hystart={:?} ", r.hystart)?;
+        }
+
+        if r.pmtud.enabled() {
+            write!(f, "pmtud={{ {:?} }} ", r.pmtud)?;
+        }
+
+        // CC-specific debug info
+        (r.cc_ops.debug_fmt)(r, f)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct Sent {
+    pub pkt_num: u64,
+
+    pub frames: Vec<frame::Frame>,
+
+    pub time_sent: Instant,
+
+    pub time_acked: Option<Instant>,
+
+    pub time_lost: Option<Instant>,
+
+    pub size: usize,
+
+    pub ack_eliciting: bool,
+
+    pub in_flight: bool,
+
+    pub delivered: usize,
+
+    pub delivered_time: Instant,
+
+    pub first_sent_time: Instant,
+
+    pub is_app_limited: bool,
+
+    pub content: SentContent,
+
+    pub is_pmtud_probe: bool,
+
+    // Padding never exceeds a single datagram, and keeping it small leaves
+    // room for the datagram ID and the burst tags without growing the struct.
+    pub padding_bytes: u16,
+
+    // Identifies the UDP datagram the packet was sent in, so that packets
+    // coalesced together can be told apart.
+    pub datagram_id: Option<NonZeroU32>,
+
+    // The position of the packet in the burst it was sent in, saturating at
+    // `u8::MAX`. This is set by `Recovery`.
+    pub burst_index: u8,
+
+    // The number of packets in the burst the packet was sent in, saturating
+    // at `u8::MAX`, or 0 until the burst is over. This is set by `Recovery`.
+    pub burst_size: u8,
+
+    // The phase congestion control was in when the packet was sent. This is
+    // set by `Recovery`.
+    pub congestion_state: CongestionState,
+}
+
+impl Sent {
+    /// Returns how long after being sent the packet was declared lost, or
+    /// zero if it wasn't.
+    pub fn loss_latency(&self) -> Duration {
+        self.time_lost.map_or(Duration::ZERO, |t| {
+            t.saturating_duration_since(self.time_sent)
+        })
+    }
+
+    // Returns the size of the packet as accounted for by congestion control.
+    //
+    // PADDING frames don't carry any data, so they are excluded from the
+    // bytes in flight, as well as from the acked and lost bytes. The packet
+    // itself is still tracked for loss detection.
+    pub fn cc_size(&self) -> usize {
+        self.size.saturating_sub(self.padding_bytes as usize)
+    }
+
+    // Returns an estimate of the memory used by the packet's frames.
+    fn frames_mem(&self) -> usize {
+        self.frames.capacity() * mem::size_of::<frame::Frame>() +
+            frames_heap_size(&self.frames)
+    }
+}
+
+/// A summary of a packet still in flight.
+///
+/// The packet's frames are borrowed from the recovery state, and only their
+/// kinds and sizes are exposed.
+#[derive(Clone, Copy)]
+pub struct OutstandingPacket<'a> {
+    /// The packet number.
+    pub pkt_num: u64,
+
+    /// The size of the packet in bytes.
+    pub size: usize,
+
+    /// The time the packet was sent.
+    pub time_sent: Instant,
+
+    /// Whether the packet is ack-eliciting.
+    pub ack_eliciting: bool,
+
+    frames: &'a [frame::Frame],
+}
+
+impl<'a> OutstandingPacket<'a> {
+    /// Returns the kind and size on the wire of each frame in the packet.
+    pub fn frames(&self) -> impl Iterator<Item = (&'static str, usize)> + 'a {
+        self.frames.iter().map(|f| (f.name(), f.wire_len()))
+    }
+}
+
+impl std::fmt::Debug for OutstandingPacket<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "pkt_num={} size={} ack_eliciting={} frames=[",
+            self.pkt_num, self.size, self.ack_eliciting
+        )?;
+
+        for (i, (name, len)) in self.frames().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+
+            write!(f, "{}/{}", name, len)?;
+        }
+
+        write!(f, "]")
+    }
+}
+
+/// The maximum number of packets per packet number space listed by
+/// `OutstandingDump`.
+pub const OUTSTANDING_DUMP_MAX: usize = 8;
+
+/// Formats the packets in flight, as returned by
+/// [`Recovery::outstanding_dump()`].
+pub struct OutstandingDump<'a> {
+    r: &'a Recovery,
+
+    now: Instant,
+}
+
+impl std::fmt::Display for OutstandingDump<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut empty = true;
+
+        for epoch in packet::epochs() {
+            let count = self.r.outstanding_packets(epoch).count();
+
+            if count == 0 {
+                continue;
+            }
+
+            if !empty {
+                write!(f, "; ")?;
+            }
+
+            empty = false;
+
+            write!(
+                f,
+                "{:?}: {} packets",
+                packet::Type::from_epoch(epoch),
+                count
+            )?;
+
+            for p in self.r.outstanding_packets(epoch).take(OUTSTANDING_DUMP_MAX)
+            {
+                write!(
+                    f,
+                    " {{{:?} sent={:?} ago}}",
+                    p,
+                    self.now.saturating_duration_since(p.time_sent)
+                )?;
+            }
+
+            if count > OUTSTANDING_DUMP_MAX {
+                write!(f, " ... {} more", count - OUTSTANDING_DUMP_MAX)?;
+            }
+        }
+
+        if empty {
+            write!(f, "none")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for OutstandingDump<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Display::fmt(self, f)
+    }
+}
+
+fn frames_heap_size(frames: &[frame::Frame]) -> usize {
+    frames.iter().map(|f| f.heap_size()).sum()
+}
+
+// Removes one copy of each of the given frames from `lost`, and returns the
+// number of frames removed.
+//
+// The frames of a packet are queued in order, and the queue keeps its order
+// until it's drained, so they are all found in a single pass. Only the frames
+// that were dropped from the queue in the meantime, e.g. when the connection
+// started draining, need to be looked for again.
+fn remove_frames(
+    lost: &mut Vec<frame::Frame>, queued: &mut BTreeMap<u64, Range<usize>>,
+    frames: &[frame::Frame],
+) -> usize {
+    let mut next = 0;
+
+    let mut removed = retain_frames(lost, queued, |f| {
+        if frames.get(next) != Some(f) {
+            return true;
+        }
+
+        next += 1;
+
+        false
+    });
+
+    let mut rest: Vec<&frame::Frame> = frames[next..].iter().collect();
+
+    if !rest.is_empty() {
+        removed += retain_frames(lost, queued, |f| {
+            match rest.iter().position(|r| *r == f) {
+                Some(i) => {
+                    rest.swap_remove(i);
+                    false
+                },
+
+                None => true,
+            }
+        });
+    }
+
+    removed
+}
+
+// Keeps the frames of `lost` for which `keep` returns true, and returns the
+// number of frames removed.
+//
+// The ranges of `queued`, which locate the frames of lost packets in `lost`,
+// are moved along with the frames they cover.
+fn retain_frames(
+    lost: &mut Vec<frame::Frame>, queued: &mut BTreeMap<u64, Range<usize>>,
+    mut keep: impl FnMut(&frame::Frame) -> bool,
+) -> usize {
+    let len = lost.len();
+
+    if queued.is_empty() {
+        lost.retain(keep);
+
+        return len - lost.len();
+    }
+
+    // The number of frames kept before each index.
+    let mut kept_before = Vec::with_capacity(len + 1);
+    let mut kept = 0;
+
+    lost.retain(|f| {
+        kept_before.push(kept);
+
+        let keep = keep(f);
+
+        if keep {
+            kept += 1;
+        }
+
+        keep
+    });
+
+    kept_before.push(kept);
+
+    queued.retain(|_, r| {
+        *r = kept_before[r.start]..kept_before[r.end];
+
+        r.start < r.end
+    });
+
+    len - kept
+}
+
+/// An estimate of the memory used by the loss recovery state, in bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RecoveryMemory {
+    /// The memory used by the sent packets being tracked, including their
+    /// frames.
+    pub sent_packets: usize,
+
+    /// The memory used by the queue of acked frames.
+    pub acked_frames: usize,
+
+    /// The memory used by the queue of lost frames.
+    pub lost_frames: usize,
+
+    /// The size of the `Recovery` structure itself.
+    pub fixed: usize,
+}
+
+impl RecoveryMemory {
+    /// Returns the total memory used.
+    pub fn total(&self) -> usize {
+        self.sent_packets + self.acked_frames + self.lost_frames + self.fixed
+    }
+}
+
+/// The phase of congestion control.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CongestionState {
+    /// The congestion window grows by the acknowledged bytes, e.g. until the
+    /// slow start threshold is reached or, with BBR, while in Startup.
+    SlowStart,
+
+    /// The congestion window grows slowly, if at all.
+    CongestionAvoidance,
+
+    /// The congestion window was reduced in response to a loss, and the
+    /// packets sent before that aren't all acknowledged yet.
+    Recovery,
+}
+
+/// The kinds of frames carried by a sent packet that are retransmitted when
+/// it is lost.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SentContent {
+    /// Whether the packet carries CRYPTO frames.
+    pub crypto: bool,
+
+    /// Whether the packet carries STREAM frames.
+    pub stream: bool,
+
+    /// Whether the packet carries control frames, e.g. MAX_DATA or
+    /// NEW_CONNECTION_ID.
+    pub control: bool,
+}
+
+impl SentContent {
+    // The number of ranks returned by `pto_rank()`.
+    const PTO_RANKS: usize = 2;
+
+    /// Classifies the given frames.
+    pub fn from_frames(frames: &[frame::Frame]) -> Self {
+        let mut content = SentContent::default();
+
+        for frame in frames {
+            match frame {
+                frame::Frame::Crypto { .. } |
+                frame::Frame::CryptoHeader { .. } => content.crypto = true,
+
+                frame::Frame::Stream { .. } |
+                frame::Frame::StreamHeader { .. } => content.stream = true,
+
+                frame::Frame::Padding { .. } |
+                frame::Frame::Ping |
+                frame::Frame::ACK { .. } |
+                frame::Frame::ACKHeader { .. } |
+                frame::Frame::PathChallenge { .. } |
+                frame::Frame::PathResponse { .. } |
+                frame::Frame::ConnectionClose { .. } |
+                frame::Frame::ApplicationClose { .. } |
+                frame::Frame::Datagram { .. } |
+                frame::Frame::DatagramHeader { .. } => (),
+
+                _ => content.control = true,
+            }
+        }
+
+        content
+    }
+
+    /// Returns whether any of the packet's frames are retransmitted when it
+    /// is lost.
+    pub fn is_retransmittable(&self) -> bool {
+        self.crypto || self.stream || self.control
+    }
+
+    // Returns the order in which the packet's frames are retransmitted on PTO
+    // in the given epoch, lower first, or `None` if it carries no data.
+    //
+    // CRYPTO frames strictly go first during the handshake, and STREAM frames
+    // after it.
+    fn pto_rank(&self, epoch: packet::Epoch) -> Option<usize> {
+        let (first, second) = if epoch == packet::EPOCH_APPLICATION {
+            (self.stream, self.crypto)
+        } else {
+            (self.crypto, self.stream)
+        };
+
+        if first {
+            Some(0)
+        } else if second {
+            Some(1)
+        } else {
+            None
+        }
+    }
+}
+
+// The packets sent in a row, close enough to each other to reach the path
+// all at once.
+#[derive(Clone, Copy, Default)]
+struct Burst {
+    // The packet number of the first packet of the burst.
+    start: u64,
+
+    // The number of packets in the burst so far.
+    len: usize,
+
+    // When the last packet of the burst was sent.
+    last_sent: Option<Instant>,
+}
+
+impl Burst {
+    // Returns the size of the burst the packet was sent in, which is that of
+    // this burst if the packet wasn't tagged with it yet.
+    fn size_of(&self, pkt: &Sent) -> usize {
+        if pkt.burst_size > 0 {
+            pkt.burst_size.into()
+        } else {
+            self.len
+        }
+    }
+}
+
+impl std::fmt::Debug for Sent {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "pkt_num={:?} ", self.pkt_num)?;
+        write!(f, "pkt_sent_time={:?} ", self.time_sent)?;
+        write!(f, "pkt_size={:?} ", self.size)?;
+        write!(f, "delivered={:?} ", self.delivered)?;
+        write!(f, "delivered_time={:?} ", self.delivered_time)?;
+        write!(f, "first_sent_time={:?} ", self.first_sent_time)?;
+        write!(f, "is_app_limited={} ", self.is_app_limited)?;
+        write!(f, "content={:?} ", self.content)?;
+        write!(f, "is_pmtud_probe={} ", self.is_pmtud_probe)?;
+        write!(f, "padding_bytes={} ", self.padding_bytes)?;
+        write!(f, "datagram_id={:?} ", self.datagram_id)?;
+        write!(f, "burst={}/{} ", self.burst_index, self.burst_size)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct Acked {
+    pub pkt_num: u64,
+
+    pub time_sent: Instant,
+
+    pub size: usize,
+
+    pub rtt: Duration,
+
+    pub delivered: usize,
+
+    pub delivered_time: Instant,
+
+    pub first_sent_time: Instant,
+
+    pub is_app_limited: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct HandshakeStatus {
+    pub has_handshake_keys: bool,
+
+    pub peer_verified_address: bool,
+
+    pub completed: bool,
+
+    // Whether the server can't send more than the anti-amplification limit
+    // on the active path yet.
+    pub amplification_limited: bool,
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl Default for HandshakeStatus {
+    fn default() -> HandshakeStatus {
+        HandshakeStatus {
+            has_handshake_keys: true,
+
+            peer_verified_address: true,
+
+            completed: true,
+
+            amplification_limited: false,
+        }
+    }
+}
+
+/// How the last ACK changed the room available to send.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AckWindowUpdate {
+    /// The congestion window before the ACK was processed.
+    pub cwnd_before: usize,
+
+    /// The congestion window after the ACK was processed.
+    pub cwnd_after: usize,
+
+    /// The bytes newly acked, plus any growth of the congestion window.
+    pub freed_bytes: usize,
+
+    /// Whether the ACK made room for a full-sized packet in a congestion
+    /// window that had none left.
+    pub unblocked: bool,
+}
+
+/// A congestion recovery episode.
+///
+/// An episode starts when the congestion window is reduced, and ends when a
+/// packet sent after that is acknowledged. Losses of packets sent before it
+/// started don't reduce the window again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecoveryEpisode {
+    /// The time the episode started.
+    pub start_time: Instant,
+
+    /// The largest packet number sent in each epoch when the episode started,
+    /// if any.
+    pub largest_sent_pkt: [Option<u64>; packet::EPOCH_COUNT],
+}
+
+impl RecoveryEpisode {
+    /// Returns whether the packet was sent before the episode started.
+    pub fn contains(&self, epoch: packet::Epoch, pkt_num: u64) -> bool {
+        match self.largest_sent_pkt[epoch] {
+            Some(largest) => pkt_num <= largest,
+
+            None => false,
+        }
+    }
+}
+
+// A congestion response held back after a loss, see
+// `Config::enable_loss_hold()`.
+#[derive(Clone, Copy, Debug)]
+struct LossHold {
+    // The packet number space of the largest lost packet.
+    epoch: packet::Epoch,
+
+    largest_lost_pkt: u64,
+
+    // The bytes lost since the hold started, minus those acked later.
+    lost_bytes: usize,
+
+    // When the loss was detected, and the recovery episode would have
+    // started.
+    start_time: Instant,
+
+    largest_sent_pkt: [Option<u64>; packet::EPOCH_COUNT],
+
+    // When the response is applied, unless the lost packets are acked.
+    deadline: Instant,
+}
+
+// An idle deadline the probe timeout was found to exceed, see
+// `Config::enable_idle_bounded_pto()`.
+#[derive(Clone, Copy, Debug)]
+struct IdleConflict {
+    idle_deadline: Instant,
+
+    // Whether a probe timeout fired since, after which the probe timeout is
+    // no longer brought forward.
+    probed: bool,
+}
+
+/// How much can be sent on a path right now, as returned by
+/// `Recovery::send_allowance()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SendAllowance {
+    /// The number of bytes that can be sent.
+    pub bytes: usize,
+
+    /// Whether the budget exceeds the congestion window because probes are
+    /// pending, and should only be used to send them.
+    pub probe_only: bool,
+
+    /// The time until which pacing defers sending, if later than now.
+    pub deferred_until: Option<Instant>,
+
+    /// The epoch probes should be sent in first, if any are pending.
+    pub epoch: Option<packet::Epoch>,
+}
+
+/// What the next packet needs, as decided by [`Recovery::plan_packet()`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct PacketPlan {
+    /// The packet number space of the packet.
+    pub epoch: packet::Epoch,
+
+    /// Whether the packet must be ack-eliciting, adding a PING frame if
+    /// nothing else is.
+    pub ack_eliciting: bool,
+
+    /// Whether an ACK is solicited because too many non-ack-eliciting packets
+    /// were sent in a row.
+    pub solicit_ack: bool,
+
+    /// Whether the packet is a probe, for which a pending probe was reserved.
+    pub probe: bool,
+
+    /// The number of bytes that can be sent.
+    pub bytes: usize,
+
+    /// The number of bytes left in the current round, see
+    /// `remaining_round_budget()`.
+    pub round_budget: usize,
+
+    /// Whether lost frames are waiting to be retransmitted, and should be
+    /// sent before new data.
+    pub lost_frames: bool,
+}
+
+/// Properties of a network path measured by a connection, that can seed the
+/// loss recovery of later connections to the same peer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PathProps {
+    /// The smoothed RTT.
+    pub rtt: Duration,
+
+    /// The RTT variation.
+    pub rttvar: Duration,
+
+    /// The congestion window in bytes.
+    pub cwnd: usize,
+
+    /// When the properties were measured.
+    pub measured_at: Instant,
+}
+
+/// The result of correcting the time a packet was sent at.
+///
+/// See [`set_packet_sent_time()`] for more details.
+///
+/// [`set_packet_sent_time()`]: struct.Connection.html#method.set_packet_sent_time
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SentTimeCorrection {
+    /// The packet was still awaiting acknowledgement, and its RTT sample will
+    /// be based on the corrected time.
+    Applied,
+
+    /// The packet was already acknowledged, so its RTT sample was based on
+    /// the time recorded when it was sent.
+    Late {
+        /// The RTT sample the corrected time would have given, not accounting
+        /// for the peer's ACK delay.
+        rtt: Duration,
+    },
+}
+
+/// What a loss detection timeout did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeoutOutcome {
+    /// Why the timer fired.
+    pub kind: TimeoutKind,
+
+    /// The packet number space packets were declared lost in, or probes were
+    /// scheduled for, unless the timer was only re-armed.
+    pub epoch: Option<packet::Epoch>,
+
+    /// The number of packets declared lost.
+    pub lost_packets: usize,
+
+    /// The number of bytes declared lost.
+    pub lost_bytes: usize,
+
+    /// The number of probe packets pending in the packet number space.
+    pub probes: usize,
+
+    /// The number of frames of packets still in flight that were queued for
+    /// retransmission in the probes.
+    pub requeued_frames: usize,
+
+    /// When the loss detection timer expires next, if armed.
+    pub next_timer: Option<Instant>,
+}
+
+impl TimeoutOutcome {
+    // Returns the outcome of a timeout that did nothing but re-arm the timer.
+    fn rearmed() -> Self {
+        TimeoutOutcome {
+            kind: TimeoutKind::Rearmed,
+            epoch: None,
+            lost_packets: 0,
+            lost_bytes: 0,
+            probes: 0,
+            requeued_frames: 0,
+            next_timer: None,
+        }
+    }
+
+    /// Returns the qlog events describing the timeout, and the timer that was
+    /// armed next.
+    #[cfg(feature = "qlog")]
+    pub fn qlog_events(&self, now: Instant) -> Vec<EventData> {
+        use qlog::events::quic::LossTimerEventType;
+        use qlog::events::quic::LossTimerUpdated;
+        use qlog::events::quic::TimerType;
+
+        let packet_number_space = self.epoch.map(|e| match e {
+            packet::EPOCH_INITIAL =>
+                qlog::events::quic::PacketNumberSpace::Initial,
+
+            packet::EPOCH_HANDSHAKE =>
+                qlog::events::quic::PacketNumberSpace::Handshake,
+
+            _ => qlog::events::quic::PacketNumberSpace::ApplicationData,
+        });
+
+        let timer_type = match self.kind {
+            TimeoutKind::Rearmed | TimeoutKind::LossHold => None,
+
+            TimeoutKind::LossTime => Some(TimerType::Ack),
+
+            TimeoutKind::Pto | TimeoutKind::AntiDeadlock => Some(TimerType::Pto),
+        };
+
+        let mut events = Vec::with_capacity(2);
+
+        if !matches!(self.kind, TimeoutKind::Rearmed | TimeoutKind::LossHold) {
+            events.push(EventData::LossTimerUpdated(LossTimerUpdated {
+                timer_type,
+                packet_number_space,
+                event_type: LossTimerEventType::Expired,
+                delta: None,
+                probes: Some(self.probes as u64),
+                requeued_frames: Some(self.requeued_frames as u64),
+            }));
+        }
+
+        let next = match self.next_timer {
+            Some(t) => LossTimerUpdated {
+                timer_type: None,
+                packet_number_space: None,
+                event_type: LossTimerEventType::Set,
+                delta: Some(
+                    t.saturating_duration_since(now).as_secs_f32() * 1000.0,
+                ),
+                probes: None,
+                requeued_frames: None,
+            },
+
+            None => LossTimerUpdated {
+                timer_type: None,
+                packet_number_space: None,
+                event_type: LossTimerEventType::Cancelled,
+                delta: None,
+                probes: None,
+                requeued_frames: None,
+            },
+        };
+
+        events.push(EventData::LossTimerUpdated(next));
+
+        events
+    }
+}
+
+/// Why a loss detection timeout fired.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeoutKind {
+    /// The timer fired before its actual deadline, which is reported late
+    /// when its updates are coalesced, and was only re-armed.
+    Rearmed,
+
+    /// Packets were declared lost by the time threshold.
+    LossTime,
+
+    /// Probe packets were scheduled on probe timeout.
+    Pto,
+
+    /// A client with nothing in flight scheduled a probe on probe timeout,
+    /// so that the server can send more before the client's address is
+    /// verified.
+    AntiDeadlock,
+
+    /// The congestion response held back after a loss was applied, and the
+    /// timer was re-armed.
+    LossHold,
+}
+
+/// A snapshot of the loss recovery state of a path.
+///
+/// Per packet number space values are indexed by epoch, in the order Initial,
+/// Handshake and Application Data.
+///
+/// This is only meant for tests and is not covered by semver guarantees.
+#[cfg(any(test, feature = "test-utils"))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecoveryInspection {
+    /// The number of sent packets still tracked by loss recovery, including
+    /// those already acked or lost that haven't been released yet.
+    pub sent_packets: [usize; packet::EPOCH_COUNT],
+
+    /// The number of sent packets counted as in flight.
+    pub in_flight_packets: [usize; packet::EPOCH_COUNT],
+
+    /// The number of probe packets pending transmission.
+    pub loss_probes: [usize; packet::EPOCH_COUNT],
+
+    /// The time at which the loss detection timer expires, if armed.
+    pub loss_detection_timer: Option<Instant>,
+
+    /// What the loss detection timer is armed for, if armed.
+    pub loss_detection_timer_kind: Option<LossDetectionTimerKind>,
+
+    /// Whether lost data is no longer retransmitted.
+    pub draining: bool,
+
+    /// The number of packets awaiting acknowledgement that carry CRYPTO
+    /// frames.
+    pub crypto_packets: [usize; packet::EPOCH_COUNT],
+
+    /// The number of packets awaiting acknowledgement that carry STREAM
+    /// frames.
+    pub stream_packets: [usize; packet::EPOCH_COUNT],
+
+    /// The number of packets awaiting acknowledgement that carry control
+    /// frames.
+    pub control_packets: [usize; packet::EPOCH_COUNT],
+
+    /// Whether the packet number space was discarded.
+    pub discarded: [bool; packet::EPOCH_COUNT],
+}
+
+/// The reason the loss detection timer is armed.
+///
+/// This is only meant for tests and is not covered by semver guarantees.
+#[cfg(any(test, feature = "test-utils"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LossDetectionTimerKind {
+    /// Packets will be declared lost once the time threshold is reached.
+    LossTime,
+
+    /// Probe packets will be sent on probe timeout.
+    Pto,
+}
+
+fn backlog_has_room(
+    backlog: &[frame::Frame], count: usize, max_frame_backlog: usize,
+) -> bool {
+    max_frame_backlog == 0 || backlog.len() + count <= max_frame_backlog
+}
+
+fn is_ack_frame(frame: &frame::Frame) -> bool {
+    matches!(frame, frame::Frame::ACKHeader { .. })
+}
+
+// Returns the earliest of the given times, one per packet number space, along
+// with the packet number space it belongs to.
+//
+// Packet number spaces without a time are skipped. On ties, the lowest packet
+// number space is picked, as it's the one that needs to make progress first.
+// Without any time, the Initial packet number space is returned.
+fn earliest_time_and_space(
+    times: [Option<Instant>; packet::EPOCH_COUNT],
+) -> (Option<Instant>, packet::Epoch) {
+    let mut earliest = (None, packet::EPOCH_INITIAL);
+
+    for e in packet::epochs() {
+        let time = match times[e] {
+            Some(v) => v,
+
+            None => continue,
+        };
+
+        if earliest.0.map_or(true, |earliest| time < earliest) {
+            earliest = (Some(time), e);
+        }
+    }
+
+    earliest
+}
+
+/// Returns how many bytes an ACK frame may take in a packet with
+/// `remaining_space` bytes left, when `pending_retransmissions` bytes of lost
+/// data are waiting to be retransmitted.
+///
+/// Retransmissions take priority, but the ACK frame is always allowed at least
+/// `MIN_ACK_BUDGET` bytes, so that the largest acknowledged packet and the
+/// most recent ranges are still reported.
+pub fn ack_budget_for_packet(
+    remaining_space: usize, pending_retransmissions: usize,
+) -> usize {
+    if pending_retransmissions == 0 {
+        return remaining_space;
+    }
+
+    let budget = remaining_space
+        .saturating_sub(pending_retransmissions + frame::MAX_STREAM_OVERHEAD);
+
+    cmp::min(cmp::max(budget, MIN_ACK_BUDGET), remaining_space)
+}
+
+fn sub_abs(lhs: Duration, rhs: Duration) -> Duration {
+    if lhs > rhs {
+        lhs - rhs
+    } else {
+        rhs - lhs
+    }
+}
+
+// We don't need to log all qlog metrics every time there is a recovery event.
 // Instead, we can log only the MetricsUpdated event data fields that we care
 // about, only when they change. To support this, the QLogMetrics structure
 // keeps a running picture of the fields.
@@ -1272,864 +5301,7155 @@ struct QlogMetrics {
     cwnd: u64,
     bytes_in_flight: u64,
     ssthresh: u64,
+    phase_losses: PhaseLosses,
+}
+
+#[cfg(feature = "qlog")]
+impl QlogMetrics {
+    // Make a qlog event if the latest instance of QlogMetrics is different.
+    //
+    // This function diffs each of the fields. A qlog MetricsUpdated event is
+    // only generated if at least one field is different. Where fields are
+    // different, the qlog event contains the latest value.
+    fn maybe_update(&mut self, latest: Self) -> Option<EventData> {
+        let mut emit_event = false;
+
+        let new_min_rtt = if self.min_rtt != latest.min_rtt {
+            self.min_rtt = latest.min_rtt;
+            emit_event = true;
+            Some(latest.min_rtt.as_secs_f32() * 1000.0)
+        } else {
+            None
+        };
+
+        let new_smoothed_rtt = if self.smoothed_rtt != latest.smoothed_rtt {
+            self.smoothed_rtt = latest.smoothed_rtt;
+            emit_event = true;
+            Some(latest.smoothed_rtt.as_secs_f32() * 1000.0)
+        } else {
+            None
+        };
+
+        let new_latest_rtt = if self.latest_rtt != latest.latest_rtt {
+            self.latest_rtt = latest.latest_rtt;
+            emit_event = true;
+            Some(latest.latest_rtt.as_secs_f32() * 1000.0)
+        } else {
+            None
+        };
+
+        let new_rttvar = if self.rttvar != latest.rttvar {
+            self.rttvar = latest.rttvar;
+            emit_event = true;
+            Some(latest.rttvar.as_secs_f32() * 1000.0)
+        } else {
+            None
+        };
+
+        let new_cwnd = if self.cwnd != latest.cwnd {
+            self.cwnd = latest.cwnd;
+            emit_event = true;
+            Some(latest.cwnd)
+        } else {
+            None
+        };
+
+        let new_bytes_in_flight =
+            if self.bytes_in_flight != latest.bytes_in_flight {
+                self.bytes_in_flight = latest.bytes_in_flight;
+                emit_event = true;
+                Some(latest.bytes_in_flight)
+            } else {
+                None
+            };
+
+        let new_ssthresh = if self.ssthresh != latest.ssthresh {
+            self.ssthresh = latest.ssthresh;
+            emit_event = true;
+            Some(latest.ssthresh)
+        } else {
+            None
+        };
+
+        let new_phase_losses = if self.phase_losses != latest.phase_losses {
+            self.phase_losses = latest.phase_losses;
+            emit_event = true;
+            Some(latest.phase_losses)
+        } else {
+            None
+        };
+
+        if emit_event {
+            // QVis can't use all these fields and they can be large.
+            return Some(EventData::MetricsUpdated(
+                qlog::events::quic::MetricsUpdated {
+                    min_rtt: new_min_rtt,
+                    smoothed_rtt: new_smoothed_rtt,
+                    latest_rtt: new_latest_rtt,
+                    rtt_variance: new_rttvar,
+                    pto_count: None,
+                    congestion_window: new_cwnd,
+                    bytes_in_flight: new_bytes_in_flight,
+                    ssthresh: new_ssthresh,
+                    packets_in_flight: None,
+                    pacing_rate: None,
+                    slow_start_losses: new_phase_losses.map(|l| l.slow_start),
+                    avoidance_losses: new_phase_losses.map(|l| l.avoidance),
+                    recovery_losses: new_phase_losses.map(|l| l.recovery),
+                },
+            ));
+        }
+
+        None
+    }
 }
 
-#[cfg(feature = "qlog")]
-impl QlogMetrics {
-    // Make a qlog event if the latest instance of QlogMetrics is different.
-    //
-    // This function diffs each of the fields. A qlog MetricsUpdated event is
-    // only generated if at least one field is different. Where fields are
-    // different, the qlog event contains the latest value.
-    fn maybe_update(&mut self, latest: Self) -> Option<EventData> {
-        let mut emit_event = false;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeSet;
+
+    use proptest::prelude::*;
+    use proptest::test_runner::Config as ProptestConfig;
+    use proptest::test_runner::RngAlgorithm;
+    use proptest::test_runner::TestRng;
+    use proptest::test_runner::TestRunner;
+
+    use super::clock::ManualClock;
+
+    #[test]
+    fn lookup_cc_algo_ok() {
+        let algo = CongestionControlAlgorithm::from_str("reno").unwrap();
+        assert_eq!(algo, CongestionControlAlgorithm::Reno);
+    }
+
+    #[test]
+    fn lookup_cc_algo_bad() {
+        assert_eq!(
+            CongestionControlAlgorithm::from_str("???"),
+            Err(crate::Error::CongestionControl)
+        );
+    }
+
+    #[test]
+    fn collapse_cwnd() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        // cwnd will be reset.
+        r.collapse_cwnd();
+        assert_eq!(r.cwnd(), r.max_datagram_size * MINIMUM_WINDOW_PACKETS);
+    }
+
+    #[test]
+    fn loss_on_pto() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let clock = ManualClock::new();
+
+        let mut r = Recovery::new(&cfg);
+        r.set_clock(Clock::Manual(clock.clone()));
+
+        let mut now = clock.now();
+
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 0);
+
+        // Start by sending a few packets.
+        let p = Sent {
+            pkt_num: 0,
+            frames: vec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 1000,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            content: SentContent::default(),
+            is_pmtud_probe: false,
+            padding_bytes: 0,
+            datagram_id: None,
+            burst_index: 0,
+            burst_size: 0,
+            congestion_state: CongestionState::SlowStart,
+        };
+
+        r.on_packet_sent(
+            p,
+            packet::EPOCH_APPLICATION,
+            HandshakeStatus::default(),
+            now,
+        );
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 1);
+        assert_eq!(r.bytes_in_flight, 1000);
+
+        let p = Sent {
+            pkt_num: 1,
+            frames: vec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 1000,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            content: SentContent::default(),
+            is_pmtud_probe: false,
+            padding_bytes: 0,
+            datagram_id: None,
+            burst_index: 0,
+            burst_size: 0,
+            congestion_state: CongestionState::SlowStart,
+        };
+
+        r.on_packet_sent(
+            p,
+            packet::EPOCH_APPLICATION,
+            HandshakeStatus::default(),
+            now,
+        );
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 2);
+        assert_eq!(r.bytes_in_flight, 2000);
+
+        let p = Sent {
+            pkt_num: 2,
+            frames: vec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 1000,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            content: SentContent::default(),
+            is_pmtud_probe: false,
+            padding_bytes: 0,
+            datagram_id: None,
+            burst_index: 0,
+            burst_size: 0,
+            congestion_state: CongestionState::SlowStart,
+        };
+
+        r.on_packet_sent(
+            p,
+            packet::EPOCH_APPLICATION,
+            HandshakeStatus::default(),
+            now,
+        );
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 3);
+        assert_eq!(r.bytes_in_flight, 3000);
+
+        let p = Sent {
+            pkt_num: 3,
+            frames: vec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 1000,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            content: SentContent::default(),
+            is_pmtud_probe: false,
+            padding_bytes: 0,
+            datagram_id: None,
+            burst_index: 0,
+            burst_size: 0,
+            congestion_state: CongestionState::SlowStart,
+        };
+
+        r.on_packet_sent(
+            p,
+            packet::EPOCH_APPLICATION,
+            HandshakeStatus::default(),
+            now,
+        );
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 4);
+        assert_eq!(r.bytes_in_flight, 4000);
+
+        // Wait for 10ms.
+        clock.advance(Duration::from_millis(10));
+        now = clock.now();
+
+        // Only the first 2 packets are acked.
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..2);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                25,
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+                None
+            ),
+            Ok((0, 0))
+        );
+
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 2);
+        assert_eq!(r.bytes_in_flight, 2000);
+        assert_eq!(r.lost_count, 0);
+
+        // Wait until loss detection timer expires.
+        clock.advance_to(r.loss_detection_timer().unwrap());
+        now = clock.now();
+
+        // PTO.
+        r.on_loss_detection_timeout(HandshakeStatus::default(), now);
+        assert_eq!(r.loss_probes[packet::EPOCH_APPLICATION], 1);
+        assert_eq!(r.lost_count, 0);
+        assert_eq!(r.pto_count, 1);
+
+        let p = Sent {
+            pkt_num: 4,
+            frames: vec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 1000,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            content: SentContent::default(),
+            is_pmtud_probe: false,
+            padding_bytes: 0,
+            datagram_id: None,
+            burst_index: 0,
+            burst_size: 0,
+            congestion_state: CongestionState::SlowStart,
+        };
+
+        r.on_packet_sent(
+            p,
+            packet::EPOCH_APPLICATION,
+            HandshakeStatus::default(),
+            now,
+        );
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 3);
+        assert_eq!(r.bytes_in_flight, 3000);
+
+        let p = Sent {
+            pkt_num: 5,
+            frames: vec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 1000,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            content: SentContent::default(),
+            is_pmtud_probe: false,
+            padding_bytes: 0,
+            datagram_id: None,
+            burst_index: 0,
+            burst_size: 0,
+            congestion_state: CongestionState::SlowStart,
+        };
+
+        r.on_packet_sent(
+            p,
+            packet::EPOCH_APPLICATION,
+            HandshakeStatus::default(),
+            now,
+        );
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 4);
+        assert_eq!(r.bytes_in_flight, 4000);
+        assert_eq!(r.lost_count, 0);
+
+        // Wait for 10ms.
+        clock.advance(Duration::from_millis(10));
+        now = clock.now();
+
+        // PTO packets are acked.
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(4..6);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                25,
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+                None
+            ),
+            Ok((2, 2000))
+        );
+
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 4);
+        assert_eq!(r.bytes_in_flight, 0);
+
+        assert_eq!(r.lost_count, 2);
+
+        // Wait 1 RTT.
+        clock.advance(r.rtt());
+        now = clock.now();
+
+        r.detect_lost_packets(packet::EPOCH_APPLICATION, now);
+
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 0);
+    }
+
+    #[test]
+    fn loss_on_timer() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let clock = ManualClock::new();
+
+        let mut r = Recovery::new(&cfg);
+        r.set_clock(Clock::Manual(clock.clone()));
+
+        let mut now = clock.now();
+
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 0);
+
+        // Start by sending a few packets.
+        let p = Sent {
+            pkt_num: 0,
+            frames: vec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 1000,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            content: SentContent::default(),
+            is_pmtud_probe: false,
+            padding_bytes: 0,
+            datagram_id: None,
+            burst_index: 0,
+            burst_size: 0,
+            congestion_state: CongestionState::SlowStart,
+        };
+
+        r.on_packet_sent(
+            p,
+            packet::EPOCH_APPLICATION,
+            HandshakeStatus::default(),
+            now,
+        );
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 1);
+        assert_eq!(r.bytes_in_flight, 1000);
+
+        let p = Sent {
+            pkt_num: 1,
+            frames: vec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 1000,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            content: SentContent::default(),
+            is_pmtud_probe: false,
+            padding_bytes: 0,
+            datagram_id: None,
+            burst_index: 0,
+            burst_size: 0,
+            congestion_state: CongestionState::SlowStart,
+        };
+
+        r.on_packet_sent(
+            p,
+            packet::EPOCH_APPLICATION,
+            HandshakeStatus::default(),
+            now,
+        );
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 2);
+        assert_eq!(r.bytes_in_flight, 2000);
+
+        let p = Sent {
+            pkt_num: 2,
+            frames: vec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 1000,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            content: SentContent::default(),
+            is_pmtud_probe: false,
+            padding_bytes: 0,
+            datagram_id: None,
+            burst_index: 0,
+            burst_size: 0,
+            congestion_state: CongestionState::SlowStart,
+        };
+
+        r.on_packet_sent(
+            p,
+            packet::EPOCH_APPLICATION,
+            HandshakeStatus::default(),
+            now,
+        );
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 3);
+        assert_eq!(r.bytes_in_flight, 3000);
+
+        let p = Sent {
+            pkt_num: 3,
+            frames: vec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 1000,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            content: SentContent::default(),
+            is_pmtud_probe: false,
+            padding_bytes: 0,
+            datagram_id: None,
+            burst_index: 0,
+            burst_size: 0,
+            congestion_state: CongestionState::SlowStart,
+        };
+
+        r.on_packet_sent(
+            p,
+            packet::EPOCH_APPLICATION,
+            HandshakeStatus::default(),
+            now,
+        );
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 4);
+        assert_eq!(r.bytes_in_flight, 4000);
+
+        // Wait for 10ms.
+        clock.advance(Duration::from_millis(10));
+        now = clock.now();
+
+        // Only the first 2 packets and the last one are acked.
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..2);
+        acked.insert(3..4);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                25,
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+                None
+            ),
+            Ok((0, 0))
+        );
+
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 2);
+        assert_eq!(r.bytes_in_flight, 1000);
+        assert_eq!(r.lost_count, 0);
+
+        // Wait until loss detection timer expires.
+        clock.advance_to(r.loss_detection_timer().unwrap());
+        now = clock.now();
+
+        // Packet is declared lost.
+        let outcome =
+            r.on_loss_detection_timeout_outcome(HandshakeStatus::default(), now);
+        assert_eq!(outcome.kind, TimeoutKind::LossTime);
+        assert_eq!(outcome.epoch, Some(packet::EPOCH_APPLICATION));
+        assert_eq!(outcome.lost_packets, 1);
+        assert_eq!(outcome.lost_bytes, 1000);
+        assert_eq!(outcome.probes, 0);
+        assert_eq!(outcome.next_timer, r.loss_detection_timer());
+        assert_eq!(r.loss_probes[packet::EPOCH_APPLICATION], 0);
+
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 2);
+        assert_eq!(r.bytes_in_flight, 0);
+
+        assert_eq!(r.lost_count, 1);
+
+        // Wait 1 RTT.
+        clock.advance(r.rtt());
+        now = clock.now();
+
+        r.detect_lost_packets(packet::EPOCH_APPLICATION, now);
+
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 0);
+    }
+
+    #[test]
+    fn loss_on_timer_granularity() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.set_timer_granularity(Duration::from_millis(10))
+            .unwrap();
+
+        let mut r = Recovery::new(&cfg);
+
+        let start = Instant::now();
+
+        // Send a few packets.
+        testing::PacketTrain::new(2, 1000).send(&mut r, start);
+        assert_eq!(r.bytes_in_flight, 2000);
+
+        // Only the last packet is acked, after 100us, which is much lower than
+        // the configured granularity.
+        let res = testing::AckScript::new()
+            .ack_delay(0)
+            .after(Duration::from_micros(100))
+            .ack(1..2)
+            .run(&mut r, start);
+        assert_eq!(res.lost, [(0, 0)]);
+
+        assert_eq!(r.rtt(), Duration::from_micros(100));
+
+        // The loss delay is floored to the configured granularity.
+        assert_eq!(
+            r.loss_detection_timer(),
+            Some(start + Duration::from_millis(10))
+        );
+
+        // The RTT variance component of the PTO is floored as well.
+        assert_eq!(r.pto(), r.rtt() + Duration::from_millis(10));
+
+        // Packet is not lost before the timer expires.
+        let now = start + Duration::from_millis(9);
+        r.detect_lost_packets(packet::EPOCH_APPLICATION, now);
+        assert_eq!(r.lost_count, 0);
+
+        let res = testing::AckScript::new()
+            .loss_detection_timeout()
+            .run(&mut r, now);
+        assert_eq!(res.lost, [(1, 1000)]);
+        assert_eq!(res.now, start + Duration::from_millis(10));
+
+        assert_eq!(r.lost_count, 1);
+        assert_eq!(r.bytes_in_flight, 0);
+    }
+
+    #[test]
+    fn loss_on_reordering() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 0);
+
+        // Start by sending a few packets.
+        testing::PacketTrain::new(4, 1000).send(&mut r, now);
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 4);
+        assert_eq!(r.bytes_in_flight, 4000);
+
+        // ACKs are reordered.
+        let res = testing::AckScript::new()
+            .after(Duration::from_millis(10))
+            .ack(2..4)
+            .run(&mut r, now);
+        assert_eq!(res.lost, [(1, 1000)]);
+
+        assert_eq!(r.pkt_thresh, INITIAL_PACKET_THRESHOLD);
+
+        let res = testing::AckScript::new()
+            .after(Duration::from_millis(10))
+            .ack(0..2)
+            .run(&mut r, res.now);
+        assert_eq!(res.lost, [(0, 0)]);
+
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 4);
+        assert_eq!(r.bytes_in_flight, 0);
+
+        // Spurious loss.
+        assert_eq!(r.lost_count, 1);
+        assert_eq!(r.lost_spurious_count, 1);
+
+        // Packet threshold was increased.
+        assert_eq!(r.pkt_thresh, 4);
+
+        // Wait 1 RTT.
+        let now = res.now + r.rtt();
+
+        r.detect_lost_packets(packet::EPOCH_APPLICATION, now);
+
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 0);
+    }
+
+    #[test]
+    fn loss_on_reordering_no_adaptive_threshold() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_adaptive_reorder_threshold(false);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 0);
+
+        // Start by sending a few packets.
+        testing::PacketTrain::new(4, 1000).send(&mut r, now);
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 4);
+        assert_eq!(r.bytes_in_flight, 4000);
+
+        // ACKs are reordered.
+        let res = testing::AckScript::new()
+            .after(Duration::from_millis(10))
+            .ack(2..4)
+            .run(&mut r, now);
+        assert_eq!(res.lost, [(1, 1000)]);
+
+        assert_eq!(r.pkt_thresh, INITIAL_PACKET_THRESHOLD);
+
+        let res = testing::AckScript::new()
+            .after(Duration::from_millis(10))
+            .ack(0..2)
+            .run(&mut r, res.now);
+        assert_eq!(res.lost, [(0, 0)]);
+
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 4);
+        assert_eq!(r.bytes_in_flight, 0);
+
+        // Spurious loss.
+        assert_eq!(r.lost_count, 1);
+        assert_eq!(r.lost_spurious_count, 1);
+
+        // Packet threshold was not increased.
+        assert_eq!(r.pkt_thresh, INITIAL_PACKET_THRESHOLD);
+        assert_eq!(r.time_thresh, INITIAL_TIME_THRESHOLD);
+
+        // Wait 1 RTT.
+        let now = res.now + r.rtt();
+
+        r.detect_lost_packets(packet::EPOCH_APPLICATION, now);
+
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 0);
+    }
+
+    #[test]
+    fn preset_reorder_thresholds() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let now = Instant::now();
+
+        // Reads reflect the adaptation to a spurious loss.
+        let mut r = Recovery::new(&cfg);
+        assert_eq!(
+            r.reorder_thresholds(),
+            (INITIAL_PACKET_THRESHOLD, INITIAL_TIME_THRESHOLD)
+        );
+
+        testing::PacketTrain::new(4, 1000).send(&mut r, now);
+
+        let res = testing::AckScript::new()
+            .after(Duration::from_millis(10))
+            .ack(2..4)
+            .after(Duration::from_millis(10))
+            .ack(0..2)
+            .run(&mut r, now);
+        assert_eq!(res.lost, [(1, 1000), (0, 0)]);
+        assert_eq!(r.lost_spurious_count, 1);
+
+        assert_eq!(r.reorder_thresholds(), (4, r.time_thresh));
+
+        // Thresholds are bounded as the configured ones.
+        for (pkt, time) in [
+            (INITIAL_PACKET_THRESHOLD - 1, 1.5),
+            (MAX_PACKET_THRESHOLD + 1, 1.5),
+            (5, 0.5),
+            (5, f64::NAN),
+            (5, f64::INFINITY),
+        ] {
+            assert_eq!(
+                r.set_reorder_thresholds(pkt, time),
+                Err(crate::Error::CongestionControl)
+            );
+        }
+        assert_eq!(r.reorder_thresholds().0, 4);
+
+        // The same reordering isn't declared as loss with high thresholds.
+        let mut r = Recovery::new(&cfg);
+        assert_eq!(r.set_reorder_thresholds(MAX_PACKET_THRESHOLD, 2.0), Ok(()));
+        assert_eq!(r.reorder_thresholds(), (MAX_PACKET_THRESHOLD, 2.0));
+
+        testing::PacketTrain::new(4, 1000).send(&mut r, now);
+
+        let res = testing::AckScript::new()
+            .after(Duration::from_millis(10))
+            .ack(2..4)
+            .after(Duration::from_millis(10))
+            .ack(0..2)
+            .run(&mut r, now);
+        assert_eq!(res.lost, [(0, 0), (0, 0)]);
+        assert_eq!(r.lost_count, 0);
+        assert_eq!(r.lost_spurious_count, 0);
+        assert_eq!(r.reorder_thresholds(), (MAX_PACKET_THRESHOLD, 2.0));
+    }
+
+    #[test]
+    fn caller_driven_time() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let clock = ManualClock::new();
+
+        let mut r = Recovery::new(&cfg);
+        r.set_clock(Clock::Manual(clock.clone()));
+
+        let start = clock.now();
+
+        // Timers are formatted relative to the clock's time, which only moves
+        // when the caller says so.
+        testing::PacketTrain::new(4, 1000).send(&mut r, start);
+
+        let timer = r.loss_detection_timer().unwrap();
+        assert!(format!("{:?}", r)
+            .starts_with(&format!("timer={:?} ", timer - start)));
+
+        // The loss delay goes back further than the first packet was sent,
+        // which is before the clock's origin on platforms where it's close to
+        // the start of the process.
+        let res = testing::AckScript::new()
+            .after(Duration::from_millis(10))
+            .ack(2..4)
+            .run(&mut r, start);
+        assert_eq!(res.lost, [(1, 1000)]);
+
+        let sent = &r.sent[packet::EPOCH_APPLICATION];
+        let time_sent = sent.iter().find(|p| p.pkt_num == 1).unwrap().time_sent;
+
+        let timer = r.loss_detection_timer().unwrap();
+        assert_eq!(timer, time_sent + r.rtt().mul_f64(r.time_thresh));
+
+        clock.advance_to(timer);
+
+        assert_eq!(
+            r.on_loss_detection_timeout(
+                testing::HANDSHAKE_COMPLETED,
+                clock.now()
+            ),
+            (1, 1000)
+        );
+        assert_eq!(r.lost_count, 2);
+    }
+
+    #[test]
+    fn pmtud_probe_acked() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.set_max_send_udp_payload_size(1500);
+        cfg.enable_pmtu_discovery(true);
+
+        let mut r = Recovery::new(&cfg);
+
+        // Start from the base datagram size.
+        assert_eq!(r.max_datagram_size(), 1200);
+        assert_eq!(r.cwnd(), 1200 * INITIAL_WINDOW_PACKETS);
+
+        let mut now = Instant::now();
+
+        let probe_size = r.pmtud_probe_size(now).unwrap();
+        assert_eq!(probe_size, 1350);
+
+        let p = Sent {
+            pkt_num: 0,
+            frames: vec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: probe_size,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            content: SentContent::default(),
+            is_pmtud_probe: true,
+            padding_bytes: 0,
+            datagram_id: None,
+            burst_index: 0,
+            burst_size: 0,
+            congestion_state: CongestionState::SlowStart,
+        };
+
+        r.on_packet_sent(
+            p,
+            packet::EPOCH_APPLICATION,
+            HandshakeStatus::default(),
+            now,
+        );
+        assert_eq!(r.bytes_in_flight, probe_size);
+
+        // Only one probe is in flight at a time.
+        assert_eq!(r.pmtud_probe_size(now), None);
+
+        now += Duration::from_millis(10);
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..1);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                25,
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+                None
+            ),
+            Ok((0, 0))
+        );
+
+        // The datagram size is raised, and the congestion window stays the
+        // same in number of packets.
+        assert_eq!(r.max_datagram_size(), probe_size);
+        assert_eq!(r.cwnd(), probe_size * INITIAL_WINDOW_PACKETS);
+        assert_eq!(r.bytes_in_flight, 0);
+
+        // The search continues with a larger size.
+        assert!(r.pmtud_probe_size(now).unwrap() > probe_size);
+    }
+
+    #[test]
+    fn pmtud_probe_lost() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.set_max_send_udp_payload_size(1500);
+        cfg.enable_pmtu_discovery(true);
+
+        let mut r = Recovery::new(&cfg);
+
+        let mut now = Instant::now();
+
+        let probe_size = r.pmtud_probe_size(now).unwrap();
+
+        let cwnd = r.cwnd();
+
+        // Send a probe in between regular packets.
+        for pkt_num in 0..5 {
+            let is_pmtud_probe = pkt_num == 1;
+
+            let p = Sent {
+                pkt_num,
+                frames: vec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: if is_pmtud_probe { probe_size } else { 1000 },
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                content: SentContent::default(),
+                is_pmtud_probe,
+                padding_bytes: 0,
+                datagram_id: None,
+                burst_index: 0,
+                burst_size: 0,
+                congestion_state: CongestionState::SlowStart,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+            );
+        }
+
+        assert_eq!(r.bytes_in_flight, 4000 + probe_size);
+
+        now += Duration::from_millis(10);
+
+        // Everything but the probe is acked, so it's declared lost.
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..1);
+        acked.insert(2..5);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                25,
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+                None
+            ),
+            Ok((0, 0))
+        );
+
+        // The lost probe doesn't count as a lost packet, nor triggers a
+        // congestion response.
+        assert_eq!(r.lost_count, 0);
+        assert_eq!(r.bytes_lost, 0);
+        assert_eq!(r.bytes_in_flight, 0);
+        assert_eq!(r.cwnd(), cwnd);
+        assert_eq!(r.congestion_recovery_start_time, None);
+
+        assert_eq!(r.max_datagram_size(), 1200);
+
+        // The same size is probed again.
+        assert_eq!(r.pmtud_probe_size(now), Some(probe_size));
+    }
+
+    #[test]
+    fn padding_excluded_from_cc() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let mut now = Instant::now();
+
+        let cwnd = r.cwnd();
+
+        // Send a padded probe in between regular packets.
+        for pkt_num in 0..5 {
+            let padded = pkt_num == 1;
+
+            let p = Sent {
+                pkt_num,
+                frames: vec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: if padded { 1200 } else { 1000 },
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                content: SentContent::default(),
+                is_pmtud_probe: false,
+                padding_bytes: if padded { 1150 } else { 0 },
+                datagram_id: None,
+                burst_index: 0,
+                burst_size: 0,
+                congestion_state: CongestionState::SlowStart,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+            );
+        }
+
+        // The padding doesn't use any congestion window.
+        assert_eq!(r.bytes_in_flight, 4050);
+        assert_eq!(r.cwnd_available(), cwnd - 4050);
+
+        now += Duration::from_millis(10);
+
+        // Everything but the padded packet is acked, so it's declared lost.
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..1);
+        acked.insert(2..5);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                25,
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+                None
+            ),
+            Ok((1, 50))
+        );
+
+        assert_eq!(r.lost_count, 1);
+        assert_eq!(r.bytes_lost, 50);
+        assert_eq!(r.bytes_in_flight, 0);
+    }
+
+    #[test]
+    fn sent_index_with_gaps() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        assert_eq!(r.sent_index(packet::EPOCH_APPLICATION, 0), 0);
+
+        // Simple LCG, so that the packet number gaps are reproducible.
+        let mut seed: u64 = 0x5eed;
+        let mut next_rand = || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            seed >> 33
+        };
+
+        let mut pkt_num = 10;
+
+        for _ in 0..5000 {
+            // Skip packet numbers once in a while.
+            if next_rand() % 16 == 0 {
+                pkt_num += next_rand() % 8 + 1;
+            }
+
+            r.sent[packet::EPOCH_APPLICATION].push_back(Sent {
+                pkt_num,
+                frames: vec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1000,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                content: SentContent::default(),
+                is_pmtud_probe: false,
+                padding_bytes: 0,
+                datagram_id: None,
+                burst_index: 0,
+                burst_size: 0,
+                congestion_state: CongestionState::SlowStart,
+            });
+
+            pkt_num += 1;
+        }
+
+        // Compare with a linear search over the whole list.
+        for n in 0..pkt_num + 10 {
+            let expected = r.sent[packet::EPOCH_APPLICATION]
+                .iter()
+                .position(|p| p.pkt_num >= n)
+                .unwrap_or(r.sent[packet::EPOCH_APPLICATION].len());
+
+            assert_eq!(r.sent_index(packet::EPOCH_APPLICATION, n), expected);
+        }
+    }
+
+    #[test]
+    fn compact_sent_packets() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let mut now = Instant::now();
+
+        for pkt_num in 0..3000 {
+            let p = Sent {
+                pkt_num,
+                frames: vec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1000,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                content: SentContent::default(),
+                is_pmtud_probe: false,
+                padding_bytes: 0,
+                datagram_id: None,
+                burst_index: 0,
+                burst_size: 0,
+                congestion_state: CongestionState::SlowStart,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+            );
+        }
+
+        assert_eq!(r.sent_packets_len(), 3000);
+
+        now += Duration::from_millis(10);
+
+        // Everything but the first packet is acked.
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(1..3000);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                25,
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+                None
+            ),
+            Ok((1, 1000))
+        );
+
+        // The lost packet is kept around in case it gets acked later, but
+        // the acked packets following it are removed.
+        assert_eq!(r.sent_packets_len(), 1);
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION][0].pkt_num, 0);
+
+        // Once expired, the lost packet is removed as well.
+        now += r.rtt();
+
+        r.detect_lost_packets(packet::EPOCH_APPLICATION, now);
+
+        assert_eq!(r.sent_packets_len(), 0);
+    }
+
+    #[test]
+    fn ack_only_packets() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let mut now = Instant::now();
+
+        let mut pings = 0;
+
+        // A connection that only receives data sends mostly ACK-only packets,
+        // and a PING every so often to get them acknowledged.
+        for pkt_num in 0..3000 {
+            let ack_eliciting = r.should_elicit_ack(packet::EPOCH_APPLICATION);
+
+            let p = Sent {
+                pkt_num,
+                frames: vec![frame::Frame::ACKHeader {
+                    largest_acked: pkt_num,
+                }],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 50,
+                ack_eliciting,
+                in_flight: ack_eliciting,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                content: SentContent::default(),
+                is_pmtud_probe: false,
+                padding_bytes: 0,
+                datagram_id: None,
+                burst_index: 0,
+                burst_size: 0,
+                congestion_state: CongestionState::SlowStart,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+            );
+
+            // ACK-only packets are not tracked.
+            assert!(r.sent_packets_len() <= 1);
+
+            now += Duration::from_millis(1);
+
+            if !ack_eliciting {
+                continue;
+            }
+
+            pings += 1;
+
+            // The peer only acknowledges the PING, but its ACK frame covers
+            // all the packets received so far.
+            let mut acked = ranges::RangeSet::default();
+            acked.insert(0..pkt_num + 1);
+
+            assert_eq!(
+                r.on_ack_received(
+                    &acked,
+                    0,
+                    packet::EPOCH_APPLICATION,
+                    HandshakeStatus::default(),
+                    now,
+                    None
+                ),
+                Ok((0, 0))
+            );
+
+            assert_eq!(r.sent_packets_len(), 0);
+        }
+
+        // Every packet following `DEFAULT_MAX_OUTSTANDING_NON_ACK_ELICITING`
+        // ACK-only ones solicits an ACK.
+        assert_eq!(
+            pings,
+            3000 / (DEFAULT_MAX_OUTSTANDING_NON_ACK_ELICITING + 1)
+        );
+
+        assert_eq!(r.lost_count, 0);
+        assert_eq!(r.bytes_in_flight, 0);
+        assert_eq!(r.loss_detection_timer(), None);
+
+        // Other packet number spaces are not affected.
+        assert!(!r.should_elicit_ack(packet::EPOCH_HANDSHAKE));
+    }
+
+    #[test]
+    fn packet_discarded() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_pacing(false);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        testing::send_packets(
+            &mut r,
+            Some(testing::sent_packet(0, testing::stream_frames(0, 1), now)),
+            now,
+        );
+
+        let bytes_in_flight = r.bytes_in_flight;
+        let bytes_sent = r.bytes_sent;
+        let sent_frames_mem = r.sent_frames_mem;
+        let timer = r.loss_detection_timer();
+
+        let later = now + Duration::from_millis(10);
+
+        testing::send_packets(
+            &mut r,
+            Some(testing::sent_packet(1, testing::stream_frames(1, 2), later)),
+            later,
+        );
+
+        assert_ne!(r.loss_detection_timer(), timer);
+
+        // The second packet couldn't be sent.
+        assert_eq!(
+            r.on_packet_discarded(
+                packet::EPOCH_APPLICATION,
+                1,
+                testing::HANDSHAKE_COMPLETED,
+                later
+            ),
+            Some(testing::stream_frames(1, 2))
+        );
+
+        assert_eq!(r.bytes_in_flight, bytes_in_flight);
+        assert_eq!(r.bytes_sent, bytes_sent);
+        assert_eq!(r.sent_frames_mem, sent_frames_mem);
+        assert_eq!(r.in_flight_count, [0, 0, 1]);
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 1);
+        assert_eq!(r.loss_detection_timer(), timer);
+
+        assert_eq!(
+            r.on_packet_discarded(
+                packet::EPOCH_APPLICATION,
+                1,
+                testing::HANDSHAKE_COMPLETED,
+                later
+            ),
+            None
+        );
+
+        // Acked packets are not discarded.
+        testing::ack_packets(&mut r, &testing::range_set(Some(0..1)), later);
+
+        assert_eq!(
+            r.on_packet_discarded(
+                packet::EPOCH_APPLICATION,
+                0,
+                testing::HANDSHAKE_COMPLETED,
+                later
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn correct_sent_time() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_pacing(false);
+        cfg.enable_adaptive_reorder_threshold(false);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        // The packets are recorded as sent when the batch was built, but only
+        // left 10ms later.
+        testing::send_packets(
+            &mut r,
+            (0..3).map(|pn| {
+                testing::sent_packet(pn, testing::stream_frames(pn, 1), now)
+            }),
+            now,
+        );
+
+        let timer = r.loss_detection_timer();
+
+        let actual_time = now + Duration::from_millis(10);
+
+        for pn in 1..3 {
+            assert_eq!(
+                r.correct_sent_time(
+                    packet::EPOCH_APPLICATION,
+                    pn,
+                    actual_time,
+                    testing::HANDSHAKE_COMPLETED,
+                    now
+                ),
+                Some(SentTimeCorrection::Applied)
+            );
+        }
+
+        assert_eq!(
+            r.correct_sent_time(
+                packet::EPOCH_APPLICATION,
+                3,
+                actual_time,
+                testing::HANDSHAKE_COMPLETED,
+                now
+            ),
+            None
+        );
+
+        // The probe timeout follows the latest packet.
+        assert_eq!(
+            r.time_of_last_sent_ack_eliciting_pkt[packet::EPOCH_APPLICATION],
+            Some(actual_time)
+        );
+        assert_eq!(
+            r.loss_detection_timer(),
+            timer.map(|t| t + Duration::from_millis(10))
+        );
+
+        testing::ack_packets(
+            &mut r,
+            &testing::range_set(Some(2..3)),
+            now + Duration::from_millis(60),
+        );
+
+        assert_eq!(r.latest_rtt, Duration::from_millis(50));
+
+        // Packet 0 was actually sent 60ms ago, and is lost.
+        assert_eq!(r.lost_count, 1);
+
+        // The sample of an acked packet can't be updated anymore.
+        assert_eq!(
+            r.correct_sent_time(
+                packet::EPOCH_APPLICATION,
+                2,
+                now + Duration::from_millis(20),
+                testing::HANDSHAKE_COMPLETED,
+                now
+            ),
+            Some(SentTimeCorrection::Late {
+                rtt: Duration::from_millis(40)
+            })
+        );
+    }
+
+    #[test]
+    fn plan_packet() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        let plan = r.plan_packet(packet::EPOCH_APPLICATION, now, None);
+
+        assert_eq!(plan, PacketPlan {
+            epoch: packet::EPOCH_APPLICATION,
+            ack_eliciting: false,
+            solicit_ack: false,
+            probe: false,
+            bytes: r.cwnd_available(),
+            round_budget: r.cwnd_available(),
+            lost_frames: false,
+        });
+
+        r.commit_plan(plan);
+
+        testing::send_packets(
+            &mut r,
+            (0..2).map(|pn| {
+                testing::sent_packet(pn, testing::stream_frames(pn, 1), now)
+            }),
+            now,
+        );
+
+        // The PTO requests a probe and queues the frames of the oldest packet.
+        let timer = r.loss_detection_timer().unwrap();
+        r.on_loss_detection_timeout(testing::HANDSHAKE_COMPLETED, timer);
+        assert_eq!(r.loss_probes, [0, 0, 1]);
+
+        let plan = r.plan_packet(packet::EPOCH_APPLICATION, timer, Some(500));
+
+        assert!(plan.probe);
+        assert!(plan.ack_eliciting);
+        assert!(plan.lost_frames);
+        assert_eq!(plan.bytes, 500);
+        assert_eq!(r.loss_probes, [0, 0, 0]);
+
+        // A packet that isn't sent gives the probe back.
+        r.abandon_plan(plan);
+        assert_eq!(r.loss_probes, [0, 0, 1]);
+
+        let plan = r.plan_packet(packet::EPOCH_APPLICATION, timer, None);
+        assert!(plan.probe);
+
+        r.commit_plan(plan);
+        assert_eq!(r.loss_probes, [0, 0, 0]);
+
+        // Other packet number spaces are not affected.
+        let plan = r.plan_packet(packet::EPOCH_HANDSHAKE, timer, None);
+        assert!(!plan.probe);
+        assert!(!plan.ack_eliciting);
+
+        r.abandon_plan(plan);
+        assert_eq!(r.loss_probes, [0, 0, 0]);
+    }
+
+    #[test]
+    fn pause_resume() {
+        for probe in [false, true] {
+            let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+            cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+            cfg.enable_pacing(false);
+            cfg.enable_path_resume_probe(probe);
+
+            let mut r = Recovery::new(&cfg);
+
+            let now = Instant::now();
+
+            testing::send_packets(
+                &mut r,
+                (0..3).map(|pn| {
+                    testing::sent_packet(pn, testing::stream_frames(pn, 1), now)
+                }),
+                now,
+            );
+
+            // The last packet is acked, so the others will be declared lost
+            // by time threshold.
+            let paused_at = now + Duration::from_millis(10);
+            testing::ack_packets(
+                &mut r,
+                &testing::range_set(Some(2..3)),
+                paused_at,
+            );
+
+            let timer = r.loss_detection_timer().unwrap();
+
+            r.pause(paused_at);
+
+            assert!(r.is_paused());
+            assert_eq!(r.loss_detection_timer(), None);
+            assert_eq!(r.send_allowance(paused_at, None).bytes, 0);
+
+            // The pause lasts longer than the loss delay.
+            let resumed_at = paused_at + Duration::from_secs(1);
+            assert!(resumed_at > timer);
+
+            r.resume(testing::HANDSHAKE_COMPLETED, resumed_at);
+
+            assert!(!r.is_paused());
+            assert_eq!(r.lost_count, 0);
+            assert!(r.send_allowance(resumed_at, None).bytes > 0);
+
+            // The timer resumes where it left off.
+            assert_eq!(
+                r.loss_detection_timer(),
+                Some(resumed_at + (timer - paused_at))
+            );
+
+            if probe {
+                assert_eq!(r.loss_probes, [0, 0, 1]);
+            } else {
+                assert_eq!(r.loss_probes, [0, 0, 0]);
+            }
+
+            // The remaining packets are acked, and the pause doesn't count
+            // toward the RTT.
+            let acked_at = resumed_at + Duration::from_millis(5);
+            testing::ack_packets(
+                &mut r,
+                &testing::range_set(Some(0..2)),
+                acked_at,
+            );
+
+            assert_eq!(r.lost_count, 0);
+            assert_eq!(r.bytes_in_flight, 0);
+            assert_eq!(r.latest_rtt, Duration::from_millis(15));
+        }
+    }
+
+    #[test]
+    fn handshake_pto_multiplier() {
+        let crypto = vec![frame::Frame::CryptoHeader {
+            offset: 0,
+            length: 1000,
+        }];
+
+        let initial = HandshakeStatus {
+            has_handshake_keys: false,
+            peer_verified_address: false,
+            completed: false,
+            amplification_limited: false,
+        };
+
+        // Returns the recovery state and how long after being sent the packet
+        // is probed, with a single packet in flight in the given epoch.
+        let pto_after = |cfg: &crate::Config, epoch, status| {
+            let mut r = Recovery::new(cfg);
+
+            let now = Instant::now();
+
+            r.on_packet_sent(
+                testing::sent_packet(0, crypto.clone(), now),
+                epoch,
+                status,
+                now,
+            );
+
+            let pto = r.loss_detection_timer().unwrap() - now;
+
+            (r, pto)
+        };
+
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+
+        let (_, app_pto) = pto_after(
+            &cfg,
+            packet::EPOCH_APPLICATION,
+            testing::HANDSHAKE_COMPLETED,
+        );
+
+        // Off by default.
+        let (_, initial_pto) = pto_after(&cfg, packet::EPOCH_INITIAL, initial);
+        assert_eq!(initial_pto, app_pto);
+
+        assert_eq!(
+            cfg.set_handshake_pto_multiplier(0.0),
+            Err(crate::Error::CongestionControl)
+        );
+        assert_eq!(
+            cfg.set_handshake_pto_multiplier(1.5),
+            Err(crate::Error::CongestionControl)
+        );
+        assert_eq!(
+            cfg.set_handshake_pto_multiplier(f64::NAN),
+            Err(crate::Error::CongestionControl)
+        );
+        assert_eq!(cfg.set_handshake_pto_multiplier(0.5), Ok(()));
+
+        let (_, multiplied_app_pto) = pto_after(
+            &cfg,
+            packet::EPOCH_APPLICATION,
+            testing::HANDSHAKE_COMPLETED,
+        );
+        assert_eq!(multiplied_app_pto, app_pto);
+
+        let (mut r, initial_pto) =
+            pto_after(&cfg, packet::EPOCH_INITIAL, initial);
+        assert_eq!(initial_pto, app_pto.mul_f64(0.5));
+
+        let timer = r.loss_detection_timer().unwrap();
+        r.on_loss_detection_timeout(initial, timer);
+
+        assert_eq!(r.pto_count, 1);
+        assert_eq!(r.handshake_pto_count, 1);
+        assert_eq!(r.loss_probes, [1, 0, 0]);
+
+        // A server limited by the anti-amplification limit doesn't probe
+        // sooner.
+        let (_, initial_pto) =
+            pto_after(&cfg, packet::EPOCH_INITIAL, HandshakeStatus {
+                amplification_limited: true,
+                ..initial
+            });
+        assert_eq!(initial_pto, app_pto);
+    }
+
+    #[test]
+    fn non_retransmittable_pto_backoff() {
+        let probe = vec![frame::Frame::Ping, frame::Frame::Padding { len: 1000 }];
+
+        for backoff in [true, false] {
+            let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+            cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+            cfg.enable_non_retransmittable_pto_backoff(backoff);
+
+            let mut r = Recovery::new(&cfg);
+
+            let now = Instant::now();
+
+            // Only a padded PING probe is in flight.
+            testing::send_packets(
+                &mut r,
+                Some(testing::sent_packet(0, probe.clone(), now)),
+                now,
+            );
+
+            let timer = r.loss_detection_timer().unwrap();
+
+            r.on_loss_detection_timeout(HandshakeStatus::default(), timer);
+
+            // A PING probe is sent either way, with nothing to retransmit.
+            assert_eq!(r.loss_probes, [0, 0, 1]);
+            assert!(r.lost[packet::EPOCH_APPLICATION].is_empty());
+
+            if backoff {
+                assert_eq!(r.pto_count, 1);
+                assert_eq!(
+                    r.loss_detection_timer(),
+                    Some(now + (timer - now) * 2)
+                );
+            } else {
+                assert_eq!(r.pto_count, 0);
+                assert_eq!(r.loss_detection_timer(), Some(timer));
+            }
+
+            let pto_count = r.pto_count;
+
+            // Once data is in flight, the timeout backs off regardless.
+            testing::send_packets(
+                &mut r,
+                Some(testing::sent_packet(
+                    1,
+                    testing::stream_frames(1, 1),
+                    timer,
+                )),
+                timer,
+            );
+
+            let timer = r.loss_detection_timer().unwrap();
+
+            r.on_loss_detection_timeout(HandshakeStatus::default(), timer);
+
+            assert_eq!(r.pto_count, pto_count + 1);
+            assert_eq!(r.lost[packet::EPOCH_APPLICATION].len(), 1);
+        }
+    }
+
+    #[test]
+    fn max_outstanding_non_ack_eliciting() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let now = Instant::now();
+
+        // Returns how many ACK-only packets are sent before an ACK needs to
+        // be solicited.
+        let ack_only_run = |r: &mut Recovery| {
+            let mut count = 0;
+
+            while !r.should_elicit_ack(packet::EPOCH_APPLICATION) {
+                let mut p = testing::sent_packet(
+                    count,
+                    vec![frame::Frame::ACKHeader {
+                        largest_acked: count,
+                    }],
+                    now,
+                );
+                p.ack_eliciting = false;
+                p.in_flight = false;
+
+                testing::send_packets(r, Some(p), now);
+
+                count += 1;
+            }
+
+            assert!(r.should_solicit_ack(packet::EPOCH_APPLICATION));
+
+            count as usize
+        };
+
+        let mut r = Recovery::new(&cfg);
+        assert_eq!(
+            ack_only_run(&mut r),
+            DEFAULT_MAX_OUTSTANDING_NON_ACK_ELICITING
+        );
+
+        cfg.set_max_outstanding_non_ack_eliciting(2);
+
+        let mut r = Recovery::new(&cfg);
+        assert_eq!(ack_only_run(&mut r), 2);
+
+        assert_eq!(
+            r.time_since_last_ack_eliciting(packet::EPOCH_APPLICATION, now),
+            None
+        );
+
+        // An ack-eliciting packet resets the count.
+        testing::send_packets(
+            &mut r,
+            Some(testing::sent_packet(2, vec![frame::Frame::Ping], now)),
+            now,
+        );
+        assert!(!r.should_elicit_ack(packet::EPOCH_APPLICATION));
+
+        assert_eq!(
+            r.time_since_last_ack_eliciting(
+                packet::EPOCH_APPLICATION,
+                now + Duration::from_millis(10)
+            ),
+            Some(Duration::from_millis(10))
+        );
+
+        // The threshold can be changed at any time.
+        r.set_max_outstanding_non_ack_eliciting(0);
+        assert!(r.should_elicit_ack(packet::EPOCH_APPLICATION));
+    }
+
+    #[test]
+    fn max_loss_probes() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        assert_eq!(
+            cfg.set_max_loss_probes(0),
+            Err(crate::Error::CongestionControl)
+        );
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        let initial = HandshakeStatus {
+            has_handshake_keys: false,
+            peer_verified_address: false,
+            completed: false,
+            amplification_limited: false,
+        };
+
+        let handshake = HandshakeStatus {
+            has_handshake_keys: true,
+            ..initial
+        };
+
+        // PTOs fire in the Initial and then the Handshake epoch before any
+        // probe could be sent.
+        r.on_loss_detection_timeout(initial, now);
+        assert_eq!(r.loss_probes, [1, 0, 0]);
+
+        r.on_loss_detection_timeout(handshake, now);
+        assert_eq!(r.loss_probes, [1, 1, 0]);
+        assert_eq!(r.total_loss_probes(), DEFAULT_MAX_LOSS_PROBES);
+
+        r.on_loss_detection_timeout(handshake, now);
+        assert_eq!(r.loss_probes, [1, 1, 0]);
+
+        // The Handshake epoch has priority when there are not enough probes
+        // for all epochs.
+        assert_eq!(cfg.set_max_loss_probes(1), Ok(()));
+
+        let mut r = Recovery::new(&cfg);
+
+        r.on_loss_detection_timeout(initial, now);
+        assert_eq!(r.loss_probes, [1, 0, 0]);
+
+        r.on_loss_detection_timeout(handshake, now);
+        assert_eq!(r.loss_probes, [0, 1, 0]);
+        assert_eq!(r.total_loss_probes(), 1);
+
+        // Extra probes go to the highest priority epoch.
+        assert_eq!(cfg.set_max_loss_probes(3), Ok(()));
+
+        let mut r = Recovery::new(&cfg);
+
+        r.on_loss_detection_timeout(initial, now);
+        r.on_loss_detection_timeout(handshake, now);
+        assert_eq!(r.loss_probes, [1, 2, 0]);
+        assert_eq!(r.total_loss_probes(), 3);
+    }
+
+    #[test]
+    fn draining() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let mut now = Instant::now();
+
+        let send = |r: &mut Recovery, pkt_num: u64, now: Instant| {
+            let p = Sent {
+                pkt_num,
+                frames: vec![
+                    frame::Frame::ACKHeader {
+                        largest_acked: pkt_num,
+                    },
+                    frame::Frame::StreamHeader {
+                        stream_id: 0,
+                        offset: pkt_num * 1000,
+                        length: 1000,
+                        fin: false,
+                    },
+                ],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1000,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                content: SentContent {
+                    stream: true,
+                    ..Default::default()
+                },
+                is_pmtud_probe: false,
+                padding_bytes: 0,
+                datagram_id: None,
+                burst_index: 0,
+                burst_size: 0,
+                congestion_state: CongestionState::SlowStart,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+            );
+        };
+
+        for pkt_num in 0..5 {
+            send(&mut r, pkt_num, now);
+        }
+
+        now += Duration::from_millis(10);
+
+        // Packets 0 and 1 are lost by packet threshold, and packets 2 and 3
+        // will be by time threshold.
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(4..5);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                0,
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+                None
+            ),
+            Ok((2, 2000))
+        );
+
+        assert_eq!(r.lost[packet::EPOCH_APPLICATION].len(), 4);
+
+        r.loss_probes[packet::EPOCH_APPLICATION] = 1;
+
+        r.enter_draining();
+        assert!(r.is_draining());
+
+        // Only the lost ACK frames are kept.
+        assert_eq!(r.lost[packet::EPOCH_APPLICATION], [
+            frame::Frame::ACKHeader { largest_acked: 0 },
+            frame::Frame::ACKHeader { largest_acked: 1 },
+        ]);
+        assert_eq!(r.loss_probes, [0; packet::EPOCH_COUNT]);
+
+        // Loss detection keeps going.
+        assert_eq!(
+            r.inspect().loss_detection_timer_kind,
+            Some(LossDetectionTimerKind::LossTime)
+        );
+
+        now = r.loss_detection_timer().unwrap();
+
+        assert_eq!(
+            r.on_loss_detection_timeout(HandshakeStatus::default(), now),
+            (2, 2000)
+        );
+
+        assert_eq!(r.lost[packet::EPOCH_APPLICATION], [
+            frame::Frame::ACKHeader { largest_acked: 0 },
+            frame::Frame::ACKHeader { largest_acked: 1 },
+            frame::Frame::ACKHeader { largest_acked: 2 },
+            frame::Frame::ACKHeader { largest_acked: 3 },
+        ]);
+
+        // The probe timeout isn't armed for packets sent while draining.
+        send(&mut r, 5, now);
+
+        assert_eq!(r.bytes_in_flight, 1000);
+        assert_eq!(r.loss_detection_timer(), None);
+    }
+
+    #[test]
+    fn sent_memory_size() {
+        // Sent packets are tracked until they are acked or lost, and each of
+        // them holds a list of frames, so make sure they don't grow unnoticed.
+        assert!(std::mem::size_of::<Sent>() <= 144);
+        assert!(std::mem::size_of::<frame::Frame>() <= 72);
+
+        // Sent ACK frames only keep track of the largest acked packet.
+        let frame = frame::Frame::ACKHeader { largest_acked: 42 };
+        assert!(!frame.ack_eliciting());
+    }
+
+    #[test]
+    fn pto_retransmits_frame_descriptors() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let mut now = Instant::now();
+
+        let frames = vec![
+            frame::Frame::StreamHeader {
+                stream_id: 4,
+                offset: 1000,
+                length: 1000,
+                fin: false,
+            },
+            frame::Frame::MaxData { max: 100_000 },
+        ];
+
+        let p = Sent {
+            pkt_num: 0,
+            frames: frames.clone(),
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 1100,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            content: SentContent::from_frames(&frames),
+            is_pmtud_probe: false,
+            padding_bytes: 0,
+            datagram_id: None,
+            burst_index: 0,
+            burst_size: 0,
+            congestion_state: CongestionState::SlowStart,
+        };
+
+        r.on_packet_sent(
+            p,
+            packet::EPOCH_APPLICATION,
+            HandshakeStatus::default(),
+            now,
+        );
+
+        now = r.loss_detection_timer().unwrap();
+
+        let outcome =
+            r.on_loss_detection_timeout_outcome(HandshakeStatus::default(), now);
+
+        assert_eq!(outcome.kind, TimeoutKind::Pto);
+        assert_eq!(outcome.epoch, Some(packet::EPOCH_APPLICATION));
+        assert_eq!(outcome.lost_packets, 0);
+        assert_eq!(outcome.probes, r.loss_probes[packet::EPOCH_APPLICATION]);
+        assert_eq!(outcome.requeued_frames, 2);
+        assert_eq!(outcome.next_timer, r.loss_detection_timer());
+
+        // The same frames are scheduled for retransmission, while the packet
+        // itself is still tracked with its own copy of them.
+        assert_eq!(r.lost[packet::EPOCH_APPLICATION], frames);
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION][0].frames, frames);
+        assert_eq!(r.lost_count, 0);
+    }
+
+    #[test]
+    fn pto_retransmits_by_content() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let crypto = frame::Frame::CryptoHeader {
+            offset: 0,
+            length: 1000,
+        };
+
+        let stream = frame::Frame::StreamHeader {
+            stream_id: 4,
+            offset: 0,
+            length: 1000,
+            fin: false,
+        };
+
+        let ack = frame::Frame::ACKHeader { largest_acked: 0 };
+
+        let handshake = HandshakeStatus {
+            has_handshake_keys: true,
+            peer_verified_address: true,
+            completed: false,
+            amplification_limited: false,
+        };
+
+        // During the handshake, CRYPTO frames are retransmitted even though
+        // later packets only carried ACK and PING frames.
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        r.on_packets_sent(
+            vec![
+                testing::sent_packet(0, vec![crypto.clone()], now),
+                testing::sent_packet(
+                    1,
+                    vec![ack.clone(), frame::Frame::Ping],
+                    now,
+                ),
+                testing::sent_packet(2, vec![frame::Frame::Ping], now),
+            ],
+            packet::EPOCH_HANDSHAKE,
+            handshake,
+            now,
+        );
+
+        let inspection = r.inspect();
+        assert_eq!(inspection.crypto_packets, [0, 1, 0]);
+        assert_eq!(inspection.stream_packets, [0, 0, 0]);
+        assert_eq!(inspection.control_packets, [0, 0, 0]);
+
+        let now = r.loss_detection_timer().unwrap();
+        r.on_loss_detection_timeout(handshake, now);
+
+        assert_eq!(r.loss_probes[packet::EPOCH_HANDSHAKE], 1);
+        assert_eq!(
+            r.lost[packet::EPOCH_HANDSHAKE],
+            std::slice::from_ref(&crypto)
+        );
+
+        // After the handshake, STREAM frames are retransmitted first.
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        r.on_packets_sent(
+            vec![
+                testing::sent_packet(0, vec![crypto.clone()], now),
+                testing::sent_packet(
+                    1,
+                    vec![ack.clone(), frame::Frame::MaxData { max: 100 }],
+                    now,
+                ),
+                testing::sent_packet(2, vec![stream.clone()], now),
+            ],
+            packet::EPOCH_APPLICATION,
+            HandshakeStatus::default(),
+            now,
+        );
+
+        let inspection = r.inspect();
+        assert_eq!(inspection.crypto_packets, [0, 0, 1]);
+        assert_eq!(inspection.stream_packets, [0, 0, 1]);
+        assert_eq!(inspection.control_packets, [0, 0, 1]);
+
+        let now = r.loss_detection_timer().unwrap();
+        r.on_loss_detection_timeout(HandshakeStatus::default(), now);
+
+        assert_eq!(r.loss_probes[packet::EPOCH_APPLICATION], 1);
+        assert_eq!(
+            r.lost[packet::EPOCH_APPLICATION],
+            std::slice::from_ref(&stream)
+        );
+
+        // Packets carrying no data are never retransmitted on PTO.
+        r.lost[packet::EPOCH_APPLICATION].clear();
+
+        let now = r.loss_detection_timer().unwrap();
+        r.on_loss_detection_timeout(HandshakeStatus::default(), now);
+
+        assert_eq!(r.loss_probes[packet::EPOCH_APPLICATION], 2);
+        assert_eq!(r.lost[packet::EPOCH_APPLICATION], [stream, crypto]);
+    }
+
+    #[test]
+    fn drop_stream_frames() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+        let later = now + Duration::from_millis(100);
+
+        assert_eq!(r.oldest_unacked_age(packet::EPOCH_APPLICATION, now), None);
+
+        // Each packet carries data of streams 0 and 4.
+        testing::send_packets(
+            &mut r,
+            (0..4).map(|pn| {
+                testing::sent_packet(pn, testing::stream_frames(pn, 2), now)
+            }),
+            now,
+        );
+
+        testing::send_packets(
+            &mut r,
+            (4..6).map(|pn| {
+                testing::sent_packet(pn, testing::stream_frames(pn, 2), later)
+            }),
+            later,
+        );
+
+        assert_eq!(
+            r.oldest_unacked_age(
+                packet::EPOCH_APPLICATION,
+                later + Duration::from_millis(50)
+            ),
+            Some(Duration::from_millis(150))
+        );
+        assert_eq!(r.oldest_unacked_age(packet::EPOCH_INITIAL, later), None);
+
+        // Only the data sent before the deadline is dropped.
+        assert_eq!(r.drop_stream_frames(0, now), 4);
+        assert_eq!(r.drop_stream_frames(0, now), 0);
+
+        for p in &r.sent[packet::EPOCH_APPLICATION] {
+            let streams: Vec<u64> = p
+                .frames
+                .iter()
+                .filter_map(|f| match f {
+                    frame::Frame::StreamHeader { stream_id, .. } =>
+                        Some(*stream_id),
+                    _ => None,
+                })
+                .collect();
+
+            if p.pkt_num < 4 {
+                assert_eq!(streams, [4]);
+            } else {
+                assert_eq!(streams, [0, 4]);
+            }
+        }
+
+        // The first packets are lost, but only the data of stream 4 is
+        // retransmitted.
+        testing::ack_packets(&mut r, &testing::range_set(Some(5..6)), later);
+
+        assert_eq!(r.lost_count, 3);
+        assert_eq!(r.lost[packet::EPOCH_APPLICATION].len(), 3);
+        assert!(r.lost[packet::EPOCH_APPLICATION].iter().all(|f| matches!(
+            f,
+            frame::Frame::StreamHeader { stream_id: 4, .. }
+        )));
+
+        assert_eq!(
+            r.oldest_unacked_age(packet::EPOCH_APPLICATION, later),
+            Some(Duration::from_millis(100))
+        );
+
+        // Lost frames of the stream are dropped as well.
+        assert_eq!(r.drop_stream_frames(4, now), 4);
+        assert!(r.lost[packet::EPOCH_APPLICATION].is_empty());
+    }
+
+    #[test]
+    fn drain_lost_frames() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let stream = |stream_id: u64, offset: u64| frame::Frame::StreamHeader {
+            stream_id,
+            offset,
+            length: 1000,
+            fin: false,
+        };
+
+        r.lost[packet::EPOCH_APPLICATION] = vec![
+            stream(0, 0),
+            frame::Frame::MaxData { max: 1000 },
+            stream(4, 0),
+            stream(0, 1000),
+            frame::Frame::ResetStream {
+                stream_id: 8,
+                error_code: 0,
+                final_size: 0,
+            },
+            stream(4, 1000),
+        ];
+
+        // Stream 4 is more urgent than stream 0.
+        let lost: Vec<frame::Frame> = r
+            .drain_lost_frames(packet::EPOCH_APPLICATION, |stream_id| {
+                if stream_id == 4 {
+                    0
+                } else {
+                    1
+                }
+            })
+            .collect();
+
+        assert_eq!(lost, [
+            frame::Frame::MaxData { max: 1000 },
+            frame::Frame::ResetStream {
+                stream_id: 8,
+                error_code: 0,
+                final_size: 0,
+            },
+            stream(4, 0),
+            stream(4, 1000),
+            stream(0, 0),
+            stream(0, 1000),
+        ]);
+
+        assert!(r.lost[packet::EPOCH_APPLICATION].is_empty());
+    }
+
+    #[test]
+    fn pto_retransmission_suppressed() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        testing::send_packets(
+            &mut r,
+            (0..2).map(|pn| {
+                testing::sent_packet(pn, testing::stream_frames(pn, 2), now)
+            }),
+            now,
+        );
+
+        // The frames of the first packet are queued for retransmission.
+        let timer = r.loss_detection_timer().unwrap();
+        r.on_loss_detection_timeout(testing::HANDSHAKE_COMPLETED, timer);
+
+        assert_eq!(r.pto_count, 1);
+        assert_eq!(
+            r.lost[packet::EPOCH_APPLICATION],
+            testing::stream_frames(0, 2)
+        );
+
+        // The packet is acked before the retransmission is sent.
+        testing::ack_packets(&mut r, &testing::range_set(Some(0..1)), timer);
+
+        assert!(r.lost[packet::EPOCH_APPLICATION].is_empty());
+        assert!(r.pto_queued[packet::EPOCH_APPLICATION].is_empty());
+        assert_eq!(r.suppressed_retrans_count, 2);
+        assert_eq!(r.lost_count, 0);
+
+        // Frames already handed to the connection aren't tracked anymore.
+        let timer = r.loss_detection_timer().unwrap();
+        r.on_loss_detection_timeout(testing::HANDSHAKE_COMPLETED, timer);
+
+        assert_eq!(
+            r.drain_lost_frames(packet::EPOCH_APPLICATION, |_| 0)
+                .count(),
+            2
+        );
+
+        testing::ack_packets(&mut r, &testing::range_set(Some(1..2)), timer);
+
+        assert_eq!(r.suppressed_retrans_count, 2);
+    }
+
+    #[test]
+    fn remove_frames() {
+        let frames = testing::stream_frames(1, 4);
+
+        let mut lost = testing::stream_frames(0, 4);
+        lost.extend_from_slice(&frames);
+
+        // The frames are found in a single pass.
+        assert_eq!(
+            super::remove_frames(&mut lost, &mut BTreeMap::new(), &frames),
+            4
+        );
+        assert_eq!(lost, testing::stream_frames(0, 4));
+
+        // Frames dropped from the queue in the meantime are skipped.
+        let frames = testing::stream_frames(0, 4);
+        lost.remove(1);
+
+        assert_eq!(
+            super::remove_frames(&mut lost, &mut BTreeMap::new(), &frames),
+            3
+        );
+        assert!(lost.is_empty());
+    }
+
+    #[test]
+    fn outstanding_packets() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+        let sent_at = |pn: u64| now + Duration::from_millis(pn * 10);
+
+        for pn in 0..5 {
+            testing::send_packets(
+                &mut r,
+                Some(testing::sent_packet(
+                    pn,
+                    testing::stream_frames(pn, 2),
+                    sent_at(pn),
+                )),
+                sent_at(pn),
+            );
+        }
+
+        assert_eq!(r.outstanding_packets(packet::EPOCH_APPLICATION).count(), 5);
+
+        testing::ack_packets(&mut r, &testing::range_set(Some(0..2)), sent_at(5));
+
+        let outstanding: Vec<OutstandingPacket> =
+            r.outstanding_packets(packet::EPOCH_APPLICATION).collect();
+
+        assert_eq!(outstanding.iter().map(|p| p.pkt_num).collect::<Vec<_>>(), [
+            2, 3, 4
+        ]);
+
+        for p in &outstanding {
+            assert_eq!(p.size, testing::PACKET_SIZE);
+            assert_eq!(p.time_sent, sent_at(p.pkt_num));
+            assert!(p.ack_eliciting);
+            assert_eq!(p.frames().collect::<Vec<_>>(), [
+                ("STREAM", 1006),
+                ("STREAM", 1006)
+            ]);
+        }
+
+        assert_eq!(r.outstanding_packets(packet::EPOCH_INITIAL).count(), 0);
+
+        let dump = r.outstanding_dump(sent_at(5)).to_string();
+
+        assert!(dump.starts_with("Short: 3 packets {pkt_num=2 "));
+        assert!(dump.contains("frames=[STREAM/1006 STREAM/1006] sent=30ms ago}"));
+    }
+
+    #[test]
+    fn acked_frames_callback() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+        let mut r_buffered = Recovery::new(&cfg);
+
+        let mut now = Instant::now();
+
+        let frames = |pkt_num: u64| {
+            vec![
+                frame::Frame::StreamHeader {
+                    stream_id: 4,
+                    offset: pkt_num * 1000,
+                    length: 1000,
+                    fin: false,
+                },
+                frame::Frame::MaxStreamData {
+                    stream_id: 8,
+                    max: pkt_num,
+                },
+                frame::Frame::ACKHeader {
+                    largest_acked: pkt_num,
+                },
+            ]
+        };
+
+        for pkt_num in 0..1000 {
+            let p = Sent {
+                pkt_num,
+                frames: frames(pkt_num),
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1200,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                content: SentContent::from_frames(&frames(pkt_num)),
+                is_pmtud_probe: false,
+                padding_bytes: 0,
+                datagram_id: None,
+                burst_index: 0,
+                burst_size: 0,
+                congestion_state: CongestionState::SlowStart,
+            };
+
+            for r in [&mut r, &mut r_buffered] {
+                r.on_packet_sent(
+                    p.clone(),
+                    packet::EPOCH_APPLICATION,
+                    HandshakeStatus::default(),
+                    now,
+                );
+            }
+        }
+
+        now += Duration::from_millis(10);
+
+        // The second ACK acknowledges some of the same packets again.
+        let mut first = ranges::RangeSet::default();
+        first.insert(0..400);
+        first.insert(401..403);
+
+        let mut second = ranges::RangeSet::default();
+        second.insert(300..1000);
+
+        let mut acked = Vec::new();
+
+        for ranges in [&first, &second] {
+            r.on_ack_received(
+                ranges,
+                25,
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+                Some(&mut |f| acked.push(f)),
+            )
+            .unwrap();
+
+            r_buffered
+                .on_ack_received(
+                    ranges,
+                    25,
+                    packet::EPOCH_APPLICATION,
+                    HandshakeStatus::default(),
+                    now,
+                    None,
+                )
+                .unwrap();
+        }
+
+        // Frames are not buffered when they are passed to the callback.
+        assert!(r.acked[packet::EPOCH_APPLICATION].is_empty());
+
+        let mut pkt_nums = (0..400).chain(401..403).collect::<Vec<u64>>();
+        pkt_nums.extend((400..401).chain(403..1000));
+
+        let expected = pkt_nums.into_iter().flat_map(frames).collect::<Vec<_>>();
+
+        assert_eq!(acked, expected);
+        assert_eq!(r_buffered.acked[packet::EPOCH_APPLICATION], expected);
+    }
+
+    #[test]
+    fn debug_at() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        assert!(format!("{:?}", r.debug_at(now)).starts_with("timer=none "));
+
+        let p = Sent {
+            pkt_num: 0,
+            frames: vec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 1000,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            content: SentContent::default(),
+            is_pmtud_probe: false,
+            padding_bytes: 0,
+            datagram_id: None,
+            burst_index: 0,
+            burst_size: 0,
+            congestion_state: CongestionState::SlowStart,
+        };
+
+        r.on_packet_sent(
+            p,
+            packet::EPOCH_APPLICATION,
+            HandshakeStatus::default(),
+            now,
+        );
+
+        let timer = r.loss_detection_timer().unwrap();
+
+        // Timers are formatted relative to the given time.
+        let at = timer - Duration::from_millis(10);
+        assert!(format!("{:?}", r.debug_at(at)).starts_with("timer=10ms "));
+
+        assert!(format!("{:?}", r.debug_at(timer)).starts_with("timer=exp "));
+    }
+
+    #[test]
+    fn on_packets_sent_burst() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::CUBIC);
+
+        let mut r = Recovery::new(&cfg);
+        let mut r_burst = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        let pkts = (0..44).map(|pkt_num| Sent {
+            pkt_num,
+            frames: vec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 1200,
+            ack_eliciting: pkt_num % 4 != 0,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            content: SentContent::default(),
+            is_pmtud_probe: false,
+            padding_bytes: 0,
+            datagram_id: None,
+            burst_index: 0,
+            burst_size: 0,
+            congestion_state: CongestionState::SlowStart,
+        });
+
+        for p in pkts.clone() {
+            r.on_packet_sent(
+                p,
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+            );
+        }
+
+        r_burst.on_packets_sent(
+            pkts,
+            packet::EPOCH_APPLICATION,
+            HandshakeStatus::default(),
+            now,
+        );
+
+        // The resulting state is the same as when recording each packet
+        // separately.
+        assert_eq!(
+            format!("{:?}", r_burst.debug_at(now)),
+            format!("{:?}", r.debug_at(now))
+        );
+
+        assert_eq!(r_burst.bytes_in_flight, 44 * 1200);
+        assert_eq!(r_burst.loss_detection_timer(), r.loss_detection_timer());
+        assert_eq!(r_burst.in_flight_count, r.in_flight_count);
+        assert_eq!(
+            r_burst.outstanding_non_ack_eliciting,
+            r.outstanding_non_ack_eliciting
+        );
+
+        let sent = &r.sent[packet::EPOCH_APPLICATION];
+        let sent_burst = &r_burst.sent[packet::EPOCH_APPLICATION];
+
+        assert_eq!(sent_burst.len(), sent.len());
+
+        for (a, b) in sent_burst.iter().zip(sent.iter()) {
+            assert_eq!(a.pkt_num, b.pkt_num);
+            assert_eq!(a.time_sent, b.time_sent);
+            assert_eq!(a.delivered, b.delivered);
+            assert_eq!(a.is_app_limited, b.is_app_limited);
+        }
+    }
+
+    #[test]
+    fn shrink_buffers_after_idle() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let mut now = Instant::now();
+
+        // Send a large burst of packets.
+        for pkt_num in 0..2000 {
+            let p = Sent {
+                pkt_num,
+                frames: vec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1000,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                content: SentContent::default(),
+                is_pmtud_probe: false,
+                padding_bytes: 0,
+                datagram_id: None,
+                burst_index: 0,
+                burst_size: 0,
+                congestion_state: CongestionState::SlowStart,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+            );
+        }
+
+        now += Duration::from_millis(10);
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..2000);
+
+        r.on_ack_received(
+            &acked,
+            25,
+            packet::EPOCH_APPLICATION,
+            HandshakeStatus::default(),
+            now,
+            None,
+        )
+        .unwrap();
+
+        // All packets are acked, but the buffer keeps its peak capacity.
+        assert_eq!(r.sent_packets_len(), 0);
+        assert!(r.buffer_capacity() >= 2000);
+
+        r.on_maintenance(now);
+
+        // Ticks happening more often than once per PTO are ignored.
+        for _ in 0..BUFFER_SHRINK_TICKS {
+            r.on_maintenance(now + r.pto() / 2);
+        }
+
+        assert!(r.buffer_capacity() >= 2000);
+
+        for _ in 2..BUFFER_SHRINK_TICKS {
+            now += r.pto();
+            r.on_maintenance(now);
+
+            assert!(r.buffer_capacity() >= 2000);
+        }
+
+        now += r.pto();
+        r.on_maintenance(now);
+
+        assert!(r.buffer_capacity() < 2000);
+        assert!(r.sent[packet::EPOCH_APPLICATION].capacity() >= 16);
+    }
+
+    #[test]
+    fn loss_time_and_space_prefers_lower_epochs() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+        let later = now + Duration::from_millis(1);
+
+        assert_eq!(r.loss_time_and_space().0, None);
+
+        r.loss_time = [Some(now), Some(now), Some(now)];
+        assert_eq!(r.loss_time_and_space(), (Some(now), packet::EPOCH_INITIAL));
+
+        r.loss_time = [None, Some(now), Some(now)];
+        assert_eq!(
+            r.loss_time_and_space(),
+            (Some(now), packet::EPOCH_HANDSHAKE)
+        );
+
+        r.loss_time = [Some(later), Some(later), Some(now)];
+        assert_eq!(
+            r.loss_time_and_space(),
+            (Some(now), packet::EPOCH_APPLICATION)
+        );
+
+        r.loss_time = [Some(now), Some(later), Some(now)];
+        assert_eq!(r.loss_time_and_space(), (Some(now), packet::EPOCH_INITIAL));
+    }
+
+    #[test]
+    fn fragmented_ack_ranges() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let mut now = Instant::now();
+
+        testing::send_packets(
+            &mut r,
+            (0..64).map(|pn| testing::sent_packet(pn, vec![], now)),
+            now,
+        );
+
+        now += Duration::from_millis(10);
+
+        // Only even packets are acked, so odd packets that are far enough
+        // from the largest acked are declared lost.
+        let acked = testing::fragmented_range_set(0..64);
+        assert_eq!(acked.len(), 32);
+
+        assert_eq!(
+            testing::ack_packets(&mut r, &acked, now),
+            (30, 30 * testing::PACKET_SIZE)
+        );
+
+        assert_eq!(r.lost_count, 30);
+        assert_eq!(r.bytes_in_flight, 2 * testing::PACKET_SIZE);
+        assert!(r.loss_time[packet::EPOCH_APPLICATION].is_some());
+    }
+
+    #[test]
+    fn duplicate_acks() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        testing::PacketTrain::new(8, testing::PACKET_SIZE)
+            .spaced_by(Duration::from_millis(1))
+            .with_stream_frames(2)
+            .send(&mut r, now);
+
+        // The first packets are acked twice, and then again along with the
+        // other ones.
+        let res = testing::AckScript::new()
+            .after(Duration::from_millis(20))
+            .ack(0..4)
+            .duplicate()
+            .after(Duration::from_millis(1))
+            .ack(0..8)
+            .duplicate()
+            .run(&mut r, now);
+
+        assert_eq!(res.lost, [(0, 0); 4]);
+        assert_eq!(res.lost_packets(), 0);
+
+        // Frames are only acked once.
+        assert_eq!(res.acked_frames, 16);
+        assert_eq!(res.lost_frames, 0);
+
+        assert_eq!(r.bytes_in_flight, 0);
+        assert_eq!(r.lost_spurious_count, 0);
+    }
+
+    #[test]
+    fn pto_with_lost_acks() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        let train = testing::PacketTrain::new(4, testing::PACKET_SIZE)
+            .with_stream_frames(1);
+
+        train.send(&mut r, now);
+
+        let timeout = r.loss_detection_timer().unwrap() - now;
+
+        // No ACK is received, so PTOs fire with exponential backoff, each one
+        // retransmitting as many packets as the PTO count.
+        let res = testing::AckScript::new()
+            .loss_detection_timeout()
+            .loss_detection_timeout()
+            .run(&mut r, now);
+
+        assert_eq!(res.lost, [(0, 0); 2]);
+        assert_eq!(res.lost_frames, 3);
+        assert_eq!(res.now, now + timeout * 2);
+        assert_eq!(r.pto_count, 2);
+
+        // A train of probes is eventually acked, along with the original
+        // packets, which are then no longer in flight.
+        let res = testing::AckScript::new()
+            .send(train.clone().starting_at(4))
+            .after(Duration::from_millis(10))
+            .ack(0..8)
+            .run(&mut r, res.now);
+
+        assert_eq!(res.lost, [(0, 0)]);
+        assert_eq!(res.acked_frames, 8);
+        assert_eq!(r.pto_count, 0);
+        assert_eq!(r.bytes_in_flight, 0);
+    }
+
+    #[test]
+    fn memory_footprint() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let mut now = Instant::now();
+
+        let initial = r.memory_footprint();
+        assert_eq!(initial.sent_packets, 0);
+        assert_eq!(initial.fixed, mem::size_of::<Recovery>());
+
+        testing::send_packets(
+            &mut r,
+            (0..100).map(|pn| {
+                testing::sent_packet(pn, testing::stream_frames(pn, 8), now)
+            }),
+            now,
+        );
+
+        let mut frames = vec![frame::Frame::NewToken {
+            token: vec![0; 1000],
+        }];
+        frames.shrink_to_fit();
+
+        testing::send_packets(
+            &mut r,
+            std::iter::once(testing::sent_packet(100, frames, now)),
+            now,
+        );
+
+        let frame_size = mem::size_of::<frame::Frame>();
+
+        let sent = r.memory_footprint();
+        assert!(
+            sent.sent_packets >=
+                101 * mem::size_of::<Sent>() + 801 * frame_size + 1000
+        );
+        assert!(sent.total() > initial.total());
+
+        now += Duration::from_millis(10);
+
+        testing::ack_packets(
+            &mut r,
+            &testing::range_set(std::iter::once(0..101)),
+            now,
+        );
+
+        // All packets are drained, so only the capacity of the buffers is left.
+        assert_eq!(r.sent_packets_len(), 0);
+        assert_eq!(r.sent_frames_mem, [0; packet::EPOCH_COUNT]);
+
+        let acked = r.memory_footprint();
+        assert_eq!(
+            acked.sent_packets,
+            r.sent[packet::EPOCH_APPLICATION].capacity() * mem::size_of::<Sent>()
+        );
+        assert!(acked.sent_packets < sent.sent_packets);
+        assert!(acked.acked_frames >= 801 * frame_size);
+    }
+
+    #[test]
+    fn stats_handle() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::CUBIC);
+
+        let mut r = Recovery::new(&cfg);
+
+        let handle = r.stats_handle();
+        assert_eq!(handle.load().cwnd, r.cwnd());
+        assert_eq!(handle.load().rtt, INITIAL_RTT);
+
+        let done = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+
+        let reader = {
+            let handle = handle.clone();
+            let done = done.clone();
+
+            std::thread::spawn(move || {
+                let mut last = handle.load();
+
+                while !done.load(std::sync::atomic::Ordering::Acquire) {
+                    let s = handle.load();
+
+                    assert!(s.cwnd > 0);
+                    assert!(s.lost_count >= last.lost_count);
+                    assert!(s.lost_bytes >= last.lost_bytes);
+                    assert!(s.bytes_in_flight <= 64 * testing::PACKET_SIZE);
+
+                    last = s;
+                }
+            })
+        };
+
+        let mut now = Instant::now();
+
+        for round in 0..100 {
+            let start = round * 64;
+            let end = start + 64;
+
+            testing::send_packets(
+                &mut r,
+                (start..end).map(|pn| testing::sent_packet(pn, vec![], now)),
+                now,
+            );
+
+            now += Duration::from_millis(10);
+
+            // Leave one packet per round unacked, so it's declared lost.
+            testing::ack_packets(
+                &mut r,
+                &testing::range_set(std::iter::once(start + 1..end)),
+                now,
+            );
+        }
+
+        done.store(true, std::sync::atomic::Ordering::Release);
+        reader.join().unwrap();
+
+        let s = handle.load();
+        assert_eq!(s, r.stats());
+        assert_eq!(s.lost_count, 100);
+        assert_eq!(s.rtt, r.rtt());
+    }
+
+    #[test]
+    fn pto_backoff_manual_clock() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let clock = ManualClock::new();
+
+        let mut recovery_config = RecoveryConfig::from_config(&cfg);
+        recovery_config.clock = Clock::Manual(clock.clone());
+
+        let mut r = Recovery::new_with_config(&recovery_config);
+
+        let start = clock.now();
+
+        testing::send_packets(
+            &mut r,
+            std::iter::once(testing::sent_packet(0, vec![], start)),
+            start,
+        );
+
+        let pto = r.pto();
+
+        for pto_count in 0..5 {
+            // The timer is backed off exponentially from the time the packet
+            // was sent.
+            let timer = r.loss_detection_timer().unwrap();
+            assert_eq!(timer, start + pto * 2_u32.pow(pto_count));
+
+            clock.advance(timer - clock.now() - Duration::from_millis(1));
+            assert!(r.loss_detection_timer().unwrap() > clock.now());
+
+            clock.advance_to(timer);
+            r.on_loss_detection_timeout(HandshakeStatus::default(), clock.now());
+
+            assert_eq!(r.pto_count, pto_count + 1);
+            assert_eq!(
+                format!("{:?}", r),
+                format!("{:?}", r.debug_at(clock.now()))
+            );
+        }
+
+        assert_eq!(clock.now(), start + pto * 16);
+        assert_eq!(r.loss_detection_timer(), Some(start + pto * 32));
+        assert_eq!(r.lost_count, 0);
+    }
+
+    #[test]
+    fn consistent_after_acks_and_losses() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let mut now = Instant::now();
+
+        testing::send_packets(
+            &mut r,
+            (0..64).map(|pn| testing::sent_packet(pn, vec![], now)),
+            now,
+        );
+
+        r.assert_consistent();
+
+        now += Duration::from_millis(10);
+
+        testing::ack_packets(&mut r, &testing::fragmented_range_set(0..64), now);
+
+        r.assert_consistent();
+
+        now += r.rtt();
+
+        r.on_loss_detection_timeout(testing::HANDSHAKE_COMPLETED, now);
+
+        r.assert_consistent();
+
+        r.on_pkt_num_space_discarded(
+            packet::EPOCH_APPLICATION,
+            testing::HANDSHAKE_COMPLETED,
+            now,
+        );
+
+        r.assert_consistent();
+    }
+
+    #[test]
+    #[should_panic(expected = "bytes_in_flight mismatch")]
+    fn inconsistent_bytes_in_flight() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        testing::send_packets(
+            &mut r,
+            (0..4).map(|pn| testing::sent_packet(pn, vec![], now)),
+            now,
+        );
+
+        r.bytes_in_flight -= 1;
+
+        r.assert_consistent();
+    }
+
+    #[test]
+    #[should_panic(expected = "in_flight_count mismatch in epoch 2")]
+    fn inconsistent_in_flight_count() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        testing::send_packets(
+            &mut r,
+            (0..4).map(|pn| testing::sent_packet(pn, vec![], now)),
+            now,
+        );
+
+        r.in_flight_count[packet::EPOCH_APPLICATION] += 1;
+
+        r.assert_consistent();
+    }
+
+    #[test]
+    #[should_panic(expected = "acked above largest acked")]
+    fn inconsistent_largest_acked() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        testing::send_packets(
+            &mut r,
+            (0..4).map(|pn| testing::sent_packet(pn, vec![], now)),
+            now,
+        );
+
+        testing::ack_packets(
+            &mut r,
+            &testing::range_set(std::iter::once(0..2)),
+            now,
+        );
+
+        r.sent[packet::EPOCH_APPLICATION][1].time_acked = Some(now);
+
+        r.assert_consistent();
+    }
+
+    #[test]
+    #[should_panic(expected = "loss detection timer not armed")]
+    fn inconsistent_loss_detection_timer() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        let p = testing::sent_packet(0, vec![], now);
+
+        r.on_packet_sent(
+            p,
+            packet::EPOCH_INITIAL,
+            HandshakeStatus::default(),
+            now,
+        );
+
+        r.loss_detection_timer.set(None);
+
+        r.assert_consistent();
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "bytes_in_flight mismatch")]
+    fn inconsistent_state_detected_on_ack() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        testing::send_packets(
+            &mut r,
+            (0..4).map(|pn| testing::sent_packet(pn, vec![], now)),
+            now,
+        );
+
+        r.bytes_in_flight += testing::PACKET_SIZE;
+
+        // The check runs at the end of ACK processing in debug builds.
+        testing::ack_packets(
+            &mut r,
+            &testing::range_set(std::iter::once(0..1)),
+            now,
+        );
+    }
+
+    #[test]
+    fn inspect_after_force_pto() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        testing::send_packets(
+            &mut r,
+            (0..4).map(|pn| {
+                testing::sent_packet(pn, testing::stream_frames(pn, 1), now)
+            }),
+            now,
+        );
+
+        let inspection = r.inspect();
+        assert_eq!(inspection.sent_packets, [0, 0, 4]);
+        assert_eq!(inspection.in_flight_packets, [0, 0, 4]);
+        assert_eq!(inspection.loss_probes, [0, 0, 0]);
+        assert_eq!(
+            inspection.loss_detection_timer_kind,
+            Some(LossDetectionTimerKind::Pto)
+        );
+
+        let timer = inspection.loss_detection_timer.unwrap();
+
+        r.force_pto(testing::HANDSHAKE_COMPLETED, now);
+
+        let inspection = r.inspect();
+        assert_eq!(inspection.loss_probes, [0, 0, 1]);
+        assert_eq!(r.pto_count, 1);
+        assert_eq!(r.lost[packet::EPOCH_APPLICATION].len(), 1);
+
+        // The timer is re-armed with the new backoff.
+        assert!(inspection.loss_detection_timer.unwrap() > timer);
+    }
+
+    #[test]
+    fn inject_loss() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        testing::send_packets(
+            &mut r,
+            (0..4).map(|pn| {
+                testing::sent_packet(pn, testing::stream_frames(pn, 1), now)
+            }),
+            now,
+        );
+
+        let cwnd = r.cwnd();
+
+        assert_eq!(
+            r.inject_loss(
+                packet::EPOCH_APPLICATION,
+                2,
+                testing::HANDSHAKE_COMPLETED,
+                now
+            ),
+            (1, testing::PACKET_SIZE)
+        );
+
+        assert_eq!(r.lost_count, 1);
+        assert_eq!(r.bytes_in_flight, 3 * testing::PACKET_SIZE);
+        assert_eq!(r.lost[packet::EPOCH_APPLICATION].len(), 1);
+        assert_eq!(r.inspect().in_flight_packets, [0, 0, 3]);
+        assert!(r.cwnd() < cwnd);
+
+        // Packets that are already lost, or were never sent, can't be lost.
+        for pn in [2, 4] {
+            assert_eq!(
+                r.inject_loss(
+                    packet::EPOCH_APPLICATION,
+                    pn,
+                    testing::HANDSHAKE_COMPLETED,
+                    now
+                ),
+                (0, 0)
+            );
+        }
+
+        assert_eq!(r.lost_count, 1);
+    }
+
+    #[test]
+    fn batch_hint() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_pacing(false);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        // Leave room for a truncated packet at the end of the window.
+        r.congestion_window = 12 * r.max_datagram_size + 500;
+        r.send_quantum = 5 * r.max_datagram_size;
+
+        let mut pkt_num = 0;
+        let mut last = usize::MAX;
+
+        loop {
+            let hint = r.batch_hint(now);
+
+            assert_eq!(hint.segment_size, r.max_datagram_size);
+            assert!(hint.max_segments <= 5);
+            assert!(hint.max_segments <= last);
+            assert_eq!(hint.release_time, now.into());
+
+            if hint.max_segments == 0 {
+                break;
+            }
+
+            last = hint.max_segments;
+
+            for _ in 0..hint.max_segments {
+                // Packets are truncated to the available congestion window.
+                let size = cmp::min(hint.segment_size, r.cwnd_available());
+
+                let mut p = testing::sent_packet(
+                    pkt_num,
+                    testing::stream_frames(pkt_num, 1),
+                    now,
+                );
+                p.size = size;
+
+                testing::send_packets(&mut r, Some(p), now);
+
+                assert!(r.bytes_in_flight <= r.cwnd());
+
+                pkt_num += 1;
+            }
+        }
+
+        assert_eq!(r.bytes_in_flight, r.cwnd());
+        assert_eq!(pkt_num, 13);
+
+        // Acknowledging packets opens the window again.
+        testing::ack_packets(&mut r, &testing::range_set(Some(0..2)), now);
+
+        assert!(r.batch_hint(now).max_segments > 0);
+    }
+
+    #[test]
+    fn send_allowance() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        let mss = r.max_datagram_size;
+
+        assert_eq!(r.send_allowance(now, None), SendAllowance {
+            bytes: r.cwnd(),
+            probe_only: false,
+            deferred_until: None,
+            epoch: None,
+        });
+
+        // The anti-amplification limit applies.
+        assert_eq!(r.send_allowance(now, Some(3 * mss)).bytes, 3 * mss);
+
+        // Fill the congestion window.
+        let packets = r.cwnd() / mss;
+
+        testing::send_packets(
+            &mut r,
+            (0..packets as u64).map(|pn| testing::sent_packet(pn, vec![], now)),
+            now,
+        );
+
+        assert_eq!(r.send_allowance(now, None).bytes, 0);
+
+        // Only probes can be sent when the congestion window is full.
+        r.loss_probes[packet::EPOCH_INITIAL] = 1;
+        r.loss_probes[packet::EPOCH_HANDSHAKE] = 1;
+
+        assert_eq!(r.send_allowance(now, None), SendAllowance {
+            bytes: 2 * mss,
+            probe_only: true,
+            deferred_until: None,
+            epoch: Some(packet::EPOCH_HANDSHAKE),
+        });
+
+        // Probes are still subject to the anti-amplification limit.
+        assert_eq!(r.send_allowance(now, Some(100)).bytes, 100);
+        assert_eq!(r.send_allowance(now, Some(0)).bytes, 0);
+    }
+
+    #[test]
+    fn send_allowance_pacing() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        let mss = r.max_datagram_size;
+
+        r.pacer = pacer::Pacer::new(true, 2 * mss, 100_000, mss, now);
+        r.bytes_sent = r.cwnd();
+
+        assert_eq!(r.send_allowance(now, None).deferred_until, None);
+
+        // Once the burst is used up, sending is deferred even though the
+        // congestion window isn't full.
+        testing::send_packets(
+            &mut r,
+            (0..2).map(|pn| testing::sent_packet(pn, vec![], now)),
+            now,
+        );
+
+        let allowance = r.send_allowance(now, None);
+        assert_eq!(allowance.bytes, r.cwnd() - 2 * mss);
+        assert!(!allowance.probe_only);
+        assert!(allowance.deferred_until.unwrap() > now);
+    }
+
+    #[test]
+    fn time_sent() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        let mss = r.max_datagram_size;
+
+        r.pacer = pacer::Pacer::new(true, mss, 100_000, mss, now);
+        r.bytes_sent = r.cwnd();
+
+        // Paced packets are recorded as sent at their release time, which is
+        // delayed once the burst is used up.
+        testing::send_packets(
+            &mut r,
+            (0..2).map(|pn| testing::sent_packet(pn, vec![], now)),
+            now,
+        );
+
+        let sent = &r.sent[packet::EPOCH_APPLICATION];
+        assert_eq!(sent[0].time_sent, now);
+        assert!(sent[1].time_sent > now);
+        assert_eq!(sent[1].time_sent, r.get_packet_send_time());
+
+        // Without pacing, packets are recorded as sent when they are, even
+        // though the pacer's release time is stale.
+        cfg.enable_pacing(false);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = now + Duration::from_millis(100);
+
+        testing::send_packets(
+            &mut r,
+            Some(testing::sent_packet(0, vec![], now)),
+            now,
+        );
+
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION][0].time_sent, now);
+    }
+
+    #[test]
+    fn batch_hint_pacing() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        let mss = r.max_datagram_size;
+
+        r.pacer = pacer::Pacer::new(true, 4 * mss, 100_000, mss, now);
+        r.bytes_sent = r.cwnd();
+
+        assert_eq!(r.batch_hint(now).max_segments, 4);
+
+        // The burst is used up as packets are sent, and the next one is
+        // delayed.
+        testing::send_packets(
+            &mut r,
+            (0..3).map(|pn| testing::sent_packet(pn, vec![], now)),
+            now,
+        );
+        assert_eq!(r.batch_hint(now).max_segments, 1);
+
+        testing::send_packets(
+            &mut r,
+            Some(testing::sent_packet(3, vec![], now)),
+            now,
+        );
+
+        let hint = r.batch_hint(now);
+        assert_eq!(hint.max_segments, 4);
+        assert!(hint.release_time > now.into());
+    }
+
+    #[test]
+    fn cancel_retransmission() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+        r.enable_events();
+
+        let now = Instant::now();
+
+        testing::send_packets(
+            &mut r,
+            (0..10).map(|pn| {
+                testing::sent_packet(pn, testing::stream_frames(pn, 2), now)
+            }),
+            now,
+        );
+
+        // The first three packets are lost by packet threshold.
+        testing::ack_packets(&mut r, &testing::range_set(Some(3..10)), now);
+
+        let lost: Vec<(u64, packet::Type, packet::Epoch)> = r
+            .drain_events()
+            .filter_map(|e| match e {
+                RecoveryEvent::PacketLost(lost, epoch) =>
+                    Some((lost.pkt_num, lost.pkt_type, epoch)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(lost, [
+            (0, packet::Type::Short, packet::EPOCH_APPLICATION),
+            (1, packet::Type::Short, packet::EPOCH_APPLICATION),
+            (2, packet::Type::Short, packet::EPOCH_APPLICATION),
+        ]);
+
+        // The second packet was recovered by the application.
+        assert_eq!(r.cancel_retransmission(packet::EPOCH_APPLICATION, 1), 2);
+        assert_eq!(r.cancel_retransmission(packet::EPOCH_APPLICATION, 1), 0);
+        assert_eq!(r.cancel_retransmission(packet::EPOCH_APPLICATION, 5), 0);
+        assert_eq!(r.cancel_retransmission(packet::EPOCH_HANDSHAKE, 0), 0);
+
+        let retrans: Vec<frame::Frame> = r
+            .drain_lost_frames(packet::EPOCH_APPLICATION, |_| 0)
+            .collect();
+
+        let mut expected = testing::stream_frames(0, 2);
+        expected.extend(testing::stream_frames(2, 2));
+        assert_eq!(retrans, expected);
+
+        // Frames already handed to the connection can't be cancelled.
+        assert_eq!(r.cancel_retransmission(packet::EPOCH_APPLICATION, 0), 0);
+        assert!(r.lost_queued[packet::EPOCH_APPLICATION].is_empty());
+    }
+
+    #[test]
+    fn cancel_retransmission_after_removal() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        testing::send_packets(
+            &mut r,
+            (0..10).map(|pn| {
+                testing::sent_packet(pn, testing::stream_frames(pn, 2), now)
+            }),
+            now,
+        );
+
+        testing::ack_packets(&mut r, &testing::range_set(Some(3..10)), now);
+
+        // Cancelling the first packet moves the frames of the others.
+        assert_eq!(r.cancel_retransmission(packet::EPOCH_APPLICATION, 0), 2);
+        assert_eq!(r.cancel_retransmission(packet::EPOCH_APPLICATION, 2), 2);
+
+        assert_eq!(
+            r.lost[packet::EPOCH_APPLICATION],
+            testing::stream_frames(1, 2)
+        );
+
+        // So does dropping frames from the queue.
+        r.enter_draining();
+
+        assert_eq!(r.cancel_retransmission(packet::EPOCH_APPLICATION, 1), 0);
+        assert!(r.lost_queued[packet::EPOCH_APPLICATION].is_empty());
+    }
+
+    #[test]
+    fn observer_events() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+        r.enable_events();
+
+        let now = Instant::now();
+        let cwnd = r.cwnd();
+
+        // The first three packets are lost by packet threshold, and the first
+        // one is acknowledged afterwards.
+        testing::AckScript::new()
+            .send(testing::PacketTrain::new(10, testing::PACKET_SIZE))
+            .after(Duration::from_millis(50))
+            .ack(3..10)
+            .ack(0..1)
+            .run(&mut r, now);
+
+        let events: Vec<RecoveryEvent> = r.drain_events().collect();
+
+        let lost: Vec<u64> = events
+            .iter()
+            .filter_map(|e| match e {
+                RecoveryEvent::PacketLost(lost, _) => Some(lost.pkt_num),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(lost, [0, 1, 2]);
+
+        let spurious: Vec<u64> = events
+            .iter()
+            .filter_map(|e| match e {
+                RecoveryEvent::SpuriousLoss(lost) => Some(lost.pkt_num),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(spurious, [0]);
+
+        let changes: Vec<CwndChange> = events
+            .iter()
+            .filter_map(|e| match e {
+                RecoveryEvent::CwndChange(change) => Some(*change),
+                _ => None,
+            })
+            .collect();
+        assert!(!changes.is_empty());
+        assert_eq!(changes[0].prev_cwnd, cwnd);
+        assert_eq!(changes.last().unwrap().cwnd, r.cwnd());
+
+        for pair in changes.windows(2) {
+            assert_eq!(pair[0].cwnd, pair[1].prev_cwnd);
+        }
+
+        // Events are only returned once.
+        assert_eq!(r.drain_events().count(), 0);
+
+        // Nothing is recorded unless events are enabled.
+        let mut r = Recovery::new(&cfg);
+
+        testing::AckScript::new()
+            .send(testing::PacketTrain::new(10, testing::PACKET_SIZE))
+            .after(Duration::from_millis(50))
+            .ack(3..10)
+            .run(&mut r, now);
+
+        assert_eq!(r.lost_count, 3);
+        assert_eq!(r.drain_events().count(), 0);
+    }
+
+    #[test]
+    fn all_acked_events() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+        r.enable_events();
+
+        let mut now = Instant::now();
+
+        let all_acked = |r: &mut Recovery| -> Vec<Instant> {
+            r.drain_events()
+                .filter_map(|e| match e {
+                    RecoveryEvent::AllAcked(ack_time) => Some(ack_time),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        // Nothing was sent yet, so there is nothing to notify.
+        assert!(r.all_acked());
+
+        testing::send_packets(
+            &mut r,
+            (0..4).map(|pn| {
+                testing::sent_packet(pn, testing::stream_frames(pn, 1), now)
+            }),
+            now,
+        );
+        assert!(!r.all_acked());
+
+        now += Duration::from_millis(50);
+
+        testing::ack_packets(&mut r, &testing::range_set(Some(0..2)), now);
+        assert!(!r.all_acked());
+        assert_eq!(all_acked(&mut r), []);
+
+        now += Duration::from_millis(10);
+
+        // The ACK of the last packets completes the transfer.
+        testing::ack_packets(&mut r, &testing::range_set(Some(0..4)), now);
+        assert!(r.all_acked());
+        assert_eq!(all_acked(&mut r), [now]);
+
+        // A duplicate ACK isn't reported again.
+        now += Duration::from_millis(10);
+
+        testing::ack_packets(&mut r, &testing::range_set(Some(0..4)), now);
+        assert_eq!(all_acked(&mut r), []);
+
+        testing::send_packets(
+            &mut r,
+            (4..8).map(|pn| {
+                testing::sent_packet(pn, testing::stream_frames(pn, 1), now)
+            }),
+            now,
+        );
+
+        now += Duration::from_millis(50);
+
+        // The first packet is lost by packet threshold, so nothing is in
+        // flight anymore, but its frames still need to be retransmitted.
+        testing::ack_packets(&mut r, &testing::range_set(Some(5..8)), now);
+        assert_eq!(r.bytes_in_flight, 0);
+        assert!(!r.all_acked());
+        assert_eq!(all_acked(&mut r), []);
+
+        let frames: Vec<frame::Frame> = r
+            .drain_lost_frames(packet::EPOCH_APPLICATION, |_| 0)
+            .collect();
+        assert_eq!(frames, testing::stream_frames(4, 1));
+
+        testing::send_packets(
+            &mut r,
+            Some(testing::sent_packet(8, frames, now)),
+            now,
+        );
+
+        now += Duration::from_millis(50);
+
+        testing::ack_packets(&mut r, &testing::range_set(Some(5..9)), now);
+        assert!(r.all_acked());
+        assert_eq!(all_acked(&mut r), [now]);
+    }
+
+    #[test]
+    fn recovery_episode() {
+        for algo in [
+            CongestionControlAlgorithm::Reno,
+            CongestionControlAlgorithm::CUBIC,
+        ] {
+            let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+            cfg.set_cc_algorithm(algo);
+
+            let mut r = Recovery::new(&cfg);
+            r.enable_events();
+
+            let now = Instant::now();
+            let cwnd = r.cwnd();
+
+            let mut cwnds = Vec::new();
+
+            // Both ACKs declare packets of the same flight lost, all sent at
+            // the same time.
+            testing::AckScript::new()
+                .send(testing::PacketTrain::new(10, testing::PACKET_SIZE))
+                .after(Duration::from_millis(50))
+                .ack(3..5)
+                .ack(5..10)
+                .run_with(&mut r, now, |step, r, _| {
+                    if step == "ack" {
+                        cwnds.push(r.cwnd());
+                    }
+                });
+
+            assert_eq!(r.lost_count, 3);
+
+            // The window is only reduced once.
+            assert!(cwnds[0] < cwnd);
+            assert_eq!(cwnds[0], cwnds[1]);
+
+            let episode = r.recovery_episode.unwrap();
+            assert_eq!(episode.largest_sent_pkt, [None, None, Some(9)]);
+            assert!(episode.contains(packet::EPOCH_APPLICATION, 9));
+            assert!(!episode.contains(packet::EPOCH_APPLICATION, 10));
+
+            // Acknowledging a packet sent after the reduction ends the
+            // episode.
+            testing::AckScript::new()
+                .send(
+                    testing::PacketTrain::new(2, testing::PACKET_SIZE)
+                        .starting_at(10),
+                )
+                .after(Duration::from_millis(50))
+                .ack(10..11)
+                .run(&mut r, now + Duration::from_millis(50));
+
+            assert_eq!(r.recovery_episode, None);
+
+            let episodes: Vec<RecoveryEvent> = r
+                .drain_events()
+                .filter(|e| {
+                    matches!(
+                        e,
+                        RecoveryEvent::RecoveryStart | RecoveryEvent::RecoveryEnd
+                    )
+                })
+                .collect();
+            assert_eq!(episodes, [
+                RecoveryEvent::RecoveryStart,
+                RecoveryEvent::RecoveryEnd
+            ]);
+        }
+    }
+
+    #[test]
+    fn loss_latency() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let rtt = Duration::from_millis(50);
+
+        // The first three packets are lost by packet threshold as soon as the
+        // ACK arrives.
+        let mut r = Recovery::new(&cfg);
+        r.enable_events();
+
+        let now = Instant::now();
+
+        testing::AckScript::new()
+            .send(testing::PacketTrain::new(10, testing::PACKET_SIZE))
+            .after(rtt)
+            .ack(3..10)
+            .run(&mut r, now);
+
+        let latency = r.loss_latency();
+        assert_eq!(latency.count, 3);
+        assert_eq!(latency.min, rtt);
+        assert_eq!(latency.max, rtt);
+        assert_eq!(latency.mean(), Some(rtt));
+
+        assert_eq!(r.stats().loss_latency, latency);
+
+        let latencies: Vec<Duration> = r
+            .drain_events()
+            .filter_map(|e| match e {
+                RecoveryEvent::PacketLost(lost, _) => Some(lost.latency),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(latencies, [rtt; 3]);
+
+        // The first packet is lost by time threshold, once the loss detection
+        // timer fires 9/8 of an RTT after it was sent.
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        let res = testing::AckScript::new()
+            .send(testing::PacketTrain::new(3, testing::PACKET_SIZE))
+            .after(rtt)
+            .ack(1..3)
+            .loss_detection_timeout()
+            .run(&mut r, now);
+
+        assert_eq!(r.lost_count, 1);
+        assert_eq!(res.now - now, rtt * 9 / 8);
+
+        let latency = r.loss_latency();
+        assert_eq!(latency.count, 1);
+        assert_eq!(latency.min, rtt * 9 / 8);
+        assert_eq!(latency.max, rtt * 9 / 8);
+    }
+
+    // A step of a random send / ACK interleaving.
+    #[derive(Clone, Debug)]
+    enum AckOp {
+        // Sends `count` packets after skipping `skip` packet numbers.
+        Send { count: u64, skip: u64 },
+
+        // Receives an ACK frame for the given blocks. Block starts are taken
+        // modulo the next packet number, so that they mostly cover sent
+        // packets.
+        Ack(Vec<(u64, u64)>),
+    }
+
+    // A brute-force model of ACK processing, used as a reference for matching
+    // acked packets and detecting losses using the packet threshold.
+    struct AckReference {
+        // Sent packet numbers, with whether they were acked and lost.
+        sent: Vec<(u64, bool, bool)>,
+
+        largest_acked: Option<u64>,
+
+        pkt_thresh: u64,
+
+        lost_spurious_count: usize,
+    }
+
+    impl AckReference {
+        fn new() -> Self {
+            AckReference {
+                sent: Vec::new(),
+                largest_acked: None,
+                pkt_thresh: INITIAL_PACKET_THRESHOLD,
+                lost_spurious_count: 0,
+            }
+        }
+
+        // Returns the packets newly acked and newly lost by the ACK.
+        fn on_ack_received(
+            &mut self, ranges: &ranges::RangeSet,
+        ) -> (Vec<u64>, Vec<u64>) {
+            let largest_acked =
+                cmp::max(ranges.last().unwrap(), self.largest_acked.unwrap_or(0));
+
+            self.largest_acked = Some(largest_acked);
+
+            let mut acked = Vec::new();
+
+            for (pkt_num, is_acked, is_lost) in self.sent.iter_mut() {
+                if *is_acked || !ranges.flatten().any(|pn| pn == *pkt_num) {
+                    continue;
+                }
+
+                *is_acked = true;
+
+                if *is_lost {
+                    self.lost_spurious_count += 1;
+
+                    self.pkt_thresh = cmp::max(
+                        self.pkt_thresh,
+                        cmp::min(
+                            MAX_PACKET_THRESHOLD,
+                            largest_acked - *pkt_num + 1,
+                        ),
+                    );
+
+                    continue;
+                }
+
+                acked.push(*pkt_num);
+            }
+
+            // Losses are only detected when new packets are acked.
+            if acked.is_empty() {
+                return (acked, Vec::new());
+            }
+
+            let mut lost = Vec::new();
+
+            for (pkt_num, is_acked, is_lost) in self.sent.iter_mut() {
+                if !*is_acked &&
+                    !*is_lost &&
+                    *pkt_num + self.pkt_thresh <= largest_acked
+                {
+                    *is_lost = true;
+
+                    lost.push(*pkt_num);
+                }
+            }
+
+            (acked, lost)
+        }
+
+        fn in_flight(&self) -> usize {
+            self.sent
+                .iter()
+                .filter(|(_, is_acked, is_lost)| !is_acked && !is_lost)
+                .count()
+        }
+    }
+
+    #[test]
+    fn ack_matching_matches_reference() {
+        let op = prop_oneof![
+            (1..8_u64, 0..3_u64)
+                .prop_map(|(count, skip)| AckOp::Send { count, skip }),
+            prop::collection::vec((any::<u64>(), 1..8_u64), 1..4)
+                .prop_map(AckOp::Ack),
+        ];
+
+        // The seed is fixed, so that failures are reproducible.
+        let mut runner = TestRunner::new_with_rng(
+            ProptestConfig {
+                cases: 256,
+                failure_persistence: None,
+                ..ProptestConfig::default()
+            },
+            TestRng::deterministic_rng(RngAlgorithm::ChaCha),
+        );
+
+        // All packets are sent at the same time, and all ACKs are received one
+        // RTT later. This way losses are only detected using the packet
+        // threshold, and lost packets are tracked long enough for spurious
+        // losses to be detected.
+        //
+        // The recovery's clock is stopped at the send time, and pacing is
+        // disabled, so that packets are recorded as sent at that exact time
+        // however long the test takes to run.
+        let clock = ManualClock::new();
+
+        let sent_time = clock.now();
+        let ack_time = sent_time + Duration::from_millis(100);
+
+        let pkt_num_of = |frame: &frame::Frame| match frame {
+            frame::Frame::StreamHeader { offset, .. } => offset / 1000,
+
+            _ => unreachable!(),
+        };
+
+        runner
+            .run(&prop::collection::vec(op, 1..64), |ops| {
+                let mut cfg =
+                    crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+                cfg.enable_pacing(false);
+
+                let mut recovery_config = RecoveryConfig::from_config(&cfg);
+                recovery_config.clock = Clock::Manual(clock.clone());
+
+                let mut r = Recovery::new_with_config(&recovery_config);
+
+                let mut reference = AckReference::new();
+
+                let mut next_pkt_num = 0;
+
+                let mut all_acked = BTreeSet::new();
+                let mut all_lost = BTreeSet::new();
+
+                for op in ops {
+                    let blocks = match op {
+                        AckOp::Send { count, skip } => {
+                            next_pkt_num += skip;
+
+                            for pn in next_pkt_num..next_pkt_num + count {
+                                let p = testing::sent_packet(
+                                    pn,
+                                    testing::stream_frames(pn, 1),
+                                    sent_time,
+                                );
+
+                                r.on_packet_sent(
+                                    p,
+                                    packet::EPOCH_APPLICATION,
+                                    testing::HANDSHAKE_COMPLETED,
+                                    sent_time,
+                                );
+
+                                reference.sent.push((pn, false, false));
+                            }
+
+                            next_pkt_num += count;
+
+                            continue;
+                        },
+
+                        AckOp::Ack(blocks) => blocks,
+                    };
+
+                    let mut ranges = ranges::RangeSet::default();
+
+                    for (start, len) in blocks {
+                        let start = start % (next_pkt_num + 2);
+
+                        ranges.insert(start..start + len);
+                    }
+
+                    let mut acked = Vec::new();
+
+                    let mut on_frame_acked =
+                        |frame: frame::Frame| acked.push(pkt_num_of(&frame));
+
+                    let (lost_packets, lost_bytes) = r
+                        .on_ack_received(
+                            &ranges,
+                            0,
+                            packet::EPOCH_APPLICATION,
+                            testing::HANDSHAKE_COMPLETED,
+                            ack_time,
+                            Some(&mut on_frame_acked),
+                        )
+                        .unwrap();
+
+                    let lost: Vec<u64> = r.lost[packet::EPOCH_APPLICATION]
+                        .drain(..)
+                        .map(|frame| pkt_num_of(&frame))
+                        .collect();
+
+                    let (expected_acked, expected_lost) =
+                        reference.on_ack_received(&ranges);
+
+                    prop_assert_eq!(&acked, &expected_acked);
+                    prop_assert_eq!(&lost, &expected_lost);
+
+                    prop_assert_eq!(lost_packets, lost.len());
+                    prop_assert_eq!(
+                        lost_bytes,
+                        lost.len() * testing::PACKET_SIZE
+                    );
+
+                    for pn in acked {
+                        prop_assert!(all_acked.insert(pn), "{} acked twice", pn);
+                    }
+
+                    for pn in lost {
+                        prop_assert!(all_lost.insert(pn), "{} lost twice", pn);
+                    }
+
+                    prop_assert_eq!(
+                        r.lost_spurious_count,
+                        reference.lost_spurious_count
+                    );
+
+                    let in_flight_bytes: usize = r.sent
+                        [packet::EPOCH_APPLICATION]
+                        .iter()
+                        .filter(|p| {
+                            p.in_flight &&
+                                p.time_acked.is_none() &&
+                                p.time_lost.is_none()
+                        })
+                        .map(|p| p.cc_size())
+                        .sum();
+
+                    prop_assert_eq!(r.bytes_in_flight, in_flight_bytes);
+                    prop_assert_eq!(
+                        r.bytes_in_flight,
+                        reference.in_flight() * testing::PACKET_SIZE
+                    );
+                }
+
+                // Acked packets carried their frames to the peer, so they
+                // can't also be retransmitted as lost.
+                prop_assert!(all_acked.is_disjoint(&all_lost));
+
+                Ok(())
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn sent_index_matches_partition_point() {
+        let op = prop_oneof![
+            (1..16_u64, 0..4_u64)
+                .prop_map(|(count, skip)| AckOp::Send { count, skip }),
+            prop::collection::vec((any::<u64>(), 1..8_u64), 1..4)
+                .prop_map(AckOp::Ack),
+        ];
+
+        // The seed is fixed, so that failures are reproducible.
+        let mut runner = TestRunner::new_with_rng(
+            ProptestConfig {
+                cases: 256,
+                failure_persistence: None,
+                ..ProptestConfig::default()
+            },
+            TestRng::deterministic_rng(RngAlgorithm::ChaCha),
+        );
+
+        let clock = ManualClock::new();
+
+        let sent_time = clock.now();
+        let ack_time = sent_time + Duration::from_millis(100);
+
+        runner
+            .run(&prop::collection::vec(op, 1..64), |ops| {
+                let mut cfg =
+                    crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+                cfg.enable_pacing(false);
+
+                let mut recovery_config = RecoveryConfig::from_config(&cfg);
+                recovery_config.clock = Clock::Manual(clock.clone());
+
+                let mut r = Recovery::new_with_config(&recovery_config);
+
+                let mut next_pkt_num = 0;
+
+                for op in ops {
+                    match op {
+                        AckOp::Send { count, skip } => {
+                            next_pkt_num += skip;
+
+                            for pn in next_pkt_num..next_pkt_num + count {
+                                r.on_packet_sent(
+                                    testing::sent_packet(
+                                        pn,
+                                        testing::stream_frames(pn, 1),
+                                        sent_time,
+                                    ),
+                                    packet::EPOCH_APPLICATION,
+                                    testing::HANDSHAKE_COMPLETED,
+                                    sent_time,
+                                );
+                            }
+
+                            next_pkt_num += count;
+                        },
+
+                        AckOp::Ack(blocks) => {
+                            let mut ranges = ranges::RangeSet::default();
+
+                            for (start, len) in blocks {
+                                let start = start % (next_pkt_num + 2);
+
+                                ranges.insert(start..start + len);
+                            }
+
+                            r.on_ack_received(
+                                &ranges,
+                                0,
+                                packet::EPOCH_APPLICATION,
+                                testing::HANDSHAKE_COMPLETED,
+                                ack_time,
+                                None,
+                            )
+                            .unwrap();
+                        },
+                    }
+
+                    // Acked and lost packets are drained from the front of
+                    // the list, so the offset of the first one moves around.
+                    let sent = &r.sent[packet::EPOCH_APPLICATION];
+
+                    for pn in 0..next_pkt_num + 2 {
+                        prop_assert_eq!(
+                            r.sent_index(packet::EPOCH_APPLICATION, pn),
+                            sent.partition_point(|p| p.pkt_num < pn),
+                            "packet {}",
+                            pn
+                        );
+                    }
+                }
+
+                Ok(())
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn congestion_control_knobs() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+
+        assert_eq!(
+            cfg.set_initial_congestion_window_packets(1),
+            Err(crate::Error::CongestionControl)
+        );
+        assert_eq!(
+            cfg.set_packet_reorder_threshold(2),
+            Err(crate::Error::CongestionControl)
+        );
+        assert_eq!(
+            cfg.set_packet_reorder_threshold(MAX_PACKET_THRESHOLD + 1),
+            Err(crate::Error::CongestionControl)
+        );
+        assert_eq!(
+            cfg.set_time_reorder_threshold(0.5),
+            Err(crate::Error::CongestionControl)
+        );
+        assert_eq!(
+            cfg.set_time_reorder_threshold(f64::NAN),
+            Err(crate::Error::CongestionControl)
+        );
+        assert_eq!(
+            cfg.set_initial_rtt(Duration::ZERO),
+            Err(crate::Error::CongestionControl)
+        );
+        assert_eq!(
+            cfg.set_timer_granularity(Duration::ZERO),
+            Err(crate::Error::CongestionControl)
+        );
+        assert_eq!(
+            cfg.set_max_pacing_rate(0),
+            Err(crate::Error::CongestionControl)
+        );
+
+        // Rejected values leave the defaults untouched.
+        let r = Recovery::new(&cfg);
+        assert_eq!(r.cwnd(), r.max_datagram_size * INITIAL_WINDOW_PACKETS);
+        assert_eq!(r.pkt_thresh, INITIAL_PACKET_THRESHOLD);
+        assert_eq!(r.rtt(), INITIAL_RTT);
+        assert_eq!(r.granularity, GRANULARITY);
+
+        assert_eq!(cfg.set_initial_congestion_window_packets(20), Ok(()));
+        assert_eq!(cfg.set_packet_reorder_threshold(5), Ok(()));
+        assert_eq!(cfg.set_time_reorder_threshold(1.5), Ok(()));
+        assert_eq!(cfg.set_initial_rtt(Duration::from_millis(100)), Ok(()));
+        assert_eq!(cfg.set_timer_granularity(Duration::from_millis(2)), Ok(()));
+        assert_eq!(cfg.set_max_pacing_rate(100_000), Ok(()));
+
+        let mut r = Recovery::new(&cfg);
+        assert_eq!(r.cwnd(), r.max_datagram_size * 20);
+        assert_eq!(r.pkt_thresh, 5);
+        assert_eq!(r.time_thresh, 1.5);
+        assert_eq!(r.rtt(), Duration::from_millis(100));
+        assert_eq!(r.granularity, Duration::from_millis(2));
+        assert_eq!(r.pto(), Duration::from_millis(300));
+
+        // The pacing rate estimated by congestion control is capped.
+        r.set_pacing_rate(1_000_000, Instant::now());
+        assert_eq!(r.pacer.rate(), 100_000);
+
+        r.set_pacing_rate(50_000, Instant::now());
+        assert_eq!(r.pacer.rate(), 50_000);
+    }
+
+    #[test]
+    fn recovery_config_from_snapshot() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.set_timer_granularity(Duration::from_millis(5)).unwrap();
+        cfg.enable_adaptive_reorder_threshold(false);
+        cfg.enable_pacing(false);
+
+        let snapshot = cfg.snapshot();
+        assert_eq!(snapshot.cc_algorithm, CongestionControlAlgorithm::Reno);
+        assert!(snapshot.to_string().starts_with("cc_algorithm=Reno "));
+
+        fn run(r: &mut Recovery, start: Instant) -> Instant {
+            let mut now = start;
+
+            for pkt_num in 0..4 {
+                let p = Sent {
+                    pkt_num,
+                    frames: vec![],
+                    time_sent: now,
+                    time_acked: None,
+                    time_lost: None,
+                    size: 1000,
+                    ack_eliciting: true,
+                    in_flight: true,
+                    delivered: 0,
+                    delivered_time: now,
+                    first_sent_time: now,
+                    is_app_limited: false,
+                    content: SentContent::default(),
+                    is_pmtud_probe: false,
+                    padding_bytes: 0,
+                    datagram_id: None,
+                    burst_index: 0,
+                    burst_size: 0,
+                    congestion_state: CongestionState::SlowStart,
+                };
+
+                r.on_packet_sent(
+                    p,
+                    packet::EPOCH_APPLICATION,
+                    HandshakeStatus::default(),
+                    now,
+                );
+            }
+
+            now += Duration::from_millis(10);
+
+            let mut acked = ranges::RangeSet::default();
+            acked.insert(0..1);
+            acked.insert(3..4);
+
+            r.on_ack_received(
+                &acked,
+                25,
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+                None,
+            )
+            .unwrap();
+
+            now = r.loss_detection_timer().unwrap();
+            r.on_loss_detection_timeout(HandshakeStatus::default(), now);
+
+            now
+        }
+
+        let mut r1 = Recovery::new(&cfg);
+        let mut r2 =
+            Recovery::new_with_config(&RecoveryConfig::from_snapshot(&snapshot));
+
+        let start = Instant::now();
+
+        assert_eq!(run(&mut r1, start), run(&mut r2, start));
+
+        assert_eq!(r1.cwnd(), r2.cwnd());
+        assert_eq!(r1.rtt(), r2.rtt());
+        assert_eq!(r1.pto(), r2.pto());
+        assert_eq!(r1.bytes_in_flight, r2.bytes_in_flight);
+        assert_eq!(r1.lost_count, r2.lost_count);
+        assert_eq!(r1.pkt_thresh, r2.pkt_thresh);
+        assert_eq!(r1.loss_detection_timer(), r2.loss_detection_timer());
+        assert_eq!(r1.granularity, Duration::from_millis(5));
+        assert_eq!(r2.granularity, Duration::from_millis(5));
+        assert!(!r2.pacer.enabled());
+    }
+
+    #[test]
+    fn pacing() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::CUBIC);
+
+        let mut r = Recovery::new(&cfg);
+
+        let mut now = Instant::now();
+
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 0);
+
+        // send out first packet (a full initcwnd).
+        let p = Sent {
+            pkt_num: 0,
+            frames: vec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 12000,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            content: SentContent::default(),
+            is_pmtud_probe: false,
+            padding_bytes: 0,
+            datagram_id: None,
+            burst_index: 0,
+            burst_size: 0,
+            congestion_state: CongestionState::SlowStart,
+        };
+
+        r.on_packet_sent(
+            p,
+            packet::EPOCH_APPLICATION,
+            HandshakeStatus::default(),
+            now,
+        );
+
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 1);
+        assert_eq!(r.bytes_in_flight, 12000);
+
+        // First packet will be sent out immediately.
+        assert_eq!(r.pacer.rate(), 0);
+        assert_eq!(r.get_packet_send_time(), now);
+
+        // Wait 50ms for ACK.
+        now += Duration::from_millis(50);
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..1);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                10,
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+                None
+            ),
+            Ok((0, 0))
+        );
+
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 0);
+        assert_eq!(r.bytes_in_flight, 0);
+        assert_eq!(r.smoothed_rtt.unwrap(), Duration::from_millis(50));
+
+        // 1 MSS increased.
+        assert_eq!(r.congestion_window, 12000 + 1200);
+
+        // Send out second packet.
+        let p = Sent {
+            pkt_num: 1,
+            frames: vec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 6000,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            content: SentContent::default(),
+            is_pmtud_probe: false,
+            padding_bytes: 0,
+            datagram_id: None,
+            burst_index: 0,
+            burst_size: 0,
+            congestion_state: CongestionState::SlowStart,
+        };
+
+        r.on_packet_sent(
+            p,
+            packet::EPOCH_APPLICATION,
+            HandshakeStatus::default(),
+            now,
+        );
+
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 1);
+        assert_eq!(r.bytes_in_flight, 6000);
+
+        // Pacing is not done during initial phase of connection.
+        assert_eq!(r.get_packet_send_time(), now);
+
+        // Send the third packet out.
+        let p = Sent {
+            pkt_num: 2,
+            frames: vec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 6000,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            content: SentContent::default(),
+            is_pmtud_probe: false,
+            padding_bytes: 0,
+            datagram_id: None,
+            burst_index: 0,
+            burst_size: 0,
+            congestion_state: CongestionState::SlowStart,
+        };
+
+        r.on_packet_sent(
+            p,
+            packet::EPOCH_APPLICATION,
+            HandshakeStatus::default(),
+            now,
+        );
+
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 2);
+        assert_eq!(r.bytes_in_flight, 12000);
+
+        // Send the third packet out.
+        let p = Sent {
+            pkt_num: 3,
+            frames: vec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 1000,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            content: SentContent::default(),
+            is_pmtud_probe: false,
+            padding_bytes: 0,
+            datagram_id: None,
+            burst_index: 0,
+            burst_size: 0,
+            congestion_state: CongestionState::SlowStart,
+        };
+
+        r.on_packet_sent(
+            p,
+            packet::EPOCH_APPLICATION,
+            HandshakeStatus::default(),
+            now,
+        );
+
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 3);
+        assert_eq!(r.bytes_in_flight, 13000);
+
+        // We pace this outgoing packet. as all conditions for pacing
+        // are passed.
+        let pacing_rate =
+            (r.congestion_window as f64 * PACING_MULTIPLIER / 0.05) as u64;
+        assert_eq!(r.pacer.rate(), pacing_rate);
+
+        assert_eq!(
+            r.get_packet_send_time(),
+            now + Duration::from_secs_f64(12000.0 / pacing_rate as f64)
+        );
+    }
+
+    #[test]
+    fn coalesced_loss() {
+        let crypto = |pkt_num: u64| {
+            vec![frame::Frame::CryptoHeader {
+                offset: pkt_num * 1000,
+                length: 1000,
+            }]
+        };
+
+        let status = HandshakeStatus {
+            has_handshake_keys: true,
+            peer_verified_address: true,
+            completed: false,
+            amplification_limited: false,
+        };
+
+        // Every datagram carries an Initial packet, and the first two also
+        // carry a Handshake packet. Packet 0 of both epochs was sent in the
+        // same datagram.
+        let sent = |coalesced_loss| {
+            let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+            cfg.enable_coalesced_loss(coalesced_loss);
+
+            let mut r = Recovery::new(&cfg);
+
+            let now = Instant::now();
+
+            for pn in 0..4 {
+                let mut pkt = testing::sent_packet(pn, crypto(pn), now);
+                pkt.datagram_id = NonZeroU32::new(pn as u32 + 1);
+
+                r.on_packet_sent(pkt, packet::EPOCH_INITIAL, status, now);
+
+                if pn < 2 {
+                    let mut pkt = testing::sent_packet(pn, crypto(pn), now);
+                    pkt.datagram_id = NonZeroU32::new(pn as u32 + 1);
+
+                    r.on_packet_sent(pkt, packet::EPOCH_HANDSHAKE, status, now);
+                }
+            }
+
+            (r, now + Duration::from_millis(10))
+        };
+
+        let ack = |r: &mut Recovery, range, epoch, now| {
+            r.on_ack_received(
+                &testing::range_set(Some(range)),
+                0,
+                epoch,
+                status,
+                now,
+                None,
+            )
+            .unwrap()
+        };
+
+        for coalesced_loss in [false, true] {
+            let (mut r, now) = sent(coalesced_loss);
+
+            // The first datagram is lost, only the Initial packet of which
+            // is past the packet threshold.
+            assert_eq!(ack(&mut r, 1..4, packet::EPOCH_INITIAL, now), (1, 1200));
+            assert_eq!(r.lost[packet::EPOCH_INITIAL], crypto(0));
+
+            // The Handshake ACK hasn't arrived yet, so nothing is lost in
+            // that epoch even when coalesced packets are lost together.
+            assert!(r.lost[packet::EPOCH_HANDSHAKE].is_empty());
+            assert_eq!(r.sent[packet::EPOCH_HANDSHAKE][0].time_lost, None);
+
+            let lost = ack(&mut r, 1..2, packet::EPOCH_HANDSHAKE, now);
+
+            if coalesced_loss {
+                // The Handshake packet is recovered within the same round
+                // trip, without waiting for the time threshold.
+                assert_eq!(lost, (1, 1200));
+                assert_eq!(r.lost[packet::EPOCH_HANDSHAKE], crypto(0));
+                assert_eq!(r.loss_time[packet::EPOCH_HANDSHAKE], None);
+            } else {
+                assert_eq!(lost, (0, 0));
+                assert!(r.lost[packet::EPOCH_HANDSHAKE].is_empty());
+                assert!(r.loss_time[packet::EPOCH_HANDSHAKE].is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn ack_window() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        // Fill the congestion window.
+        testing::send_packets(
+            &mut r,
+            (0..10).map(|pn| {
+                testing::sent_packet(pn, testing::stream_frames(pn, 1), now)
+            }),
+            now,
+        );
+
+        assert_eq!(r.cwnd(), 12000);
+        assert_eq!(r.cwnd_available(), 0);
+
+        let now = now + Duration::from_millis(10);
+
+        // Acking two packets in slow start frees their bytes and grows the
+        // window by as much.
+        testing::ack_packets(&mut r, &testing::range_set(Some(0..2)), now);
+
+        assert_eq!(r.ack_window(), AckWindowUpdate {
+            cwnd_before: 12000,
+            cwnd_after: 14400,
+            freed_bytes: 4800,
+            unblocked: true,
+        });
+
+        // The window wasn't full anymore.
+        testing::ack_packets(&mut r, &testing::range_set(Some(2..3)), now);
+
+        assert_eq!(r.ack_window(), AckWindowUpdate {
+            cwnd_before: 14400,
+            cwnd_after: 15600,
+            freed_bytes: 2400,
+            unblocked: false,
+        });
+
+        // Nothing is freed by a duplicate ACK.
+        testing::ack_packets(&mut r, &testing::range_set(Some(2..3)), now);
+
+        assert_eq!(r.ack_window(), AckWindowUpdate {
+            cwnd_before: 15600,
+            cwnd_after: 15600,
+            freed_bytes: 0,
+            unblocked: false,
+        });
+    }
+
+    #[test]
+    fn ack_without_ack_eliciting() {
+        // Returns the recovery state after a padded ACK-only packet was sent
+        // and acknowledged.
+        let acked = |ack_only_rate_samples| {
+            let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+            cfg.enable_ack_only_rate_samples(ack_only_rate_samples);
+
+            let mut r = Recovery::new(&cfg);
+
+            let now = Instant::now();
+
+            let mut pkt = testing::sent_packet(
+                0,
+                vec![
+                    frame::Frame::ACKHeader { largest_acked: 0 },
+                    frame::Frame::Padding { len: 1000 },
+                ],
+                now,
+            );
+            pkt.ack_eliciting = false;
+
+            testing::send_packets(&mut r, Some(pkt), now);
+
+            let now = now + Duration::from_millis(10);
+
+            assert_eq!(
+                testing::ack_packets(
+                    &mut r,
+                    &testing::range_set(Some(0..1)),
+                    now
+                ),
+                (0, 0)
+            );
+
+            r
+        };
+
+        let r = acked(true);
+
+        assert_eq!(r.acks_without_ack_eliciting, 1);
+        assert_eq!(r.rtt_samples_skipped, 1);
+        assert_eq!(r.smoothed_rtt, None);
+        assert_eq!(r.bytes_in_flight, 0);
+        assert_eq!(r.delivery_rate.delivered(), testing::PACKET_SIZE);
+
+        // The packet is still passed on to congestion control, but not to
+        // the delivery rate sampler.
+        let r = acked(false);
+
+        assert_eq!(r.acks_without_ack_eliciting, 1);
+        assert_eq!(r.rtt_samples_skipped, 1);
+        assert_eq!(r.smoothed_rtt, None);
+        assert_eq!(r.bytes_in_flight, 0);
+        assert_eq!(r.delivery_rate.delivered(), 0);
+    }
+
+    #[test]
+    fn loss_timer_coalescing() {
+        let threshold = Duration::from_millis(1);
+
+        // Sends a packet every 10us, and returns how many times the reported
+        // loss detection timer changed.
+        let send = |coalescing| {
+            let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+            cfg.set_loss_timer_coalescing(coalescing);
+            cfg.enable_pacing(false);
+
+            let mut r = Recovery::new(&cfg);
+
+            let start = Instant::now();
+
+            let mut timer = None;
+            let mut changes = 0;
+
+            for pn in 0..1000 {
+                let now = start + Duration::from_micros(pn * 10);
+
+                let pkt =
+                    testing::sent_packet(pn, testing::stream_frames(pn, 1), now);
+                testing::send_packets(&mut r, Some(pkt), now);
+
+                if r.loss_detection_timer() != timer {
+                    timer = r.loss_detection_timer();
+                    changes += 1;
+                }
+
+                // The reported deadline never strays further than the
+                // threshold from the true one.
+                let reported = timer.unwrap();
+                let deadline = r.loss_detection_timer.deadline().unwrap();
+
+                assert!(reported <= deadline + coalescing);
+                assert!(reported + coalescing >= deadline);
+            }
+
+            (r, changes)
+        };
+
+        let (r, changes) = send(Duration::ZERO);
+        assert_eq!(changes, 1000);
+        assert_eq!(r.loss_timer_suppressed_updates(), 0);
+
+        let (mut r, changes) = send(threshold);
+        assert!(changes <= 10);
+        assert_eq!(r.loss_timer_suppressed_updates(), 1000 - changes);
+
+        let reported = r.loss_detection_timer().unwrap();
+        let deadline = r.loss_detection_timer.deadline().unwrap();
+        assert!(reported < deadline);
+
+        // The reported deadline expires first, which only re-arms the timer
+        // for the true one.
+        assert_eq!(
+            r.on_loss_detection_timeout(testing::HANDSHAKE_COMPLETED, reported),
+            (0, 0)
+        );
+        assert_eq!(r.pto_count, 0);
+        assert_eq!(r.loss_detection_timer(), Some(deadline));
+
+        r.on_loss_detection_timeout(testing::HANDSHAKE_COMPLETED, deadline);
+        assert_eq!(r.pto_count, 1);
+    }
+
+    #[test]
+    fn ack_cost() {
+        let mut r =
+            Recovery::new(&crate::Config::new(crate::PROTOCOL_VERSION).unwrap());
+
+        let now = Instant::now();
+
+        testing::send_packets(
+            &mut r,
+            (0..600).map(|pn| {
+                testing::sent_packet(pn, testing::stream_frames(pn, 1), now)
+            }),
+            now,
+        );
+
+        let now = now + Duration::from_millis(10);
+
+        // Every other packet is acked in its own range.
+        let ranges = testing::fragmented_range_set(0..600);
+        assert_eq!(ranges.len(), 300);
+
+        testing::ack_packets(&mut r, &ranges, now);
+
+        assert_eq!(r.ack_cost(), AckCost {
+            count: 1,
+            ranges: 300,
+            scanned: 300,
+            frames: 300,
+            max: 900,
+        });
+
+        // Packets that were already acked or lost are still scanned, except
+        // for the first one which was removed from the list. Only the last two
+        // packets, that were not declared lost, have frames left to drain.
+        testing::ack_packets(&mut r, &testing::range_set(Some(0..600)), now);
+
+        assert_eq!(r.ack_cost(), AckCost {
+            count: 2,
+            ranges: 301,
+            scanned: 899,
+            frames: 302,
+            max: 900,
+        });
+        assert_eq!(r.stats().ack_cost, r.ack_cost());
+    }
+
+    #[test]
+    fn lost_then_acked() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        testing::send_packets(
+            &mut r,
+            (0..4).map(|pn| testing::sent_packet(pn, vec![], now)),
+            now,
+        );
+
+        let now = now + Duration::from_millis(10);
+
+        // Packet 0 is declared lost by the packet threshold, and leaves the
+        // flight.
+        assert_eq!(
+            testing::ack_packets(&mut r, &testing::range_set(Some(3..4)), now),
+            (1, testing::PACKET_SIZE)
+        );
+        assert_eq!(r.bytes_in_flight, 2 * testing::PACKET_SIZE);
+
+        // A late ACK for the lost packet must not remove it again.
+        assert_eq!(
+            testing::ack_packets(&mut r, &testing::range_set(Some(0..1)), now),
+            (0, 0)
+        );
+        assert_eq!(r.bytes_in_flight, 2 * testing::PACKET_SIZE);
+        assert_eq!(r.lost_spurious_count, 1);
+
+        assert_eq!(
+            testing::ack_packets(&mut r, &testing::range_set(Some(0..4)), now),
+            (0, 0)
+        );
+        assert_eq!(r.bytes_in_flight, 0);
+        assert_eq!(r.bytes_in_flight_underflows, 0);
+    }
+
+    #[test]
+    fn discard_then_acked() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        for pn in 0..2 {
+            r.on_packet_sent(
+                testing::sent_packet(pn, vec![], now),
+                packet::EPOCH_HANDSHAKE,
+                HandshakeStatus::default(),
+                now,
+            );
+        }
+
+        testing::send_packets(
+            &mut r,
+            (0..2).map(|pn| testing::sent_packet(pn, vec![], now)),
+            now,
+        );
+
+        assert_eq!(r.bytes_in_flight, 4 * testing::PACKET_SIZE);
+
+        r.on_pkt_num_space_discarded(
+            packet::EPOCH_HANDSHAKE,
+            testing::HANDSHAKE_COMPLETED,
+            now,
+        );
+
+        assert_eq!(r.bytes_in_flight, 2 * testing::PACKET_SIZE);
+
+        let now = now + Duration::from_millis(10);
+
+        // An ACK for the discarded space that was already in flight finds
+        // nothing left to remove.
+        assert_eq!(
+            r.on_ack_received(
+                &testing::range_set(Some(0..2)),
+                25,
+                packet::EPOCH_HANDSHAKE,
+                testing::HANDSHAKE_COMPLETED,
+                now,
+                None,
+            )
+            .unwrap(),
+            (0, 0)
+        );
+        assert_eq!(r.bytes_in_flight, 2 * testing::PACKET_SIZE);
+
+        assert_eq!(
+            testing::ack_packets(&mut r, &testing::range_set(Some(0..2)), now),
+            (0, 0)
+        );
+        assert_eq!(r.bytes_in_flight, 0);
+        assert_eq!(r.bytes_in_flight_underflows, 0);
+
+        r.assert_consistent();
+    }
+
+    #[test]
+    fn max_datagram_size_drop() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.set_max_send_udp_payload_size(1350);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        r.congestion_window = 20 * 1350;
+        r.send_quantum = 5 * 1350;
+        r.pacer.update(r.send_quantum, 1_000_000, now);
+
+        testing::send_packets(
+            &mut r,
+            (0..2).map(|pn| testing::sent_packet(pn, vec![], now)),
+            now,
+        );
+
+        let hint = r.batch_hint(now);
+        assert_eq!(hint.segment_size, 1350);
+        assert_eq!(hint.max_segments, 5);
+
+        r.update_max_datagram_size(1200);
+
+        // The send quantum and the pacer's burst are kept the same in number
+        // of packets, while the congestion window is left alone as it was
+        // already grown.
+        assert_eq!(r.max_datagram_size(), 1200);
+        assert_eq!(r.send_quantum(), 5 * 1200);
+        assert_eq!(r.cwnd(), 20 * 1350);
+        assert_eq!(r.pacer.rate(), 1_000_000);
+        assert_eq!(r.pacer.burst_budget(), Some(5 * 1200));
+
+        let hint = r.batch_hint(now);
+        assert_eq!(hint.segment_size, 1200);
+        assert_eq!(hint.max_segments, 5);
+
+        // The limit is never raised again, other than by DPLPMTUD.
+        r.update_max_datagram_size(1350);
+
+        assert_eq!(r.max_datagram_size(), 1200);
+        assert_eq!(r.send_quantum(), 5 * 1200);
+    }
+
+    #[test]
+    fn max_datagram_size_drop_initial_window() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.set_max_send_udp_payload_size(1350);
+
+        let mut r = Recovery::new(&cfg);
+
+        assert_eq!(r.cwnd(), 1350 * INITIAL_WINDOW_PACKETS);
+        assert_eq!(r.send_quantum(), 1350 * INITIAL_WINDOW_PACKETS);
+
+        r.update_max_datagram_size(1200);
+
+        // Values derived from the initial window follow the new size.
+        assert_eq!(r.cwnd(), 1200 * INITIAL_WINDOW_PACKETS);
+        assert_eq!(r.send_quantum(), 1200 * INITIAL_WINDOW_PACKETS);
+    }
+
+    #[test]
+    fn blackhole_suspected() {
+        // Returns the recovery state after consecutive PTOs, along with
+        // whether a black hole was suspected after each one.
+        let ptos = |mtu_clamp| {
+            let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+            cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+            cfg.set_max_send_udp_payload_size(1350);
+            cfg.enable_blackhole_mtu_clamp(mtu_clamp);
+
+            let mut r = Recovery::new(&cfg);
+
+            let now = Instant::now();
+
+            testing::PacketTrain::new(4, 1350)
+                .with_stream_frames(1)
+                .send(&mut r, now);
+
+            let mut suspected = Vec::new();
+
+            let res = testing::AckScript::new()
+                .loss_detection_timeout()
+                .loss_detection_timeout()
+                .loss_detection_timeout()
+                .loss_detection_timeout()
+                .run_with(&mut r, now, |_, r, _| {
+                    suspected.push(r.is_blackhole_suspected())
+                });
+
+            assert_eq!(r.pto_count, 4);
+
+            (r, suspected, res.now)
+        };
+
+        let (mut r, suspected, now) = ptos(false);
+
+        assert_eq!(suspected, [false, false, true, true]);
+        assert_eq!(r.max_datagram_size(), 1350);
+
+        // The next ACK clears the suspicion.
+        testing::AckScript::new()
+            .send(testing::PacketTrain::new(4, 1350).starting_at(4))
+            .after(Duration::from_millis(10))
+            .ack(0..8)
+            .run(&mut r, now);
+
+        assert!(!r.is_blackhole_suspected());
+        assert_eq!(r.pto_count, 0);
+
+        // The datagram size is clamped once the black hole is suspected, and
+        // the congestion window is kept the same in number of packets.
+        let (r, suspected, _) = ptos(true);
+
+        assert_eq!(suspected, [false, false, true, true]);
+        assert_eq!(r.max_datagram_size(), 1200);
+        assert_eq!(r.cwnd(), 1200 * INITIAL_WINDOW_PACKETS);
+    }
+
+    #[test]
+    fn blackhole_suspected_disabled() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.set_blackhole_pto_threshold(0);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        testing::PacketTrain::new(4, testing::PACKET_SIZE)
+            .with_stream_frames(1)
+            .send(&mut r, now);
+
+        testing::AckScript::new()
+            .loss_detection_timeout()
+            .loss_detection_timeout()
+            .loss_detection_timeout()
+            .run(&mut r, now);
+
+        assert_eq!(r.pto_count, 3);
+        assert!(!r.is_blackhole_suspected());
+    }
+
+    #[test]
+    fn declare_lost() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        testing::send_packets(
+            &mut r,
+            (0..6).map(|pn| {
+                testing::sent_packet(pn, testing::stream_frames(pn, 1), now)
+            }),
+            now,
+        );
+
+        let cwnd = r.cwnd();
+
+        // The packets are lost without waiting for any threshold, and without
+        // a congestion response.
+        assert_eq!(
+            r.declare_lost(
+                packet::EPOCH_APPLICATION,
+                1..3,
+                false,
+                testing::HANDSHAKE_COMPLETED,
+                now
+            ),
+            (2, 2 * testing::PACKET_SIZE)
+        );
+
+        assert_eq!(
+            r.drain_lost_frames(packet::EPOCH_APPLICATION, |_| 0)
+                .collect::<Vec<_>>(),
+            [testing::stream_frames(1, 1), testing::stream_frames(2, 1)].concat()
+        );
+
+        assert_eq!(r.cwnd(), cwnd);
+        assert_eq!(r.bytes_in_flight, 4 * testing::PACKET_SIZE);
+        assert_eq!(r.lost_count, 2);
+        assert_eq!(r.congestion_recovery_start_time, None);
+
+        // Acked and already lost packets are left alone.
+        testing::ack_packets(&mut r, &testing::range_set(Some(0..1)), now);
+
+        assert_eq!(
+            r.declare_lost(
+                packet::EPOCH_APPLICATION,
+                0..3,
+                false,
+                testing::HANDSHAKE_COMPLETED,
+                now
+            ),
+            (0, 0)
+        );
+        assert!(r.lost[packet::EPOCH_APPLICATION].is_empty());
+        assert_eq!(r.bytes_in_flight, 3 * testing::PACKET_SIZE);
+
+        // With a congestion response, the window shrinks as with any other
+        // loss.
+        assert_eq!(
+            r.declare_lost(
+                packet::EPOCH_APPLICATION,
+                3..5,
+                true,
+                testing::HANDSHAKE_COMPLETED,
+                now
+            ),
+            (2, 2 * testing::PACKET_SIZE)
+        );
+
+        assert!(r.cwnd() < cwnd);
+        assert_eq!(r.bytes_in_flight, testing::PACKET_SIZE);
+        assert_eq!(r.lost_count, 4);
+        assert_eq!(r.lost[packet::EPOCH_APPLICATION].len(), 2);
+
+        // Losses are accounted for as when they are detected.
+        assert_eq!(r.loss_latency().count, 4);
+
+        // A PMTU probe is not counted as a loss of the path.
+        testing::send_packets(
+            &mut r,
+            (6..7).map(|pn| Sent {
+                is_pmtud_probe: true,
+                ..testing::sent_packet(pn, testing::stream_frames(pn, 1), now)
+            }),
+            now,
+        );
+
+        assert_eq!(
+            r.declare_lost(
+                packet::EPOCH_APPLICATION,
+                6..7,
+                false,
+                testing::HANDSHAKE_COMPLETED,
+                now
+            ),
+            (0, 0)
+        );
+
+        assert_eq!(r.bytes_in_flight, testing::PACKET_SIZE);
+        assert_eq!(r.lost_count, 4);
+        assert_eq!(r.lost[packet::EPOCH_APPLICATION].len(), 3);
+    }
+
+    #[test]
+    fn frame_backlog() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.set_max_frame_backlog(4);
+
+        let mut r = Recovery::new(&cfg);
+
+        let mut now = Instant::now();
+
+        testing::send_packets(
+            &mut r,
+            (0..8).map(|pn| {
+                testing::sent_packet(pn, testing::stream_frames(pn, 2), now)
+            }),
+            now,
+        );
+
+        // The connection never processes lost frames, while PTOs keep firing.
+        for _ in 0..5 {
+            now = r.loss_detection_timer().unwrap();
+
+            r.on_loss_detection_timeout(testing::HANDSHAKE_COMPLETED, now);
+
+            assert!(r.lost[packet::EPOCH_APPLICATION].len() <= 4);
+        }
+
+        assert_eq!(r.frame_backlog(), 4);
+        assert!(r.frame_backlog_overflows > 0);
+
+        let overflows = r.frame_backlog_overflows;
+
+        // Neither does it process acked frames.
+        r.on_ack_received(
+            &testing::range_set(Some(0..8)),
+            25,
+            packet::EPOCH_APPLICATION,
+            testing::HANDSHAKE_COMPLETED,
+            now,
+            None,
+        )
+        .unwrap();
+
+        // Only the frames of the first two packets fit.
+        assert_eq!(r.acked[packet::EPOCH_APPLICATION].len(), 4);
+        assert!(r.lost[packet::EPOCH_APPLICATION].len() <= 4);
+        assert!(r.frame_backlog() <= 8);
+        assert_eq!(r.frame_backlog_overflows, overflows + 6);
+    }
+
+    #[test]
+    fn lost_frames() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        let frames = vec![
+            frame::Frame::StreamHeader {
+                stream_id: 4,
+                offset: 0,
+                length: 1000,
+                fin: false,
+            },
+            frame::Frame::DatagramHeader { length: 100 },
+            frame::Frame::MaxData { max: 100_000 },
+            frame::Frame::ACKHeader { largest_acked: 0 },
+            frame::Frame::Padding { len: 10 },
+        ];
+
+        let control = frames[2].wire_len() as u64;
+
+        testing::send_packets(
+            &mut r,
+            Some(testing::sent_packet(0, frames, now))
+                .into_iter()
+                .chain((1..4).map(|pn| {
+                    testing::sent_packet(pn, testing::stream_frames(pn, 1), now)
+                })),
+            now,
+        );
+
+        assert_eq!(r.lost_frames(), LostFrames::default());
+        assert_eq!(r.lost_frames().not_retransmitted_ratio(), None);
+
+        // The first packet is lost.
+        assert_eq!(
+            testing::ack_packets(&mut r, &testing::range_set(Some(3..4)), now),
+            (1, testing::PACKET_SIZE)
+        );
+
+        assert_eq!(r.lost_frames(), LostFrames {
+            stream: 1000,
+            crypto: 0,
+            datagram: 100,
+            control,
+            not_retransmitted: 100,
+        });
+        assert_eq!(r.stats().lost_frames, r.lost_frames());
+
+        // The stream's data is no longer retransmitted.
+        assert_eq!(r.drop_stream_frames(4, now), 1);
+
+        assert_eq!(r.lost_frames().not_retransmitted, 1100);
+        assert_eq!(
+            r.lost_frames().not_retransmitted_ratio(),
+            Some(1100.0 / (1100 + control) as f64)
+        );
+    }
+
+    #[test]
+    fn earliest_time_and_space() {
+        use super::earliest_time_and_space;
+
+        let now = Instant::now();
+        let later = now + Duration::from_millis(1);
+
+        assert_eq!(
+            earliest_time_and_space([None, None, None]),
+            (None, packet::EPOCH_INITIAL)
+        );
+
+        // Ties go to the lowest packet number space.
+        assert_eq!(
+            earliest_time_and_space([Some(now), Some(now), Some(now)]),
+            (Some(now), packet::EPOCH_INITIAL)
+        );
+        assert_eq!(
+            earliest_time_and_space([None, Some(now), Some(now)]),
+            (Some(now), packet::EPOCH_HANDSHAKE)
+        );
+        assert_eq!(
+            earliest_time_and_space([Some(later), Some(now), Some(now)]),
+            (Some(now), packet::EPOCH_HANDSHAKE)
+        );
+
+        // Missing times never win over existing ones.
+        assert_eq!(
+            earliest_time_and_space([Some(later), None, None]),
+            (Some(later), packet::EPOCH_INITIAL)
+        );
+        assert_eq!(
+            earliest_time_and_space([None, Some(later), None]),
+            (Some(later), packet::EPOCH_HANDSHAKE)
+        );
+        assert_eq!(
+            earliest_time_and_space([None, None, Some(later)]),
+            (Some(later), packet::EPOCH_APPLICATION)
+        );
+        assert_eq!(
+            earliest_time_and_space([Some(later), None, Some(now)]),
+            (Some(now), packet::EPOCH_APPLICATION)
+        );
+        assert_eq!(
+            earliest_time_and_space([Some(now), None, Some(later)]),
+            (Some(now), packet::EPOCH_INITIAL)
+        );
+    }
+
+    #[test]
+    fn loss_time_and_space_skips_missing_times() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        // A loss time in a lower packet number space is not hidden by the
+        // higher ones not having any.
+        r.loss_time = [Some(now), None, None];
+        assert_eq!(r.loss_time_and_space(), (Some(now), packet::EPOCH_INITIAL));
+
+        r.loss_time = [None, Some(now), None];
+        assert_eq!(
+            r.loss_time_and_space(),
+            (Some(now), packet::EPOCH_HANDSHAKE)
+        );
+    }
+
+    #[test]
+    fn pto_zero_rtt_before_handshake_completion() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+        r.max_ack_delay = Duration::from_millis(25);
+
+        let handshake_status = HandshakeStatus {
+            has_handshake_keys: true,
+
+            peer_verified_address: false,
+
+            completed: false,
+
+            amplification_limited: false,
+        };
+
+        let mut now = Instant::now();
+
+        // The Initial flight is acked, only 0-RTT data is left in flight.
+        r.on_packet_sent(
+            testing::sent_packet(0, vec![frame::Frame::Ping], now),
+            packet::EPOCH_INITIAL,
+            handshake_status,
+            now,
+        );
+
+        now += Duration::from_millis(10);
+
+        r.on_ack_received(
+            &testing::range_set(std::iter::once(0..1)),
+            0,
+            packet::EPOCH_INITIAL,
+            handshake_status,
+            now,
+            None,
+        )
+        .unwrap();
+
+        r.on_packet_sent(
+            testing::sent_packet(0, testing::stream_frames(0, 1), now),
+            packet::EPOCH_APPLICATION,
+            handshake_status,
+            now,
+        );
+
+        assert_eq!(r.in_flight_count[packet::EPOCH_INITIAL], 0);
+        assert_eq!(r.in_flight_count[packet::EPOCH_APPLICATION], 1);
+
+        // The 0-RTT data is probed along with the handshake, without
+        // max_ack_delay.
+        let pto = r.pto().mul_f64(r.handshake_pto_multiplier);
+        assert_eq!(r.loss_detection_timer(), Some(now + pto));
+        assert_eq!(
+            r.pto_time_and_space(handshake_status, now).1,
+            packet::EPOCH_HANDSHAKE
+        );
+
+        now += pto;
+
+        r.on_loss_detection_timeout(handshake_status, now);
+
+        assert_eq!(r.pto_count, 1);
+        assert_eq!(r.loss_probes[packet::EPOCH_HANDSHAKE], 1);
+        assert_eq!(r.loss_probes[packet::EPOCH_APPLICATION], 0);
+
+        // The timer is backed off rather than firing again right away.
+        let sent_time = now - pto;
+        assert_eq!(r.loss_detection_timer(), Some(sent_time + pto * 2));
+
+        // Once the handshake completes, the 0-RTT data gets its own probe
+        // timeout, including max_ack_delay.
+        r.on_handshake_completed(testing::HANDSHAKE_COMPLETED, now);
+
+        assert_eq!(
+            r.loss_detection_timer(),
+            Some(sent_time + (r.pto() + r.max_ack_delay) * 2)
+        );
+        assert_eq!(
+            r.pto_time_and_space(testing::HANDSHAKE_COMPLETED, now).1,
+            packet::EPOCH_APPLICATION
+        );
+    }
+
+    #[test]
+    fn ack_budget_for_packet() {
+        use super::ack_budget_for_packet;
+
+        // Without retransmissions the ACK frame can use all the space.
+        assert_eq!(ack_budget_for_packet(1200, 0), 1200);
+
+        assert_eq!(
+            ack_budget_for_packet(1200, 1000),
+            1200 - 1000 - frame::MAX_STREAM_OVERHEAD
+        );
+
+        // The ACK frame always gets a minimum budget.
+        assert_eq!(ack_budget_for_packet(1200, 1200), MIN_ACK_BUDGET);
+        assert_eq!(ack_budget_for_packet(32, 1200), 32);
+
+        // 100 ACK ranges along with a lost STREAM frame don't fit in a
+        // single packet.
+        let ranges = testing::fragmented_range_set(0..200);
+        assert_eq!(ranges.len(), 100);
+
+        let lost = frame::Frame::StreamHeader {
+            stream_id: 0,
+            offset: 0,
+            length: 1000,
+            fin: false,
+        };
+
+        let left = testing::PACKET_SIZE - 30;
+
+        let full = frame::Frame::ack_within(0, &ranges, usize::MAX);
+        assert!(full.wire_len() + lost.wire_len() > left);
+
+        // With the ACK frame limited by the budget, both fit.
+        let budget = ack_budget_for_packet(left, 1000);
+
+        let ack = frame::Frame::ack_within(0, &ranges, budget);
+        assert!(ack.wire_len() <= budget);
+        assert!(ack.wire_len() + lost.wire_len() <= left);
+
+        match ack {
+            frame::Frame::ACK { ranges: acked, .. } => {
+                assert!(acked.len() < ranges.len());
+                assert_eq!(acked.last(), ranges.last());
+            },
+
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn delay_asymmetry() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let clock = ManualClock::new();
+
+        let mut recovery_config = RecoveryConfig::from_config(&cfg);
+        recovery_config.clock = Clock::Manual(clock.clone());
+
+        let mut r = Recovery::new_with_config(&recovery_config);
+
+        assert_eq!(r.delay_asymmetry(), DelayAsymmetry::default());
+
+        // Each packet is acked after a known forward delay, peer ACK delay,
+        // reverse delay and local processing delay.
+        let exchange = |r: &mut Recovery,
+                        pn: u64,
+                        forward: u64,
+                        ack_delay: u64,
+                        reverse: u64,
+                        local: u64| {
+            let sent = clock.now();
+
+            testing::send_packets(
+                r,
+                std::iter::once(testing::sent_packet(
+                    pn,
+                    testing::stream_frames(pn, 1),
+                    sent,
+                )),
+                sent,
+            );
+
+            let received =
+                sent + Duration::from_millis(forward + ack_delay + reverse);
+
+            clock.advance_to(received + Duration::from_millis(local));
+
+            r.on_ack_received(
+                &testing::range_set(std::iter::once(pn..pn + 1)),
+                ack_delay * 1000,
+                packet::EPOCH_APPLICATION,
+                testing::HANDSHAKE_COMPLETED,
+                received,
+                None,
+            )
+            .unwrap();
+
+            r.delay_asymmetry()
+        };
+
+        // Symmetric path.
+        assert_eq!(exchange(&mut r, 0, 40, 10, 40, 2), DelayAsymmetry {
+            latest_rtt: Duration::from_millis(90),
+            peer_ack_delay: Duration::from_millis(10),
+            local_delay: Duration::from_millis(2),
+            forward: Duration::from_millis(40),
+            reverse: Duration::from_millis(40),
+        });
+
+        // Delay is added on the reverse path.
+        let delay = exchange(&mut r, 1, 40, 5, 70, 1);
+        assert_eq!(delay, DelayAsymmetry {
+            latest_rtt: Duration::from_millis(115),
+            peer_ack_delay: Duration::from_millis(5),
+            local_delay: Duration::from_millis(1),
+            forward: Duration::from_millis(40),
+            reverse: Duration::from_millis(70),
+        });
+        assert_eq!(delay.network_rtt(), Duration::from_millis(110));
+        assert_eq!(delay.reverse_ratio(), Some(0.070 / 0.110));
+
+        assert_eq!(r.stats().delay_asymmetry, delay);
+
+        // The baseline follows the lowest network RTT.
+        let delay = exchange(&mut r, 2, 30, 0, 30, 0);
+        assert_eq!(delay.forward, Duration::from_millis(30));
+        assert_eq!(delay.reverse, Duration::from_millis(30));
+    }
+
+    #[test]
+    fn loss_delay_before_clock_origin() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
 
-        let new_min_rtt = if self.min_rtt != latest.min_rtt {
-            self.min_rtt = latest.min_rtt;
-            emit_event = true;
-            Some(latest.min_rtt.as_secs_f32() * 1000.0)
-        } else {
-            None
+        // Without any RTT sample the loss delay is based on the initial RTT,
+        // which goes back further than the clock's origin, even if it's the
+        // start of the system rather than of the process.
+        let initial_rtt = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+        cfg.set_initial_rtt(initial_rtt).unwrap();
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        let mut pkt = testing::sent_packet(1, vec![], now);
+        pkt.ack_eliciting = false;
+
+        testing::send_packets(
+            &mut r,
+            vec![
+                testing::sent_packet(0, testing::stream_frames(0, 1), now),
+                pkt,
+            ],
+            now,
+        );
+
+        // ACKs of non ack-eliciting packets don't produce RTT samples.
+        assert_eq!(
+            testing::ack_packets(
+                &mut r,
+                &testing::range_set(std::iter::once(1..2)),
+                now
+            ),
+            (0, 0)
+        );
+
+        assert_eq!(r.smoothed_rtt, None);
+        assert_eq!(r.lost_count, 0);
+        assert_eq!(r.bytes_in_flight, testing::PACKET_SIZE);
+
+        let loss_time = now + initial_rtt.mul_f64(r.time_thresh);
+        assert_eq!(r.loss_time[packet::EPOCH_APPLICATION], Some(loss_time));
+        assert_eq!(r.loss_detection_timer(), Some(loss_time));
+
+        assert!(format!("{:?}", r.debug_at(now)).starts_with("timer="));
+    }
+
+    #[test]
+    fn clock_going_backward() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+        let earlier = now - Duration::from_millis(10);
+
+        testing::PacketTrain::new(4, 1000).send(&mut r, now);
+
+        // An ACK received before the packets were sent produces no RTT sample,
+        // but the packets are still acknowledged, and the oldest one is lost
+        // by packet threshold.
+        assert_eq!(
+            testing::ack_packets(
+                &mut r,
+                &testing::range_set(std::iter::once(2..4)),
+                earlier
+            ),
+            (1, 1000)
+        );
+
+        assert_eq!(r.smoothed_rtt, None);
+        assert_eq!(r.latest_rtt, Duration::ZERO);
+        assert_eq!(r.bytes_in_flight, 1000);
+        assert!(r.loss_time[packet::EPOCH_APPLICATION].unwrap() > now);
+
+        let timer = r.loss_detection_timer().unwrap();
+        assert!(format!("{:?}", r.debug_at(earlier))
+            .starts_with(&format!("timer={:?} ", timer - earlier)));
+
+        // The clock stalls, and the loss time is reached.
+        assert_eq!(
+            r.on_loss_detection_timeout(testing::HANDSHAKE_COMPLETED, timer),
+            (1, 1000)
+        );
+        assert_eq!(
+            r.on_loss_detection_timeout(testing::HANDSHAKE_COMPLETED, timer),
+            (0, 0)
+        );
+        assert_eq!(r.bytes_in_flight, 0);
+        assert_eq!(r.lost_count, 2);
+    }
+
+    #[test]
+    fn ack_implied_loss() {
+        let rtt = Duration::from_millis(50);
+
+        // Only the first packet is lost, and a run of packets sent after it
+        // is acknowledged, but not enough for the packet threshold.
+        let loss_latency = |ack_implied_loss| {
+            let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+            cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+            cfg.set_packet_reorder_threshold(10).unwrap();
+            cfg.enable_ack_implied_loss(ack_implied_loss);
+
+            let mut r = Recovery::new(&cfg);
+
+            let now = Instant::now();
+
+            testing::PacketTrain::new(8, 1000).send(&mut r, now);
+
+            let res = testing::AckScript::new()
+                .after(rtt)
+                .ack(1..6)
+                .loss_detection_timeout()
+                .run(&mut r, now);
+
+            assert_eq!(res.lost_packets(), 1);
+            assert_eq!(r.lost_count, 1);
+
+            r.loss_latency().max
         };
 
-        let new_smoothed_rtt = if self.smoothed_rtt != latest.smoothed_rtt {
-            self.smoothed_rtt = latest.smoothed_rtt;
-            emit_event = true;
-            Some(latest.smoothed_rtt.as_secs_f32() * 1000.0)
-        } else {
-            None
+        assert_eq!(loss_latency(false), rtt.mul_f64(INITIAL_TIME_THRESHOLD));
+        assert_eq!(loss_latency(true), rtt);
+    }
+
+    #[test]
+    fn ack_implied_loss_spurious() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.set_packet_reorder_threshold(10).unwrap();
+        cfg.enable_ack_implied_loss(true);
+
+        let mut r = Recovery::new(&cfg);
+
+        let mut now = Instant::now();
+
+        testing::PacketTrain::new(16, 1000).send(&mut r, now);
+
+        now += Duration::from_millis(50);
+
+        let ack = |r: &mut Recovery, pkt_nums: Range<u64>| {
+            testing::ack_packets(
+                r,
+                &testing::range_set(std::iter::once(pkt_nums)),
+                now,
+            )
         };
 
-        let new_latest_rtt = if self.latest_rtt != latest.latest_rtt {
-            self.latest_rtt = latest.latest_rtt;
-            emit_event = true;
-            Some(latest.latest_rtt.as_secs_f32() * 1000.0)
-        } else {
-            None
+        assert_eq!(ack(&mut r, 1..6), (1, 1000));
+
+        // The loss is spurious, so a longer run is needed from now on.
+        assert_eq!(ack(&mut r, 0..6), (0, 0));
+
+        assert_eq!(r.lost_spurious_count, 1);
+        assert_eq!(r.ack_implied_loss_thresh, 6);
+
+        assert_eq!(ack(&mut r, 7..13), (0, 0));
+        assert_eq!(ack(&mut r, 7..14), (1, 1000));
+
+        assert_eq!(r.lost_count, 2);
+    }
+
+    #[test]
+    fn rounds() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let rtt = Duration::from_millis(50);
+
+        let mut rounds = Vec::new();
+
+        testing::AckScript::new()
+            .send(testing::PacketTrain::new(2, 1000))
+            .after(rtt)
+            .ack(0..1)
+            .send(testing::PacketTrain::new(3, 1100).starting_at(2))
+            .after(rtt)
+            // The round only ends once its first packet is acknowledged.
+            .ack(1..2)
+            .ack(2..3)
+            .send(testing::PacketTrain::new(4, 1200).starting_at(5))
+            .after(rtt)
+            .ack(3..7)
+            .run_with(&mut r, Instant::now(), |step, r, _| {
+                if step == "ack" {
+                    rounds.push(r.rounds());
+                }
+            });
+
+        let round = |count, last_packets, last_bytes| Rounds {
+            count,
+            packets: 0,
+            bytes: 0,
+            last_packets,
+            last_bytes,
         };
 
-        let new_rttvar = if self.rttvar != latest.rttvar {
-            self.rttvar = latest.rttvar;
-            emit_event = true;
-            Some(latest.rttvar.as_secs_f32() * 1000.0)
-        } else {
-            None
+        assert_eq!(rounds, [
+            round(1, 2, 2000),
+            Rounds {
+                packets: 3,
+                bytes: 3300,
+                ..round(1, 2, 2000)
+            },
+            round(2, 3, 3300),
+            round(3, 4, 4800),
+        ]);
+
+        assert_eq!(r.stats().rounds, round(3, 4, 4800));
+
+        // The next packet sent starts a new round.
+        testing::PacketTrain::new(1, 1000)
+            .starting_at(9)
+            .send(&mut r, Instant::now());
+
+        assert_eq!(r.rounds().count, 3);
+        assert_eq!(r.rounds().packets, 1);
+        assert_eq!(r.rounds().bytes, 1000);
+    }
+
+    #[test]
+    fn bandwidth_delay_estimates() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_pacing(false);
+
+        let mut r = Recovery::new(&cfg);
+
+        let start = Instant::now();
+
+        assert_eq!(
+            r.bandwidth_delay_estimates(),
+            BandwidthDelayEstimates::default()
+        );
+
+        // One packet is sent every millisecond, and acknowledged `rtt` later,
+        // so the throughput is one packet per millisecond.
+        let rate = (testing::PACKET_SIZE * 1000) as f64;
+
+        let run = |r: &mut Recovery, ticks: Range<u64>, rtt: u64| {
+            for t in ticks {
+                let now = start + Duration::from_millis(t);
+
+                testing::send_packets(
+                    r,
+                    Some(testing::sent_packet(t, vec![], now)),
+                    now,
+                );
+
+                if let Some(pn) = t.checked_sub(rtt) {
+                    testing::ack_packets(
+                        r,
+                        &testing::range_set(Some(pn..pn + 1)),
+                        now,
+                    );
+                }
+            }
         };
 
-        let new_cwnd = if self.cwnd != latest.cwnd {
-            self.cwnd = latest.cwnd;
-            emit_event = true;
-            Some(latest.cwnd)
-        } else {
-            None
+        // Over an uncongested 50ms path, nothing is queued.
+        run(&mut r, 0..1000, 50);
+
+        let estimates = r.bandwidth_delay_estimates();
+        assert!((estimates.throughput as f64 - rate).abs() < rate * 0.05);
+        assert!(estimates.queue_delay < Duration::from_millis(1));
+
+        // Then packets are queued for 30ms.
+        run(&mut r, 1000..2000, 80);
+
+        let estimates = r.bandwidth_delay_estimates();
+        assert!((estimates.throughput as f64 - rate).abs() < rate * 0.05);
+
+        let queue_delay = estimates.queue_delay.as_secs_f64();
+        assert!((queue_delay - 0.030).abs() < 0.030 * 0.05);
+
+        assert_eq!(r.stats().bandwidth_delay, estimates);
+    }
+
+    #[test]
+    fn strict_rfc9002() {
+        let recovery = |strict| {
+            let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+            cfg.set_strict_rfc9002(strict);
+
+            Recovery::new(&cfg)
         };
 
-        let new_bytes_in_flight =
-            if self.bytes_in_flight != latest.bytes_in_flight {
-                self.bytes_in_flight = latest.bytes_in_flight;
-                emit_event = true;
-                Some(latest.bytes_in_flight)
-            } else {
-                None
-            };
+        let mut default = recovery(false);
+        let mut strict = recovery(true);
 
-        let new_ssthresh = if self.ssthresh != latest.ssthresh {
-            self.ssthresh = latest.ssthresh;
-            emit_event = true;
-            Some(latest.ssthresh)
-        } else {
-            None
+        assert!(!default.strict_rfc9002);
+        assert!(strict.strict_rfc9002);
+
+        #[cfg(feature = "qlog")]
+        match strict.parameters_to_qlog() {
+            EventData::RecoveryParametersSet(p) =>
+                assert_eq!(p.strict_rfc9002, Some(true)),
+
+            _ => unreachable!(),
+        }
+
+        let now = Instant::now();
+
+        // ACKs are reordered, so that a packet is declared lost spuriously.
+        for r in [&mut default, &mut strict] {
+            testing::PacketTrain::new(4, 1000).send(r, now);
+
+            let res = testing::AckScript::new()
+                .after(Duration::from_millis(10))
+                .ack(2..4)
+                .ack(0..2)
+                .run(r, now);
+            assert_eq!(res.lost, [(1, 1000), (0, 0)]);
+            assert_eq!(r.lost_spurious_count, 1);
+        }
+
+        // Only the default mode adapts the packet threshold.
+        assert_eq!(default.pkt_thresh, 4);
+        assert_eq!(strict.pkt_thresh, INITIAL_PACKET_THRESHOLD);
+
+        // CUBIC reduces the congestion window less than NewReno on loss.
+        let initial_cwnd = INITIAL_WINDOW_PACKETS * strict.max_datagram_size();
+        assert_eq!(default.cwnd(), initial_cwnd * 7 / 10);
+        assert_eq!(strict.cwnd(), initial_cwnd / 2);
+
+        // On PTO, the default mode retransmits the frames of the oldest packet
+        // in flight in a single probe, while RFC 9002 sends two probes with
+        // new data, or PINGs.
+        let mut now = now + Duration::from_millis(20);
+
+        for r in [&mut default, &mut strict] {
+            r.on_packet_sent(
+                testing::sent_packet(4, testing::stream_frames(4, 1), now),
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+            );
+        }
+
+        now = default.loss_detection_timer().unwrap();
+        assert_eq!(strict.loss_detection_timer(), Some(now));
+
+        for r in [&mut default, &mut strict] {
+            r.on_loss_detection_timeout(HandshakeStatus::default(), now);
+            assert_eq!(r.pto_count, 1);
+        }
+
+        assert_eq!(default.loss_probes[packet::EPOCH_APPLICATION], 1);
+        assert_eq!(default.lost[packet::EPOCH_APPLICATION].len(), 1);
+
+        assert_eq!(strict.loss_probes[packet::EPOCH_APPLICATION], 2);
+        assert!(strict.lost[packet::EPOCH_APPLICATION].is_empty());
+    }
+
+    #[test]
+    fn handshake_unreachable() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.set_max_handshake_ptos(2);
+
+        let mut r = Recovery::new(&cfg);
+
+        let mut now = Instant::now();
+
+        // The client has yet to hear from the server.
+        let handshake_status = HandshakeStatus {
+            has_handshake_keys: false,
+            peer_verified_address: false,
+            completed: false,
+            amplification_limited: false,
         };
 
-        if emit_event {
-            // QVis can't use all these fields and they can be large.
-            return Some(EventData::MetricsUpdated(
-                qlog::events::quic::MetricsUpdated {
-                    min_rtt: new_min_rtt,
-                    smoothed_rtt: new_smoothed_rtt,
-                    latest_rtt: new_latest_rtt,
-                    rtt_variance: new_rttvar,
-                    pto_count: None,
-                    congestion_window: new_cwnd,
-                    bytes_in_flight: new_bytes_in_flight,
-                    ssthresh: new_ssthresh,
-                    packets_in_flight: None,
-                    pacing_rate: None,
-                },
-            ));
+        // Nothing the client sends ever gets through.
+        r.on_packet_sent(
+            testing::sent_packet(0, testing::stream_frames(0, 1), now),
+            packet::EPOCH_INITIAL,
+            handshake_status,
+            now,
+        );
+
+        let mut unreachable = Vec::new();
+
+        for _ in 0..2 {
+            now = r.loss_detection_timer().unwrap();
+
+            r.on_loss_detection_timeout(handshake_status, now);
+
+            unreachable.push(r.is_handshake_unreachable());
         }
 
-        None
-    }
-}
+        assert_eq!(unreachable, [false, true]);
+        assert_eq!(r.handshake_pto_count, 2);
+
+        // The second PTO still sends probes, but no more PTOs follow.
+        assert_eq!(r.loss_probes[packet::EPOCH_INITIAL], 2);
+        assert_eq!(r.loss_detection_timer(), None);
+
+        // The limit doesn't apply once the handshake completed.
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        testing::PacketTrain::new(4, testing::PACKET_SIZE)
+            .with_stream_frames(1)
+            .send(&mut r, now);
+
+        testing::AckScript::new()
+            .loss_detection_timeout()
+            .loss_detection_timeout()
+            .loss_detection_timeout()
+            .run(&mut r, now);
+
+        assert_eq!(r.pto_count, 3);
+        assert!(!r.is_handshake_unreachable());
+        assert!(r.loss_detection_timer().is_some());
+    }
+
+    #[test]
+    fn burst_losses() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_pacing(false);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        // Two bursts of 8 packets, an isolated packet, and a last burst of 4
+        // packets that is still going on.
+        let res = testing::AckScript::new()
+            .send(testing::PacketTrain::new(8, 1000))
+            .after(Duration::from_millis(5))
+            .send(testing::PacketTrain::new(8, 1000).starting_at(8))
+            .after(Duration::from_millis(5))
+            .send(testing::PacketTrain::new(1, 1000).starting_at(16))
+            .after(Duration::from_millis(5))
+            .send(
+                testing::PacketTrain::new(4, 1000)
+                    .starting_at(17)
+                    .spaced_by(Duration::from_micros(50)),
+            )
+            .run(&mut r, now);
+
+        let tags = r.sent[packet::EPOCH_APPLICATION]
+            .iter()
+            .map(|p| (p.burst_index, p.burst_size))
+            .collect::<Vec<_>>();
+
+        assert_eq!(tags[..8], [0, 1, 2, 3, 4, 5, 6, 7].map(|i| (i, 8)));
+        assert_eq!(tags[8..16], [0, 1, 2, 3, 4, 5, 6, 7].map(|i| (i, 8)));
+        assert_eq!(tags[16], (0, 1));
+        assert_eq!(tags[17..], [(0, 0), (1, 0), (2, 0), (3, 0)]);
+
+        // The tails of both full bursts are lost, along with a packet at the
+        // head of the second one, and the isolated packet.
+        let res = testing::AckScript::new()
+            .after(Duration::from_millis(20))
+            .ack_ranges(testing::range_set([0..6, 8..9, 10..14, 17..21]))
+            .run(&mut r, res.now);
+        assert_eq!(res.lost, [(6, 6000)]);
+
+        assert_eq!(r.burst_losses(), BurstLosses {
+            isolated: 1,
+            head: 1,
+            tail: 4,
+        });
+        assert_eq!(r.stats().burst_losses, r.burst_losses());
+    }
+
+    #[test]
+    fn phase_losses() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_pacing(false);
+        cfg.enable_hystart(false);
+        cfg.enable_adaptive_reorder_threshold(false);
+
+        let mut r = Recovery::new(&cfg);
+
+        let mut now = Instant::now();
+
+        assert_eq!(r.congestion_state(), CongestionState::SlowStart);
+
+        testing::send_packets(
+            &mut r,
+            (0..10).map(|pn| testing::sent_packet(pn, vec![], now)),
+            now,
+        );
+
+        now += Duration::from_millis(50);
+
+        // Packet 0 is lost, and packet 9 is left in flight.
+        testing::ack_packets(&mut r, &testing::range_set(Some(1..9)), now);
+
+        assert_eq!(r.phase_losses(), PhaseLosses {
+            slow_start: 1,
+            avoidance: 0,
+            recovery: 0,
+        });
+        assert_eq!(r.congestion_state(), CongestionState::Recovery);
+
+        testing::send_packets(
+            &mut r,
+            Some(testing::sent_packet(10, vec![], now)),
+            now,
+        );
+
+        now += Duration::from_millis(50);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        // Packet 9 is lost as recovery ends, but was sent in slow start.
+        testing::ack_packets(&mut r, &testing::range_set(Some(10..11)), now);
 
-    #[test]
-    fn lookup_cc_algo_ok() {
-        let algo = CongestionControlAlgorithm::from_str("reno").unwrap();
-        assert_eq!(algo, CongestionControlAlgorithm::Reno);
-    }
+        assert_eq!(r.phase_losses(), PhaseLosses {
+            slow_start: 2,
+            avoidance: 0,
+            recovery: 0,
+        });
+        assert_eq!(r.congestion_state(), CongestionState::CongestionAvoidance);
 
-    #[test]
-    fn lookup_cc_algo_bad() {
-        assert_eq!(
-            CongestionControlAlgorithm::from_str("???"),
-            Err(crate::Error::CongestionControl)
+        testing::send_packets(
+            &mut r,
+            (11..16).map(|pn| testing::sent_packet(pn, vec![], now)),
+            now,
         );
+
+        now += Duration::from_millis(50);
+
+        testing::ack_packets(&mut r, &testing::range_set(Some(12..16)), now);
+
+        assert_eq!(r.phase_losses(), PhaseLosses {
+            slow_start: 2,
+            avoidance: 1,
+            recovery: 0,
+        });
+        assert_eq!(r.congestion_state(), CongestionState::Recovery);
+        assert_eq!(r.stats().phase_losses, r.phase_losses());
     }
 
     #[test]
-    fn collapse_cwnd() {
+    fn discarded_epoch_timers() {
         let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
         cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
 
         let mut r = Recovery::new(&cfg);
 
-        // cwnd will be reset.
-        r.collapse_cwnd();
-        assert_eq!(r.cwnd(), r.max_datagram_size * MINIMUM_WINDOW_PACKETS);
+        let mut now = Instant::now();
+
+        let handshake_status = HandshakeStatus {
+            has_handshake_keys: true,
+            peer_verified_address: true,
+            completed: false,
+            amplification_limited: false,
+        };
+
+        for epoch in [packet::EPOCH_INITIAL, packet::EPOCH_HANDSHAKE] {
+            r.on_packet_sent(
+                testing::sent_packet(0, testing::stream_frames(0, 1), now),
+                epoch,
+                handshake_status,
+                now,
+            );
+        }
+
+        r.on_pkt_num_space_discarded(
+            packet::EPOCH_INITIAL,
+            handshake_status,
+            now,
+        );
+
+        assert_eq!(r.inspect().discarded, [true, false, false]);
+
+        // Only the Handshake packet number space is probed from now on.
+        for _ in 0..3 {
+            now = r.loss_detection_timer().unwrap();
+
+            r.on_loss_detection_timeout(handshake_status, now);
+
+            assert_eq!(r.loss_probes[packet::EPOCH_INITIAL], 0);
+            assert!(r.loss_probes[packet::EPOCH_HANDSHAKE] > 0);
+            assert!(!r.should_elicit_ack(packet::EPOCH_INITIAL));
+        }
+
+        // Stale state of the Initial packet number space is ignored.
+        r.loss_time[packet::EPOCH_INITIAL] = Some(now);
+        r.time_of_last_sent_ack_eliciting_pkt[packet::EPOCH_INITIAL] = Some(now);
+        r.in_flight_count[packet::EPOCH_INITIAL] = 1;
+        r.loss_probes[packet::EPOCH_INITIAL] = 1;
+
+        assert_eq!(r.loss_time_and_space().0, None);
+        assert_eq!(
+            r.pto_time_and_space(handshake_status, now).1,
+            packet::EPOCH_HANDSHAKE
+        );
+        assert!(!r.should_elicit_ack(packet::EPOCH_INITIAL));
+
+        r.loss_time[packet::EPOCH_INITIAL] = None;
+        r.time_of_last_sent_ack_eliciting_pkt[packet::EPOCH_INITIAL] = None;
+        r.in_flight_count[packet::EPOCH_INITIAL] = 0;
+        r.loss_probes[packet::EPOCH_INITIAL] = 0;
+
+        // Once the handshake packet number spaces are gone, the PTO isn't
+        // armed for them anymore.
+        r.on_pkt_num_space_discarded(
+            packet::EPOCH_HANDSHAKE,
+            handshake_status,
+            now,
+        );
+
+        assert_eq!(
+            r.pto_time_and_space(handshake_status, now),
+            (None, packet::EPOCH_HANDSHAKE)
+        );
     }
 
     #[test]
-    fn loss_on_pto() {
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "packet sent in discarded epoch 0")]
+    fn discarded_epoch_packet_sent() {
         let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
         cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
 
         let mut r = Recovery::new(&cfg);
 
-        let mut now = Instant::now();
-
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 0);
-
-        // Start by sending a few packets.
-        let p = Sent {
-            pkt_num: 0,
-            frames: vec![],
-            time_sent: now,
-            time_acked: None,
-            time_lost: None,
-            size: 1000,
-            ack_eliciting: true,
-            in_flight: true,
-            delivered: 0,
-            delivered_time: now,
-            first_sent_time: now,
-            is_app_limited: false,
-            has_data: false,
-        };
+        let now = Instant::now();
 
-        r.on_packet_sent(
-            p,
-            packet::EPOCH_APPLICATION,
+        r.on_pkt_num_space_discarded(
+            packet::EPOCH_INITIAL,
             HandshakeStatus::default(),
             now,
-            "",
         );
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 1);
-        assert_eq!(r.bytes_in_flight, 1000);
-
-        let p = Sent {
-            pkt_num: 1,
-            frames: vec![],
-            time_sent: now,
-            time_acked: None,
-            time_lost: None,
-            size: 1000,
-            ack_eliciting: true,
-            in_flight: true,
-            delivered: 0,
-            delivered_time: now,
-            first_sent_time: now,
-            is_app_limited: false,
-            has_data: false,
-        };
 
         r.on_packet_sent(
-            p,
-            packet::EPOCH_APPLICATION,
+            testing::sent_packet(0, vec![], now),
+            packet::EPOCH_INITIAL,
             HandshakeStatus::default(),
             now,
-            "",
         );
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 2);
-        assert_eq!(r.bytes_in_flight, 2000);
+    }
 
-        let p = Sent {
-            pkt_num: 2,
-            frames: vec![],
-            time_sent: now,
-            time_acked: None,
-            time_lost: None,
-            size: 1000,
-            ack_eliciting: true,
-            in_flight: true,
-            delivered: 0,
-            delivered_time: now,
-            first_sent_time: now,
-            is_app_limited: false,
-            has_data: false,
-        };
+    #[test]
+    fn reset_epoch_packet_sent() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
 
         r.on_packet_sent(
-            p,
-            packet::EPOCH_APPLICATION,
+            testing::sent_packet(0, vec![], now),
+            packet::EPOCH_INITIAL,
             HandshakeStatus::default(),
             now,
-            "",
         );
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 3);
-        assert_eq!(r.bytes_in_flight, 3000);
 
-        let p = Sent {
-            pkt_num: 3,
-            frames: vec![],
-            time_sent: now,
-            time_acked: None,
-            time_lost: None,
-            size: 1000,
-            ack_eliciting: true,
-            in_flight: true,
-            delivered: 0,
-            delivered_time: now,
-            first_sent_time: now,
-            is_app_limited: false,
-            has_data: false,
-        };
+        // Packets sent before the reset are forgotten, but the packet number
+        // space can still be used.
+        r.on_pkt_num_space_reset(
+            packet::EPOCH_INITIAL,
+            HandshakeStatus::default(),
+            now,
+        );
+        assert_eq!(r.inspect().discarded, [false, false, false]);
+        assert_eq!(r.bytes_in_flight, 0);
 
         r.on_packet_sent(
-            p,
-            packet::EPOCH_APPLICATION,
+            testing::sent_packet(1, vec![], now),
+            packet::EPOCH_INITIAL,
             HandshakeStatus::default(),
             now,
-            "",
         );
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 4);
-        assert_eq!(r.bytes_in_flight, 4000);
+        assert_eq!(r.sent[packet::EPOCH_INITIAL].len(), 1);
+        assert_eq!(r.bytes_in_flight, testing::PACKET_SIZE);
+    }
 
-        // Wait for 10ms.
-        now += Duration::from_millis(10);
+    #[test]
+    fn pto_anti_deadlock() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
 
-        // Only the first 2 packets are acked.
-        let mut acked = ranges::RangeSet::default();
-        acked.insert(0..2);
+        let mut r = Recovery::new(&cfg);
 
-        assert_eq!(
-            r.on_ack_received(
-                &acked,
-                25,
-                packet::EPOCH_APPLICATION,
-                HandshakeStatus::default(),
-                now,
-                ""
-            ),
-            Ok((0, 0))
-        );
+        let now = Instant::now();
 
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 2);
-        assert_eq!(r.bytes_in_flight, 2000);
-        assert_eq!(r.lost_count, 0);
+        // A client that hasn't had its address verified yet, with nothing in
+        // flight, still arms the PTO.
+        let mut handshake = HandshakeStatus {
+            has_handshake_keys: false,
+            peer_verified_address: false,
+            completed: false,
+            amplification_limited: false,
+        };
 
-        // Wait until loss detection timer expires.
-        now = r.loss_detection_timer().unwrap();
+        r.set_loss_detection_timer(handshake, now);
 
-        // PTO.
-        r.on_loss_detection_timeout(HandshakeStatus::default(), now, "");
-        assert_eq!(r.loss_probes[packet::EPOCH_APPLICATION], 1);
-        assert_eq!(r.lost_count, 0);
-        assert_eq!(r.pto_count, 1);
+        assert_eq!(r.bytes_in_flight, 0);
 
-        let p = Sent {
-            pkt_num: 4,
-            frames: vec![],
-            time_sent: now,
-            time_acked: None,
-            time_lost: None,
-            size: 1000,
-            ack_eliciting: true,
-            in_flight: true,
-            delivered: 0,
-            delivered_time: now,
-            first_sent_time: now,
-            is_app_limited: false,
-            has_data: false,
-        };
+        let timer = r.loss_detection_timer().unwrap();
 
-        r.on_packet_sent(
-            p,
-            packet::EPOCH_APPLICATION,
-            HandshakeStatus::default(),
+        let outcome = r.on_loss_detection_timeout_outcome(handshake, timer);
+
+        assert_eq!(outcome.kind, TimeoutKind::AntiDeadlock);
+        assert_eq!(outcome.epoch, Some(packet::EPOCH_INITIAL));
+        assert_eq!(outcome.lost_packets, 0);
+        assert_eq!(outcome.lost_bytes, 0);
+        assert_eq!(outcome.probes, r.loss_probes[packet::EPOCH_INITIAL]);
+        assert!(outcome.probes > 0);
+        assert_eq!(outcome.requeued_frames, 0);
+        assert_eq!(outcome.next_timer, r.loss_detection_timer());
+        assert!(outcome.next_timer.unwrap() > timer);
+
+        // Once Handshake keys are available, the probe proves address
+        // ownership instead.
+        handshake.has_handshake_keys = true;
+
+        let timer = r.loss_detection_timer().unwrap();
+
+        let outcome = r.on_loss_detection_timeout_outcome(handshake, timer);
+
+        assert_eq!(outcome.kind, TimeoutKind::AntiDeadlock);
+        assert_eq!(outcome.epoch, Some(packet::EPOCH_HANDSHAKE));
+        assert_eq!(outcome.probes, r.loss_probes[packet::EPOCH_HANDSHAKE]);
+    }
+
+    #[test]
+    fn seed_from_path_properties() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let mut now = Instant::now();
+
+        // Nothing is measured before the first RTT sample.
+        assert_eq!(r.export_path_properties(now), None);
+
+        testing::send_packets(
+            &mut r,
+            (0..10).map(|pn| {
+                testing::sent_packet(pn, testing::stream_frames(pn, 1), now)
+            }),
             now,
-            "",
         );
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 3);
-        assert_eq!(r.bytes_in_flight, 3000);
 
-        let p = Sent {
-            pkt_num: 5,
-            frames: vec![],
-            time_sent: now,
-            time_acked: None,
-            time_lost: None,
-            size: 1000,
-            ack_eliciting: true,
-            in_flight: true,
-            delivered: 0,
-            delivered_time: now,
-            first_sent_time: now,
-            is_app_limited: false,
-            has_data: false,
-        };
+        now += Duration::from_millis(50);
 
-        r.on_packet_sent(
-            p,
-            packet::EPOCH_APPLICATION,
-            HandshakeStatus::default(),
+        testing::ack_packets(&mut r, &testing::range_set(Some(0..10)), now);
+
+        let props = r.export_path_properties(now).unwrap();
+        assert_eq!(props.rtt, Duration::from_millis(50));
+        assert_eq!(props.rttvar, r.rttvar());
+        assert_eq!(props.cwnd, r.cwnd());
+        assert_eq!(props.measured_at, now);
+
+        // A connection that already sampled the RTT isn't seeded.
+        assert!(!r.seed_from_path_properties(&props, now));
+
+        let mds = r.max_datagram_size();
+        let initial_cwnd = r.max_datagram_size() * INITIAL_WINDOW_PACKETS;
+
+        for (cwnd, seeded_cwnd) in [
+            // Half of the window is used.
+            (30 * mds, 15 * mds),
+            // Up to a cap.
+            (1000 * mds, MAX_SEEDED_WINDOW_PACKETS * mds),
+            // But never less than the initial window.
+            (4 * mds, initial_cwnd),
+        ] {
+            let mut r = Recovery::new(&cfg);
+            assert_eq!(r.rtt(), INITIAL_RTT);
+
+            let props = PathProps { cwnd, ..props };
+            assert!(r.seed_from_path_properties(&props, now));
+
+            assert_eq!(r.rtt(), props.rtt);
+            assert_eq!(r.rttvar(), props.rttvar);
+            assert_eq!(r.cwnd(), seeded_cwnd);
+        }
+
+        // The seeded RTT is replaced by the first sample.
+        let mut r = Recovery::new(&cfg);
+        assert!(r.seed_from_path_properties(&props, now));
+
+        testing::send_packets(
+            &mut r,
+            Some(testing::sent_packet(0, testing::stream_frames(0, 1), now)),
             now,
-            "",
         );
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 4);
-        assert_eq!(r.bytes_in_flight, 4000);
-        assert_eq!(r.lost_count, 0);
 
-        // Wait for 10ms.
-        now += Duration::from_millis(10);
+        testing::ack_packets(
+            &mut r,
+            &testing::range_set(Some(0..1)),
+            now + Duration::from_millis(20),
+        );
+        assert_eq!(r.rtt(), Duration::from_millis(20));
+
+        // Stale properties are ignored.
+        let mut r = Recovery::new(&cfg);
+
+        let later = now + MAX_PATH_PROPS_AGE + Duration::from_secs(1);
+        assert!(!r.seed_from_path_properties(&props, later));
+
+        assert_eq!(r.rtt(), INITIAL_RTT);
+        assert_eq!(r.cwnd(), initial_cwnd);
+    }
+
+    #[test]
+    fn estimate_transfer_time() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_pacing(false);
+
+        let rtt = Duration::from_millis(100);
+
+        let assert_close = |estimate: Duration, rtts: f64| {
+            let expected = rtt.as_secs_f64() * rtts;
+
+            assert!(
+                (estimate.as_secs_f64() - expected).abs() < expected * 0.01,
+                "estimate {:?} expected {:?}",
+                estimate,
+                expected
+            );
+        };
+
+        let mut r = Recovery::new(&cfg);
+        r.smoothed_rtt = Some(rtt);
+
+        let mds = r.max_datagram_size();
+
+        assert_eq!(r.estimate_transfer_time(0), Duration::ZERO);
+
+        // Slow start: 10, 20 and 40 packets are sent in successive RTTs, and
+        // one more RTT is needed for the last ones to be acknowledged.
+        r.congestion_window = 10 * mds;
+        assert_close(r.estimate_transfer_time(70 * mds), 4.0);
 
-        // PTO packets are acked.
-        let mut acked = ranges::RangeSet::default();
-        acked.insert(4..6);
+        // Part of the first window.
+        assert_close(r.estimate_transfer_time(5 * mds), 1.5);
 
-        assert_eq!(
-            r.on_ack_received(
-                &acked,
-                25,
-                packet::EPOCH_APPLICATION,
-                HandshakeStatus::default(),
+        // The window stops growing at the slow start threshold: 10, 20, 20
+        // and 20 packets.
+        r.ssthresh = 20 * mds;
+        assert_close(r.estimate_transfer_time(70 * mds), 5.0);
+
+        // Congestion avoidance, limited by the window.
+        r.congestion_window = 20 * mds;
+        assert_close(r.estimate_transfer_time(100 * mds), 6.0);
+
+        // Limited by the delivery rate, sampled at 10 packets per RTT.
+        for app_limited in [false, true] {
+            let mut r = Recovery::new(&cfg);
+
+            let now = Instant::now();
+
+            r.delivery_rate_update_app_limited(app_limited);
+
+            testing::send_packets(
+                &mut r,
+                (0..10).map(|pn| {
+                    testing::sent_packet(pn, testing::stream_frames(pn, 1), now)
+                }),
                 now,
-                ""
-            ),
-            Ok((2, 2000))
-        );
+            );
 
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 4);
-        assert_eq!(r.bytes_in_flight, 0);
+            testing::ack_packets(
+                &mut r,
+                &testing::range_set(Some(0..10)),
+                now + rtt,
+            );
 
-        assert_eq!(r.lost_count, 2);
+            assert_eq!(r.rtt(), rtt);
+            assert_eq!(
+                r.delivery_rate(),
+                (10 * testing::PACKET_SIZE) as u64 * 1000 /
+                    rtt.as_millis() as u64
+            );
 
-        // Wait 1 RTT.
-        now += r.rtt();
+            r.congestion_window = 20 * testing::PACKET_SIZE;
+            r.ssthresh = r.congestion_window;
 
-        r.detect_lost_packets(packet::EPOCH_APPLICATION, now, "");
+            let estimate = r.estimate_transfer_time(100 * testing::PACKET_SIZE);
 
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 0);
+            // The delivery rate sampled while app-limited doesn't reflect the
+            // path, so the window is used instead.
+            if app_limited {
+                assert_close(estimate, 6.0);
+            } else {
+                assert_close(estimate, 11.0);
+            }
+        }
     }
 
     #[test]
-    fn loss_on_timer() {
+    fn idle_restart() {
         let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
         cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
 
+        let rtt = Duration::from_millis(50);
+
+        // Grows the congestion window in slow start for a few rounds.
+        let grow = |r: &mut Recovery, now: &mut Instant| {
+            let mut pn = 0;
+
+            for _ in 0..4 {
+                let count = (r.cwnd() / testing::PACKET_SIZE) as u64;
+
+                testing::send_packets(
+                    r,
+                    (pn..pn + count).map(|pn| {
+                        testing::sent_packet(
+                            pn,
+                            testing::stream_frames(pn, 1),
+                            *now,
+                        )
+                    }),
+                    *now,
+                );
+
+                *now += rtt;
+
+                testing::ack_packets(
+                    r,
+                    &testing::range_set(Some(pn..pn + count)),
+                    *now,
+                );
+
+                pn += count;
+            }
+
+            pn
+        };
+
         let mut r = Recovery::new(&cfg);
+        r.enable_events();
 
         let mut now = Instant::now();
 
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 0);
+        let initial_cwnd = r.cwnd();
 
-        // Start by sending a few packets.
-        let p = Sent {
-            pkt_num: 0,
-            frames: vec![],
-            time_sent: now,
-            time_acked: None,
-            time_lost: None,
-            size: 1000,
-            ack_eliciting: true,
-            in_flight: true,
-            delivered: 0,
-            delivered_time: now,
-            first_sent_time: now,
-            is_app_limited: false,
-            has_data: false,
-        };
+        let pn = grow(&mut r, &mut now);
 
-        r.on_packet_sent(
-            p,
-            packet::EPOCH_APPLICATION,
-            HandshakeStatus::default(),
-            now,
-            "",
-        );
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 1);
-        assert_eq!(r.bytes_in_flight, 1000);
+        let cwnd = r.cwnd();
+        let srtt = r.rtt();
+        assert!(cwnd > 4 * initial_cwnd);
+        assert_eq!(r.bytes_in_flight, 0);
 
-        let p = Sent {
-            pkt_num: 1,
-            frames: vec![],
-            time_sent: now,
-            time_acked: None,
-            time_lost: None,
-            size: 1000,
-            ack_eliciting: true,
-            in_flight: true,
-            delivered: 0,
-            delivered_time: now,
-            first_sent_time: now,
-            is_app_limited: false,
-            has_data: false,
-        };
+        r.drain_events().count();
 
-        r.on_packet_sent(
-            p,
-            packet::EPOCH_APPLICATION,
-            HandshakeStatus::default(),
+        // Nothing is sent for a minute.
+        now += Duration::from_secs(60);
+
+        testing::send_packets(
+            &mut r,
+            Some(testing::sent_packet(pn, testing::stream_frames(pn, 1), now)),
             now,
-            "",
         );
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 2);
-        assert_eq!(r.bytes_in_flight, 2000);
 
-        let p = Sent {
-            pkt_num: 2,
-            frames: vec![],
-            time_sent: now,
-            time_acked: None,
-            time_lost: None,
-            size: 1000,
-            ack_eliciting: true,
-            in_flight: true,
-            delivered: 0,
-            delivered_time: now,
-            first_sent_time: now,
-            is_app_limited: false,
-            has_data: false,
-        };
+        // The first burst is bounded by the initial window, while the RTT
+        // estimates are kept.
+        assert_eq!(r.cwnd(), initial_cwnd);
+        assert!(r.cwnd_available() < initial_cwnd);
+        assert!(r.ssthresh >= cwnd * 3 / 4);
+        assert_eq!(r.rtt(), srtt);
+
+        let restarts: Vec<CwndChange> = r
+            .drain_events()
+            .filter_map(|e| match e {
+                RecoveryEvent::IdleRestart(change) => Some(change),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(restarts.len(), 1);
+        assert_eq!(restarts[0].prev_cwnd, cwnd);
+        assert_eq!(restarts[0].cwnd, initial_cwnd);
 
-        r.on_packet_sent(
-            p,
-            packet::EPOCH_APPLICATION,
-            HandshakeStatus::default(),
-            now,
-            "",
-        );
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 3);
-        assert_eq!(r.bytes_in_flight, 3000);
+        // The window is halved for every PTO of idle time.
+        let mut r = Recovery::new(&cfg);
 
-        let p = Sent {
-            pkt_num: 3,
-            frames: vec![],
-            time_sent: now,
-            time_acked: None,
-            time_lost: None,
-            size: 1000,
-            ack_eliciting: true,
-            in_flight: true,
-            delivered: 0,
-            delivered_time: now,
-            first_sent_time: now,
-            is_app_limited: false,
-            has_data: false,
-        };
+        let mut now = Instant::now();
 
-        r.on_packet_sent(
-            p,
-            packet::EPOCH_APPLICATION,
-            HandshakeStatus::default(),
-            now,
-            "",
-        );
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 4);
-        assert_eq!(r.bytes_in_flight, 4000);
+        let pn = grow(&mut r, &mut now);
 
-        // Wait for 10ms.
-        now += Duration::from_millis(10);
+        let cwnd = r.cwnd();
+        let pto = r.pto();
 
-        // Only the first 2 packets and the last one are acked.
-        let mut acked = ranges::RangeSet::default();
-        acked.insert(0..2);
-        acked.insert(3..4);
+        now += pto * 3 + pto / 2;
 
-        assert_eq!(
-            r.on_ack_received(
-                &acked,
-                25,
-                packet::EPOCH_APPLICATION,
-                HandshakeStatus::default(),
-                now,
-                ""
-            ),
-            Ok((0, 0))
+        testing::send_packets(
+            &mut r,
+            Some(testing::sent_packet(pn, testing::stream_frames(pn, 1), now)),
+            now,
         );
+        assert_eq!(r.cwnd(), cmp::max(cwnd / 8, initial_cwnd));
+        assert!(r.cwnd() < cwnd);
 
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 2);
-        assert_eq!(r.bytes_in_flight, 1000);
-        assert_eq!(r.lost_count, 0);
+        // Shorter gaps, or disabling the restart, leave the window alone.
+        for (threshold, gap) in [(3, pto * 2), (0, Duration::from_secs(60))] {
+            cfg.set_idle_restart_threshold(threshold);
 
-        // Wait until loss detection timer expires.
-        now = r.loss_detection_timer().unwrap();
+            let mut r = Recovery::new(&cfg);
 
-        // Packet is declared lost.
-        r.on_loss_detection_timeout(HandshakeStatus::default(), now, "");
-        assert_eq!(r.loss_probes[packet::EPOCH_APPLICATION], 0);
+            let mut now = Instant::now();
 
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 2);
-        assert_eq!(r.bytes_in_flight, 0);
+            let pn = grow(&mut r, &mut now);
 
-        assert_eq!(r.lost_count, 1);
+            let cwnd = r.cwnd();
 
-        // Wait 1 RTT.
-        now += r.rtt();
+            now += gap;
 
-        r.detect_lost_packets(packet::EPOCH_APPLICATION, now, "");
+            testing::send_packets(
+                &mut r,
+                Some(testing::sent_packet(
+                    pn,
+                    testing::stream_frames(pn, 1),
+                    now,
+                )),
+                now,
+            );
+            assert_eq!(r.cwnd(), cwnd);
+        }
 
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 0);
-    }
+        cfg.set_idle_restart_threshold(3);
 
-    #[test]
-    fn loss_on_reordering() {
-        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
-        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        let restarts = |r: &mut Recovery| {
+            r.drain_events()
+                .filter(|e| matches!(e, RecoveryEvent::IdleRestart(_)))
+                .count()
+        };
 
+        // Idle time is counted from the last in-flight packet, even if it
+        // isn't ack-eliciting.
         let mut r = Recovery::new(&cfg);
+        r.enable_events();
 
         let mut now = Instant::now();
 
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 0);
+        let pn = grow(&mut r, &mut now);
+        let pto = r.pto();
 
-        // Start by sending a few packets.
-        let p = Sent {
-            pkt_num: 0,
-            frames: vec![],
-            time_sent: now,
-            time_acked: None,
-            time_lost: None,
-            size: 1000,
-            ack_eliciting: true,
-            in_flight: true,
-            delivered: 0,
-            delivered_time: now,
-            first_sent_time: now,
-            is_app_limited: false,
-            has_data: false,
-        };
+        now += pto * 2;
 
-        r.on_packet_sent(
-            p,
-            packet::EPOCH_APPLICATION,
-            HandshakeStatus::default(),
-            now,
-            "",
-        );
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 1);
-        assert_eq!(r.bytes_in_flight, 1000);
+        let mut padding = testing::sent_packet(pn, vec![], now);
+        padding.ack_eliciting = false;
+        testing::send_packets(&mut r, Some(padding), now);
+        testing::ack_packets(&mut r, &testing::range_set(Some(pn..pn + 1)), now);
+        assert_eq!(r.bytes_in_flight, 0);
 
-        let p = Sent {
-            pkt_num: 1,
-            frames: vec![],
-            time_sent: now,
-            time_acked: None,
-            time_lost: None,
-            size: 1000,
-            ack_eliciting: true,
-            in_flight: true,
-            delivered: 0,
-            delivered_time: now,
-            first_sent_time: now,
-            is_app_limited: false,
-            has_data: false,
-        };
+        now += pto * 2;
 
-        r.on_packet_sent(
-            p,
-            packet::EPOCH_APPLICATION,
-            HandshakeStatus::default(),
+        testing::send_packets(
+            &mut r,
+            Some(testing::sent_packet(
+                pn + 1,
+                testing::stream_frames(pn + 1, 1),
+                now,
+            )),
             now,
-            "",
         );
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 2);
-        assert_eq!(r.bytes_in_flight, 2000);
-
-        let p = Sent {
-            pkt_num: 2,
-            frames: vec![],
-            time_sent: now,
-            time_acked: None,
-            time_lost: None,
-            size: 1000,
-            ack_eliciting: true,
-            in_flight: true,
-            delivered: 0,
-            delivered_time: now,
-            first_sent_time: now,
-            is_app_limited: false,
-            has_data: false,
-        };
+        assert_eq!(restarts(&mut r), 0);
 
-        r.on_packet_sent(
-            p,
-            packet::EPOCH_APPLICATION,
-            HandshakeStatus::default(),
-            now,
-            "",
-        );
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 3);
-        assert_eq!(r.bytes_in_flight, 3000);
+        // Nothing is restarted while packets are still in flight.
+        let mut r = Recovery::new(&cfg);
+        r.enable_events();
 
-        let p = Sent {
-            pkt_num: 3,
-            frames: vec![],
-            time_sent: now,
-            time_acked: None,
-            time_lost: None,
-            size: 1000,
-            ack_eliciting: true,
-            in_flight: true,
-            delivered: 0,
-            delivered_time: now,
-            first_sent_time: now,
-            is_app_limited: false,
-            has_data: false,
-        };
+        let mut now = Instant::now();
 
-        r.on_packet_sent(
-            p,
-            packet::EPOCH_APPLICATION,
-            HandshakeStatus::default(),
+        let pn = grow(&mut r, &mut now);
+
+        testing::send_packets(
+            &mut r,
+            Some(testing::sent_packet(pn, testing::stream_frames(pn, 1), now)),
             now,
-            "",
         );
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 4);
-        assert_eq!(r.bytes_in_flight, 4000);
 
-        // Wait for 10ms.
-        now += Duration::from_millis(10);
+        let cwnd = r.cwnd();
 
-        // ACKs are reordered.
-        let mut acked = ranges::RangeSet::default();
-        acked.insert(2..4);
+        now += Duration::from_secs(60);
 
-        assert_eq!(
-            r.on_ack_received(
-                &acked,
-                25,
-                packet::EPOCH_APPLICATION,
-                HandshakeStatus::default(),
+        testing::send_packets(
+            &mut r,
+            Some(testing::sent_packet(
+                pn + 1,
+                testing::stream_frames(pn + 1, 1),
                 now,
-                ""
-            ),
-            Ok((1, 1000))
+            )),
+            now,
         );
+        assert_eq!(r.cwnd(), cwnd);
+        assert_eq!(restarts(&mut r), 0);
+    }
 
-        now += Duration::from_millis(10);
-
-        let mut acked = ranges::RangeSet::default();
-        acked.insert(0..2);
+    #[test]
+    fn loss_hold() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_loss_hold(true);
 
-        assert_eq!(r.pkt_thresh, INITIAL_PACKET_THRESHOLD);
+        let episodes = |r: &mut Recovery| {
+            r.drain_events()
+                .filter(|e| *e == RecoveryEvent::RecoveryStart)
+                .count()
+        };
 
-        assert_eq!(
-            r.on_ack_received(
-                &acked,
-                25,
-                packet::EPOCH_APPLICATION,
-                HandshakeStatus::default(),
+        // Packets 0 and 1 are lost by the packet threshold, which holds back
+        // the congestion response.
+        let lose = |r: &mut Recovery, now: Instant| {
+            testing::send_packets(
+                r,
+                (0..10).map(|pn| testing::sent_packet(pn, vec![], now)),
                 now,
-                ""
-            ),
-            Ok((0, 0))
+            );
+
+            let now = now + Duration::from_millis(50);
+
+            assert_eq!(
+                testing::ack_packets(r, &testing::range_set(Some(2..10)), now),
+                (2, 2 * testing::PACKET_SIZE)
+            );
+
+            assert!(r.held_loss.is_some());
+            assert_eq!(r.recovery_episode, None);
+            assert!(r.loss_detection_timer().is_some());
+
+            now
+        };
+
+        // Both lost packets are acked within the hold, so the window is never
+        // reduced.
+        let mut r = Recovery::new(&cfg);
+        r.enable_events();
+
+        let now = lose(&mut r, Instant::now());
+        let cwnd = r.cwnd();
+
+        let now = now + Duration::from_millis(5);
+
+        testing::ack_packets(&mut r, &testing::range_set(Some(0..1)), now);
+        assert!(r.held_loss.is_some());
+
+        testing::ack_packets(&mut r, &testing::range_set(Some(1..2)), now);
+        assert!(r.held_loss.is_none());
+
+        assert_eq!(r.lost_spurious_count, 2);
+        assert_eq!(r.cwnd(), cwnd);
+        assert_eq!(r.recovery_episode, None);
+        assert_eq!(r.loss_detection_timer(), None);
+        assert_eq!(episodes(&mut r), 0);
+
+        // Only one of the lost packets is acked within the hold, so the window
+        // is reduced once it expires, with an episode covering the packets
+        // sent before the loss only.
+        let mut r = Recovery::new(&cfg);
+        r.enable_events();
+
+        let start = lose(&mut r, Instant::now());
+        let cwnd = r.cwnd();
+
+        let now = start + Duration::from_millis(5);
+
+        testing::ack_packets(&mut r, &testing::range_set(Some(0..1)), now);
+
+        testing::send_packets(
+            &mut r,
+            (10..12).map(|pn| testing::sent_packet(pn, vec![], now)),
+            now,
         );
+        assert_eq!(r.cwnd(), cwnd);
 
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 4);
-        assert_eq!(r.bytes_in_flight, 0);
+        let now = r.loss_detection_timer().unwrap();
+        assert_eq!(now, start + r.rtt().mul_f64(LOSS_HOLD_RTT_FRACTION));
 
-        // Spurious loss.
-        assert_eq!(r.lost_count, 1);
-        assert_eq!(r.lost_spurious_count, 1);
+        let outcome = r
+            .on_loss_detection_timeout_outcome(testing::HANDSHAKE_COMPLETED, now);
+        assert_eq!(outcome.kind, TimeoutKind::LossHold);
+        assert_eq!(outcome.lost_packets, 0);
+        assert!(outcome.next_timer.unwrap() > now);
 
-        // Packet threshold was increased.
-        assert_eq!(r.pkt_thresh, 4);
+        assert!(r.held_loss.is_none());
+        assert!(r.cwnd() < cwnd);
+        assert_eq!(r.ssthresh, r.cwnd());
 
-        // Wait 1 RTT.
-        now += r.rtt();
+        let episode = r.recovery_episode.unwrap();
+        assert_eq!(episode.start_time, start);
+        assert_eq!(episode.largest_sent_pkt, [None, None, Some(9)]);
 
-        r.detect_lost_packets(packet::EPOCH_APPLICATION, now, "");
+        // The late ACK of the other lost packet doesn't undo the reduction,
+        // and the window isn't reduced again in the same episode.
+        let cwnd = r.cwnd();
 
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 0);
+        testing::ack_packets(&mut r, &testing::range_set(Some(1..2)), now);
+        assert_eq!(r.cwnd(), cwnd);
+
+        assert_eq!(episodes(&mut r), 1);
+
+        // Without the hold, the window is reduced right away.
+        cfg.enable_loss_hold(false);
+
+        let mut r = Recovery::new(&cfg);
+        r.enable_events();
+
+        let now = Instant::now();
+        let cwnd = r.cwnd();
+
+        testing::send_packets(
+            &mut r,
+            (0..10).map(|pn| testing::sent_packet(pn, vec![], now)),
+            now,
+        );
+
+        let now = now + Duration::from_millis(50);
+
+        testing::ack_packets(&mut r, &testing::range_set(Some(2..10)), now);
+
+        assert!(r.held_loss.is_none());
+        assert!(r.cwnd() < cwnd);
+        assert_eq!(episodes(&mut r), 1);
     }
 
     #[test]
-    fn pacing() {
+    fn loss_rates() {
         let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
-        cfg.set_cc_algorithm(CongestionControlAlgorithm::CUBIC);
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_adaptive_reorder_threshold(false);
 
         let mut r = Recovery::new(&cfg);
 
         let mut now = Instant::now();
+        let mut pn = 0;
+
+        // Sends 10 packets every 100ms, and acks them 50ms later, except for
+        // the first `lost` ones, which are lost.
+        let mut run = |r: &mut Recovery, steps: usize, lost: u64| {
+            for _ in 0..steps {
+                testing::send_packets(
+                    r,
+                    (pn..pn + 10).map(|pn| testing::sent_packet(pn, vec![], now)),
+                    now,
+                );
 
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 0);
+                testing::ack_packets(
+                    r,
+                    &testing::range_set(Some(pn + lost..pn + 10)),
+                    now + Duration::from_millis(50),
+                );
 
-        // send out first packet (a full initcwnd).
-        let p = Sent {
-            pkt_num: 0,
-            frames: vec![],
-            time_sent: now,
-            time_acked: None,
-            time_lost: None,
-            size: 12000,
-            ack_eliciting: true,
-            in_flight: true,
-            delivered: 0,
-            delivered_time: now,
-            first_sent_time: now,
-            is_app_limited: false,
-            has_data: false,
+                now += Duration::from_millis(100);
+                pn += 10;
+            }
         };
 
-        r.on_packet_sent(
-            p,
-            packet::EPOCH_APPLICATION,
-            HandshakeStatus::default(),
-            now,
-            "",
-        );
+        run(&mut r, 50, 0);
 
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 1);
-        assert_eq!(r.bytes_in_flight, 12000);
+        assert_eq!(r.loss_rate(Duration::from_secs(1)), 0.0);
+        assert_eq!(r.stats().loss_rates.sent_10s, 500);
 
-        // First packet will be sent out immediately.
-        assert_eq!(r.pacer.rate(), 0);
-        assert_eq!(r.get_packet_send_time(), now);
+        // A burst of losses half a second long.
+        run(&mut r, 5, 5);
 
-        // Wait 50ms for ACK.
-        now += Duration::from_millis(50);
+        let short = r.loss_rate(Duration::from_secs(1));
+        let long = r.loss_rate(Duration::from_secs(10));
 
-        let mut acked = ranges::RangeSet::default();
-        acked.insert(0..1);
+        assert_eq!(r.lost_count, 25);
+        assert!(short > 0.2, "{}", short);
+        assert!(long < 0.05, "{}", long);
 
-        assert_eq!(
-            r.on_ack_received(
-                &acked,
-                10,
-                packet::EPOCH_APPLICATION,
-                HandshakeStatus::default(),
-                now,
-                ""
-            ),
-            Ok((0, 0))
-        );
+        let rates = r.stats().loss_rates;
+        assert_eq!(rates.rate_1s(), short);
+        assert_eq!(rates.rate_10s(), long);
 
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 0);
-        assert_eq!(r.bytes_in_flight, 0);
-        assert_eq!(r.smoothed_rtt.unwrap(), Duration::from_millis(50));
+        // The short window forgets the burst once it's over, while the long
+        // one still accounts for it.
+        run(&mut r, 20, 0);
 
-        // 1 MSS increased.
-        assert_eq!(r.congestion_window, 12000 + 1200);
+        assert_eq!(r.loss_rate(Duration::from_secs(1)), 0.0);
 
-        // Send out second packet.
-        let p = Sent {
-            pkt_num: 1,
-            frames: vec![],
-            time_sent: now,
-            time_acked: None,
-            time_lost: None,
-            size: 6000,
-            ack_eliciting: true,
-            in_flight: true,
-            delivered: 0,
-            delivered_time: now,
-            first_sent_time: now,
-            is_app_limited: false,
-            has_data: false,
-        };
+        let rate = r.loss_rate(Duration::from_secs(10));
+        assert!(rate > 0.0 && rate < long, "{}", rate);
+        assert_eq!(r.stats().loss_rates.lost_10s, 25);
+    }
 
-        r.on_packet_sent(
-            p,
-            packet::EPOCH_APPLICATION,
-            HandshakeStatus::default(),
-            now,
-            "",
-        );
+    #[test]
+    fn trace_id() {
+        struct Capture;
 
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 1);
-        assert_eq!(r.bytes_in_flight, 6000);
+        std::thread_local! {
+            static LINES: std::cell::RefCell<Option<Vec<String>>> =
+                std::cell::RefCell::new(None);
+        }
 
-        // Pacing is not done during initial phase of connection.
-        assert_eq!(r.get_packet_send_time(), now);
+        impl log::Log for Capture {
+            fn enabled(&self, _: &log::Metadata) -> bool {
+                true
+            }
 
-        // Send the third packet out.
-        let p = Sent {
-            pkt_num: 2,
-            frames: vec![],
-            time_sent: now,
-            time_acked: None,
-            time_lost: None,
-            size: 6000,
-            ack_eliciting: true,
-            in_flight: true,
-            delivered: 0,
-            delivered_time: now,
-            first_sent_time: now,
-            is_app_limited: false,
-            has_data: false,
-        };
+            fn log(&self, record: &log::Record) {
+                LINES.with(|lines| {
+                    if let Some(lines) = &mut *lines.borrow_mut() {
+                        lines.push(record.args().to_string());
+                    }
+                });
+            }
 
-        r.on_packet_sent(
-            p,
-            packet::EPOCH_APPLICATION,
-            HandshakeStatus::default(),
+            fn flush(&self) {}
+        }
+
+        static LOGGER: Capture = Capture;
+
+        // Only lines logged by this thread are captured, as other tests run
+        // in parallel.
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+
+        LINES.with(|lines| *lines.borrow_mut() = Some(Vec::new()));
+
+        let cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+
+        let mut recovery_config = RecoveryConfig::from_config(&cfg);
+        recovery_config.trace_id = Arc::from("c0ffee");
+
+        let mut r = Recovery::new_with_config(&recovery_config);
+
+        let now = Instant::now();
+
+        testing::send_packets(
+            &mut r,
+            (0..5).map(|pn| testing::sent_packet(pn, vec![], now)),
             now,
-            "",
         );
 
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 2);
-        assert_eq!(r.bytes_in_flight, 12000);
+        testing::ack_packets(
+            &mut r,
+            &testing::range_set(Some(1..5)),
+            now + Duration::from_millis(50),
+        );
 
-        // Send the third packet out.
-        let p = Sent {
-            pkt_num: 3,
-            frames: vec![],
-            time_sent: now,
-            time_acked: None,
-            time_lost: None,
-            size: 1000,
-            ack_eliciting: true,
-            in_flight: true,
-            delivered: 0,
-            delivered_time: now,
-            first_sent_time: now,
-            is_app_limited: false,
-            has_data: false,
-        };
+        let lines = LINES.with(|lines| lines.borrow_mut().take().unwrap());
 
-        r.on_packet_sent(
-            p,
-            packet::EPOCH_APPLICATION,
-            HandshakeStatus::default(),
+        assert!(!lines.is_empty());
+        assert!(lines.iter().all(|line| line.starts_with("c0ffee ")));
+        assert!(lines
+            .iter()
+            .any(|line| line == "c0ffee packet newly acked 4"));
+        assert!(lines
+            .iter()
+            .any(|line| line == "c0ffee packet 0 lost on epoch 2"));
+    }
+
+    #[test]
+    fn idle_bounded_pto() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.set_initial_rtt(Duration::from_secs(2)).unwrap();
+        cfg.enable_pacing(false);
+        cfg.enable_idle_bounded_pto(true);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        // The probe timeout of 6 seconds exceeds the idle timeout.
+        r.set_idle_deadline(
+            Some(now + Duration::from_secs(5)),
+            testing::HANDSHAKE_COMPLETED,
             now,
-            "",
         );
 
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 3);
-        assert_eq!(r.bytes_in_flight, 13000);
+        testing::send_packets(
+            &mut r,
+            Some(testing::sent_packet(0, testing::stream_frames(0, 1), now)),
+            now,
+        );
 
-        // We pace this outgoing packet. as all conditions for pacing
-        // are passed.
-        let pacing_rate =
-            (r.congestion_window as f64 * PACING_MULTIPLIER / 0.05) as u64;
-        assert_eq!(r.pacer.rate(), pacing_rate);
+        // The probe is sent early enough for its ACK to arrive in time.
+        let timer = now + Duration::from_secs(3);
+
+        assert_eq!(r.loss_detection_timer(), Some(timer));
+        assert_eq!(r.stats().idle_timeout_conflicts, 1);
+
+        r.on_loss_detection_timeout(testing::HANDSHAKE_COMPLETED, timer);
+        assert_eq!(r.pto_count, 1);
 
+        // Only one probe is brought forward.
         assert_eq!(
-            r.get_packet_send_time(),
-            now + Duration::from_secs_f64(12000.0 / pacing_rate as f64)
+            r.loss_detection_timer(),
+            Some(now + Duration::from_secs(12))
+        );
+        assert_eq!(r.stats().idle_timeout_conflicts, 1);
+
+        // Until the idle timer restarts.
+        r.set_idle_deadline(
+            Some(timer + Duration::from_secs(5)),
+            testing::HANDSHAKE_COMPLETED,
+            timer,
+        );
+
+        assert_eq!(
+            r.loss_detection_timer(),
+            Some(timer + Duration::from_secs(3))
+        );
+        assert_eq!(r.stats().idle_timeout_conflicts, 2);
+
+        // Conflicts are still counted when the probe timeout isn't bounded.
+        cfg.enable_idle_bounded_pto(false);
+
+        let mut r = Recovery::new(&cfg);
+
+        r.set_idle_deadline(
+            Some(now + Duration::from_secs(5)),
+            testing::HANDSHAKE_COMPLETED,
+            now,
+        );
+
+        testing::send_packets(
+            &mut r,
+            Some(testing::sent_packet(0, testing::stream_frames(0, 1), now)),
+            now,
         );
+
+        assert_eq!(r.loss_detection_timer(), Some(now + Duration::from_secs(6)));
+        assert_eq!(r.stats().idle_timeout_conflicts, 1);
     }
 }
 
 mod bbr;
+mod clock;
 mod cubic;
 mod delivery_rate;
+#[cfg(test)]
+mod golden;
 mod hystart;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod lockstep;
+mod observer;
 mod pacer;
+mod pmtud;
 mod prr;
 mod reno;
+#[cfg(feature = "qlog")]
+pub mod replay;
+#[cfg(any(test, feature = "simulation"))]
+pub mod simulation;
+mod stats;
+#[doc(hidden)]
+pub mod testing;
+mod timer;
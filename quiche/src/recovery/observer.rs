@@ -0,0 +1,203 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Notifications of loss recovery events.
+//!
+//! While an observer is registered on a connection, `Recovery` records the
+//! packets it declares lost, the ones that turn out to be spuriously lost and
+//! changes of the congestion window. The connection then delivers them to the
+//! observer before returning from `recv()` and `on_timeout()`. Lost packets
+//! and congestion recovery episodes are also logged to qlog if enabled.
+//!
+//! The observer is also told when all data sent on a path was acknowledged,
+//! once per period of activity.
+
+use std::time::Duration;
+
+use crate::packet;
+use crate::time::Instant;
+
+/// A packet declared lost, or acknowledged after being declared lost.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LostPacket {
+    /// The packet number.
+    pub pkt_num: u64,
+
+    /// The type of the packet, which identifies its packet number space.
+    /// Packets carrying application data are `Short`.
+    pub pkt_type: packet::Type,
+
+    /// The size of the packet in bytes.
+    pub size: usize,
+
+    /// How long after being sent the packet was declared lost.
+    pub latency: Duration,
+}
+
+/// A change of the congestion window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CwndChange {
+    /// The previous size of the congestion window in bytes.
+    pub prev_cwnd: usize,
+
+    /// The new size of the congestion window in bytes.
+    pub cwnd: usize,
+
+    /// The slow start threshold in bytes.
+    pub ssthresh: usize,
+
+    /// The number of bytes in flight.
+    pub bytes_in_flight: usize,
+}
+
+/// Receives loss recovery events of a connection.
+///
+/// Methods are called synchronously from [`recv()`] and [`on_timeout()`],
+/// after the events happened, so they should return quickly. All methods do
+/// nothing by default.
+///
+/// [`recv()`]: struct.Connection.html#method.recv
+/// [`on_timeout()`]: struct.Connection.html#method.on_timeout
+pub trait RecoveryObserver: Send + Sync {
+    /// Called when a packet is declared lost.
+    fn on_packet_lost(&mut self, _lost: &LostPacket) {}
+
+    /// Called when a packet that was declared lost is acknowledged.
+    fn on_spurious_loss(&mut self, _lost: &LostPacket) {}
+
+    /// Called when the congestion window changes.
+    fn on_cwnd_change(&mut self, _change: &CwndChange) {}
+
+    /// Called when all data sent on a path was acknowledged, and neither
+    /// retransmissions nor probes are pending, with the time at which the
+    /// ACK that completed it was received.
+    ///
+    /// This is only called again once more data is sent and acknowledged.
+    fn on_all_acked(&mut self, _ack_time: Instant) {}
+}
+
+/// A loss recovery event recorded for a `RecoveryObserver`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecoveryEvent {
+    PacketLost(LostPacket, packet::Epoch),
+
+    SpuriousLoss(LostPacket),
+
+    CwndChange(CwndChange),
+
+    RecoveryStart,
+
+    RecoveryEnd,
+
+    AllAcked(Instant),
+
+    IdleRestart(CwndChange),
+}
+
+impl RecoveryEvent {
+    /// Delivers the event to `observer`.
+    pub fn notify(&self, observer: &mut dyn RecoveryObserver) {
+        match self {
+            RecoveryEvent::PacketLost(lost, _) => observer.on_packet_lost(lost),
+
+            RecoveryEvent::SpuriousLoss(lost) => observer.on_spurious_loss(lost),
+
+            RecoveryEvent::CwndChange(change) => observer.on_cwnd_change(change),
+
+            RecoveryEvent::AllAcked(ack_time) => observer.on_all_acked(*ack_time),
+
+            RecoveryEvent::RecoveryStart |
+            RecoveryEvent::RecoveryEnd |
+            RecoveryEvent::IdleRestart(_) => (),
+        }
+    }
+
+    /// Returns the qlog event corresponding to the event, if any.
+    #[cfg(feature = "qlog")]
+    pub fn to_qlog(self) -> Option<qlog::events::EventData> {
+        match self {
+            RecoveryEvent::PacketLost(lost, epoch) => {
+                let header = qlog::events::quic::PacketHeader::new(
+                    packet::Type::from_epoch(epoch).to_qlog(),
+                    lost.pkt_num,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                );
+
+                Some(qlog::events::EventData::PacketLost(
+                    qlog::events::quic::PacketLost {
+                        header: Some(header),
+                        frames: None,
+                        trigger: None,
+                        loss_latency: Some(lost.latency.as_secs_f32() * 1000.0),
+                    },
+                ))
+            },
+
+            RecoveryEvent::RecoveryStart =>
+                Some(qlog::events::EventData::CongestionStateUpdated(
+                    qlog::events::quic::CongestionStateUpdated {
+                        old: None,
+                        new: "recovery".to_string(),
+                        trigger: None,
+                    },
+                )),
+
+            RecoveryEvent::RecoveryEnd =>
+                Some(qlog::events::EventData::CongestionStateUpdated(
+                    qlog::events::quic::CongestionStateUpdated {
+                        old: Some("recovery".to_string()),
+                        new: "congestion_avoidance".to_string(),
+                        trigger: None,
+                    },
+                )),
+
+            RecoveryEvent::IdleRestart(change) => {
+                let new = if change.cwnd < change.ssthresh {
+                    "slow_start"
+                } else {
+                    "congestion_avoidance"
+                };
+
+                Some(qlog::events::EventData::CongestionStateUpdated(
+                    qlog::events::quic::CongestionStateUpdated {
+                        old: None,
+                        new: new.to_string(),
+                        trigger: Some(
+                            qlog::events::quic::CongestionStateUpdatedTrigger::IdleRestart,
+                        ),
+                    },
+                ))
+            },
+
+            _ => None,
+        }
+    }
+}
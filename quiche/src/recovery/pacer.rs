@@ -39,7 +39,8 @@
 //! still try to provide close timestamp if the send burst is implemented.
 
 use std::time::Duration;
-use std::time::Instant;
+
+use crate::time::Instant;
 
 #[derive(Debug)]
 pub struct Pacer {
@@ -74,6 +75,7 @@ pub struct Pacer {
 impl Pacer {
     pub fn new(
         enabled: bool, capacity: usize, rate: u64, max_datagram_size: usize,
+        now: Instant,
     ) -> Self {
         // Round capacity to MSS.
         let capacity = capacity / max_datagram_size * max_datagram_size;
@@ -87,9 +89,9 @@ impl Pacer {
 
             rate,
 
-            last_update: Instant::now(),
+            last_update: now,
 
-            next_time: Instant::now(),
+            next_time: now,
 
             max_datagram_size,
 
@@ -104,6 +106,24 @@ impl Pacer {
         self.enabled
     }
 
+    /// Returns the number of bytes left in the current burst, or `None` if
+    /// bursts are not limited.
+    pub fn burst_budget(&self) -> Option<usize> {
+        if !self.enabled || self.rate == 0 {
+            return None;
+        }
+
+        Some(self.capacity.saturating_sub(self.used))
+    }
+
+    /// Returns the timestamp for the next packet to be sent, if it were sent
+    /// at `now`.
+    ///
+    /// Unlike `next_time()`, this includes the interval added after a burst.
+    pub fn next_send_time(&self, now: Instant) -> Instant {
+        self.next_time.max(now) + self.iv
+    }
+
     /// Returns the current pacing rate.
     pub fn rate(&self) -> u64 {
         self.rate
@@ -197,10 +217,10 @@ mod tests {
         let max_burst = datagram_size * 10;
         let pacing_rate = 100_000;
 
-        let mut p = Pacer::new(true, max_burst, pacing_rate, datagram_size);
-
         let now = Instant::now();
 
+        let mut p = Pacer::new(true, max_burst, pacing_rate, datagram_size, now);
+
         // Send 6000 (half of max_burst) -> no timestamp change yet.
         p.send(6000, now);
 
@@ -230,10 +250,10 @@ mod tests {
         let max_burst = datagram_size * 10;
         let pacing_rate = 100_000;
 
-        let mut p = Pacer::new(true, max_burst, pacing_rate, datagram_size);
-
         let now = Instant::now();
 
+        let mut p = Pacer::new(true, max_burst, pacing_rate, datagram_size, now);
+
         // Send 6000 (half of max_burst) -> no timestamp change yet.
         p.send(6000, now);
 
@@ -0,0 +1,463 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Datagram Packetization Layer Path MTU Discovery (DPLPMTUD).
+//!
+//! This implementation is based on RFC 8899, as applied to QUIC by RFC 9000,
+//! Section 14.3:
+//!
+//! <https://www.rfc-editor.org/rfc/rfc8899.html>
+//!
+//! Probe packets of increasing sizes are sent, one at a time, and the largest
+//! acknowledged size becomes the new maximum datagram size. The search is a
+//! binary search between the currently confirmed size and the maximum size
+//! allowed by the local and remote endpoints. A size is considered to be
+//! unsupported by the path once `MAX_PROBES` probes of that size are lost.
+//!
+//! Once the search completes, it is restarted after `PMTU_RAISE_TIMER` to
+//! detect paths that started supporting larger datagrams. Consecutive losses
+//! of full-sized packets are treated as a black hole, in which case the
+//! datagram size falls back to `BASE_PLPMTU` and the search restarts.
+
+use std::cmp;
+use std::time::Duration;
+
+use crate::time::Instant;
+
+/// The datagram size that all QUIC paths are required to support.
+pub const BASE_PLPMTU: usize = 1200;
+
+/// The number of lost probes of the same size after which the size is
+/// considered to be unsupported by the path.
+const MAX_PROBES: usize = 3;
+
+/// The time after which a completed search is restarted.
+const PMTU_RAISE_TIMER: Duration = Duration::from_secs(600);
+
+/// DPLPMTUD states.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    /// Probing is disabled.
+    Disabled,
+
+    /// Only `BASE_PLPMTU` is confirmed, and the search hasn't started yet.
+    Base,
+
+    /// Probes are being sent to find a larger datagram size.
+    Search,
+
+    /// The search completed, waiting for the raise timer to expire.
+    SearchComplete,
+
+    /// A black hole was detected and the datagram size was reduced.
+    Error,
+}
+
+pub struct Pmtud {
+    state: State,
+
+    /// The largest datagram size allowed by both endpoints.
+    max_size: usize,
+
+    /// The largest confirmed datagram size.
+    pmtu: usize,
+
+    /// The largest datagram size that might still be supported by the path.
+    search_high: usize,
+
+    /// The size of the current probe.
+    probe_size: usize,
+
+    /// Whether a probe is currently in flight.
+    probe_in_flight: bool,
+
+    /// How many probes of the current size were lost.
+    probe_count: usize,
+
+    /// When the search should be restarted, after it completed.
+    raise_time: Option<Instant>,
+
+    /// How many full-sized packets were lost in a row.
+    black_hole_count: usize,
+
+    /// The sent time of the last acknowledged full-sized packet.
+    last_acked_time: Option<Instant>,
+}
+
+impl std::fmt::Debug for Pmtud {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "state={:?} ", self.state)?;
+        write!(f, "pmtu={} ", self.pmtu)?;
+        write!(f, "search_high={} ", self.search_high)?;
+        write!(f, "probe_size={} ", self.probe_size)?;
+        write!(f, "probe_count={}", self.probe_count)?;
+
+        Ok(())
+    }
+}
+
+impl Pmtud {
+    pub fn new(enabled: bool, max_size: usize) -> Self {
+        let (state, pmtu) = if enabled {
+            (State::Base, cmp::min(BASE_PLPMTU, max_size))
+        } else {
+            (State::Disabled, max_size)
+        };
+
+        Pmtud {
+            state,
+
+            max_size,
+
+            pmtu,
+
+            search_high: max_size,
+
+            probe_size: pmtu,
+
+            probe_in_flight: false,
+
+            probe_count: 0,
+
+            raise_time: None,
+
+            black_hole_count: 0,
+
+            last_acked_time: None,
+        }
+    }
+
+    /// Restarts probing from `BASE_PLPMTU`, e.g. after a path change.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.enabled(), self.max_size);
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.state != State::Disabled
+    }
+
+    /// Returns the largest confirmed datagram size.
+    pub fn pmtu(&self) -> usize {
+        self.pmtu
+    }
+
+    /// Lowers the largest datagram size allowed by the endpoints, e.g. once
+    /// the peer's `max_udp_payload_size` transport parameter is known.
+    pub fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = cmp::min(self.max_size, max_size);
+        self.search_high = cmp::min(self.search_high, self.max_size);
+
+        if self.enabled() {
+            self.pmtu = cmp::min(self.pmtu, self.max_size);
+        }
+    }
+
+    /// Returns the size of the probe to send, if one should be sent now.
+    pub fn probe_size(&mut self, now: Instant) -> Option<usize> {
+        match self.state {
+            State::Disabled => return None,
+
+            State::Base | State::Error => self.start_search(now),
+
+            State::SearchComplete => match self.raise_time {
+                Some(raise_time) if raise_time <= now => self.start_search(now),
+
+                _ => return None,
+            },
+
+            State::Search => (),
+        }
+
+        if self.state != State::Search || self.probe_in_flight {
+            return None;
+        }
+
+        Some(self.probe_size)
+    }
+
+    pub fn on_probe_sent(&mut self) {
+        self.probe_in_flight = true;
+    }
+
+    /// Processes the acknowledgment of a probe, returning the new datagram
+    /// size if it increased.
+    pub fn on_probe_acked(&mut self, size: usize, now: Instant) -> Option<usize> {
+        self.probe_in_flight = false;
+
+        if self.state != State::Search || size <= self.pmtu {
+            return None;
+        }
+
+        self.pmtu = cmp::min(size, self.max_size);
+        self.probe_count = 0;
+
+        self.update_search(now);
+
+        Some(self.pmtu)
+    }
+
+    pub fn on_probe_lost(&mut self, size: usize, now: Instant) {
+        self.probe_in_flight = false;
+
+        if self.state != State::Search || size != self.probe_size {
+            return;
+        }
+
+        self.probe_count += 1;
+
+        if self.probe_count >= MAX_PROBES {
+            self.search_high = size - 1;
+            self.probe_count = 0;
+        }
+
+        self.update_search(now);
+    }
+
+    /// Forgets about the probe in flight, without drawing any conclusion
+    /// about its size.
+    pub fn on_probe_abandoned(&mut self) {
+        self.probe_in_flight = false;
+    }
+
+    /// Processes the acknowledgment of a non-probe packet.
+    pub fn on_packet_acked(&mut self, size: usize, time_sent: Instant) {
+        if !self.enabled() || size <= BASE_PLPMTU {
+            return;
+        }
+
+        self.black_hole_count = 0;
+
+        self.last_acked_time = cmp::max(self.last_acked_time, Some(time_sent));
+    }
+
+    /// Processes the loss of a non-probe packet, returning the new datagram
+    /// size if a black hole was detected.
+    pub fn on_packet_lost(
+        &mut self, size: usize, time_sent: Instant,
+    ) -> Option<usize> {
+        if !self.enabled() || size <= BASE_PLPMTU || self.pmtu <= BASE_PLPMTU {
+            return None;
+        }
+
+        // Full-sized packets sent before the last acknowledged one can't be a
+        // sign of a black hole, as the path delivered a later one.
+        if let Some(last_acked_time) = self.last_acked_time {
+            if time_sent <= last_acked_time {
+                return None;
+            }
+        }
+
+        self.black_hole_count += 1;
+
+        if self.black_hole_count < MAX_PROBES {
+            return None;
+        }
+
+        self.state = State::Error;
+        self.pmtu = cmp::min(BASE_PLPMTU, self.max_size);
+        self.black_hole_count = 0;
+        self.last_acked_time = None;
+        self.probe_in_flight = false;
+
+        Some(self.pmtu)
+    }
+
+    fn start_search(&mut self, now: Instant) {
+        self.state = State::Search;
+        self.search_high = self.max_size;
+        self.probe_count = 0;
+        self.raise_time = None;
+
+        self.update_search(now);
+    }
+
+    fn update_search(&mut self, now: Instant) {
+        if self.pmtu >= self.search_high {
+            self.state = State::SearchComplete;
+            self.raise_time = Some(now + PMTU_RAISE_TIMER);
+
+            return;
+        }
+
+        self.probe_size = self.pmtu + (self.search_high - self.pmtu + 1) / 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Simulates a path supporting datagrams up to `path_mtu` bytes, and
+    // returns the number of probes sent until the search completed.
+    fn search(p: &mut Pmtud, path_mtu: usize, now: Instant) -> usize {
+        let mut probes = 0;
+
+        while let Some(size) = p.probe_size(now) {
+            p.on_probe_sent();
+
+            probes += 1;
+
+            if size <= path_mtu {
+                p.on_probe_acked(size, now);
+            } else {
+                p.on_probe_lost(size, now);
+            }
+        }
+
+        probes
+    }
+
+    #[test]
+    fn disabled() {
+        let mut p = Pmtud::new(false, 1500);
+
+        assert_eq!(p.state, State::Disabled);
+        assert_eq!(p.pmtu(), 1500);
+        assert_eq!(p.probe_size(Instant::now()), None);
+    }
+
+    #[test]
+    fn search_converges() {
+        let mut p = Pmtud::new(true, 1500);
+        let now = Instant::now();
+
+        assert_eq!(p.state, State::Base);
+        assert_eq!(p.pmtu(), BASE_PLPMTU);
+
+        assert!(search(&mut p, 1400, now) > 0);
+
+        assert_eq!(p.state, State::SearchComplete);
+        assert_eq!(p.pmtu(), 1400);
+    }
+
+    #[test]
+    fn search_max_size() {
+        let mut p = Pmtud::new(true, 1500);
+        let now = Instant::now();
+
+        p.set_max_size(1350);
+
+        search(&mut p, 9000, now);
+
+        assert_eq!(p.state, State::SearchComplete);
+        assert_eq!(p.pmtu(), 1350);
+    }
+
+    #[test]
+    fn one_probe_in_flight() {
+        let mut p = Pmtud::new(true, 1500);
+        let now = Instant::now();
+
+        let size = p.probe_size(now).unwrap();
+        p.on_probe_sent();
+
+        assert_eq!(p.probe_size(now), None);
+
+        // A single loss doesn't make the size unsupported.
+        p.on_probe_lost(size, now);
+
+        assert_eq!(p.probe_size(now), Some(size));
+        assert_eq!(p.pmtu(), BASE_PLPMTU);
+    }
+
+    #[test]
+    fn black_hole_path() {
+        let mut p = Pmtud::new(true, 1500);
+        let now = Instant::now();
+
+        search(&mut p, BASE_PLPMTU, now);
+
+        assert_eq!(p.state, State::SearchComplete);
+        assert_eq!(p.pmtu(), BASE_PLPMTU);
+    }
+
+    #[test]
+    fn raise_timer() {
+        let mut p = Pmtud::new(true, 1500);
+        let now = Instant::now();
+
+        search(&mut p, 1400, now);
+
+        assert_eq!(p.probe_size(now), None);
+
+        // The path now supports larger datagrams.
+        let now = now + PMTU_RAISE_TIMER;
+
+        assert!(search(&mut p, 1500, now) > 0);
+
+        assert_eq!(p.state, State::SearchComplete);
+        assert_eq!(p.pmtu(), 1500);
+    }
+
+    #[test]
+    fn black_hole_detection() {
+        let mut p = Pmtud::new(true, 1500);
+        let now = Instant::now();
+
+        search(&mut p, 1500, now);
+
+        assert_eq!(p.pmtu(), 1500);
+
+        p.on_packet_acked(1500, now);
+
+        // Losses of packets sent before the last acked one are ignored.
+        for _ in 0..MAX_PROBES {
+            assert_eq!(p.on_packet_lost(1500, now), None);
+        }
+
+        // The path stops delivering full-sized packets.
+        let now = now + Duration::from_millis(10);
+
+        for _ in 0..MAX_PROBES - 1 {
+            assert_eq!(p.on_packet_lost(1500, now), None);
+        }
+
+        assert_eq!(p.on_packet_lost(1500, now), Some(BASE_PLPMTU));
+
+        assert_eq!(p.state, State::Error);
+        assert_eq!(p.pmtu(), BASE_PLPMTU);
+
+        // The search is restarted on the new path MTU.
+        search(&mut p, 1280, now);
+
+        assert_eq!(p.state, State::SearchComplete);
+        assert_eq!(p.pmtu(), 1280);
+    }
+
+    #[test]
+    fn reset() {
+        let mut p = Pmtud::new(true, 1500);
+        let now = Instant::now();
+
+        search(&mut p, 1400, now);
+
+        p.reset();
+
+        assert_eq!(p.state, State::Base);
+        assert_eq!(p.pmtu(), BASE_PLPMTU);
+        assert!(p.probe_size(now).is_some());
+    }
+}
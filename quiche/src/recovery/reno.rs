@@ -29,10 +29,10 @@
 //! Note that Slow Start can use HyStart++ when enabled.
 
 use std::cmp;
-use std::time::Instant;
 
 use crate::packet;
 use crate::recovery;
+use crate::time::Instant;
 
 use crate::recovery::Acked;
 use crate::recovery::CongestionControlOps;
@@ -48,6 +48,7 @@ pub static RENO: CongestionControlOps = CongestionControlOps {
     checkpoint,
     rollback,
     has_custom_pacing,
+    in_slow_start,
     debug_fmt,
 };
 
@@ -70,9 +71,9 @@ fn on_packets_acked(
 fn on_packet_acked(
     r: &mut Recovery, packet: &Acked, epoch: packet::Epoch, now: Instant,
 ) {
-    r.bytes_in_flight = r.bytes_in_flight.saturating_sub(packet.size);
+    r.sub_bytes_in_flight(packet.size);
 
-    if r.in_congestion_recovery(packet.time_sent) {
+    if r.in_congestion_recovery(epoch, packet.pkt_num) {
         return;
     }
 
@@ -107,13 +108,13 @@ fn on_packet_acked(
 }
 
 fn congestion_event(
-    r: &mut Recovery, _lost_bytes: usize, time_sent: Instant,
+    r: &mut Recovery, _lost_bytes: usize, largest_lost_pkt: u64,
     epoch: packet::Epoch, now: Instant,
 ) {
     // Start a new congestion event if packet was sent after the
     // start of the previous congestion recovery period.
-    if !r.in_congestion_recovery(time_sent) {
-        r.congestion_recovery_start_time = Some(now);
+    if !r.in_congestion_recovery(epoch, largest_lost_pkt) {
+        r.start_recovery_episode(now);
 
         r.congestion_window = (r.congestion_window as f64 *
             recovery::LOSS_REDUCTION_FACTOR)
@@ -155,6 +156,10 @@ fn has_custom_pacing() -> bool {
     false
 }
 
+fn in_slow_start(r: &Recovery) -> bool {
+    r.congestion_window < r.ssthresh
+}
+
 fn debug_fmt(_r: &Recovery, _f: &mut std::fmt::Formatter) -> std::fmt::Result {
     Ok(())
 }
@@ -209,10 +214,16 @@ mod tests {
             ack_eliciting: true,
             in_flight: true,
             delivered: 0,
-            delivered_time: std::time::Instant::now(),
-            first_sent_time: std::time::Instant::now(),
+            delivered_time: Instant::now(),
+            first_sent_time: Instant::now(),
             is_app_limited: false,
-            has_data: false,
+            content: recovery::SentContent::default(),
+            is_pmtud_probe: false,
+            padding_bytes: 0,
+            datagram_id: None,
+            burst_index: 0,
+            burst_size: 0,
+            congestion_state: recovery::CongestionState::SlowStart,
         };
 
         // Send initcwnd full MSS packets to become no longer app limited
@@ -258,10 +269,16 @@ mod tests {
             ack_eliciting: true,
             in_flight: true,
             delivered: 0,
-            delivered_time: std::time::Instant::now(),
-            first_sent_time: std::time::Instant::now(),
+            delivered_time: Instant::now(),
+            first_sent_time: Instant::now(),
             is_app_limited: false,
-            has_data: false,
+            content: recovery::SentContent::default(),
+            is_pmtud_probe: false,
+            padding_bytes: 0,
+            datagram_id: None,
+            burst_index: 0,
+            burst_size: 0,
+            congestion_state: recovery::CongestionState::SlowStart,
         };
 
         // Send initcwnd full MSS packets to become no longer app limited
@@ -323,7 +340,7 @@ mod tests {
 
         r.congestion_event(
             r.max_datagram_size,
-            now,
+            0,
             packet::EPOCH_APPLICATION,
             now,
         );
@@ -347,7 +364,7 @@ mod tests {
         // Trigger congestion event to update ssthresh
         r.congestion_event(
             r.max_datagram_size,
-            now,
+            0,
             packet::EPOCH_APPLICATION,
             now,
         );
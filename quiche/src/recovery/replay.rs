@@ -0,0 +1,692 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Replay of qlog traces through loss recovery.
+//!
+//! The packets sent, the ACK frames received and the peer's transport
+//! parameters recorded in a qlog trace, as written by quiche itself, are fed
+//! to a local `Recovery` instance at their original times. Every recorded
+//! `metrics_updated` event is then compared with the local state, so that
+//! changes to loss recovery and congestion control can be checked against
+//! captures of real connections, and the first point where they behave
+//! differently can be found.
+//!
+//! The trace doesn't record everything the connection knows, so some of the
+//! connection's state is inferred:
+//!
+//! * The handshake status is derived from the packet types and frames that were
+//!   sent and received, e.g. the handshake is considered confirmed when a
+//!   HANDSHAKE_DONE frame is received.
+//!
+//! * The loss detection timer fires exactly when it expires.
+//!
+//! * The sizes of PADDING frames, whether the application was limited and path
+//!   MTU discovery probes are not recorded, so packets that carry PADDING
+//!   frames are replayed as if they were fully counted towards the bytes in
+//!   flight, and the application is never considered limited.
+
+use std::fmt;
+
+use std::time::Duration;
+
+use qlog::events::connectivity::TransportOwner;
+use qlog::events::quic::AckedRanges;
+use qlog::events::quic::MetricsUpdated;
+use qlog::events::quic::PacketReceived;
+use qlog::events::quic::PacketSent;
+use qlog::events::quic::PacketType;
+use qlog::events::quic::QuicFrame;
+use qlog::events::quic::TransportParametersSet;
+use qlog::events::Event;
+use qlog::events::EventData;
+use qlog::VantagePointType;
+
+use crate::packet;
+use crate::ranges;
+use crate::time::Instant;
+
+use crate::recovery::clock::Clock;
+use crate::recovery::clock::ManualClock;
+use crate::recovery::CongestionState;
+use crate::recovery::HandshakeStatus;
+use crate::recovery::Recovery;
+use crate::recovery::RecoveryConfig;
+use crate::recovery::Sent;
+use crate::recovery::SentContent;
+
+use crate::Config;
+use crate::TransportParams;
+
+/// The maximum difference between a recorded and a computed RTT metric, in
+/// milliseconds, for them to be considered equal.
+///
+/// Event times are recorded with limited precision, so RTTs computed from them
+/// can be slightly off.
+const RTT_TOLERANCE: f64 = 0.01;
+
+/// A metric recorded in `metrics_updated` events.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Metric {
+    /// The minimum RTT.
+    MinRtt,
+
+    /// The smoothed RTT.
+    SmoothedRtt,
+
+    /// The most recent RTT sample.
+    LatestRtt,
+
+    /// The RTT variation.
+    RttVariance,
+
+    /// The congestion window.
+    CongestionWindow,
+
+    /// The bytes in flight.
+    BytesInFlight,
+
+    /// The slow start threshold.
+    Ssthresh,
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Metric::MinRtt => "min_rtt",
+            Metric::SmoothedRtt => "smoothed_rtt",
+            Metric::LatestRtt => "latest_rtt",
+            Metric::RttVariance => "rtt_variance",
+            Metric::CongestionWindow => "congestion_window",
+            Metric::BytesInFlight => "bytes_in_flight",
+            Metric::Ssthresh => "ssthresh",
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// A recorded metric that differs from the locally computed one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence {
+    /// The time of the `metrics_updated` event, in milliseconds.
+    pub time: f32,
+
+    /// The metric that differs.
+    pub metric: Metric,
+
+    /// The recorded value. RTTs are in milliseconds, and sizes in bytes.
+    pub recorded: f64,
+
+    /// The locally computed value.
+    pub computed: f64,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.3}ms {} recorded={} computed={}",
+            self.time, self.metric, self.recorded, self.computed
+        )
+    }
+}
+
+/// The outcome of replaying a trace.
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    /// The number of sent packets that were replayed.
+    pub packets_sent: usize,
+
+    /// The number of ACK frames that were replayed.
+    pub acks_received: usize,
+
+    /// The number of times the loss detection timer fired.
+    pub loss_detection_timeouts: usize,
+
+    /// The number of recorded metrics compared with the local ones.
+    pub metrics_checked: usize,
+
+    /// The recorded metrics that differ from the local ones, in the order
+    /// they were found.
+    pub divergences: Vec<Divergence>,
+}
+
+impl Report {
+    /// Returns the first recorded metric that differs from the local one.
+    pub fn first_divergence(&self) -> Option<&Divergence> {
+        self.divergences.first()
+    }
+}
+
+/// Replays a JSON-SEQ qlog trace, as written by quiche, read from the given
+/// reader.
+///
+/// The trace's vantage point tells whether the connection was a client or a
+/// server. Loss recovery is configured from `config`, which should match the
+/// configuration of the recorded connection.
+pub fn replay_seq(
+    reader: Box<dyn std::io::BufRead + Send + Sync>, config: &Config,
+) -> qlog::Result<Report> {
+    let reader = qlog::reader::QlogSeqReader::new(reader)?;
+
+    let is_server =
+        reader.qlog.trace.vantage_point.ty == VantagePointType::Server;
+
+    Ok(replay(reader, is_server, config))
+}
+
+/// Replays the given qlog events, recorded by a client or a server.
+pub fn replay<I: IntoIterator<Item = Event>>(
+    events: I, is_server: bool, config: &Config,
+) -> Report {
+    let mut replay = Replay::new(is_server, config);
+
+    for ev in events {
+        replay.on_event(&ev);
+    }
+
+    replay.report
+}
+
+struct Replay {
+    r: Recovery,
+
+    clock: ManualClock,
+
+    start: Instant,
+
+    is_server: bool,
+
+    ack_delay_exponent: u64,
+
+    has_handshake_keys: bool,
+
+    peer_verified_address: bool,
+
+    completed: bool,
+
+    discarded: [bool; packet::EPOCH_COUNT],
+
+    report: Report,
+}
+
+impl Replay {
+    fn new(is_server: bool, config: &Config) -> Self {
+        let clock = ManualClock::new();
+        let start = clock.now();
+
+        let mut recovery_config = RecoveryConfig::from_config(config);
+        recovery_config.clock = Clock::Manual(clock.clone());
+
+        let mut r = Recovery::new_with_config(&recovery_config);
+        r.on_init();
+
+        Replay {
+            r,
+            clock,
+            start,
+            is_server,
+            ack_delay_exponent: TransportParams::default().ack_delay_exponent,
+            has_handshake_keys: false,
+            // Servers don't need to validate their own address.
+            peer_verified_address: is_server,
+            completed: false,
+            discarded: [false; packet::EPOCH_COUNT],
+            report: Report::default(),
+        }
+    }
+
+    fn handshake_status(&self) -> HandshakeStatus {
+        HandshakeStatus {
+            has_handshake_keys: self.has_handshake_keys &&
+                !self.discarded[packet::EPOCH_HANDSHAKE],
+
+            peer_verified_address: self.peer_verified_address,
+
+            completed: self.completed,
+
+            amplification_limited: false,
+        }
+    }
+
+    fn on_event(&mut self, ev: &Event) {
+        let time = Duration::from_secs_f64(f64::from(ev.time.max(0.0)) / 1000.0);
+        let now = self.start + time;
+
+        self.on_timeout(now);
+
+        self.clock.advance_to(now);
+
+        match &ev.data {
+            EventData::TransportParametersSet(params) =>
+                self.on_transport_params(params),
+
+            EventData::PacketSent(pkt) => self.on_packet_sent(pkt, now),
+
+            EventData::PacketReceived(pkt) => self.on_packet_received(pkt, now),
+
+            EventData::MetricsUpdated(metrics) =>
+                self.check_metrics(ev.time, metrics),
+
+            _ => (),
+        }
+    }
+
+    // Fires the loss detection timer until its next expiration is after `now`.
+    fn on_timeout(&mut self, now: Instant) {
+        while let Some(timer) = self.r.loss_detection_timer() {
+            if timer > now {
+                break;
+            }
+
+            self.clock.advance_to(timer);
+
+            let handshake_status = self.handshake_status();
+
+            self.r.on_loss_detection_timeout(handshake_status, timer);
+
+            self.clear_lost();
+
+            self.report.loss_detection_timeouts += 1;
+
+            if self.r.loss_detection_timer() == Some(timer) {
+                break;
+            }
+        }
+    }
+
+    fn on_transport_params(&mut self, params: &TransportParametersSet) {
+        if params.owner != Some(TransportOwner::Remote) {
+            return;
+        }
+
+        if let Some(v) = params.ack_delay_exponent {
+            self.ack_delay_exponent = u64::from(v);
+        }
+
+        if let Some(v) = params.max_ack_delay {
+            self.r.max_ack_delay = Duration::from_millis(u64::from(v));
+        }
+
+        if let Some(v) = params.max_udp_payload_size {
+            self.r.update_max_datagram_size(v as usize);
+        }
+    }
+
+    fn on_packet_sent(&mut self, pkt: &PacketSent, now: Instant) {
+        let epoch = match epoch(&pkt.header.packet_type) {
+            Some(v) => v,
+
+            None => return,
+        };
+
+        // The keys are assumed to be dropped earlier than they might have
+        // been, so late packets of the packet number space are skipped.
+        if self.discarded[epoch] {
+            return;
+        }
+
+        let frames = pkt.frames.as_deref().unwrap_or(&[]);
+
+        // Unlike the peer, the sender counts CONNECTION_CLOSE frames as
+        // ack-eliciting.
+        let ack_eliciting = frames
+            .iter()
+            .any(|f| !matches!(f, QuicFrame::Ack { .. } | QuicFrame::Padding));
+
+        let in_flight = ack_eliciting ||
+            frames.iter().any(|f| matches!(f, QuicFrame::Padding));
+
+        let content = SentContent {
+            crypto: frames.iter().any(|f| matches!(f, QuicFrame::Crypto { .. })),
+            stream: frames.iter().any(|f| matches!(f, QuicFrame::Stream { .. })),
+            ..Default::default()
+        };
+
+        let size = pkt.raw.as_ref().and_then(|r| r.length).unwrap_or(0) as usize;
+
+        if epoch == packet::EPOCH_HANDSHAKE {
+            self.has_handshake_keys = true;
+        }
+
+        // Clients only send 1-RTT packets once the handshake is complete,
+        // while servers complete it before sending HANDSHAKE_DONE.
+        if (!self.is_server && epoch == packet::EPOCH_APPLICATION) ||
+            frames.iter().any(|f| matches!(f, QuicFrame::HandshakeDone))
+        {
+            self.completed = true;
+        }
+
+        let sent = Sent {
+            pkt_num: pkt.header.packet_number,
+            frames: vec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: if ack_eliciting { size } else { 0 },
+            ack_eliciting,
+            in_flight,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            content,
+            is_pmtud_probe: false,
+            padding_bytes: 0,
+            datagram_id: None,
+            burst_index: 0,
+            burst_size: 0,
+            congestion_state: CongestionState::SlowStart,
+        };
+
+        let handshake_status = self.handshake_status();
+
+        self.r.on_packet_sent(sent, epoch, handshake_status, now);
+
+        self.report.packets_sent += 1;
+
+        // Clients drop Initial keys after sending a Handshake packet.
+        if !self.is_server && epoch == packet::EPOCH_HANDSHAKE {
+            self.discard(packet::EPOCH_INITIAL, now);
+        }
+    }
+
+    fn on_packet_received(&mut self, pkt: &PacketReceived, now: Instant) {
+        let epoch = match epoch(&pkt.header.packet_type) {
+            Some(v) => v,
+
+            None => return,
+        };
+
+        if epoch == packet::EPOCH_HANDSHAKE {
+            self.has_handshake_keys = true;
+        }
+
+        let mut handshake_confirmed = false;
+
+        for frame in pkt.frames.as_deref().unwrap_or(&[]) {
+            match frame {
+                QuicFrame::Ack {
+                    ack_delay,
+                    acked_ranges: Some(acked_ranges),
+                    ..
+                } => {
+                    if epoch == packet::EPOCH_HANDSHAKE {
+                        self.peer_verified_address = true;
+                    }
+
+                    if epoch == packet::EPOCH_APPLICATION && self.completed {
+                        self.peer_verified_address = true;
+
+                        handshake_confirmed = true;
+                    }
+
+                    self.on_ack_received(
+                        acked_ranges,
+                        ack_delay.unwrap_or(0.0),
+                        epoch,
+                        now,
+                    );
+                },
+
+                QuicFrame::HandshakeDone if !self.is_server => {
+                    self.peer_verified_address = true;
+
+                    handshake_confirmed = true;
+                },
+
+                _ => (),
+            }
+        }
+
+        if handshake_confirmed {
+            self.discard(packet::EPOCH_HANDSHAKE, now);
+        }
+
+        // Servers drop Initial keys after receiving a Handshake packet.
+        if self.is_server && epoch == packet::EPOCH_HANDSHAKE {
+            self.discard(packet::EPOCH_INITIAL, now);
+        }
+    }
+
+    fn on_ack_received(
+        &mut self, acked_ranges: &AckedRanges, ack_delay: f32,
+        epoch: packet::Epoch, now: Instant,
+    ) {
+        let mut ranges = ranges::RangeSet::default();
+
+        // Ranges are inclusive, and deserialized as single packet numbers or
+        // pairs alike.
+        match acked_ranges {
+            AckedRanges::Single(v) =>
+                for r in v {
+                    if let (Some(&start), Some(&end)) = (r.first(), r.last()) {
+                        ranges.insert(start..end + 1);
+                    }
+                },
+
+            AckedRanges::Double(v) =>
+                for &(start, end) in v {
+                    ranges.insert(start..end + 1);
+                },
+        }
+
+        if ranges.len() == 0 {
+            return;
+        }
+
+        // The ACK Delay field is recorded as is, before being scaled by the
+        // peer's exponent.
+        let ack_delay = (f64::from(ack_delay) * 1000.0).round() as u64;
+        let ack_delay = ack_delay << self.ack_delay_exponent;
+
+        let handshake_status = self.handshake_status();
+
+        let mut on_frame_acked = |_| ();
+
+        self.r
+            .on_ack_received(
+                &ranges,
+                ack_delay,
+                epoch,
+                handshake_status,
+                now,
+                Some(&mut on_frame_acked),
+            )
+            .ok();
+
+        self.clear_lost();
+
+        self.report.acks_received += 1;
+    }
+
+    fn discard(&mut self, epoch: packet::Epoch, now: Instant) {
+        if self.discarded[epoch] {
+            return;
+        }
+
+        self.discarded[epoch] = true;
+
+        let handshake_status = self.handshake_status();
+
+        self.r
+            .on_pkt_num_space_discarded(epoch, handshake_status, now);
+    }
+
+    // Frames are not replayed, so there is nothing to retransmit.
+    fn clear_lost(&mut self) {
+        for lost in self.r.lost.iter_mut() {
+            lost.clear();
+        }
+    }
+
+    fn check_metrics(&mut self, time: f32, metrics: &MetricsUpdated) {
+        let r = &self.r;
+
+        let rtts = [
+            (Metric::MinRtt, metrics.min_rtt, r.min_rtt),
+            (Metric::SmoothedRtt, metrics.smoothed_rtt, r.rtt()),
+            (Metric::LatestRtt, metrics.latest_rtt, r.latest_rtt),
+            (Metric::RttVariance, metrics.rtt_variance, r.rttvar),
+        ];
+
+        let sizes = [
+            (
+                Metric::CongestionWindow,
+                metrics.congestion_window,
+                r.cwnd(),
+            ),
+            (
+                Metric::BytesInFlight,
+                metrics.bytes_in_flight,
+                r.bytes_in_flight,
+            ),
+            (Metric::Ssthresh, metrics.ssthresh, r.ssthresh),
+        ];
+
+        let rtts = rtts.iter().filter_map(|&(metric, recorded, computed)| {
+            let recorded = f64::from(recorded?);
+            let computed = f64::from(computed.as_secs_f32() * 1000.0);
+
+            Some((metric, recorded, computed, RTT_TOLERANCE))
+        });
+
+        let sizes = sizes.iter().filter_map(|&(metric, recorded, computed)| {
+            Some((metric, recorded? as f64, computed as f64, 0.0))
+        });
+
+        for (metric, recorded, computed, tolerance) in rtts.chain(sizes) {
+            self.report.metrics_checked += 1;
+
+            if (recorded - computed).abs() > tolerance {
+                self.report.divergences.push(Divergence {
+                    time,
+                    metric,
+                    recorded,
+                    computed,
+                });
+            }
+        }
+    }
+}
+
+fn epoch(ty: &PacketType) -> Option<packet::Epoch> {
+    match ty {
+        PacketType::Initial => Some(packet::EPOCH_INITIAL),
+
+        PacketType::Handshake => Some(packet::EPOCH_HANDSHAKE),
+
+        PacketType::ZeroRtt | PacketType::OneRtt =>
+            Some(packet::EPOCH_APPLICATION),
+
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::recovery::CongestionControlAlgorithm;
+
+    // A client trace of a handshake followed by a short transfer, during which
+    // a packet is lost and the loss detection timer fires twice.
+    const TRACE: &str = "examples/replay.sqlog";
+
+    fn replay_trace(config: &Config) -> Report {
+        let file = std::fs::File::open(TRACE).unwrap();
+        let reader = std::io::BufReader::new(file);
+
+        replay_seq(Box::new(reader), config).unwrap()
+    }
+
+    #[test]
+    fn replay_matches() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config.set_cc_algorithm(CongestionControlAlgorithm::CUBIC);
+
+        let report = replay_trace(&config);
+
+        assert_eq!(report.packets_sent, 28);
+        assert_eq!(report.acks_received, 8);
+        assert_eq!(report.loss_detection_timeouts, 2);
+        assert!(report.metrics_checked > 0);
+        assert_eq!(report.first_divergence(), None);
+    }
+
+    #[test]
+    fn replay_diverges() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let report = replay_trace(&config);
+
+        // The trace was recorded with CUBIC, so the congestion window differs
+        // as soon as it is updated by ACKs of 1-RTT packets.
+        let first = report.first_divergence().unwrap();
+
+        assert_eq!(first.time, 41.198);
+        assert_eq!(first.metric, Metric::CongestionWindow);
+        assert_ne!(first.computed, first.recorded);
+    }
+
+    #[test]
+    fn replay_events() {
+        let config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+
+        let file = std::fs::File::open(TRACE).unwrap();
+        let reader = std::io::BufReader::new(file);
+        let reader = qlog::reader::QlogSeqReader::new(Box::new(reader)).unwrap();
+
+        // Corrupt the bytes in flight of the last recorded metrics.
+        let mut events: Vec<Event> = reader.collect();
+
+        let (time, bytes_in_flight) = events
+            .iter_mut()
+            .rev()
+            .find_map(|ev| match &mut ev.data {
+                EventData::MetricsUpdated(MetricsUpdated {
+                    bytes_in_flight: Some(v),
+                    ..
+                }) => {
+                    *v += 1;
+                    Some((ev.time, *v))
+                },
+
+                _ => None,
+            })
+            .unwrap();
+
+        let report = replay(events, false, &config);
+
+        assert_eq!(report.divergences, vec![Divergence {
+            time,
+            metric: Metric::BytesInFlight,
+            recorded: bytes_in_flight as f64,
+            computed: (bytes_in_flight - 1) as f64,
+        }]);
+    }
+}
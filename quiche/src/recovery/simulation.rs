@@ -0,0 +1,831 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Simulation of loss recovery and congestion control over a model link.
+//!
+//! A [`Scenario`] describes a bottleneck [`Link`], with its bandwidth,
+//! propagation delay, queue size, random loss and reordering, and how long to
+//! run for. Running a scenario drives one `Recovery` instance per flow, all
+//! sharing the same link, and returns a per-tick trace of each flow's state
+//! along with a [`Summary`] of the metrics that matter when comparing
+//! congestion controllers.
+//!
+//! Simulations are fully deterministic: time is simulated instead of being
+//! read from the system clock, and random events are drawn from a generator
+//! seeded by the scenario.
+//!
+//! [`Scenario`]: struct.Scenario.html
+//! [`Link`]: struct.Link.html
+//! [`Summary`]: struct.Summary.html
+
+use std::cmp;
+
+use std::cmp::Reverse;
+
+use std::collections::BinaryHeap;
+use std::collections::VecDeque;
+
+use std::time::Duration;
+
+use crate::packet;
+use crate::ranges;
+use crate::time::Instant;
+
+use crate::recovery::clock::Clock;
+use crate::recovery::clock::ManualClock;
+use crate::recovery::testing::HANDSHAKE_COMPLETED;
+use crate::recovery::CongestionControlAlgorithm;
+use crate::recovery::CongestionState;
+use crate::recovery::Recovery;
+use crate::recovery::RecoveryConfig;
+use crate::recovery::Sent;
+use crate::recovery::SentContent;
+
+/// The default interval between simulation steps.
+pub const DEFAULT_TICK: Duration = Duration::from_millis(1);
+
+/// A bottleneck link between senders and receivers.
+///
+/// Packets are queued in front of the link and forwarded at the link's
+/// bandwidth, then reach the receiver after the propagation delay. ACKs
+/// travel back with the same propagation delay, but aren't subject to the
+/// bottleneck.
+#[derive(Clone, Debug)]
+pub struct Link {
+    /// The rate at which packets are forwarded, in bytes per second. It must
+    /// not be zero.
+    pub bandwidth: u64,
+
+    /// The one-way propagation delay.
+    pub delay: Duration,
+
+    /// The size of the queue in front of the link, in bytes. Packets that
+    /// don't fit in the queue are dropped.
+    pub queue_size: usize,
+
+    /// The probability of a packet being lost after it leaves the queue.
+    pub loss: f64,
+
+    /// The probability of a packet being delayed by `reorder_delay`, so that
+    /// it arrives after packets sent later.
+    pub reorder: f64,
+
+    /// The additional delay of reordered packets.
+    pub reorder_delay: Duration,
+
+    /// Changes of bandwidth over time, as pairs of time since the start of
+    /// the simulation and new bandwidth, in chronological order.
+    pub bandwidth_changes: Vec<(Duration, u64)>,
+}
+
+impl Link {
+    /// Creates a link that neither loses nor reorders packets.
+    pub fn new(bandwidth: u64, delay: Duration, queue_size: usize) -> Self {
+        Link {
+            bandwidth,
+            delay,
+            queue_size,
+            loss: 0.0,
+            reorder: 0.0,
+            reorder_delay: Duration::ZERO,
+            bandwidth_changes: Vec::new(),
+        }
+    }
+
+    /// Returns the bandwidth-delay product of the link, in bytes.
+    pub fn bdp(&self) -> usize {
+        (self.bandwidth as f64 * (self.delay * 2).as_secs_f64()) as usize
+    }
+}
+
+/// A simulation scenario.
+#[derive(Clone, Debug)]
+pub struct Scenario {
+    /// The link shared by all flows.
+    pub link: Link,
+
+    /// How long the simulation runs for.
+    pub duration: Duration,
+
+    /// The interval between simulation steps. Senders react to ACKs and
+    /// timers, and trace samples are taken, once per tick.
+    pub tick: Duration,
+
+    /// The seed of the random loss and reordering.
+    pub seed: u64,
+}
+
+impl Scenario {
+    /// Creates a scenario running over the given link for the given duration.
+    pub fn new(link: Link, duration: Duration) -> Self {
+        Scenario {
+            link,
+            duration,
+            tick: DEFAULT_TICK,
+            seed: 0,
+        }
+    }
+
+    /// A 10 Mbps link with a 40ms RTT behind a queue holding over 800ms of
+    /// traffic.
+    pub fn bufferbloat() -> Self {
+        let link = Link::new(1_250_000, Duration::from_millis(20), 1_000_000);
+
+        Scenario::new(link, Duration::from_secs(10))
+    }
+
+    /// A 20 Mbps link with a 60ms RTT and a queue of one BDP, that randomly
+    /// loses 1% of packets and reorders another 1%.
+    pub fn lossy_wireless() -> Self {
+        let mut link = Link::new(2_500_000, Duration::from_millis(30), 0);
+        link.queue_size = link.bdp();
+        link.loss = 0.01;
+        link.reorder = 0.01;
+        link.reorder_delay = Duration::from_millis(5);
+
+        Scenario::new(link, Duration::from_secs(10))
+    }
+
+    /// A 50 Mbps link with a 50ms RTT and a queue of one BDP, whose
+    /// bandwidth drops to 5 Mbps halfway through.
+    pub fn bandwidth_drop() -> Self {
+        let mut link = Link::new(6_250_000, Duration::from_millis(25), 0);
+        link.queue_size = link.bdp();
+        link.bandwidth_changes = vec![(Duration::from_secs(5), 625_000)];
+
+        Scenario::new(link, Duration::from_secs(10))
+    }
+
+    /// Runs the scenario with one bulk flow per congestion control algorithm
+    /// given, all starting at the same time.
+    ///
+    /// Reports are returned in the same order as the algorithms.
+    pub fn run(
+        &self, cc_algorithms: &[CongestionControlAlgorithm],
+    ) -> Vec<Report> {
+        let mut sim = Simulation::new(self, cc_algorithms);
+
+        while sim.elapsed() < self.duration {
+            sim.step();
+        }
+
+        sim.into_reports()
+    }
+}
+
+/// The state of a flow at the end of a simulation step.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sample {
+    /// The time since the start of the simulation.
+    pub time: Duration,
+
+    /// The congestion window, in bytes.
+    pub cwnd: usize,
+
+    /// The number of bytes in flight.
+    pub bytes_in_flight: usize,
+
+    /// The smoothed RTT.
+    pub rtt: Duration,
+
+    /// The number of packets declared lost so far.
+    pub lost_count: usize,
+
+    /// The number of bytes queued in front of the link, by all flows.
+    pub queued_bytes: usize,
+}
+
+/// Metrics summarizing a flow over a whole simulation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Summary {
+    /// The rate at which data reached the receiver, in bytes per second.
+    pub throughput: u64,
+
+    /// The 99th percentile of the time packets spent in the link's queue.
+    pub queuing_delay_p99: Duration,
+
+    /// The number of packets declared lost by the sender.
+    pub lost_count: usize,
+
+    /// The number of packets dropped by the link, either because the queue
+    /// was full or randomly.
+    pub dropped_count: usize,
+}
+
+/// The outcome of a flow in a simulation.
+#[derive(Clone, Debug)]
+pub struct Report {
+    /// The congestion control algorithm used by the flow.
+    pub cc_algorithm: CongestionControlAlgorithm,
+
+    /// The state of the flow after each simulation step.
+    pub trace: Vec<Sample>,
+
+    /// Metrics summarizing the flow.
+    pub summary: Summary,
+}
+
+// A deterministic pseudo-random number generator (SplitMix64).
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+
+        z ^ (z >> 31)
+    }
+
+    // Returns true with the given probability.
+    fn chance(&mut self, p: f64) -> bool {
+        p > 0.0 && ((self.next_u64() >> 11) as f64 / (1_u64 << 53) as f64) < p
+    }
+}
+
+// The queue in front of the link, and the link itself.
+struct Bottleneck {
+    link: Link,
+
+    bandwidth: u64,
+
+    // The index of the next change of bandwidth to apply.
+    next_change: usize,
+
+    // When the link is done forwarding the packets currently queued.
+    free_at: Instant,
+
+    // Departure time and size of each queued packet.
+    queue: VecDeque<(Instant, usize)>,
+
+    queued_bytes: usize,
+}
+
+impl Bottleneck {
+    fn new(link: &Link, now: Instant) -> Self {
+        Bottleneck {
+            link: link.clone(),
+            bandwidth: link.bandwidth,
+            next_change: 0,
+            free_at: now,
+            queue: VecDeque::new(),
+            queued_bytes: 0,
+        }
+    }
+
+    // Applies bandwidth changes and removes packets that have left the queue.
+    fn update(&mut self, elapsed: Duration, now: Instant) {
+        while let Some(&(at, bandwidth)) =
+            self.link.bandwidth_changes.get(self.next_change)
+        {
+            if at > elapsed {
+                break;
+            }
+
+            self.bandwidth = bandwidth;
+            self.next_change += 1;
+        }
+
+        while let Some(&(departure, size)) = self.queue.front() {
+            if departure > now {
+                break;
+            }
+
+            self.queued_bytes -= size;
+            self.queue.pop_front();
+        }
+    }
+
+    // Queues a packet arriving at the given time, returning when it will leave
+    // the queue and how long it will have waited, or `None` if it was dropped.
+    fn enqueue(
+        &mut self, size: usize, now: Instant,
+    ) -> Option<(Instant, Duration)> {
+        if self.queued_bytes + size > self.link.queue_size {
+            return None;
+        }
+
+        let start = cmp::max(self.free_at, now);
+
+        let departure =
+            start + Duration::from_secs_f64(size as f64 / self.bandwidth as f64);
+
+        self.free_at = departure;
+
+        self.queue.push_back((departure, size));
+        self.queued_bytes += size;
+
+        Some((departure, start - now))
+    }
+}
+
+struct Flow {
+    cc_algorithm: CongestionControlAlgorithm,
+
+    r: Recovery,
+
+    next_pkt_num: u64,
+
+    // Packets received by the peer, as reported in ACK frames.
+    received: ranges::RangeSet,
+
+    // The arrival time of the last packet received since the last ACK.
+    ack_pending: Option<Instant>,
+
+    delivered_bytes: u64,
+
+    queuing_delays: Vec<Duration>,
+
+    dropped_count: usize,
+
+    trace: Vec<Sample>,
+}
+
+// A packet scheduled to be released into the link, or to arrive at the
+// receiver: time, sequence number to keep the order of packets scheduled at
+// the same time, flow, packet number and size.
+type Scheduled = Reverse<(Instant, u64, usize, u64, usize)>;
+
+struct Simulation {
+    tick: Duration,
+
+    clock: ManualClock,
+
+    start: Instant,
+
+    rng: Rng,
+
+    bottleneck: Bottleneck,
+
+    flows: Vec<Flow>,
+
+    // Packets waiting for their pacing time.
+    paced: BinaryHeap<Scheduled>,
+
+    // Packets on their way to the receiver.
+    in_transit: BinaryHeap<Scheduled>,
+
+    scheduled_seq: u64,
+
+    // ACK frames on their way to the senders, in order of arrival.
+    acks: VecDeque<(Instant, usize, ranges::RangeSet)>,
+}
+
+impl Simulation {
+    fn new(
+        scenario: &Scenario, cc_algorithms: &[CongestionControlAlgorithm],
+    ) -> Self {
+        let clock = ManualClock::new();
+        let start = clock.now();
+
+        let flows = cc_algorithms
+            .iter()
+            .map(|&cc_algorithm| {
+                let mut config =
+                    crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+                config.set_cc_algorithm(cc_algorithm);
+
+                let mut recovery_config = RecoveryConfig::from_config(&config);
+                recovery_config.clock = Clock::Manual(clock.clone());
+
+                Flow {
+                    cc_algorithm,
+                    r: Recovery::new_with_config(&recovery_config),
+                    next_pkt_num: 0,
+                    received: ranges::RangeSet::new(crate::MAX_ACK_RANGES),
+                    ack_pending: None,
+                    delivered_bytes: 0,
+                    queuing_delays: Vec::new(),
+                    dropped_count: 0,
+                    trace: Vec::new(),
+                }
+            })
+            .collect();
+
+        Simulation {
+            tick: scenario.tick,
+            clock,
+            start,
+            rng: Rng(scenario.seed),
+            bottleneck: Bottleneck::new(&scenario.link, start),
+            flows,
+            paced: BinaryHeap::new(),
+            in_transit: BinaryHeap::new(),
+            scheduled_seq: 0,
+            acks: VecDeque::new(),
+        }
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.clock.now() - self.start
+    }
+
+    fn step(&mut self) {
+        let now = self.clock.now();
+
+        self.bottleneck.update(self.elapsed(), now);
+
+        while let Some(&(arrival, ..)) = self.acks.front() {
+            if arrival > now {
+                break;
+            }
+
+            let (_, idx, ranges) = self.acks.pop_front().unwrap();
+
+            self.flows[idx]
+                .r
+                .on_ack_received(
+                    &ranges,
+                    0,
+                    packet::EPOCH_APPLICATION,
+                    HANDSHAKE_COMPLETED,
+                    now,
+                    None,
+                )
+                .unwrap();
+        }
+
+        for idx in 0..self.flows.len() {
+            let f = &mut self.flows[idx];
+
+            match f.r.loss_detection_timer() {
+                Some(timer) if timer <= now => {
+                    f.r.on_loss_detection_timeout(HANDSHAKE_COMPLETED, now);
+                },
+
+                _ => (),
+            }
+
+            // Lost frames would be retransmitted in new packets, but bulk
+            // flows always have new data to send anyway.
+            f.r.lost[packet::EPOCH_APPLICATION].clear();
+            f.r.lost_queued[packet::EPOCH_APPLICATION].clear();
+
+            self.send(idx, now);
+        }
+
+        while let Some(&Reverse((release, ..))) = self.paced.peek() {
+            if release > now {
+                break;
+            }
+
+            let Reverse((release, _, idx, pkt_num, size)) =
+                self.paced.pop().unwrap();
+
+            self.forward(idx, pkt_num, size, release);
+        }
+
+        while let Some(&Reverse((arrival, ..))) = self.in_transit.peek() {
+            if arrival > now {
+                break;
+            }
+
+            let Reverse((arrival, _, idx, pkt_num, size)) =
+                self.in_transit.pop().unwrap();
+
+            let f = &mut self.flows[idx];
+
+            f.received.insert(pkt_num..pkt_num + 1);
+            f.delivered_bytes += size as u64;
+            f.ack_pending = Some(arrival);
+        }
+
+        // The receivers acknowledge packets as soon as they arrive.
+        let delay = self.bottleneck.link.delay;
+
+        let mut acks: Vec<_> = self
+            .flows
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(idx, f)| {
+                f.ack_pending
+                    .take()
+                    .map(|arrival| (arrival + delay, idx, f.received.clone()))
+            })
+            .collect();
+
+        acks.sort_by_key(|&(arrival, idx, _)| (arrival, idx));
+        self.acks.extend(acks);
+
+        let time = self.elapsed();
+        let queued_bytes = self.bottleneck.queued_bytes;
+
+        for f in &mut self.flows {
+            f.trace.push(Sample {
+                time,
+                cwnd: f.r.cwnd(),
+                bytes_in_flight: f.r.bytes_in_flight,
+                rtt: f.r.rtt(),
+                lost_count: f.r.lost_count,
+                queued_bytes,
+            });
+        }
+
+        self.clock.advance(self.tick);
+    }
+
+    // Sends as many packets from the given flow as congestion control allows.
+    //
+    // Like applications using quiche, packets are built right away, but are
+    // only released into the link at the time given by the pacer.
+    fn send(&mut self, idx: usize, now: Instant) {
+        let f = &mut self.flows[idx];
+
+        loop {
+            let size = cmp::min(f.r.max_datagram_size(), f.r.cwnd_available());
+
+            if size == 0 {
+                break;
+            }
+
+            let pkt_num = f.next_pkt_num;
+            f.next_pkt_num += 1;
+
+            let pkt = Sent {
+                pkt_num,
+                frames: vec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                content: SentContent {
+                    stream: true,
+                    ..Default::default()
+                },
+                is_pmtud_probe: false,
+                padding_bytes: 0,
+                datagram_id: None,
+                burst_index: 0,
+                burst_size: 0,
+                congestion_state: CongestionState::SlowStart,
+            };
+
+            f.r.on_packet_sent(
+                pkt,
+                packet::EPOCH_APPLICATION,
+                HANDSHAKE_COMPLETED,
+                now,
+            );
+
+            let probes = &mut f.r.loss_probes[packet::EPOCH_APPLICATION];
+            *probes = probes.saturating_sub(1);
+
+            let release = cmp::max(f.r.get_packet_send_time(), now);
+
+            self.scheduled_seq += 1;
+
+            self.paced.push(Reverse((
+                release,
+                self.scheduled_seq,
+                idx,
+                pkt_num,
+                size,
+            )));
+        }
+    }
+
+    // Releases a packet into the link.
+    fn forward(&mut self, idx: usize, pkt_num: u64, size: usize, now: Instant) {
+        let f = &mut self.flows[idx];
+
+        let (departure, queuing_delay) = match self.bottleneck.enqueue(size, now)
+        {
+            Some(v) => v,
+
+            None => {
+                f.dropped_count += 1;
+                return;
+            },
+        };
+
+        f.queuing_delays.push(queuing_delay);
+
+        if self.rng.chance(self.bottleneck.link.loss) {
+            f.dropped_count += 1;
+            return;
+        }
+
+        let mut arrival = departure + self.bottleneck.link.delay;
+
+        if self.rng.chance(self.bottleneck.link.reorder) {
+            arrival += self.bottleneck.link.reorder_delay;
+        }
+
+        self.scheduled_seq += 1;
+
+        self.in_transit.push(Reverse((
+            arrival,
+            self.scheduled_seq,
+            idx,
+            pkt_num,
+            size,
+        )));
+    }
+
+    fn into_reports(self) -> Vec<Report> {
+        let elapsed = self.elapsed().as_secs_f64();
+
+        self.flows
+            .into_iter()
+            .map(|mut f| {
+                f.queuing_delays.sort_unstable();
+
+                let queuing_delay_p99 = match f.queuing_delays.len() {
+                    0 => Duration::ZERO,
+
+                    n => f.queuing_delays[(n * 99 + 99) / 100 - 1],
+                };
+
+                Report {
+                    cc_algorithm: f.cc_algorithm,
+                    trace: f.trace,
+                    summary: Summary {
+                        throughput: (f.delivered_bytes as f64 / elapsed) as u64,
+                        queuing_delay_p99,
+                        lost_count: f.r.lost_count,
+                        dropped_count: f.dropped_count,
+                    },
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CC_ALGORITHMS: [CongestionControlAlgorithm; 2] = [
+        CongestionControlAlgorithm::Reno,
+        CongestionControlAlgorithm::CUBIC,
+    ];
+
+    #[test]
+    fn rtt_matches_link_delay() {
+        let link = Link::new(100_000_000, Duration::from_millis(10), 1_000_000);
+
+        let mut scenario = Scenario::new(link, Duration::from_millis(200));
+        scenario.tick = Duration::from_micros(100);
+
+        for &cc in CC_ALGORITHMS.iter() {
+            let report = &scenario.run(&[cc])[0];
+
+            assert_eq!(report.trace.len(), 2000);
+            assert_eq!(
+                report.trace.last().unwrap().time,
+                Duration::from_micros(199_900)
+            );
+
+            // Before the queue builds up, the RTT is the round-trip
+            // propagation delay, plus up to one tick for the sender to notice
+            // the ACK.
+            let min_rtt = report.trace.iter().map(|s| s.rtt).min().unwrap();
+
+            assert!(min_rtt >= Duration::from_millis(20));
+            assert!(min_rtt <= Duration::from_micros(20_200));
+        }
+    }
+
+    #[test]
+    fn bufferbloat() {
+        let mut scenario = Scenario::bufferbloat();
+        scenario.duration = Duration::from_secs(3);
+
+        for &cc in CC_ALGORITHMS.iter() {
+            let summary = scenario.run(&[cc])[0].summary;
+
+            // Loss-based algorithms fill up the link, and its queue.
+            assert!(summary.throughput > scenario.link.bandwidth * 9 / 10);
+            assert!(summary.throughput <= scenario.link.bandwidth);
+            assert!(summary.queuing_delay_p99 > Duration::from_millis(100));
+        }
+    }
+
+    #[test]
+    fn lossy_wireless() {
+        let mut scenario = Scenario::lossy_wireless();
+        scenario.duration = Duration::from_secs(3);
+
+        let reports: Vec<_> = CC_ALGORITHMS
+            .iter()
+            .map(|&cc| scenario.run(&[cc]).remove(0))
+            .collect();
+
+        for report in &reports {
+            assert!(report.summary.dropped_count > 0);
+            assert!(report.summary.lost_count > 0);
+            assert!(report.summary.throughput < scenario.link.bandwidth);
+        }
+
+        // CUBIC recovers from random losses faster than Reno.
+        assert!(reports[1].summary.throughput > reports[0].summary.throughput);
+    }
+
+    #[test]
+    fn bandwidth_drop() {
+        let mut scenario = Scenario::bandwidth_drop();
+        scenario.duration = Duration::from_secs(4);
+        scenario.link.bandwidth_changes[0].0 = Duration::from_secs(2);
+
+        for &cc in CC_ALGORITHMS.iter() {
+            let report = &scenario.run(&[cc])[0];
+
+            let (before, after): (Vec<&Sample>, Vec<&Sample>) = report
+                .trace
+                .iter()
+                .partition(|s| s.time < Duration::from_secs(2));
+
+            let max_cwnd_before = before.iter().map(|s| s.cwnd).max().unwrap();
+
+            // The queue overflows after the drop, and the congestion window
+            // shrinks in response.
+            assert!(
+                after.last().unwrap().lost_count >
+                    before.last().unwrap().lost_count
+            );
+            assert!(after.iter().any(|s| s.cwnd < max_cwnd_before / 2));
+        }
+    }
+
+    #[test]
+    fn shared_link() {
+        let mut scenario = Scenario::bufferbloat();
+        scenario.duration = Duration::from_secs(3);
+
+        let reports = scenario.run(&CC_ALGORITHMS);
+
+        assert_eq!(reports.len(), 2);
+
+        let throughput: u64 = reports
+            .iter()
+            .map(|report| {
+                assert!(report.summary.throughput > 0);
+                report.summary.throughput
+            })
+            .sum();
+
+        assert!(throughput > scenario.link.bandwidth * 9 / 10);
+        assert!(throughput <= scenario.link.bandwidth);
+
+        // Both flows see the same queue.
+        assert_eq!(reports[0].trace.len(), reports[1].trace.len());
+        assert!(reports[0]
+            .trace
+            .iter()
+            .zip(reports[1].trace.iter())
+            .all(|(a, b)| a.queued_bytes == b.queued_bytes));
+    }
+
+    #[test]
+    fn deterministic() {
+        let mut scenario = Scenario::lossy_wireless();
+        scenario.duration = Duration::from_secs(2);
+        scenario.seed = 42;
+
+        let a = scenario.run(&CC_ALGORITHMS);
+        let b = scenario.run(&CC_ALGORITHMS);
+
+        for (a, b) in a.iter().zip(b.iter()) {
+            assert_eq!(a.cc_algorithm, b.cc_algorithm);
+            assert_eq!(a.trace, b.trace);
+            assert_eq!(a.summary, b.summary);
+        }
+
+        scenario.seed = 43;
+
+        let c = scenario.run(&CC_ALGORITHMS);
+
+        assert_ne!(a[0].summary, c[0].summary);
+    }
+}
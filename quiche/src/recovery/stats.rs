@@ -0,0 +1,1422 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Loss recovery statistics that can be read from other threads.
+//!
+//! The most frequently read values are published by `Recovery` into a small
+//! structure shared with any number of `RecoveryStatsHandle`, without the
+//! need to lock the connection. Values are updated using a sequence lock, so
+//! that readers always observe a consistent set of values, while the writer
+//! never waits for readers.
+
+use std::sync::atomic;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use std::time::Duration;
+
+use crate::frame;
+use crate::time::Instant;
+
+use super::CongestionState;
+
+/// The weight of each delivery rate sample in the throughput estimate.
+const THROUGHPUT_GAIN: f64 = 1.0 / 8.0;
+
+/// The time covered by each bucket of the loss rate windows.
+const LOSS_RATE_BUCKET: Duration = Duration::from_millis(200);
+
+/// The number of buckets of the loss rate windows, covering the longest
+/// window.
+const LOSS_RATE_BUCKETS: usize = 50;
+
+/// A snapshot of the loss recovery statistics of a path.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RecoveryStats {
+    /// The size of the congestion window in bytes.
+    pub cwnd: usize,
+
+    /// The number of bytes in flight.
+    pub bytes_in_flight: usize,
+
+    /// The smoothed round-trip time.
+    pub rtt: Duration,
+
+    /// The minimum round-trip time.
+    pub min_rtt: Duration,
+
+    /// The pacing rate in bytes/s.
+    pub pacing_rate: u64,
+
+    /// The most recent data delivery rate estimate in bytes/s.
+    pub delivery_rate: u64,
+
+    /// The number of packets that were lost.
+    pub lost_count: usize,
+
+    /// The number of bytes lost.
+    pub lost_bytes: u64,
+
+    /// The number of packets that were declared lost, but were acknowledged
+    /// afterwards.
+    pub spurious_lost_count: usize,
+
+    /// The slow start threshold in bytes.
+    pub ssthresh: usize,
+
+    /// The number of consecutive PTOs since the last acknowledgement.
+    pub pto_count: u32,
+
+    /// How long after being sent packets were declared lost.
+    pub loss_latency: LossLatency,
+
+    /// The work done processing ACK frames.
+    pub ack_cost: AckCost,
+
+    /// The lost bytes, by type of frame.
+    pub lost_frames: LostFrames,
+
+    /// An experimental estimate of how the round-trip delay splits between
+    /// the forward and the reverse path.
+    pub delay_asymmetry: DelayAsymmetry,
+
+    /// The round trips of the path.
+    pub rounds: Rounds,
+
+    /// Smoothed estimates of the available throughput and of the queuing
+    /// delay.
+    pub bandwidth_delay: BandwidthDelayEstimates,
+
+    /// The lost packets, by their position in the burst they were sent in.
+    pub burst_losses: BurstLosses,
+
+    /// The packets sent and lost over the last second and the last 10
+    /// seconds.
+    pub loss_rates: LossRates,
+
+    /// The lost packets, by the phase congestion control was in when they
+    /// were sent.
+    pub phase_losses: PhaseLosses,
+
+    /// The number of times the probe timeout was found to fire after the
+    /// connection would time out, e.g. because the idle timeout is too short
+    /// for the RTT of the path.
+    pub idle_timeout_conflicts: u64,
+}
+
+/// Statistics of the time between sending packets and declaring them lost.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LossLatency {
+    /// The number of lost packets accounted for.
+    pub count: u64,
+
+    /// The shortest latency.
+    pub min: Duration,
+
+    /// The longest latency.
+    pub max: Duration,
+
+    /// The sum of all latencies.
+    pub total: Duration,
+}
+
+impl LossLatency {
+    /// Accounts for a packet declared lost `latency` after being sent.
+    pub fn record(&mut self, latency: Duration) {
+        self.min = if self.count == 0 {
+            latency
+        } else {
+            self.min.min(latency)
+        };
+
+        self.max = self.max.max(latency);
+        self.total += latency;
+        self.count += 1;
+    }
+
+    /// Returns the mean latency, if any packet was lost.
+    pub fn mean(&self) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+
+        Some(Duration::from_nanos(
+            (self.total.as_nanos() / u128::from(self.count)) as u64,
+        ))
+    }
+}
+
+/// Statistics of the work done processing ACK frames.
+///
+/// The cost of an ACK frame is the number of ACK ranges, sent packets and
+/// acknowledged frames it required looking at, which grows quickly with
+/// fragmented ACK frames.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AckCost {
+    /// The number of ACK frames accounted for.
+    pub count: u64,
+
+    /// The number of ACK ranges processed.
+    pub ranges: u64,
+
+    /// The number of sent packets scanned.
+    pub scanned: u64,
+
+    /// The number of frames of newly acknowledged packets.
+    pub frames: u64,
+
+    /// The highest cost of a single ACK frame.
+    pub max: u64,
+}
+
+impl AckCost {
+    /// Accounts for an ACK frame.
+    pub fn record(&mut self, ranges: u64, scanned: u64, frames: u64) {
+        self.count += 1;
+        self.ranges += ranges;
+        self.scanned += scanned;
+        self.frames += frames;
+        self.max = self.max.max(ranges + scanned + frames);
+    }
+
+    /// Returns the total cost of the ACK frames accounted for.
+    pub fn total(&self) -> u64 {
+        self.ranges + self.scanned + self.frames
+    }
+}
+
+/// Lost bytes broken down by the type of frames that carried them.
+///
+/// Only the payload of frames is accounted for, e.g. the data of STREAM
+/// frames, and not packet headers or padding. ACK frames are not accounted
+/// for, as they are never retransmitted as such.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LostFrames {
+    /// The number of bytes of STREAM frames lost.
+    pub stream: u64,
+
+    /// The number of bytes of CRYPTO frames lost.
+    pub crypto: u64,
+
+    /// The number of bytes of DATAGRAM frames lost.
+    pub datagram: u64,
+
+    /// The number of bytes of other frames lost, e.g. MAX_DATA or
+    /// NEW_CONNECTION_ID.
+    pub control: u64,
+
+    /// The number of lost bytes that were never retransmitted, i.e. DATAGRAM
+    /// frames, and STREAM frames of streams that were closed or whose
+    /// retransmission was canceled in the meantime.
+    pub not_retransmitted: u64,
+}
+
+impl LostFrames {
+    /// Accounts for the frames of a lost packet.
+    pub fn record(&mut self, frames: &[frame::Frame]) {
+        for f in frames {
+            match f {
+                frame::Frame::StreamHeader { length, .. } =>
+                    self.stream += *length as u64,
+
+                frame::Frame::CryptoHeader { length, .. } =>
+                    self.crypto += *length as u64,
+
+                frame::Frame::DatagramHeader { length } => {
+                    self.datagram += *length as u64;
+                    self.not_retransmitted += *length as u64;
+                },
+
+                frame::Frame::Padding { .. } |
+                frame::Frame::ACK { .. } |
+                frame::Frame::ACKHeader { .. } => (),
+
+                _ => self.control += f.wire_len() as u64,
+            }
+        }
+    }
+
+    /// Returns the total number of lost bytes accounted for.
+    pub fn total(&self) -> u64 {
+        self.stream + self.crypto + self.datagram + self.control
+    }
+
+    /// Returns the fraction of lost bytes that were never retransmitted, if
+    /// any byte was lost.
+    pub fn not_retransmitted_ratio(&self) -> Option<f64> {
+        if self.total() == 0 {
+            return None;
+        }
+
+        Some(self.not_retransmitted as f64 / self.total() as f64)
+    }
+}
+
+/// An experimental estimate of how the round-trip delay splits between the
+/// forward and the reverse path, based on the latest RTT sample.
+///
+/// One-way delays can't be measured without synchronized clocks, so this is
+/// only a heuristic. The forward path is assumed to keep the delay it had when
+/// the network round-trip time was the lowest, i.e. half of it, and any delay
+/// beyond that is attributed to the reverse path. The raw components of the
+/// latest sample are included, so that other estimates can be derived.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DelayAsymmetry {
+    /// The latest RTT sample.
+    pub latest_rtt: Duration,
+
+    /// The ACK delay reported by the peer for the latest RTT sample.
+    pub peer_ack_delay: Duration,
+
+    /// How long the ACK frame of the latest RTT sample waited locally after
+    /// being received until it was processed. This is not part of the RTT
+    /// sample, which ends when the ACK frame is received.
+    pub local_delay: Duration,
+
+    /// The estimated forward path delay.
+    pub forward: Duration,
+
+    /// The estimated reverse path delay.
+    pub reverse: Duration,
+}
+
+impl DelayAsymmetry {
+    /// Returns the network round-trip time of the latest RTT sample, i.e.
+    /// without the peer's ACK delay.
+    pub fn network_rtt(&self) -> Duration {
+        self.latest_rtt.saturating_sub(self.peer_ack_delay)
+    }
+
+    /// Returns the estimated fraction of the network round-trip time spent on
+    /// the reverse path, if any RTT sample was taken.
+    pub fn reverse_ratio(&self) -> Option<f64> {
+        let total = self.forward + self.reverse;
+
+        if total.is_zero() {
+            return None;
+        }
+
+        Some(self.reverse.as_secs_f64() / total.as_secs_f64())
+    }
+}
+
+/// Statistics of the round trips of a path.
+///
+/// A round starts with the first packet sent after the previous one ended, and
+/// ends once that packet, or a later one, is acknowledged. Only packets of the
+/// application data packet number space are accounted for.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rounds {
+    /// The number of rounds that ended, which is also the number of the
+    /// current round, starting from 0.
+    pub count: u64,
+
+    /// The number of packets sent in the current round so far.
+    pub packets: u64,
+
+    /// The number of bytes sent in the current round so far.
+    pub bytes: u64,
+
+    /// The number of packets sent in the last round that ended.
+    pub last_packets: u64,
+
+    /// The number of bytes sent in the last round that ended.
+    pub last_bytes: u64,
+}
+
+impl Rounds {
+    /// Accounts for a packet sent in the current round.
+    pub fn record_sent(&mut self, size: usize) {
+        self.packets += 1;
+        self.bytes += size as u64;
+    }
+
+    /// Ends the current round.
+    pub fn end_round(&mut self) {
+        self.count += 1;
+
+        self.last_packets = self.packets;
+        self.last_bytes = self.bytes;
+
+        self.packets = 0;
+        self.bytes = 0;
+    }
+}
+
+/// Smoothed estimates of the available throughput and of the queuing delay of
+/// a path.
+///
+/// Unlike the congestion window and RTT samples, these move slowly, which
+/// suits decisions such as prioritizing HTTP/3 responses or choosing a media
+/// bitrate.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BandwidthDelayEstimates {
+    /// The estimated available throughput in bytes/s, or 0 until the delivery
+    /// rate is first sampled.
+    ///
+    /// This is an exponentially weighted moving average of the delivery rate
+    /// samples, each weighing 1/8. Samples taken while the application was
+    /// not using all the available bandwidth only count when they raise the
+    /// estimate.
+    pub throughput: u64,
+
+    /// The estimated queuing delay, i.e. how much the smoothed RTT exceeds the
+    /// minimum RTT of the last 5 minutes, or zero until the RTT is first
+    /// sampled.
+    pub queue_delay: Duration,
+}
+
+impl BandwidthDelayEstimates {
+    /// Accounts for a delivery rate sample, in bytes/s.
+    pub fn on_delivery_rate(&mut self, rate: u64, app_limited: bool) {
+        if app_limited && rate <= self.throughput {
+            return;
+        }
+
+        self.throughput = if self.throughput == 0 {
+            rate
+        } else {
+            (self.throughput as f64 * (1.0 - THROUGHPUT_GAIN) +
+                rate as f64 * THROUGHPUT_GAIN) as u64
+        };
+    }
+
+    /// Updates the queuing delay from new RTT estimates.
+    pub fn on_rtt_update(&mut self, smoothed_rtt: Duration, min_rtt: Duration) {
+        self.queue_delay = smoothed_rtt.saturating_sub(min_rtt);
+    }
+}
+
+/// Lost packets, by their position in the burst of packets they were sent in.
+///
+/// Packets of the same packet number space sent less than 100us apart belong
+/// to the same burst. Losses concentrated at the tail of bursts usually mean
+/// that the sender overflows a buffer on the path by sending too much at once,
+/// e.g. without pacing, rather than that the path is congested.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BurstLosses {
+    /// The number of lost packets that were sent alone.
+    pub isolated: u64,
+
+    /// The number of lost packets that were sent in the first half of a
+    /// burst.
+    pub head: u64,
+
+    /// The number of lost packets that were sent in the second half of a
+    /// burst.
+    pub tail: u64,
+}
+
+impl BurstLosses {
+    /// Accounts for a lost packet, given its position in the burst it was
+    /// sent in, and the size of the burst.
+    pub fn record(&mut self, index: usize, size: usize) {
+        if size <= 1 {
+            self.isolated += 1;
+        } else if index * 2 < size {
+            self.head += 1;
+        } else {
+            self.tail += 1;
+        }
+    }
+
+    /// Returns the fraction of the lost packets that were sent at the tail of
+    /// a burst, or `None` if no packet was lost.
+    pub fn tail_ratio(&self) -> Option<f64> {
+        let total = self.isolated + self.head + self.tail;
+
+        if total == 0 {
+            return None;
+        }
+
+        Some(self.tail as f64 / total as f64)
+    }
+}
+
+/// The number of lost packets, by the phase congestion control was in when
+/// they were sent.
+///
+/// Losses of packets sent in slow start usually mean that the initial window
+/// or the slow start exit point is too aggressive for the path.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PhaseLosses {
+    /// The number of lost packets sent in slow start.
+    pub slow_start: u64,
+
+    /// The number of lost packets sent in congestion avoidance.
+    pub avoidance: u64,
+
+    /// The number of lost packets sent in congestion recovery.
+    pub recovery: u64,
+}
+
+impl PhaseLosses {
+    /// Accounts for a lost packet, given the phase it was sent in.
+    pub fn record(&mut self, state: CongestionState) {
+        match state {
+            CongestionState::SlowStart => self.slow_start += 1,
+
+            CongestionState::CongestionAvoidance => self.avoidance += 1,
+
+            CongestionState::Recovery => self.recovery += 1,
+        }
+    }
+}
+
+/// The packets sent and declared lost over the last second and the last 10
+/// seconds of activity, i.e. ending with the last packet sent or declared
+/// lost.
+///
+/// Packets are accounted for when they are sent and when they are declared
+/// lost, so a loss can fall in a window that doesn't include the packet being
+/// sent.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LossRates {
+    /// The number of packets sent over the last second.
+    pub sent_1s: u64,
+
+    /// The number of packets declared lost over the last second.
+    pub lost_1s: u64,
+
+    /// The number of packets sent over the last 10 seconds.
+    pub sent_10s: u64,
+
+    /// The number of packets declared lost over the last 10 seconds.
+    pub lost_10s: u64,
+}
+
+impl LossRates {
+    /// Returns the fraction of packets lost over the last second.
+    pub fn rate_1s(&self) -> f64 {
+        loss_rate(self.sent_1s, self.lost_1s)
+    }
+
+    /// Returns the fraction of packets lost over the last 10 seconds.
+    pub fn rate_10s(&self) -> f64 {
+        loss_rate(self.sent_10s, self.lost_10s)
+    }
+}
+
+/// Counts of the packets sent and declared lost over sliding windows of up to
+/// 10 seconds.
+///
+/// Packets are counted in a ring of buckets of 200ms, so the memory used
+/// doesn't depend on the number of packets, and windows are rounded up to
+/// whole buckets. Windows end with the bucket of the last packet sent or
+/// declared lost.
+pub struct LossRateWindows {
+    origin: Instant,
+
+    // The number of the bucket last accounted for.
+    latest: u64,
+
+    buckets: [LossRateBucket; LOSS_RATE_BUCKETS],
+}
+
+#[derive(Clone, Copy, Default)]
+struct LossRateBucket {
+    // The number of the bucket since the origin, which tells whether it's
+    // still part of the windows.
+    index: u64,
+
+    sent: u32,
+
+    lost: u32,
+}
+
+impl LossRateWindows {
+    /// Creates empty windows, with buckets starting at `origin`.
+    pub fn new(origin: Instant) -> Self {
+        LossRateWindows {
+            origin,
+            latest: 0,
+            buckets: [LossRateBucket::default(); LOSS_RATE_BUCKETS],
+        }
+    }
+
+    /// Accounts for a packet sent at `now`.
+    pub fn on_packet_sent(&mut self, now: Instant) {
+        let bucket = self.bucket_mut(now);
+
+        bucket.sent = bucket.sent.saturating_add(1);
+    }
+
+    /// Accounts for a packet declared lost at `now`.
+    pub fn on_packet_lost(&mut self, now: Instant) {
+        let bucket = self.bucket_mut(now);
+
+        bucket.lost = bucket.lost.saturating_add(1);
+    }
+
+    /// Returns the number of packets sent and declared lost over `window`,
+    /// capped at 10 seconds.
+    pub fn counts(&self, window: Duration) -> (u64, u64) {
+        let len = window.as_nanos() / LOSS_RATE_BUCKET.as_nanos() +
+            u128::from(window.as_nanos() % LOSS_RATE_BUCKET.as_nanos() != 0);
+
+        let len = (len as u64).clamp(1, LOSS_RATE_BUCKETS as u64);
+
+        // Buckets last used before the window started are stale.
+        let first = (self.latest + 1).saturating_sub(len);
+
+        self.buckets
+            .iter()
+            .filter(|b| b.index >= first && b.index <= self.latest)
+            .fold((0, 0), |(sent, lost), b| {
+                (sent + u64::from(b.sent), lost + u64::from(b.lost))
+            })
+    }
+
+    /// Returns the fraction of packets lost over `window`, capped at 10
+    /// seconds.
+    pub fn loss_rate(&self, window: Duration) -> f64 {
+        let (sent, lost) = self.counts(window);
+
+        loss_rate(sent, lost)
+    }
+
+    /// Returns the counts of the last second and the last 10 seconds.
+    pub fn loss_rates(&self) -> LossRates {
+        let (sent_1s, lost_1s) = self.counts(Duration::from_secs(1));
+        let (sent_10s, lost_10s) = self.counts(Duration::from_secs(10));
+
+        LossRates {
+            sent_1s,
+            lost_1s,
+            sent_10s,
+            lost_10s,
+        }
+    }
+
+    // Returns the bucket of `now`, emptying it if it was last used for an
+    // earlier time.
+    fn bucket_mut(&mut self, now: Instant) -> &mut LossRateBucket {
+        let index = (now.saturating_duration_since(self.origin).as_nanos() /
+            LOSS_RATE_BUCKET.as_nanos()) as u64;
+
+        // Time going backward is accounted for in the latest bucket.
+        let index = index.max(self.latest);
+
+        self.latest = index;
+
+        let bucket =
+            &mut self.buckets[(index % LOSS_RATE_BUCKETS as u64) as usize];
+
+        if bucket.index != index {
+            *bucket = LossRateBucket {
+                index,
+                sent: 0,
+                lost: 0,
+            };
+        }
+
+        bucket
+    }
+}
+
+// Losses can be counted in a window that doesn't include the packets being
+// sent, so the rate is capped.
+fn loss_rate(sent: u64, lost: u64) -> f64 {
+    if lost == 0 {
+        return 0.0;
+    }
+
+    lost as f64 / sent.max(lost) as f64
+}
+
+#[derive(Default)]
+struct Shared {
+    // Odd while an update is in progress.
+    seq: AtomicU64,
+
+    cwnd: AtomicU64,
+
+    bytes_in_flight: AtomicU64,
+
+    rtt: AtomicU64,
+
+    min_rtt: AtomicU64,
+
+    pacing_rate: AtomicU64,
+
+    delivery_rate: AtomicU64,
+
+    lost_count: AtomicU64,
+
+    lost_bytes: AtomicU64,
+
+    spurious_lost_count: AtomicU64,
+
+    ssthresh: AtomicU64,
+
+    pto_count: AtomicU64,
+
+    loss_latency_count: AtomicU64,
+
+    loss_latency_min: AtomicU64,
+
+    loss_latency_max: AtomicU64,
+
+    loss_latency_total: AtomicU64,
+
+    ack_cost_count: AtomicU64,
+
+    ack_cost_ranges: AtomicU64,
+
+    ack_cost_scanned: AtomicU64,
+
+    ack_cost_frames: AtomicU64,
+
+    ack_cost_max: AtomicU64,
+
+    lost_stream_bytes: AtomicU64,
+
+    lost_crypto_bytes: AtomicU64,
+
+    lost_datagram_bytes: AtomicU64,
+
+    lost_control_bytes: AtomicU64,
+
+    lost_not_retransmitted_bytes: AtomicU64,
+
+    delay_latest_rtt: AtomicU64,
+
+    delay_peer_ack_delay: AtomicU64,
+
+    delay_local_delay: AtomicU64,
+
+    delay_forward: AtomicU64,
+
+    delay_reverse: AtomicU64,
+
+    round_count: AtomicU64,
+
+    round_packets: AtomicU64,
+
+    round_bytes: AtomicU64,
+
+    round_last_packets: AtomicU64,
+
+    round_last_bytes: AtomicU64,
+
+    bandwidth_delay_throughput: AtomicU64,
+
+    bandwidth_delay_queue_delay: AtomicU64,
+
+    burst_losses_isolated: AtomicU64,
+
+    burst_losses_head: AtomicU64,
+
+    burst_losses_tail: AtomicU64,
+
+    loss_rates_sent_1s: AtomicU64,
+
+    loss_rates_lost_1s: AtomicU64,
+
+    loss_rates_sent_10s: AtomicU64,
+
+    loss_rates_lost_10s: AtomicU64,
+
+    phase_losses_slow_start: AtomicU64,
+
+    phase_losses_avoidance: AtomicU64,
+
+    phase_losses_recovery: AtomicU64,
+
+    idle_timeout_conflicts: AtomicU64,
+}
+
+/// The writing side of the shared statistics, owned by `Recovery`.
+#[derive(Default)]
+pub struct StatsPublisher {
+    shared: Arc<Shared>,
+}
+
+impl StatsPublisher {
+    /// Returns whether there is any handle to read the statistics.
+    #[inline]
+    pub fn has_readers(&self) -> bool {
+        Arc::strong_count(&self.shared) > 1
+    }
+
+    /// Publishes new values.
+    ///
+    /// Only a single thread should publish values at any given time, which
+    /// is guaranteed by `Recovery` being the only owner of the publisher.
+    pub fn publish(&self, stats: &RecoveryStats) {
+        let s = &self.shared;
+
+        let seq = s.seq.load(Ordering::Relaxed);
+
+        s.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        atomic::fence(Ordering::Release);
+
+        s.cwnd.store(stats.cwnd as u64, Ordering::Relaxed);
+        s.bytes_in_flight
+            .store(stats.bytes_in_flight as u64, Ordering::Relaxed);
+        s.rtt.store(stats.rtt.as_nanos() as u64, Ordering::Relaxed);
+        s.min_rtt
+            .store(stats.min_rtt.as_nanos() as u64, Ordering::Relaxed);
+        s.pacing_rate.store(stats.pacing_rate, Ordering::Relaxed);
+        s.delivery_rate
+            .store(stats.delivery_rate, Ordering::Relaxed);
+        s.lost_count
+            .store(stats.lost_count as u64, Ordering::Relaxed);
+        s.lost_bytes.store(stats.lost_bytes, Ordering::Relaxed);
+        s.spurious_lost_count
+            .store(stats.spurious_lost_count as u64, Ordering::Relaxed);
+        s.ssthresh.store(stats.ssthresh as u64, Ordering::Relaxed);
+        s.pto_count
+            .store(u64::from(stats.pto_count), Ordering::Relaxed);
+        s.loss_latency_count
+            .store(stats.loss_latency.count, Ordering::Relaxed);
+        s.loss_latency_min
+            .store(stats.loss_latency.min.as_nanos() as u64, Ordering::Relaxed);
+        s.loss_latency_max
+            .store(stats.loss_latency.max.as_nanos() as u64, Ordering::Relaxed);
+        s.loss_latency_total.store(
+            stats.loss_latency.total.as_nanos() as u64,
+            Ordering::Relaxed,
+        );
+        s.ack_cost_count
+            .store(stats.ack_cost.count, Ordering::Relaxed);
+        s.ack_cost_ranges
+            .store(stats.ack_cost.ranges, Ordering::Relaxed);
+        s.ack_cost_scanned
+            .store(stats.ack_cost.scanned, Ordering::Relaxed);
+        s.ack_cost_frames
+            .store(stats.ack_cost.frames, Ordering::Relaxed);
+        s.ack_cost_max.store(stats.ack_cost.max, Ordering::Relaxed);
+        s.lost_stream_bytes
+            .store(stats.lost_frames.stream, Ordering::Relaxed);
+        s.lost_crypto_bytes
+            .store(stats.lost_frames.crypto, Ordering::Relaxed);
+        s.lost_datagram_bytes
+            .store(stats.lost_frames.datagram, Ordering::Relaxed);
+        s.lost_control_bytes
+            .store(stats.lost_frames.control, Ordering::Relaxed);
+        s.lost_not_retransmitted_bytes
+            .store(stats.lost_frames.not_retransmitted, Ordering::Relaxed);
+        s.delay_latest_rtt.store(
+            stats.delay_asymmetry.latest_rtt.as_nanos() as u64,
+            Ordering::Relaxed,
+        );
+        s.delay_peer_ack_delay.store(
+            stats.delay_asymmetry.peer_ack_delay.as_nanos() as u64,
+            Ordering::Relaxed,
+        );
+        s.delay_local_delay.store(
+            stats.delay_asymmetry.local_delay.as_nanos() as u64,
+            Ordering::Relaxed,
+        );
+        s.delay_forward.store(
+            stats.delay_asymmetry.forward.as_nanos() as u64,
+            Ordering::Relaxed,
+        );
+        s.delay_reverse.store(
+            stats.delay_asymmetry.reverse.as_nanos() as u64,
+            Ordering::Relaxed,
+        );
+        s.round_count.store(stats.rounds.count, Ordering::Relaxed);
+        s.round_packets
+            .store(stats.rounds.packets, Ordering::Relaxed);
+        s.round_bytes.store(stats.rounds.bytes, Ordering::Relaxed);
+        s.round_last_packets
+            .store(stats.rounds.last_packets, Ordering::Relaxed);
+        s.round_last_bytes
+            .store(stats.rounds.last_bytes, Ordering::Relaxed);
+        s.bandwidth_delay_throughput
+            .store(stats.bandwidth_delay.throughput, Ordering::Relaxed);
+        s.bandwidth_delay_queue_delay.store(
+            stats.bandwidth_delay.queue_delay.as_nanos() as u64,
+            Ordering::Relaxed,
+        );
+        s.burst_losses_isolated
+            .store(stats.burst_losses.isolated, Ordering::Relaxed);
+        s.burst_losses_head
+            .store(stats.burst_losses.head, Ordering::Relaxed);
+        s.burst_losses_tail
+            .store(stats.burst_losses.tail, Ordering::Relaxed);
+        s.loss_rates_sent_1s
+            .store(stats.loss_rates.sent_1s, Ordering::Relaxed);
+        s.loss_rates_lost_1s
+            .store(stats.loss_rates.lost_1s, Ordering::Relaxed);
+        s.loss_rates_sent_10s
+            .store(stats.loss_rates.sent_10s, Ordering::Relaxed);
+        s.loss_rates_lost_10s
+            .store(stats.loss_rates.lost_10s, Ordering::Relaxed);
+        s.phase_losses_slow_start
+            .store(stats.phase_losses.slow_start, Ordering::Relaxed);
+        s.phase_losses_avoidance
+            .store(stats.phase_losses.avoidance, Ordering::Relaxed);
+        s.phase_losses_recovery
+            .store(stats.phase_losses.recovery, Ordering::Relaxed);
+        s.idle_timeout_conflicts
+            .store(stats.idle_timeout_conflicts, Ordering::Relaxed);
+
+        s.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+
+    /// Returns a new handle to read the statistics.
+    pub fn handle(&self) -> RecoveryStatsHandle {
+        RecoveryStatsHandle {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+/// A handle to the loss recovery statistics of a path.
+///
+/// The handle can be cloned and sent to other threads, and reading from it
+/// doesn't require access to the connection. It is kept up to date as
+/// packets are sent, acknowledged and lost on the path.
+#[derive(Clone)]
+pub struct RecoveryStatsHandle {
+    shared: Arc<Shared>,
+}
+
+impl RecoveryStatsHandle {
+    /// Returns the latest published statistics.
+    pub fn load(&self) -> RecoveryStats {
+        let s = &self.shared;
+
+        loop {
+            let seq = s.seq.load(Ordering::Acquire);
+
+            // An update is in progress.
+            if seq & 1 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+
+            let stats = RecoveryStats {
+                cwnd: s.cwnd.load(Ordering::Relaxed) as usize,
+                bytes_in_flight: s.bytes_in_flight.load(Ordering::Relaxed)
+                    as usize,
+                rtt: Duration::from_nanos(s.rtt.load(Ordering::Relaxed)),
+                min_rtt: Duration::from_nanos(s.min_rtt.load(Ordering::Relaxed)),
+                pacing_rate: s.pacing_rate.load(Ordering::Relaxed),
+                delivery_rate: s.delivery_rate.load(Ordering::Relaxed),
+                lost_count: s.lost_count.load(Ordering::Relaxed) as usize,
+                lost_bytes: s.lost_bytes.load(Ordering::Relaxed),
+                spurious_lost_count: s.spurious_lost_count.load(Ordering::Relaxed)
+                    as usize,
+                ssthresh: s.ssthresh.load(Ordering::Relaxed) as usize,
+                pto_count: s.pto_count.load(Ordering::Relaxed) as u32,
+                loss_latency: LossLatency {
+                    count: s.loss_latency_count.load(Ordering::Relaxed),
+                    min: Duration::from_nanos(
+                        s.loss_latency_min.load(Ordering::Relaxed),
+                    ),
+                    max: Duration::from_nanos(
+                        s.loss_latency_max.load(Ordering::Relaxed),
+                    ),
+                    total: Duration::from_nanos(
+                        s.loss_latency_total.load(Ordering::Relaxed),
+                    ),
+                },
+                ack_cost: AckCost {
+                    count: s.ack_cost_count.load(Ordering::Relaxed),
+                    ranges: s.ack_cost_ranges.load(Ordering::Relaxed),
+                    scanned: s.ack_cost_scanned.load(Ordering::Relaxed),
+                    frames: s.ack_cost_frames.load(Ordering::Relaxed),
+                    max: s.ack_cost_max.load(Ordering::Relaxed),
+                },
+                lost_frames: LostFrames {
+                    stream: s.lost_stream_bytes.load(Ordering::Relaxed),
+                    crypto: s.lost_crypto_bytes.load(Ordering::Relaxed),
+                    datagram: s.lost_datagram_bytes.load(Ordering::Relaxed),
+                    control: s.lost_control_bytes.load(Ordering::Relaxed),
+                    not_retransmitted: s
+                        .lost_not_retransmitted_bytes
+                        .load(Ordering::Relaxed),
+                },
+                delay_asymmetry: DelayAsymmetry {
+                    latest_rtt: Duration::from_nanos(
+                        s.delay_latest_rtt.load(Ordering::Relaxed),
+                    ),
+                    peer_ack_delay: Duration::from_nanos(
+                        s.delay_peer_ack_delay.load(Ordering::Relaxed),
+                    ),
+                    local_delay: Duration::from_nanos(
+                        s.delay_local_delay.load(Ordering::Relaxed),
+                    ),
+                    forward: Duration::from_nanos(
+                        s.delay_forward.load(Ordering::Relaxed),
+                    ),
+                    reverse: Duration::from_nanos(
+                        s.delay_reverse.load(Ordering::Relaxed),
+                    ),
+                },
+                rounds: Rounds {
+                    count: s.round_count.load(Ordering::Relaxed),
+                    packets: s.round_packets.load(Ordering::Relaxed),
+                    bytes: s.round_bytes.load(Ordering::Relaxed),
+                    last_packets: s.round_last_packets.load(Ordering::Relaxed),
+                    last_bytes: s.round_last_bytes.load(Ordering::Relaxed),
+                },
+                bandwidth_delay: BandwidthDelayEstimates {
+                    throughput: s
+                        .bandwidth_delay_throughput
+                        .load(Ordering::Relaxed),
+                    queue_delay: Duration::from_nanos(
+                        s.bandwidth_delay_queue_delay.load(Ordering::Relaxed),
+                    ),
+                },
+                burst_losses: BurstLosses {
+                    isolated: s.burst_losses_isolated.load(Ordering::Relaxed),
+                    head: s.burst_losses_head.load(Ordering::Relaxed),
+                    tail: s.burst_losses_tail.load(Ordering::Relaxed),
+                },
+                loss_rates: LossRates {
+                    sent_1s: s.loss_rates_sent_1s.load(Ordering::Relaxed),
+                    lost_1s: s.loss_rates_lost_1s.load(Ordering::Relaxed),
+                    sent_10s: s.loss_rates_sent_10s.load(Ordering::Relaxed),
+                    lost_10s: s.loss_rates_lost_10s.load(Ordering::Relaxed),
+                },
+                phase_losses: PhaseLosses {
+                    slow_start: s.phase_losses_slow_start.load(Ordering::Relaxed),
+                    avoidance: s.phase_losses_avoidance.load(Ordering::Relaxed),
+                    recovery: s.phase_losses_recovery.load(Ordering::Relaxed),
+                },
+                idle_timeout_conflicts: s
+                    .idle_timeout_conflicts
+                    .load(Ordering::Relaxed),
+            };
+
+            atomic::fence(Ordering::Acquire);
+
+            // Values were not changed while being read.
+            if s.seq.load(Ordering::Relaxed) == seq {
+                return stats;
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for RecoveryStatsHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self.load())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn publish_and_load() {
+        let publisher = StatsPublisher::default();
+        assert!(!publisher.has_readers());
+
+        let handle = publisher.handle();
+        assert!(publisher.has_readers());
+
+        assert_eq!(handle.load(), RecoveryStats::default());
+
+        let stats = RecoveryStats {
+            cwnd: 12000,
+            bytes_in_flight: 1200,
+            rtt: Duration::from_millis(50),
+            min_rtt: Duration::from_millis(40),
+            pacing_rate: 100_000,
+            delivery_rate: 80_000,
+            lost_count: 1,
+            lost_bytes: 1200,
+            spurious_lost_count: 1,
+            ssthresh: 6000,
+            pto_count: 2,
+            loss_latency: LossLatency {
+                count: 1,
+                min: Duration::from_millis(60),
+                max: Duration::from_millis(60),
+                total: Duration::from_millis(60),
+            },
+            ack_cost: AckCost {
+                count: 2,
+                ranges: 3,
+                scanned: 4,
+                frames: 5,
+                max: 9,
+            },
+            lost_frames: LostFrames {
+                stream: 1000,
+                crypto: 0,
+                datagram: 100,
+                control: 10,
+                not_retransmitted: 100,
+            },
+            delay_asymmetry: DelayAsymmetry {
+                latest_rtt: Duration::from_millis(60),
+                peer_ack_delay: Duration::from_millis(5),
+                local_delay: Duration::from_millis(1),
+                forward: Duration::from_millis(20),
+                reverse: Duration::from_millis(34),
+            },
+            rounds: Rounds {
+                count: 3,
+                packets: 2,
+                bytes: 2400,
+                last_packets: 10,
+                last_bytes: 12000,
+            },
+            bandwidth_delay: BandwidthDelayEstimates {
+                throughput: 75_000,
+                queue_delay: Duration::from_millis(10),
+            },
+            burst_losses: BurstLosses {
+                isolated: 0,
+                head: 0,
+                tail: 1,
+            },
+            loss_rates: LossRates {
+                sent_1s: 100,
+                lost_1s: 1,
+                sent_10s: 1000,
+                lost_10s: 1,
+            },
+            phase_losses: PhaseLosses {
+                slow_start: 1,
+                avoidance: 0,
+                recovery: 0,
+            },
+            idle_timeout_conflicts: 1,
+        };
+
+        publisher.publish(&stats);
+
+        assert_eq!(handle.load(), stats);
+        assert_eq!(handle.clone().load(), stats);
+
+        drop(handle);
+        assert!(!publisher.has_readers());
+    }
+
+    #[test]
+    fn bandwidth_delay_estimates() {
+        let mut estimates = BandwidthDelayEstimates::default();
+
+        // The first sample is taken as is, even if app-limited.
+        estimates.on_delivery_rate(80_000, true);
+        assert_eq!(estimates.throughput, 80_000);
+
+        estimates.on_delivery_rate(160_000, false);
+        assert_eq!(estimates.throughput, 90_000);
+
+        // Lower app-limited samples are ignored, higher ones are not.
+        estimates.on_delivery_rate(10_000, true);
+        assert_eq!(estimates.throughput, 90_000);
+
+        estimates.on_delivery_rate(170_000, true);
+        assert_eq!(estimates.throughput, 100_000);
+
+        estimates.on_delivery_rate(20_000, false);
+        assert_eq!(estimates.throughput, 90_000);
+
+        estimates
+            .on_rtt_update(Duration::from_millis(60), Duration::from_millis(40));
+        assert_eq!(estimates.queue_delay, Duration::from_millis(20));
+
+        estimates
+            .on_rtt_update(Duration::from_millis(40), Duration::from_millis(50));
+        assert_eq!(estimates.queue_delay, Duration::ZERO);
+    }
+
+    #[test]
+    fn loss_latency() {
+        let mut latency = LossLatency::default();
+        assert_eq!(latency.mean(), None);
+
+        for ms in [30, 10, 20] {
+            latency.record(Duration::from_millis(ms));
+        }
+
+        assert_eq!(latency.count, 3);
+        assert_eq!(latency.min, Duration::from_millis(10));
+        assert_eq!(latency.max, Duration::from_millis(30));
+        assert_eq!(latency.mean(), Some(Duration::from_millis(20)));
+    }
+
+    #[test]
+    fn ack_cost() {
+        let mut cost = AckCost::default();
+        assert_eq!(cost.total(), 0);
+
+        cost.record(1, 2, 4);
+        cost.record(10, 10, 0);
+
+        assert_eq!(cost, AckCost {
+            count: 2,
+            ranges: 11,
+            scanned: 12,
+            frames: 4,
+            max: 20,
+        });
+        assert_eq!(cost.total(), 27);
+    }
+
+    #[test]
+    fn lost_frames() {
+        let mut lost = LostFrames::default();
+        assert_eq!(lost.not_retransmitted_ratio(), None);
+
+        lost.record(&[
+            frame::Frame::CryptoHeader {
+                offset: 0,
+                length: 300,
+            },
+            frame::Frame::Padding { len: 900 },
+        ]);
+        lost.record(&[
+            frame::Frame::ACKHeader { largest_acked: 0 },
+            frame::Frame::Ping,
+            frame::Frame::DatagramHeader { length: 100 },
+        ]);
+
+        assert_eq!(lost, LostFrames {
+            stream: 0,
+            crypto: 300,
+            datagram: 100,
+            control: 1,
+            not_retransmitted: 100,
+        });
+        assert_eq!(lost.total(), 401);
+        assert_eq!(lost.not_retransmitted_ratio(), Some(100.0 / 401.0));
+    }
+
+    #[test]
+    fn delay_asymmetry() {
+        let delay = DelayAsymmetry::default();
+        assert_eq!(delay.network_rtt(), Duration::ZERO);
+        assert_eq!(delay.reverse_ratio(), None);
+
+        let delay = DelayAsymmetry {
+            latest_rtt: Duration::from_millis(100),
+            peer_ack_delay: Duration::from_millis(15),
+            local_delay: Duration::from_millis(5),
+            forward: Duration::from_millis(20),
+            reverse: Duration::from_millis(60),
+        };
+        assert_eq!(delay.network_rtt(), Duration::from_millis(85));
+        assert_eq!(delay.reverse_ratio(), Some(0.75));
+
+        // The delays reported can exceed the RTT sample.
+        let delay = DelayAsymmetry {
+            latest_rtt: Duration::from_millis(10),
+            peer_ack_delay: Duration::from_millis(15),
+            ..delay
+        };
+        assert_eq!(delay.network_rtt(), Duration::ZERO);
+    }
+
+    #[test]
+    fn rounds() {
+        let mut rounds = Rounds::default();
+
+        rounds.record_sent(1200);
+        rounds.record_sent(1000);
+        rounds.end_round();
+
+        rounds.record_sent(500);
+
+        assert_eq!(rounds, Rounds {
+            count: 1,
+            packets: 1,
+            bytes: 500,
+            last_packets: 2,
+            last_bytes: 2200,
+        });
+    }
+
+    #[test]
+    fn burst_losses() {
+        let mut losses = BurstLosses::default();
+        assert_eq!(losses.tail_ratio(), None);
+
+        losses.record(0, 1);
+
+        // The middle packet of an odd-sized burst is at its head.
+        losses.record(0, 3);
+        losses.record(1, 3);
+        losses.record(2, 3);
+
+        losses.record(2, 4);
+        losses.record(3, 4);
+
+        assert_eq!(losses, BurstLosses {
+            isolated: 1,
+            head: 2,
+            tail: 3,
+        });
+        assert_eq!(losses.tail_ratio(), Some(0.5));
+    }
+
+    #[test]
+    fn loss_rate_windows() {
+        let start = Instant::now();
+
+        let mut windows = LossRateWindows::new(start);
+        assert_eq!(windows.loss_rate(Duration::from_secs(1)), 0.0);
+
+        for i in 0..100 {
+            windows.on_packet_sent(start + Duration::from_millis(i * 10));
+        }
+
+        windows.on_packet_lost(start + Duration::from_millis(990));
+
+        // Windows are rounded up to whole buckets.
+        assert_eq!(windows.counts(Duration::from_millis(150)), (20, 1));
+        assert_eq!(windows.counts(Duration::from_millis(200)), (20, 1));
+        assert_eq!(windows.counts(Duration::from_millis(201)), (40, 1));
+        assert_eq!(windows.counts(Duration::from_secs(1)), (100, 1));
+        assert_eq!(windows.loss_rate(Duration::from_secs(1)), 0.01);
+
+        // Buckets are reused once they leave the longest window.
+        let later = start + Duration::from_secs(10);
+
+        windows.on_packet_sent(later);
+        windows.on_packet_lost(later);
+        assert_eq!(windows.counts(Duration::from_secs(60)), (81, 2));
+
+        windows.on_packet_lost(later + Duration::from_millis(200));
+        assert_eq!(windows.counts(Duration::from_secs(60)), (61, 3));
+        assert_eq!(windows.counts(Duration::from_millis(400)), (1, 2));
+
+        // Losses of packets sent outside of the window are capped.
+        assert_eq!(windows.loss_rate(Duration::from_millis(200)), 1.0);
+
+        assert_eq!(windows.loss_rates(), LossRates {
+            sent_1s: 1,
+            lost_1s: 2,
+            sent_10s: 61,
+            lost_10s: 3,
+        });
+    }
+
+    #[test]
+    fn concurrent_load() {
+        let publisher = StatsPublisher::default();
+
+        // All values are derived from the same number, so that a reader can
+        // tell if it observed a partial update.
+        let stats = |i: u64| RecoveryStats {
+            cwnd: i as usize,
+            bytes_in_flight: i as usize * 2,
+            rtt: Duration::from_nanos(i * 3),
+            min_rtt: Duration::from_nanos(i * 4),
+            pacing_rate: i * 5,
+            delivery_rate: i * 6,
+            lost_count: i as usize * 7,
+            lost_bytes: i * 8,
+            spurious_lost_count: i as usize * 9,
+            ssthresh: i as usize * 10,
+            pto_count: i as u32 * 11,
+            loss_latency: LossLatency {
+                count: i * 12,
+                min: Duration::from_nanos(i * 13),
+                max: Duration::from_nanos(i * 14),
+                total: Duration::from_nanos(i * 15),
+            },
+            ack_cost: AckCost {
+                count: i * 16,
+                ranges: i * 17,
+                scanned: i * 18,
+                frames: i * 19,
+                max: i * 20,
+            },
+            lost_frames: LostFrames {
+                stream: i * 21,
+                crypto: i * 22,
+                datagram: i * 23,
+                control: i * 24,
+                not_retransmitted: i * 25,
+            },
+            delay_asymmetry: DelayAsymmetry {
+                latest_rtt: Duration::from_nanos(i * 26),
+                peer_ack_delay: Duration::from_nanos(i * 27),
+                local_delay: Duration::from_nanos(i * 28),
+                forward: Duration::from_nanos(i * 29),
+                reverse: Duration::from_nanos(i * 30),
+            },
+            rounds: Rounds {
+                count: i * 31,
+                packets: i * 32,
+                bytes: i * 33,
+                last_packets: i * 34,
+                last_bytes: i * 35,
+            },
+            bandwidth_delay: BandwidthDelayEstimates {
+                throughput: i * 36,
+                queue_delay: Duration::from_nanos(i * 37),
+            },
+            burst_losses: BurstLosses {
+                isolated: i * 38,
+                head: i * 39,
+                tail: i * 40,
+            },
+            loss_rates: LossRates {
+                sent_1s: i * 41,
+                lost_1s: i * 42,
+                sent_10s: i * 43,
+                lost_10s: i * 44,
+            },
+            phase_losses: PhaseLosses {
+                slow_start: i * 45,
+                avoidance: i * 46,
+                recovery: i * 47,
+            },
+            idle_timeout_conflicts: i * 48,
+        };
+
+        let readers = (0..2)
+            .map(|_| {
+                let handle = publisher.handle();
+
+                std::thread::spawn(move || {
+                    let mut last = 0;
+
+                    while last < 100_000 {
+                        let s = handle.load();
+
+                        assert_eq!(s, stats(s.cwnd as u64));
+                        assert!(s.cwnd as u64 >= last);
+
+                        last = s.cwnd as u64;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for i in 1..=100_000 {
+            publisher.publish(&stats(i));
+        }
+
+        for r in readers {
+            r.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,983 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Fixtures for loss recovery tests and benchmarks.
+//!
+//! This provides builders for sent packets and acknowledged ranges, trains of
+//! packets and scripts of ACKs and timeouts to drive loss recovery with, as
+//! well as a set of scenarios exercising the loss recovery hot paths, which
+//! are used by the `recovery` benchmarks, and a replayer of arbitrary
+//! sequences of operations, which is used by the `recovery_ack` fuzzer.
+
+use std::cmp;
+
+use std::ops::Range;
+
+use std::time::Duration;
+
+use crate::frame;
+use crate::packet;
+use crate::ranges;
+use crate::time::Instant;
+
+use crate::recovery::CongestionControlAlgorithm;
+use crate::recovery::CongestionState;
+use crate::recovery::HandshakeStatus;
+use crate::recovery::Recovery;
+use crate::recovery::Sent;
+use crate::recovery::SentContent;
+
+/// The handshake status of a connection whose handshake is completed.
+pub(crate) const HANDSHAKE_COMPLETED: HandshakeStatus = HandshakeStatus {
+    has_handshake_keys: true,
+
+    peer_verified_address: true,
+
+    completed: true,
+
+    amplification_limited: false,
+};
+
+/// The size of the packets built by `sent_packet()`.
+pub(crate) const PACKET_SIZE: usize = 1200;
+
+// The number of packets in flight in the contiguous and fragmented ACKs
+// scenarios.
+const WINDOW_PACKETS: u64 = 65536;
+
+// The number of packets in flight in the lookup window scenario.
+const LOOKUP_WINDOW_PACKETS: u64 = 50_000;
+
+// The number of packets acknowledged by each ACK frame.
+const ACK_PACKETS: u64 = 64;
+
+// The number of packets sent in the mixed packets scenario.
+const MIXED_PACKETS: u64 = 4096;
+
+// The number of ranges of the ACK frames carried by packets of a train.
+const ACK_FRAME_RANGES: u64 = 16;
+
+// The number of packets outstanding in the PTO scenario.
+const PTO_PACKETS: u64 = 4096;
+
+// The number of frames carried by each packet in the PTO scenario.
+const PTO_FRAMES: usize = 16;
+
+// The number of consecutive PTOs in the PTO scenario.
+const PTO_COUNT: usize = 8;
+
+// The number of packets in flight in the PTO backlog scenario, the first half
+// of which are declared lost.
+const BACKLOG_PACKETS: u64 = 4096;
+
+// The number of consecutive PTOs in the PTO backlog scenario. The PTO backoff
+// doubles with each of them, so this stays well below 32.
+const BACKLOG_PTO_COUNT: usize = 16;
+
+// The number of send and ACK rounds in the interleaved loss scenario.
+const INTERLEAVED_ROUNDS: u64 = 1024;
+
+// One in every `INTERLEAVED_LOSS_INTERVAL` packets is never acknowledged in
+// the interleaved loss scenario.
+const INTERLEAVED_LOSS_INTERVAL: u64 = 16;
+
+/// Returns an ack-eliciting, in-flight packet carrying the given frames.
+pub(crate) fn sent_packet(
+    pkt_num: u64, frames: Vec<frame::Frame>, now: Instant,
+) -> Sent {
+    Sent {
+        pkt_num,
+        content: SentContent::from_frames(&frames),
+        frames,
+        time_sent: now,
+        time_acked: None,
+        time_lost: None,
+        size: PACKET_SIZE,
+        ack_eliciting: true,
+        in_flight: true,
+        delivered: 0,
+        delivered_time: now,
+        first_sent_time: now,
+        is_app_limited: false,
+        is_pmtud_probe: false,
+        padding_bytes: 0,
+        datagram_id: None,
+        burst_index: 0,
+        burst_size: 0,
+        congestion_state: CongestionState::SlowStart,
+    }
+}
+
+/// Returns `count` STREAM frames, each on a different stream, carrying data
+/// at an offset based on the packet number.
+#[cfg(test)]
+pub(crate) fn stream_frames(pkt_num: u64, count: usize) -> Vec<frame::Frame> {
+    stream_frames_of_length(pkt_num, count, 1000)
+}
+
+/// Same as `stream_frames()`, but each frame carries `length` bytes.
+pub(crate) fn stream_frames_of_length(
+    pkt_num: u64, count: usize, length: usize,
+) -> Vec<frame::Frame> {
+    (0..count as u64)
+        .map(|i| frame::Frame::StreamHeader {
+            stream_id: i * 4,
+            offset: pkt_num * length as u64,
+            length,
+            fin: false,
+        })
+        .collect()
+}
+
+/// Returns a set of the given ranges.
+pub(crate) fn range_set<I: IntoIterator<Item = Range<u64>>>(
+    ranges: I,
+) -> ranges::RangeSet {
+    let mut set = ranges::RangeSet::default();
+
+    for r in ranges {
+        set.insert(r);
+    }
+
+    set
+}
+
+/// Returns a set acknowledging every other packet in the given range,
+/// starting with the first one.
+pub(crate) fn fragmented_range_set(range: Range<u64>) -> ranges::RangeSet {
+    range_set(range.step_by(2).map(|pn| pn..pn + 1))
+}
+
+/// Records the given packets as sent in the application packet number space.
+#[cfg(test)]
+pub(crate) fn send_packets<I: IntoIterator<Item = Sent>>(
+    r: &mut Recovery, pkts: I, now: Instant,
+) {
+    for p in pkts {
+        r.on_packet_sent(p, packet::EPOCH_APPLICATION, HANDSHAKE_COMPLETED, now);
+    }
+}
+
+/// Processes an ACK frame for the application packet number space.
+#[cfg(test)]
+pub(crate) fn ack_packets(
+    r: &mut Recovery, ranges: &ranges::RangeSet, now: Instant,
+) -> (usize, usize) {
+    r.on_ack_received(
+        ranges,
+        25,
+        packet::EPOCH_APPLICATION,
+        HANDSHAKE_COMPLETED,
+        now,
+        None,
+    )
+    .unwrap()
+}
+
+/// A train of packets with consecutive packet numbers.
+///
+/// Packets are ack-eliciting and in flight, and are all sent at once unless
+/// they are spaced with `spaced_by()`.
+#[derive(Clone, Debug)]
+pub struct PacketTrain {
+    count: u64,
+
+    size: usize,
+
+    first_pkt_num: u64,
+
+    interval: Duration,
+
+    frames: usize,
+
+    frame_length: usize,
+
+    ack_frame: Option<AckFrameLayout>,
+}
+
+/// How the ACK frames carried by the packets of a train are recorded.
+#[derive(Clone, Copy, Debug)]
+pub enum AckFrameLayout {
+    /// Only the largest acknowledged packet number is kept, as connections do.
+    Header,
+
+    /// The whole frame is kept, including its ranges.
+    Ranges,
+}
+
+impl PacketTrain {
+    /// Creates a train of `count` packets of `size` bytes each, starting at
+    /// packet number 0.
+    pub fn new(count: u64, size: usize) -> Self {
+        PacketTrain {
+            count,
+            size,
+            first_pkt_num: 0,
+            interval: Duration::ZERO,
+            frames: 0,
+            frame_length: 1000,
+            ack_frame: None,
+        }
+    }
+
+    /// Numbers the packets starting from `pkt_num`.
+    pub fn starting_at(mut self, pkt_num: u64) -> Self {
+        self.first_pkt_num = pkt_num;
+        self
+    }
+
+    /// Sends each packet `interval` after the previous one.
+    pub fn spaced_by(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Makes each packet carry `count` STREAM frames, as returned by
+    /// `stream_frames()`.
+    pub fn with_stream_frames(mut self, count: usize) -> Self {
+        self.frames = count;
+        self
+    }
+
+    /// Makes the STREAM frames carried by each packet describe `length` bytes
+    /// of data each, instead of 1000.
+    pub fn with_frame_length(mut self, length: usize) -> Self {
+        self.frame_length = length;
+        self
+    }
+
+    /// Makes each packet also carry an ACK frame for a few packets before it,
+    /// recorded with the given layout.
+    pub fn with_ack_frame(mut self, layout: AckFrameLayout) -> Self {
+        self.ack_frame = Some(layout);
+        self
+    }
+
+    /// Returns the range of packet numbers of the train.
+    pub fn pkt_nums(&self) -> Range<u64> {
+        self.first_pkt_num..self.first_pkt_num + self.count
+    }
+
+    /// Returns the packets of the train, with the first one sent at `start`.
+    pub fn packets(&self, start: Instant) -> impl Iterator<Item = Sent> + '_ {
+        self.pkt_nums().zip(0..).map(move |(pn, i)| {
+            let time_sent = start + self.interval * i;
+
+            let mut frames =
+                stream_frames_of_length(pn, self.frames, self.frame_length);
+
+            if let Some(layout) = self.ack_frame {
+                let ranges = fragmented_range_set(
+                    pn.saturating_sub(ACK_FRAME_RANGES * 2)..pn,
+                );
+
+                frames.push(match layout {
+                    AckFrameLayout::Header => frame::Frame::ACKHeader {
+                        largest_acked: ranges.last().unwrap_or(0),
+                    },
+
+                    AckFrameLayout::Ranges => frame::Frame::ACK {
+                        ack_delay: 0,
+                        ranges,
+                        ecn_counts: None,
+                    },
+                });
+            }
+
+            let mut p = sent_packet(pn, frames, time_sent);
+            p.size = self.size;
+
+            p
+        })
+    }
+
+    /// Records the packets of the train as sent in the application packet
+    /// number space, with the first one sent at `start`, and returns the time
+    /// the last one was sent.
+    pub fn send(&self, r: &mut Recovery, start: Instant) -> Instant {
+        let mut now = start;
+
+        for p in self.packets(start) {
+            now = p.time_sent;
+
+            r.on_packet_sent(
+                p,
+                packet::EPOCH_APPLICATION,
+                HANDSHAKE_COMPLETED,
+                now,
+            );
+        }
+
+        now
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum Step {
+    Wait(Duration),
+
+    Send(PacketTrain),
+
+    Ack(ranges::RangeSet),
+
+    Duplicate,
+
+    LossDetectionTimeout,
+}
+
+impl Step {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Step::Wait(_) => "wait",
+
+            Step::Send(_) => "send",
+
+            Step::Ack(_) => "ack",
+
+            Step::Duplicate => "duplicate",
+
+            Step::LossDetectionTimeout => "timeout",
+        }
+    }
+}
+
+/// A script of packets acknowledged by the peer over time, in the application
+/// packet number space.
+///
+/// Steps run in the order they were added, starting at the time given to
+/// `run()`. Time only passes on `after()` and `loss_detection_timeout()`
+/// steps, so packets can be acknowledged in any order, and more than once.
+#[derive(Clone, Debug)]
+pub struct AckScript {
+    pub(crate) steps: Vec<Step>,
+
+    pub(crate) ack_delay: u64,
+
+    pub(crate) keep_lost_frames: bool,
+}
+
+impl AckScript {
+    /// Creates an empty script, with an ACK delay of 25 microseconds.
+    pub fn new() -> Self {
+        AckScript {
+            steps: Vec::new(),
+            ack_delay: 25,
+            keep_lost_frames: false,
+        }
+    }
+
+    /// Sets the ACK delay carried by all ACK frames, in microseconds.
+    pub fn ack_delay(mut self, ack_delay: u64) -> Self {
+        self.ack_delay = ack_delay;
+        self
+    }
+
+    /// Keeps the frames scheduled for retransmission queued until the end of
+    /// the script, as if the connection couldn't send them, instead of
+    /// dropping them after each step.
+    pub fn keep_lost_frames(mut self) -> Self {
+        self.keep_lost_frames = true;
+        self
+    }
+
+    /// Lets `delay` pass before the next step.
+    pub fn after(mut self, delay: Duration) -> Self {
+        self.steps.push(Step::Wait(delay));
+        self
+    }
+
+    /// Sends the given packets. The first one is sent at the current time.
+    pub fn send(mut self, train: PacketTrain) -> Self {
+        self.steps.push(Step::Send(train));
+        self
+    }
+
+    /// Receives an ACK frame with a single range.
+    pub fn ack(self, range: Range<u64>) -> Self {
+        self.ack_ranges(range_set(std::iter::once(range)))
+    }
+
+    /// Receives an ACK frame with the given ranges.
+    pub fn ack_ranges(mut self, ranges: ranges::RangeSet) -> Self {
+        self.steps.push(Step::Ack(ranges));
+        self
+    }
+
+    /// Receives one ACK frame for every `chunk` packets of the given range,
+    /// in order.
+    pub fn ack_in_chunks(mut self, range: Range<u64>, chunk: u64) -> Self {
+        for start in range.clone().step_by(chunk as usize) {
+            let end = cmp::min(start + chunk, range.end);
+
+            self = self.ack(start..end);
+        }
+
+        self
+    }
+
+    /// Receives the previous ACK frame again.
+    pub fn duplicate(mut self) -> Self {
+        self.steps.push(Step::Duplicate);
+        self
+    }
+
+    /// Waits for the loss detection timer to expire, and fires it. Nothing
+    /// happens if the timer is not armed.
+    pub fn loss_detection_timeout(mut self) -> Self {
+        self.steps.push(Step::LossDetectionTimeout);
+        self
+    }
+
+    /// Runs the script against `r`, starting at `start`.
+    ///
+    /// Frames of acknowledged and lost packets are counted and dropped, as if
+    /// the connection processed them.
+    pub fn run(&self, r: &mut Recovery, start: Instant) -> AckResults {
+        self.run_with(r, start, |_, _, _| ())
+    }
+
+    /// Same as `run()`, but calls `f` after each step with the name of the
+    /// step, the loss recovery state and the current time.
+    pub fn run_with<F>(
+        &self, r: &mut Recovery, start: Instant, mut f: F,
+    ) -> AckResults
+    where
+        F: FnMut(&str, &Recovery, Instant),
+    {
+        let mut res = AckResults {
+            lost: Vec::new(),
+            acked_frames: 0,
+            lost_frames: 0,
+            now: start,
+        };
+
+        let mut last_ack = None;
+
+        for step in &self.steps {
+            self.run_step(step, r, &mut res, &mut last_ack);
+
+            f(step.name(), r, res.now);
+        }
+
+        res.lost_frames += r.lost[packet::EPOCH_APPLICATION].len();
+        r.lost[packet::EPOCH_APPLICATION].clear();
+        r.lost_queued[packet::EPOCH_APPLICATION].clear();
+
+        res
+    }
+
+    pub(crate) fn run_step<'a>(
+        &self, step: &'a Step, r: &mut Recovery, res: &mut AckResults,
+        last_ack: &mut Option<&'a ranges::RangeSet>,
+    ) {
+        let epoch = packet::EPOCH_APPLICATION;
+
+        let ranges = match step {
+            Step::Wait(delay) => {
+                res.now += *delay;
+                return;
+            },
+
+            Step::Send(train) => {
+                res.now = train.send(r, res.now);
+                return;
+            },
+
+            Step::LossDetectionTimeout => {
+                if let Some(timer) = r.loss_detection_timer() {
+                    res.now = cmp::max(res.now, timer);
+
+                    res.lost.push(
+                        r.on_loss_detection_timeout(HANDSHAKE_COMPLETED, res.now),
+                    );
+                }
+
+                self.drop_lost_frames(r, res);
+
+                return;
+            },
+
+            Step::Ack(ranges) => ranges,
+
+            Step::Duplicate => match *last_ack {
+                Some(ranges) => ranges,
+
+                None => return,
+            },
+        };
+
+        let mut acked_frames = 0;
+
+        let lost = r
+            .on_ack_received(
+                ranges,
+                self.ack_delay,
+                epoch,
+                HANDSHAKE_COMPLETED,
+                res.now,
+                Some(&mut |_| acked_frames += 1),
+            )
+            .unwrap();
+
+        res.lost.push(lost);
+
+        res.acked_frames += acked_frames;
+
+        self.drop_lost_frames(r, res);
+
+        *last_ack = Some(ranges);
+    }
+
+    fn drop_lost_frames(&self, r: &mut Recovery, res: &mut AckResults) {
+        if self.keep_lost_frames {
+            return;
+        }
+
+        res.lost_frames += r.lost[packet::EPOCH_APPLICATION].len();
+        r.lost[packet::EPOCH_APPLICATION].clear();
+        r.lost_queued[packet::EPOCH_APPLICATION].clear();
+    }
+}
+
+impl Default for AckScript {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The outcome of running an `AckScript`.
+#[derive(Clone, Debug)]
+pub struct AckResults {
+    /// The number of packets and bytes declared lost by each ACK frame and
+    /// loss detection timeout, in order.
+    pub lost: Vec<(usize, usize)>,
+
+    /// The number of frames carried by acknowledged packets.
+    pub acked_frames: usize,
+
+    /// The number of frames scheduled for retransmission, either because
+    /// packets were lost or on PTO.
+    pub lost_frames: usize,
+
+    /// The time at which the script ended.
+    pub now: Instant,
+}
+
+impl AckResults {
+    /// Returns the total number of packets declared lost.
+    pub fn lost_packets(&self) -> usize {
+        self.lost.iter().map(|(pkts, _)| pkts).sum()
+    }
+}
+
+/// A loss recovery benchmark scenario.
+///
+/// Scenarios are created with all the state they need already set up, so
+/// that only `run()` needs to be measured.
+pub struct Scenario {
+    r: Recovery,
+
+    now: Instant,
+
+    script: AckScript,
+}
+
+impl Scenario {
+    /// A scenario where the given packets are already in flight, and the
+    /// script runs 10ms after they were sent.
+    pub fn new(
+        cc: CongestionControlAlgorithm, sent: Option<PacketTrain>,
+        script: AckScript,
+    ) -> Self {
+        Scenario::starting_at(cc, sent, script, Instant::now())
+    }
+
+    /// Same as `new()`, but the packets are sent at `now`, as given by the
+    /// caller, instead of the current time.
+    pub fn starting_at(
+        cc: CongestionControlAlgorithm, sent: Option<PacketTrain>,
+        script: AckScript, now: Instant,
+    ) -> Self {
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config.set_cc_algorithm(cc);
+
+        let mut r = Recovery::new(&config);
+
+        if let Some(train) = sent {
+            train.send(&mut r, now);
+        }
+
+        Scenario { r, now, script }
+    }
+
+    /// A large window of packets, acknowledged by ACK frames with a single
+    /// range each.
+    pub fn contiguous_acks(cc: CongestionControlAlgorithm) -> Self {
+        Scenario::new(
+            cc,
+            Some(PacketTrain::new(WINDOW_PACKETS, PACKET_SIZE)),
+            AckScript::new().ack_in_chunks(0..WINDOW_PACKETS, ACK_PACKETS),
+        )
+    }
+
+    /// A large window of packets, acknowledged by ACK frames with many small
+    /// ranges each, so that the packets in between are declared lost.
+    pub fn fragmented_acks(cc: CongestionControlAlgorithm) -> Self {
+        let script = (0..WINDOW_PACKETS).step_by(ACK_PACKETS as usize).fold(
+            AckScript::new(),
+            |script, start| {
+                script
+                    .ack_ranges(fragmented_range_set(start..start + ACK_PACKETS))
+            },
+        );
+
+        Scenario::new(
+            cc,
+            Some(PacketTrain::new(WINDOW_PACKETS, PACKET_SIZE)),
+            script,
+        )
+    }
+
+    /// A large window of packets, the first of which is never acknowledged,
+    /// so that acknowledged packets can't be drained from the front of the
+    /// list and need to be looked up in the whole window.
+    pub fn lookup_window(cc: CongestionControlAlgorithm) -> Self {
+        Scenario::new(
+            cc,
+            Some(PacketTrain::new(LOOKUP_WINDOW_PACKETS, PACKET_SIZE)),
+            AckScript::new().ack_in_chunks(1..LOOKUP_WINDOW_PACKETS, ACK_PACKETS),
+        )
+    }
+
+    /// Packets carrying both a STREAM and an ACK frame are sent, with the ACK
+    /// frames recorded with the given layout.
+    pub fn mixed_packets(
+        cc: CongestionControlAlgorithm, layout: AckFrameLayout,
+    ) -> Self {
+        Scenario::new(
+            cc,
+            None,
+            AckScript::new().send(
+                PacketTrain::new(MIXED_PACKETS, PACKET_SIZE)
+                    .with_stream_frames(1)
+                    .with_ack_frame(layout),
+            ),
+        )
+    }
+
+    /// Packets carrying many frames each are outstanding when consecutive
+    /// PTOs fire.
+    pub fn pto(cc: CongestionControlAlgorithm) -> Self {
+        Scenario::pto_with_frame_length(cc, 1000)
+    }
+
+    /// Same as `pto()`, but the frames carried by each packet describe
+    /// `length` bytes of data each.
+    pub fn pto_with_frame_length(
+        cc: CongestionControlAlgorithm, length: usize,
+    ) -> Self {
+        let script = (0..PTO_COUNT).fold(AckScript::new(), |script, _| {
+            script.loss_detection_timeout()
+        });
+
+        Scenario::new(
+            cc,
+            Some(
+                PacketTrain::new(PTO_PACKETS, PACKET_SIZE)
+                    .with_stream_frames(PTO_FRAMES)
+                    .with_frame_length(length),
+            ),
+            script,
+        )
+    }
+
+    /// Half of a window of packets carrying many frames each is declared
+    /// lost, and the frames stay queued for retransmission while consecutive
+    /// PTOs fire and the rest of the window is acknowledged, so that the
+    /// frames copied on PTO need to be found in a large backlog.
+    pub fn pto_backlog(cc: CongestionControlAlgorithm) -> Self {
+        let half = BACKLOG_PACKETS / 2;
+
+        let script = (0..BACKLOG_PTO_COUNT).fold(
+            AckScript::new()
+                .keep_lost_frames()
+                .ack_ranges(fragmented_range_set(0..half)),
+            |script, _| script.loss_detection_timeout(),
+        );
+
+        Scenario::new(
+            cc,
+            Some(
+                PacketTrain::new(BACKLOG_PACKETS, PACKET_SIZE)
+                    .with_stream_frames(PTO_FRAMES),
+            ),
+            script.ack_in_chunks(half..BACKLOG_PACKETS, ACK_PACKETS),
+        )
+    }
+
+    /// Packets are sent and acknowledged in turns, with a few of them never
+    /// being acknowledged, so that loss detection runs alongside.
+    pub fn interleaved_loss(cc: CongestionControlAlgorithm) -> Self {
+        let script =
+            (0..INTERLEAVED_ROUNDS).fold(AckScript::new(), |script, round| {
+                let train = PacketTrain::new(ACK_PACKETS, PACKET_SIZE)
+                    .starting_at(round * ACK_PACKETS);
+
+                let acked = range_set(
+                    train
+                        .pkt_nums()
+                        .filter(|pn| pn % INTERLEAVED_LOSS_INTERVAL != 0)
+                        .map(|pn| pn..pn + 1),
+                );
+
+                script
+                    .send(train)
+                    .after(Duration::from_millis(1))
+                    .ack_ranges(acked)
+            });
+
+        Scenario::new(cc, None, script)
+    }
+
+    /// Runs the scenario.
+    pub fn run(&mut self) -> AckResults {
+        let now = self.now + Duration::from_millis(10);
+
+        self.script.run(&mut self.r, now)
+    }
+}
+
+/// Replays a sequence of loss recovery operations decoded from arbitrary
+/// input, and checks that the loss recovery state stays consistent after each
+/// of them. This is used by the `recovery_ack` fuzzer.
+///
+/// The first byte selects the congestion control algorithm, and each of the
+/// following operations starts with a byte selecting its type. The sequence
+/// ends when the input runs out.
+///
+/// The state is only checked in debug builds, or with the `test-utils`
+/// feature, which the fuzzer enables.
+pub fn replay(data: &[u8]) {
+    let mut b = octets::Octets::with_slice(data);
+
+    let cc = match b.get_u8() {
+        Ok(v) => CC_ALGORITHMS[v as usize % CC_ALGORITHMS.len()],
+
+        Err(_) => return,
+    };
+
+    let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+    config.set_cc_algorithm(cc);
+
+    let mut replay = Replay {
+        r: Recovery::new(&config),
+        now: Instant::now(),
+        next_pkt_num: [0; packet::EPOCH_COUNT],
+        handshake_status: HANDSHAKE_COMPLETED,
+    };
+
+    while let Ok(op) = b.get_u8() {
+        if replay.apply(op, &mut b).is_err() {
+            break;
+        }
+
+        #[cfg(any(debug_assertions, feature = "test-utils"))]
+        replay.r.assert_consistent();
+    }
+}
+
+const CC_ALGORITHMS: [CongestionControlAlgorithm; 3] = [
+    CongestionControlAlgorithm::Reno,
+    CongestionControlAlgorithm::CUBIC,
+    CongestionControlAlgorithm::BBR,
+];
+
+struct Replay {
+    r: Recovery,
+
+    now: Instant,
+
+    next_pkt_num: [u64; packet::EPOCH_COUNT],
+
+    handshake_status: HandshakeStatus,
+}
+
+impl Replay {
+    fn apply(&mut self, op: u8, b: &mut octets::Octets) -> octets::Result<()> {
+        let r = &mut self.r;
+        let now = self.now;
+
+        match op % 6 {
+            // Send a burst of packets, with optional gaps in packet numbers.
+            0 => {
+                let epoch = b.get_u8()? as usize % packet::EPOCH_COUNT;
+                let count = b.get_u8()? % 16 + 1;
+                let flags = b.get_u8()?;
+                let size = b.get_u16()? as usize;
+
+                // Nothing can be sent once the keys are dropped.
+                if r.discarded[epoch] {
+                    return Ok(());
+                }
+
+                let next_pkt_num = &mut self.next_pkt_num[epoch];
+
+                let pkts: Vec<Sent> = (0..count)
+                    .map(|_| {
+                        let mut p = sent_packet(*next_pkt_num, vec![], now);
+
+                        p.size = size;
+                        p.ack_eliciting = flags & 0x01 != 0;
+                        p.in_flight = flags & 0x02 != 0;
+                        p.padding_bytes = if flags & 0x04 != 0 {
+                            (size / 2) as u16
+                        } else {
+                            0
+                        };
+
+                        *next_pkt_num += 1 + u64::from(flags >> 4);
+
+                        p
+                    })
+                    .collect();
+
+                r.on_packets_sent(pkts, epoch, self.handshake_status, now);
+            },
+
+            // Receive an ACK frame, with arbitrary ranges and ACK delay.
+            1 => {
+                let epoch = b.get_u8()? as usize % packet::EPOCH_COUNT;
+                let ack_delay = b.get_varint()?;
+                let count = b.get_u8()? % 8 + 1;
+
+                let mut ranges = ranges::RangeSet::default();
+
+                for _ in 0..count {
+                    let start = b.get_varint()?;
+                    let len = u64::from(b.get_u8()?) + 1;
+
+                    ranges.insert(start..start + len);
+                }
+
+                r.on_ack_received(
+                    &ranges,
+                    ack_delay,
+                    epoch,
+                    self.handshake_status,
+                    now,
+                    None,
+                )
+                .ok();
+
+                r.acked[epoch].clear();
+            },
+
+            // Fire the loss detection timer, if set.
+            2 =>
+                if let Some(timer) = r.loss_detection_timer() {
+                    self.now = cmp::max(now, timer);
+
+                    r.on_loss_detection_timeout(self.handshake_status, self.now);
+                },
+
+            // Let time pass.
+            3 => self.now += Duration::from_millis(u64::from(b.get_u16()?)),
+
+            // Discard a packet number space.
+            4 => {
+                let epoch = b.get_u8()? as usize % packet::EPOCH_COUNT;
+
+                r.on_pkt_num_space_discarded(epoch, self.handshake_status, now);
+            },
+
+            // Change the handshake status.
+            _ => {
+                let flags = b.get_u8()?;
+
+                self.handshake_status = HandshakeStatus {
+                    has_handshake_keys: flags & 0x01 != 0,
+
+                    peer_verified_address: flags & 0x02 != 0,
+
+                    completed: flags & 0x04 != 0,
+
+                    amplification_limited: flags & 0x08 != 0,
+                };
+            },
+        }
+
+        for lost in r.lost.iter_mut() {
+            lost.clear();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_regressions() {
+        // Sending a packet that is not in flight while nothing else is.
+        replay(&[0x00, 0x00, 0x02, 0x00, 0x00, 0x04, 0xb0]);
+
+        // Acknowledging a packet that is not in flight, while another one is.
+        replay(&[
+            0x00, 0x00, 0x02, 0x00, 0x03, 0x04, 0xb0, 0x00, 0x02, 0x00, 0x00,
+            0x04, 0xb0, 0x01, 0x02, 0x00, 0x00, 0x01, 0x00,
+        ]);
+    }
+
+    #[test]
+    fn scenarios() {
+        let algorithms = [
+            CongestionControlAlgorithm::Reno,
+            CongestionControlAlgorithm::CUBIC,
+            CongestionControlAlgorithm::BBR,
+        ];
+
+        for &cc in algorithms.iter() {
+            let mut s = Scenario::contiguous_acks(cc);
+            s.run();
+            assert_eq!(s.r.bytes_in_flight, 0);
+            assert_eq!(s.r.lost_count, 0);
+
+            let mut s = Scenario::fragmented_acks(cc);
+            s.run();
+            assert!(s.r.lost_count > 0);
+
+            let mut s = Scenario::pto(cc);
+            s.run();
+            assert_eq!(s.r.pto_count, PTO_COUNT as u32);
+
+            let mut s = Scenario::interleaved_loss(cc);
+            s.run();
+            assert!(s.r.lost_count > 0);
+        }
+    }
+}
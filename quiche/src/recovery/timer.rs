@@ -0,0 +1,199 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Loss detection timer.
+//!
+//! The deadline of the loss detection timer is updated after every packet
+//! sent and every ACK received, often by just a few microseconds. Event loops
+//! that re-arm an OS timer whenever the deadline changes would spend most of
+//! their time doing so, so small changes can be coalesced: the reported
+//! deadline only follows the true one once it moved by more than a threshold.
+//!
+//! Loss recovery itself always uses the true deadline. When the reported
+//! deadline expires first, the timeout is spurious and the timer is simply
+//! re-armed for the true deadline. When it expires last, loss detection runs
+//! at most a threshold late.
+
+use std::cmp;
+
+use std::time::Duration;
+
+use crate::time::Instant;
+
+/// A loss detection timer whose reported deadline is only updated when it
+/// moves by more than a threshold.
+#[derive(Debug, Default)]
+pub struct LossDetectionTimer {
+    // The deadline loss recovery works with.
+    deadline: Option<Instant>,
+
+    // The deadline reported to the application.
+    reported: Option<Instant>,
+
+    // How far the true deadline can move before the reported one follows.
+    coalescing: Duration,
+
+    // The number of deadline changes that were not reported.
+    suppressed_updates: usize,
+}
+
+impl LossDetectionTimer {
+    pub fn new(coalescing: Duration) -> Self {
+        LossDetectionTimer {
+            coalescing,
+
+            ..Default::default()
+        }
+    }
+
+    /// Sets the true deadline of the timer, or disarms it.
+    ///
+    /// Disarming and arming the timer are always reported.
+    pub fn set(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+
+        if let (Some(reported), Some(deadline)) = (self.reported, deadline) {
+            let delta = distance(reported, deadline);
+
+            if !delta.is_zero() && delta <= self.coalescing {
+                self.suppressed_updates += 1;
+                return;
+            }
+        }
+
+        self.reported = deadline;
+    }
+
+    /// Returns the true deadline.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Returns the deadline reported to the application.
+    pub fn reported(&self) -> Option<Instant> {
+        self.reported
+    }
+
+    /// Returns the number of deadline changes that were not reported.
+    pub fn suppressed_updates(&self) -> usize {
+        self.suppressed_updates
+    }
+
+    /// Returns whether the true deadline expired at `now`.
+    ///
+    /// If the reported deadline expired before the true one, the true one is
+    /// reported from now on.
+    pub fn on_timeout(&mut self, now: Instant) -> bool {
+        match self.deadline {
+            Some(deadline)
+                if deadline > now && self.reported != self.deadline =>
+            {
+                self.reported = self.deadline;
+
+                false
+            },
+
+            _ => true,
+        }
+    }
+}
+
+// Returns the distance between two points in time.
+fn distance(a: Instant, b: Instant) -> Duration {
+    cmp::max(
+        a.saturating_duration_since(b),
+        b.saturating_duration_since(a),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_coalescing() {
+        let mut timer = LossDetectionTimer::new(Duration::ZERO);
+
+        let now = Instant::now();
+
+        for i in 0..10 {
+            let deadline = Some(now + Duration::from_micros(i));
+
+            timer.set(deadline);
+
+            assert_eq!(timer.deadline(), deadline);
+            assert_eq!(timer.reported(), deadline);
+        }
+
+        timer.set(None);
+        assert_eq!(timer.reported(), None);
+
+        assert_eq!(timer.suppressed_updates(), 0);
+    }
+
+    #[test]
+    fn coalescing() {
+        let threshold = Duration::from_millis(1);
+
+        let mut timer = LossDetectionTimer::new(threshold);
+
+        let now = Instant::now();
+
+        timer.set(Some(now + Duration::from_millis(100)));
+        assert_eq!(timer.reported(), Some(now + Duration::from_millis(100)));
+
+        // Small moves in either direction are not reported.
+        timer.set(Some(now + Duration::from_micros(100_500)));
+        timer.set(Some(now + Duration::from_micros(99_500)));
+
+        assert_eq!(timer.deadline(), Some(now + Duration::from_micros(99_500)));
+        assert_eq!(timer.reported(), Some(now + Duration::from_millis(100)));
+        assert_eq!(timer.suppressed_updates(), 2);
+
+        // The reported deadline expired after the true one.
+        assert!(timer.on_timeout(now + Duration::from_millis(100)));
+
+        // Larger moves are.
+        timer.set(Some(now + Duration::from_millis(102)));
+        assert_eq!(timer.reported(), Some(now + Duration::from_millis(102)));
+
+        timer.set(Some(now + Duration::from_micros(102_800)));
+        assert_eq!(timer.reported(), Some(now + Duration::from_millis(102)));
+        assert_eq!(timer.suppressed_updates(), 3);
+
+        // The reported deadline expired before the true one, which is now
+        // reported.
+        assert!(!timer.on_timeout(now + Duration::from_millis(102)));
+        assert_eq!(timer.reported(), Some(now + Duration::from_micros(102_800)));
+
+        assert!(timer.on_timeout(now + Duration::from_micros(102_800)));
+
+        // Disarming the timer is always reported.
+        timer.set(None);
+        assert_eq!(timer.reported(), None);
+        assert_eq!(timer.suppressed_updates(), 3);
+    }
+}
@@ -36,13 +36,12 @@ use std::collections::HashMap;
 use std::collections::HashSet;
 use std::collections::VecDeque;
 
-use std::time;
-
 use crate::Error;
 use crate::Result;
 
 use crate::flowcontrol;
 use crate::ranges;
+use crate::time;
 
 const DEFAULT_URGENCY: u8 = 127;
 
@@ -598,6 +597,19 @@ impl StreamMap {
         !self.blocked.is_empty()
     }
 
+    /// Returns the lowest ID of the streams that data couldn't be written to
+    /// because of their current flow control limit, along with the limit.
+    pub fn flow_control_blocked(&self) -> Option<(u64, u64)> {
+        self.streams
+            .iter()
+            .filter_map(|(&id, stream)| {
+                let max_off = stream.send.max_off();
+
+                (stream.send.blocked_at() == Some(max_off)).then(|| (id, max_off))
+            })
+            .min()
+    }
+
     /// Returns true if there are any streams that are reset.
     pub fn has_reset(&self) -> bool {
         !self.reset.is_empty()
@@ -0,0 +1,268 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Time types used by loss recovery.
+//!
+//! `std::time::Instant` can't be used on targets such as
+//! wasm32-unknown-unknown, where reading the time panics, and where there's no
+//! other way to build one. Loss recovery uses the [`Instant`] type of this
+//! module instead, which is counted from an arbitrary origin:
+//!
+//! * On wasm32-unknown-unknown, [`Instant::now()`] reads `performance.now()`,
+//!   so it shares its origin with timestamps taken by JavaScript code.
+//!
+//! * On other targets, it wraps a `std::time::Instant`, and converts to and
+//!   from it for free. Its origin is the first time it's used.
+//!
+//! Applications can also build instants from their own timestamps with
+//! [`Instant::from_origin()`], and only pass those to quiche.
+//!
+//! The connection API keeps using `std::time::Instant`.
+//!
+//! [`Instant`]: struct.Instant.html
+//! [`Instant::now()`]: struct.Instant.html#method.now
+//! [`Instant::from_origin()`]: struct.Instant.html#method.from_origin
+
+use std::ops::Add;
+use std::ops::AddAssign;
+use std::ops::Sub;
+use std::ops::SubAssign;
+
+pub use std::time::Duration;
+
+/// A measurement of a monotonically nondecreasing clock.
+///
+/// This mirrors the API of `std::time::Instant`, and can be converted to and
+/// from it on targets where the latter is usable.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(clock::Inner);
+
+impl Instant {
+    /// Returns the instant `since_origin` after the origin of the clock.
+    pub fn from_origin(since_origin: Duration) -> Instant {
+        Instant(clock::from_origin(since_origin))
+    }
+
+    /// Returns the amount of time elapsed since the origin of the clock, or
+    /// zero if this instant is before it.
+    pub fn since_origin(&self) -> Duration {
+        clock::since_origin(self.0)
+    }
+
+    /// Returns the current time.
+    pub fn now() -> Instant {
+        Instant(clock::now())
+    }
+
+    /// Returns the amount of time elapsed since this instant.
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().saturating_duration_since(*self)
+    }
+
+    /// Returns the amount of time elapsed from another instant to this one,
+    /// or zero if that instant is later than this one.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.saturating_duration_since(earlier)
+    }
+
+    /// Returns the amount of time elapsed from another instant to this one,
+    /// or `None` if that instant is later than this one.
+    pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration> {
+        clock::checked_duration_since(self.0, earlier.0)
+    }
+
+    /// Returns the amount of time elapsed from another instant to this one,
+    /// or zero if that instant is later than this one.
+    pub fn saturating_duration_since(&self, earlier: Instant) -> Duration {
+        self.checked_duration_since(earlier).unwrap_or_default()
+    }
+
+    /// Returns `Some(t)` where `t` is the time `self + duration` if it can be
+    /// represented, `None` otherwise.
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_add(duration).map(Instant)
+    }
+
+    /// Returns `Some(t)` where `t` is the time `self - duration` if it can be
+    /// represented, `None` otherwise.
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_sub(duration).map(Instant)
+    }
+}
+
+impl std::fmt::Debug for Instant {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, other: Duration) -> Instant {
+        self.checked_add(other)
+            .expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, other: Duration) {
+        *self = *self + other;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, other: Duration) -> Instant {
+        self.checked_sub(other)
+            .expect("overflow when subtracting duration from instant")
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, other: Duration) {
+        *self = *self - other;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, other: Instant) -> Duration {
+        self.duration_since(other)
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl From<std::time::Instant> for Instant {
+    fn from(t: std::time::Instant) -> Instant {
+        Instant(t)
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl From<Instant> for std::time::Instant {
+    fn from(t: Instant) -> std::time::Instant {
+        t.0
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+mod clock {
+    use super::Duration;
+
+    use wasm_bindgen::prelude::*;
+
+    pub type Inner = Duration;
+
+    #[wasm_bindgen]
+    extern {
+        #[wasm_bindgen(js_namespace = performance, js_name = now)]
+        fn performance_now() -> f64;
+    }
+
+    pub fn now() -> Inner {
+        Duration::from_secs_f64(performance_now() / 1000.0)
+    }
+
+    pub fn from_origin(since_origin: Duration) -> Inner {
+        since_origin
+    }
+
+    pub fn since_origin(t: Inner) -> Duration {
+        t
+    }
+
+    pub fn checked_duration_since(t: Inner, earlier: Inner) -> Option<Duration> {
+        t.checked_sub(earlier)
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+mod clock {
+    use super::Duration;
+
+    pub type Inner = std::time::Instant;
+
+    lazy_static::lazy_static! {
+        static ref ORIGIN: Inner = Inner::now();
+    }
+
+    pub fn now() -> Inner {
+        Inner::now()
+    }
+
+    pub fn from_origin(since_origin: Duration) -> Inner {
+        *ORIGIN + since_origin
+    }
+
+    pub fn since_origin(t: Inner) -> Duration {
+        t.saturating_duration_since(*ORIGIN)
+    }
+
+    pub fn checked_duration_since(t: Inner, earlier: Inner) -> Option<Duration> {
+        t.checked_duration_since(earlier)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arithmetic() {
+        let origin = Instant::from_origin(Duration::ZERO);
+        let t = origin + Duration::from_millis(10);
+
+        assert_eq!(t.since_origin(), Duration::from_millis(10));
+        assert_eq!(t - origin, Duration::from_millis(10));
+        assert_eq!(origin - t, Duration::ZERO);
+        assert_eq!(t - Duration::from_millis(10), origin);
+
+        assert_eq!(origin.checked_duration_since(t), None);
+    }
+
+    #[test]
+    fn now() {
+        let before = Instant::now();
+        let after = Instant::now();
+
+        assert!(after >= before);
+        assert!(before.elapsed() >= after - before);
+    }
+
+    #[test]
+    fn std_conversion() {
+        let t = std::time::Instant::now();
+
+        assert_eq!(std::time::Instant::from(Instant::from(t)), t);
+        assert_eq!(
+            Instant::from(t) + Duration::from_secs(1),
+            Instant::from(t + Duration::from_secs(1))
+        );
+    }
+}
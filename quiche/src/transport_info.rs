@@ -0,0 +1,215 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! TCP_INFO-style transport diagnostics.
+//!
+//! The connection attributes the time elapsed between updates to the states
+//! it was in at the previous update: busy sending, limited by the congestion
+//! window and limited by the peer's flow control. This is coarser than what
+//! the kernel does for TCP, as updates only happen when the application calls
+//! `send()`, `recv()` or `on_timeout()`, but it's enough to tell apart
+//! connections that are network bound from the ones that are peer bound.
+
+use std::time::Duration;
+
+use crate::time::Instant;
+
+/// A snapshot of the transport state of a connection, in the shape of Linux's
+/// `TCP_INFO`.
+///
+/// It can be collected using the [`transport_info()`] method. Fields are named
+/// after their `tcpi_*` counterparts, with the following conventions:
+///
+/// * times are [`Duration`]s (microseconds in the C API, like `TCP_INFO`);
+///
+/// * `snd_cwnd` is in bytes rather than in segments;
+///
+/// * rates are in bytes per second.
+///
+/// Path-specific fields refer to the active path, and are zero if there is
+/// none. Counters and limited times cover the whole connection.
+///
+/// There is no equivalent of `tcpi_sndbuf_limited`, as stream send buffers are
+/// only bounded by flow control, which is reported in `rwnd_limited`.
+///
+/// [`transport_info()`]: struct.Connection.html#method.transport_info
+/// [`Duration`]: https://doc.rust-lang.org/std/time/struct.Duration.html
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TransportInfo {
+    /// The smoothed round-trip time (`tcpi_rtt`).
+    pub rtt: Duration,
+
+    /// The round-trip time variation (`tcpi_rttvar`).
+    pub rttvar: Duration,
+
+    /// The size of the congestion window in bytes (`tcpi_snd_cwnd`).
+    pub snd_cwnd: usize,
+
+    /// The number of sent packets with retransmitted data
+    /// (`tcpi_total_retrans`).
+    pub retrans: usize,
+
+    /// The number of packets declared lost (`tcpi_lost`).
+    pub lost: usize,
+
+    /// The most recent delivery rate estimate (`tcpi_delivery_rate`).
+    pub delivery_rate: u64,
+
+    /// The pacing rate (`tcpi_pacing_rate`).
+    pub pacing_rate: u64,
+
+    /// The time spent with data in flight (`tcpi_busy_time`).
+    pub busy_time: Duration,
+
+    /// The part of `busy_time` spent with a full congestion window.
+    ///
+    /// `TCP_INFO` has no such field, and leaves it to be derived from the
+    /// others instead.
+    pub cwnd_limited: Duration,
+
+    /// The time spent unable to send stream data because of the peer's
+    /// connection-level flow control limit (`tcpi_rwnd_limited`).
+    pub rwnd_limited: Duration,
+}
+
+/// The time spent by a connection in each limited state.
+#[derive(Default)]
+pub struct LimitedTime {
+    busy: Duration,
+
+    cwnd_limited: Duration,
+
+    rwnd_limited: Duration,
+
+    // The states at the last update, and when it happened.
+    state: LimitedState,
+
+    updated: Option<Instant>,
+}
+
+/// The states a connection can be limited by.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct LimitedState {
+    /// Whether there is data in flight.
+    pub busy: bool,
+
+    /// Whether the congestion window is full.
+    pub cwnd_limited: bool,
+
+    /// Whether the peer's flow control limit has been reached.
+    pub rwnd_limited: bool,
+}
+
+impl LimitedTime {
+    /// Attributes the time elapsed since the last update to the previous
+    /// states, and records the new ones.
+    pub fn update(&mut self, state: LimitedState, now: Instant) {
+        let (busy, cwnd_limited, rwnd_limited) = self.totals(now);
+
+        self.busy = busy;
+        self.cwnd_limited = cwnd_limited;
+        self.rwnd_limited = rwnd_limited;
+
+        self.state = state;
+        self.updated = Some(now);
+    }
+
+    /// Returns the busy, cwnd-limited and rwnd-limited times up to `now`.
+    pub fn totals(&self, now: Instant) -> (Duration, Duration, Duration) {
+        let elapsed = match self.updated {
+            Some(updated) => now.saturating_duration_since(updated),
+
+            None => Duration::ZERO,
+        };
+
+        let add = |total: Duration, limited: bool| {
+            if limited {
+                total + elapsed
+            } else {
+                total
+            }
+        };
+
+        (
+            add(self.busy, self.state.busy),
+            add(
+                self.cwnd_limited,
+                self.state.busy && self.state.cwnd_limited,
+            ),
+            add(self.rwnd_limited, self.state.rwnd_limited),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limited_time() {
+        let mut limited = LimitedTime::default();
+
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+
+        // Nothing is accounted before the first update.
+        limited.update(
+            LimitedState {
+                busy: true,
+                cwnd_limited: false,
+                rwnd_limited: false,
+            },
+            start + ms(10),
+        );
+        assert_eq!(limited.totals(start + ms(10)), (ms(0), ms(0), ms(0)));
+
+        limited.update(
+            LimitedState {
+                busy: true,
+                cwnd_limited: true,
+                rwnd_limited: false,
+            },
+            start + ms(30),
+        );
+        assert_eq!(limited.totals(start + ms(30)), (ms(20), ms(0), ms(0)));
+
+        // Totals include the time since the last update.
+        assert_eq!(limited.totals(start + ms(35)), (ms(25), ms(5), ms(0)));
+
+        limited.update(
+            LimitedState {
+                busy: false,
+                cwnd_limited: true,
+                rwnd_limited: true,
+            },
+            start + ms(40),
+        );
+
+        // The congestion window doesn't limit an idle connection.
+        limited.update(LimitedState::default(), start + ms(50));
+        assert_eq!(limited.totals(start + ms(60)), (ms(30), ms(10), ms(10)));
+    }
+}